@@ -0,0 +1,824 @@
+//! Wire format of the chat WebSocket protocol, shared by the server and the clients.
+//!
+//! Every message is a JSON object with a `type` tag. Clients that negotiate the
+//! [`SUBPROTOCOL_MSGPACK`] subprotocol get the same objects as MessagePack maps in binary
+//! frames instead. [`ClientMessage`] is what clients
+//! send, [`ServerMessage`] what the server sends. Fields that the deprecated Bun backend
+//! or older Rust servers leave out are `Option`s, so clients keep working against them;
+//! the Rust server always fills them in. How messages are shown is up to each client.
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Current protocol version, reported in `status`
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The subprotocol of the current protocol version
+pub const SUBPROTOCOL: &str = "chat.v1";
+
+/// The current protocol in MessagePack: the same messages, as maps in binary frames.
+/// Text frames with JSON keep working on such a connection.
+pub const SUBPROTOCOL_MSGPACK: &str = "chat.msgpack";
+
+/// WebSocket subprotocols the server accepts, newest first.
+/// Clients that don't request a subprotocol get the current protocol.
+pub const SUBPROTOCOLS: &[&str] = &[SUBPROTOCOL, SUBPROTOCOL_MSGPACK];
+
+/// Client → server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClientMessage {
+    #[serde(rename = "chat")]
+    Chat {
+        text: String,
+        /// Id of the message this answers; it must still be in the server's history
+        #[serde(rename = "replyTo", default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<u64>,
+    },
+    #[serde(rename = "setName")]
+    SetName { name: String },
+    #[serde(rename = "status")]
+    Status,
+    /// All fields are optional; without them every user is listed in no particular order
+    #[serde(rename = "listUsers")]
+    ListUsers {
+        /// `name` or `connected` (longest connected first)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sort: Option<String>,
+        /// Case-insensitive substring of the name
+        #[serde(skip_serializing_if = "Option::is_none")]
+        filter: Option<String>,
+        /// Users to skip, after sorting and filtering
+        #[serde(skip_serializing_if = "Option::is_none")]
+        offset: Option<usize>,
+        /// Page size, capped by the server
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+    #[serde(rename = "ping")]
+    Ping {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    #[serde(rename = "ai")]
+    Ai { prompt: String },
+    /// Like `ai`, but the answer goes only to the requester
+    #[serde(rename = "aiPrivate")]
+    AiPrivate { prompt: String },
+    #[serde(rename = "aiCancel")]
+    AiCancel,
+    #[serde(rename = "edit")]
+    Edit { id: u64, text: String },
+    #[serde(rename = "delete")]
+    Delete { id: u64 },
+    #[serde(rename = "auth")]
+    Auth { token: String },
+    #[serde(rename = "stats")]
+    Stats,
+    /// Admin only: a notice for everyone, shown more prominently than `system`
+    #[serde(rename = "announce")]
+    Announce { text: String },
+    /// Admin only: delete the latest `lastN` messages of a user for everyone, e.g. a
+    /// spammer's. Answered with a `delete` per message.
+    #[serde(rename = "purge")]
+    Purge {
+        name: String,
+        #[serde(rename = "lastN")]
+        last_n: usize,
+    },
+    #[serde(rename = "whoami")]
+    WhoAmI,
+    #[serde(rename = "version")]
+    Version,
+    #[serde(rename = "resume")]
+    Resume { token: String },
+    /// Chat messages after `since`, the `seq` of an earlier `ackName`. For catching up
+    /// after a reconnect.
+    #[serde(rename = "backfill")]
+    Backfill { since: u64 },
+    /// Admin only: receive events of these kinds instead of the chat; empty to stop.
+    /// Plain strings, so the server can name the one it doesn't know.
+    #[serde(rename = "subscribe")]
+    Subscribe { events: Vec<String> },
+    /// Offer a file to one user. The file is hosted elsewhere; only this metadata goes
+    /// through the server.
+    #[serde(rename = "fileOffer")]
+    FileOffer {
+        /// Name of the receiving user
+        to: String,
+        /// File name, without a path
+        name: String,
+        /// Size in bytes
+        size: u64,
+        /// Where the receiver can download the file
+        url: String,
+    },
+}
+
+/// Server → client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ServerMessage {
+    #[serde(rename = "chat")]
+    Chat {
+        /// For `edit` and `delete`; the Bun backend doesn't number messages
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        from: String,
+        text: String,
+        /// Id of the message this answers
+        #[serde(rename = "replyTo", default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<u64>,
+        at: u64,
+    },
+    /// Several `chat` messages in one frame, sent when the server batches chat
+    #[serde(rename = "chatBatch")]
+    ChatBatch { messages: Vec<ServerMessage> },
+    /// Answer to `backfill`: the most recent `chat` messages after `since`, oldest first.
+    /// Messages that arrived live since connecting can be in here too; ids tell them apart.
+    #[serde(rename = "backfill")]
+    Backfill {
+        messages: Vec<ServerMessage>,
+        /// Sequence to pass in the next `backfill`
+        seq: u64,
+        /// Some messages after `since` are gone: too old for the history, past the cap,
+        /// or from before a server restart
+        gap: bool,
+    },
+    #[serde(rename = "edit")]
+    Edit { id: u64, text: String, at: u64 },
+    #[serde(rename = "delete")]
+    Delete { id: u64, at: u64 },
+    #[serde(rename = "system")]
+    System { text: String, at: u64 },
+    #[serde(rename = "announcement")]
+    Announcement { text: String, at: u64 },
+    /// Something that happened on the server, for admins that subscribed
+    #[serde(rename = "event")]
+    Event {
+        event: EventKind,
+        #[serde(rename = "clientId")]
+        client_id: String,
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+        at: u64,
+    },
+    /// Structured join/leave event with the new user count, next to the `system` text
+    #[serde(rename = "presence")]
+    Presence {
+        #[serde(rename = "userCount")]
+        user_count: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        joined: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        left: Option<String>,
+        at: u64,
+    },
+    #[serde(rename = "ackName")]
+    AckName {
+        name: String,
+        /// Send back in `resume` after a reconnect to get this name again
+        #[serde(rename = "resumeToken", skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+        /// Id of the latest chat message; keep the highest seen id for `backfill`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        at: u64,
+    },
+    /// Everything but `uptimeSeconds`, `userCount` and `messagesSent` is optional: the
+    /// Bun backend and older Rust servers send a much smaller status.
+    #[serde(rename = "status")]
+    Status {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+        #[serde(rename = "rustVersion", skip_serializing_if = "Option::is_none")]
+        rust_version: Option<String>,
+        #[serde(rename = "protocolVersion", skip_serializing_if = "Option::is_none")]
+        protocol_version: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        os: Option<String>,
+        #[serde(rename = "cpuCores", skip_serializing_if = "Option::is_none")]
+        cpu_cores: Option<usize>,
+        #[serde(rename = "uptimeSeconds", deserialize_with = "whole_seconds")]
+        uptime_seconds: u64,
+        #[serde(rename = "userCount")]
+        user_count: usize,
+        #[serde(rename = "peakUsers", skip_serializing_if = "Option::is_none")]
+        peak_users: Option<usize>,
+        #[serde(rename = "connectionsTotal", skip_serializing_if = "Option::is_none")]
+        connections_total: Option<u64>,
+        #[serde(rename = "messagesSent")]
+        messages_sent: u64,
+        #[serde(rename = "messagesPerSecond", skip_serializing_if = "Option::is_none")]
+        messages_per_second: Option<f64>,
+        #[serde(rename = "memoryMb", skip_serializing_if = "Option::is_none")]
+        memory_mb: Option<f64>,
+        /// Process CPU usage; 100 is one full core
+        #[serde(rename = "cpuPercent", skip_serializing_if = "Option::is_none")]
+        cpu_percent: Option<f64>,
+        #[serde(
+            rename = "totalSessionSeconds",
+            skip_serializing_if = "Option::is_none"
+        )]
+        total_session_seconds: Option<u64>,
+        /// Connected clients whose handshake offered permessage-deflate. The server
+        /// doesn't negotiate it, so every frame goes out uncompressed.
+        #[serde(rename = "compressionOffered", skip_serializing_if = "Option::is_none")]
+        compression_offered: Option<usize>,
+        #[serde(rename = "aiEnabled", skip_serializing_if = "Option::is_none")]
+        ai_enabled: Option<bool>,
+        #[serde(rename = "aiModel", skip_serializing_if = "Option::is_none")]
+        ai_model: Option<String>,
+        #[serde(rename = "aiCostToday", skip_serializing_if = "Option::is_none")]
+        ai_cost_today: Option<f64>,
+        #[serde(rename = "aiTokensToday", skip_serializing_if = "Option::is_none")]
+        ai_tokens_today: Option<u64>,
+        #[serde(rename = "aiRetries", skip_serializing_if = "Option::is_none")]
+        ai_retries: Option<u64>,
+    },
+    /// One page of users; `total` counts the matching users on all pages. Both are
+    /// missing on servers without paging.
+    #[serde(rename = "listUsers")]
+    ListUsers {
+        users: Vec<UserInfo>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        offset: Option<usize>,
+    },
+    /// The requesting connection's own identity, sent only to that client
+    #[serde(rename = "whoami")]
+    WhoAmI {
+        id: String,
+        name: String,
+        ip: String,
+        admin: bool,
+        at: u64,
+    },
+    /// Answer to `version`: which build of the server this is
+    #[serde(rename = "version")]
+    Version {
+        #[serde(rename = "crateVersion")]
+        crate_version: String,
+        #[serde(rename = "rustVersion")]
+        rust_version: String,
+        /// Short commit hash, `unknown` when built outside a git checkout
+        #[serde(rename = "gitCommit")]
+        git_commit: String,
+        /// Epoch millis
+        #[serde(rename = "builtAt")]
+        built_at: u64,
+        at: u64,
+    },
+    /// Chat message rejected by the rate limit; `message` is the same text as the plain error
+    #[serde(rename = "rateLimited")]
+    RateLimited {
+        #[serde(rename = "retryAfterSecs")]
+        retry_after_secs: u64,
+        message: String,
+        at: u64,
+    },
+    /// Admin only
+    #[serde(rename = "stats")]
+    Stats {
+        #[serde(rename = "uptimeSeconds")]
+        uptime_seconds: u64,
+        #[serde(rename = "userCount")]
+        user_count: usize,
+        #[serde(rename = "peakUsers")]
+        peak_users: usize,
+        #[serde(rename = "connectionsTotal")]
+        connections_total: u64,
+        #[serde(rename = "messagesSent")]
+        messages_sent: u64,
+        #[serde(rename = "memoryMb")]
+        memory_mb: f64,
+        #[serde(rename = "rateLimitEnabled")]
+        rate_limit_enabled: bool,
+        #[serde(rename = "rateLimitPerMinute")]
+        rate_limit_per_minute: u32,
+        #[serde(rename = "aiEnabled")]
+        ai_enabled: bool,
+        #[serde(rename = "aiCostToday", skip_serializing_if = "Option::is_none")]
+        ai_cost_today: Option<f64>,
+        #[serde(rename = "aiTokensToday", skip_serializing_if = "Option::is_none")]
+        ai_tokens_today: Option<u64>,
+        #[serde(rename = "aiRetries", skip_serializing_if = "Option::is_none")]
+        ai_retries: Option<u64>,
+        users: Vec<UserStats>,
+        at: u64,
+    },
+    /// A file offered by `from` to `to`; sent to both of them
+    #[serde(rename = "fileOffer")]
+    FileOffer {
+        from: String,
+        to: String,
+        name: String,
+        size: u64,
+        url: String,
+        at: u64,
+    },
+    #[serde(rename = "error")]
+    Error {
+        message: String,
+        /// The AI question this error answers, so the asker stops waiting for it
+        #[serde(skip_serializing_if = "Option::is_none")]
+        prompt: Option<String>,
+    },
+    #[serde(rename = "pong")]
+    Pong { token: Option<String>, at: u64 },
+    #[serde(rename = "ai")]
+    Ai {
+        from: String,
+        prompt: String,
+        response: String,
+        #[serde(rename = "responseMs")]
+        response_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tokens: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cost: Option<f64>,
+        /// Model that actually answered; OpenRouter may route to another than configured
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+        /// Why the model stopped: `stop`, `length` (cut off at `AI_MAX_TOKENS`), `content_filter`, ...
+        #[serde(
+            rename = "finishReason",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        finish_reason: Option<String>,
+        /// Answer to `aiPrivate`, sent only to the requester
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        private: bool,
+        at: u64,
+    },
+}
+
+impl ServerMessage {
+    /// The `type` tag
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerMessage::Chat { .. } => "chat",
+            ServerMessage::ChatBatch { .. } => "chatBatch",
+            ServerMessage::Backfill { .. } => "backfill",
+            ServerMessage::Edit { .. } => "edit",
+            ServerMessage::Delete { .. } => "delete",
+            ServerMessage::System { .. } => "system",
+            ServerMessage::Announcement { .. } => "announcement",
+            ServerMessage::Event { .. } => "event",
+            ServerMessage::Presence { .. } => "presence",
+            ServerMessage::AckName { .. } => "ackName",
+            ServerMessage::Status { .. } => "status",
+            ServerMessage::ListUsers { .. } => "listUsers",
+            ServerMessage::WhoAmI { .. } => "whoami",
+            ServerMessage::Version { .. } => "version",
+            ServerMessage::RateLimited { .. } => "rateLimited",
+            ServerMessage::Stats { .. } => "stats",
+            ServerMessage::FileOffer { .. } => "fileOffer",
+            ServerMessage::Error { .. } => "error",
+            ServerMessage::Pong { .. } => "pong",
+            ServerMessage::Ai { .. } => "ai",
+        }
+    }
+
+    /// Server timestamp (epoch millis) for message types that carry one
+    pub fn at(&self) -> Option<u64> {
+        match self {
+            ServerMessage::Chat { at, .. }
+            | ServerMessage::Edit { at, .. }
+            | ServerMessage::Delete { at, .. }
+            | ServerMessage::System { at, .. }
+            | ServerMessage::Announcement { at, .. }
+            | ServerMessage::Event { at, .. }
+            | ServerMessage::Presence { at, .. }
+            | ServerMessage::AckName { at, .. }
+            | ServerMessage::WhoAmI { at, .. }
+            | ServerMessage::Version { at, .. }
+            | ServerMessage::RateLimited { at, .. }
+            | ServerMessage::Stats { at, .. }
+            | ServerMessage::FileOffer { at, .. }
+            | ServerMessage::Pong { at, .. }
+            | ServerMessage::Ai { at, .. } => Some(*at),
+            ServerMessage::ChatBatch { .. }
+            | ServerMessage::Backfill { .. }
+            | ServerMessage::Status { .. }
+            | ServerMessage::ListUsers { .. }
+            | ServerMessage::Error { .. } => None,
+        }
+    }
+}
+
+/// `uptimeSeconds` is fractional on the Bun backend
+fn whole_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    Ok(f64::deserialize(deserializer)? as u64)
+}
+
+/// Kinds of [`ServerMessage::Event`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Join,
+    Leave,
+    Chat,
+    Edit,
+    Delete,
+    Rename,
+    Ai,
+    Error,
+}
+
+impl EventKind {
+    pub const ALL: &'static [EventKind] = &[
+        EventKind::Join,
+        EventKind::Leave,
+        EventKind::Chat,
+        EventKind::Edit,
+        EventKind::Delete,
+        EventKind::Rename,
+        EventKind::Ai,
+        EventKind::Error,
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.as_str() == value)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Join => "join",
+            EventKind::Leave => "leave",
+            EventKind::Chat => "chat",
+            EventKind::Edit => "edit",
+            EventKind::Delete => "delete",
+            EventKind::Rename => "rename",
+            EventKind::Ai => "ai",
+            EventKind::Error => "error",
+        }
+    }
+}
+
+/// One user in `listUsers`. The Bun backend only sends `id` and `name`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub ip: String,
+    #[serde(rename = "messageCount", default)]
+    pub message_count: u64,
+    /// Only present (as `true`) for bot accounts
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bot: bool,
+    /// Only present (as `true`) for users who authenticated as admin
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub admin: bool,
+    /// Seconds since the user's last chat message, or since connecting if there was none
+    #[serde(
+        rename = "idleSeconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub idle_seconds: Option<u64>,
+}
+
+/// Per-user breakdown in the admin `stats` response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserStats {
+    pub id: String,
+    pub name: String,
+    pub ip: String,
+    pub message_count: u64,
+    /// Epoch millis
+    pub connected_at: u64,
+    pub admin: bool,
+    #[serde(default)]
+    pub bot: bool,
+    /// `User-Agent` header of the WebSocket upgrade, if the client sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Chat messages in the current rate-limit window (last 60 seconds)
+    pub messages_last_minute: usize,
+    pub rate_limited: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Serialize `message` and compare it field by field with the documented JSON
+    fn assert_wire<T: Serialize>(message: &T, expected: Value) {
+        assert_eq!(serde_json::to_value(message).unwrap(), expected);
+    }
+
+    #[test]
+    fn client_messages_match_the_documented_format() {
+        assert_wire(
+            &ClientMessage::Chat {
+                text: "Hallo allemaal".into(),
+                reply_to: None,
+            },
+            json!({ "type": "chat", "text": "Hallo allemaal" }),
+        );
+        assert_wire(
+            &ClientMessage::Chat {
+                text: "Goed idee".into(),
+                reply_to: Some(42),
+            },
+            json!({ "type": "chat", "text": "Goed idee", "replyTo": 42 }),
+        );
+        assert_wire(
+            &ClientMessage::ListUsers {
+                sort: None,
+                filter: None,
+                offset: None,
+                limit: None,
+            },
+            json!({ "type": "listUsers" }),
+        );
+        assert_wire(
+            &ClientMessage::ListUsers {
+                sort: Some("name".into()),
+                filter: Some("bas".into()),
+                offset: Some(0),
+                limit: Some(50),
+            },
+            json!({ "type": "listUsers", "sort": "name", "filter": "bas", "offset": 0, "limit": 50 }),
+        );
+        assert_wire(
+            &ClientMessage::Ping { token: None },
+            json!({ "type": "ping" }),
+        );
+        assert_wire(&ClientMessage::AiCancel, json!({ "type": "aiCancel" }));
+        assert_wire(&ClientMessage::WhoAmI, json!({ "type": "whoami" }));
+        assert_wire(&ClientMessage::Version, json!({ "type": "version" }));
+        assert_wire(
+            &ClientMessage::Backfill { since: 42 },
+            json!({ "type": "backfill", "since": 42 }),
+        );
+        assert_wire(
+            &ClientMessage::Subscribe {
+                events: vec!["join".into(), "leave".into(), "chat".into()],
+            },
+            json!({ "type": "subscribe", "events": ["join", "leave", "chat"] }),
+        );
+        assert_wire(
+            &ClientMessage::FileOffer {
+                to: "Eva".into(),
+                name: "notulen.pdf".into(),
+                size: 482133,
+                url: "https://files.example.com/notulen.pdf".into(),
+            },
+            json!({ "type": "fileOffer", "to": "Eva", "name": "notulen.pdf", "size": 482133, "url": "https://files.example.com/notulen.pdf" }),
+        );
+    }
+
+    #[test]
+    fn server_messages_match_the_documented_format() {
+        let chat = |id, from: &str, text: &str, at| ServerMessage::Chat {
+            id: Some(id),
+            from: from.into(),
+            text: text.into(),
+            reply_to: None,
+            at,
+        };
+        assert_wire(
+            &ServerMessage::ChatBatch {
+                messages: vec![
+                    chat(42, "Bas", "Hallo allemaal", 1733312410000),
+                    ServerMessage::Chat {
+                        id: Some(43),
+                        from: "Eva".into(),
+                        text: "Hoi!".into(),
+                        reply_to: Some(42),
+                        at: 1733312410002,
+                    },
+                ],
+            },
+            json!({
+                "type": "chatBatch",
+                "messages": [
+                    { "type": "chat", "id": 42, "from": "Bas", "text": "Hallo allemaal", "at": 1733312410000u64 },
+                    { "type": "chat", "id": 43, "from": "Eva", "text": "Hoi!", "replyTo": 42, "at": 1733312410002u64 }
+                ]
+            }),
+        );
+        assert_wire(
+            &ServerMessage::AckName {
+                name: "guest-a1b2c3".into(),
+                resume_token: Some("3f2b9c0e8d7a4b6f9e1c2d3a4b5c6d7e".into()),
+                seq: Some(41),
+                at: 1733312400000,
+            },
+            json!({ "type": "ackName", "name": "guest-a1b2c3", "resumeToken": "3f2b9c0e8d7a4b6f9e1c2d3a4b5c6d7e", "seq": 41, "at": 1733312400000u64 }),
+        );
+        assert_wire(
+            &ServerMessage::Backfill {
+                messages: vec![chat(42, "Bas", "Hallo allemaal", 1733312410000)],
+                seq: 42,
+                gap: false,
+            },
+            json!({
+                "type": "backfill",
+                "messages": [
+                    { "type": "chat", "id": 42, "from": "Bas", "text": "Hallo allemaal", "at": 1733312410000u64 }
+                ],
+                "seq": 42,
+                "gap": false
+            }),
+        );
+        assert_wire(
+            &ServerMessage::Presence {
+                user_count: 2,
+                joined: None,
+                left: Some("Bas".into()),
+                at: 1733312460000,
+            },
+            json!({ "type": "presence", "userCount": 2, "left": "Bas", "at": 1733312460000u64 }),
+        );
+        assert_wire(
+            &ServerMessage::Event {
+                event: EventKind::Chat,
+                client_id: "550e8400-e29b-41d4-a716-446655440000".into(),
+                name: "Bas".into(),
+                text: Some("Hallo!".into()),
+                at: 1733312400001,
+            },
+            json!({ "type": "event", "event": "chat", "clientId": "550e8400-e29b-41d4-a716-446655440000", "name": "Bas", "text": "Hallo!", "at": 1733312400001u64 }),
+        );
+        assert_wire(
+            &ServerMessage::ListUsers {
+                users: vec![UserInfo {
+                    id: "8b7e27d4-6f2f-4cd7-a939-0a44a3f90b2e".into(),
+                    name: "Bas".into(),
+                    ip: "192.168.1.10".into(),
+                    message_count: 42,
+                    bot: false,
+                    admin: true,
+                    idle_seconds: Some(75),
+                }],
+                total: Some(1),
+                offset: Some(0),
+            },
+            json!({
+                "type": "listUsers",
+                "users": [
+                    { "id": "8b7e27d4-6f2f-4cd7-a939-0a44a3f90b2e", "name": "Bas", "ip": "192.168.1.10", "messageCount": 42, "admin": true, "idleSeconds": 75 }
+                ],
+                "total": 1,
+                "offset": 0
+            }),
+        );
+        assert_wire(
+            &ServerMessage::FileOffer {
+                from: "Bas".into(),
+                to: "Eva".into(),
+                name: "notulen.pdf".into(),
+                size: 482133,
+                url: "https://files.example.com/notulen.pdf".into(),
+                at: 1733312418000,
+            },
+            json!({ "type": "fileOffer", "from": "Bas", "to": "Eva", "name": "notulen.pdf", "size": 482133, "url": "https://files.example.com/notulen.pdf", "at": 1733312418000u64 }),
+        );
+        assert_wire(
+            &ServerMessage::Pong {
+                token: None,
+                at: 1733312420000,
+            },
+            json!({ "type": "pong", "token": null, "at": 1733312420000u64 }),
+        );
+        assert_wire(
+            &ServerMessage::RateLimited {
+                retry_after_secs: 42,
+                message: "Rate limit overschreden. Wacht 42 seconden.".into(),
+                at: 1733312417000,
+            },
+            json!({ "type": "rateLimited", "retryAfterSecs": 42, "message": "Rate limit overschreden. Wacht 42 seconden.", "at": 1733312417000u64 }),
+        );
+        assert_wire(
+            &ServerMessage::Ai {
+                from: "Bas".into(),
+                prompt: "Vat TCP en UDP kort samen.".into(),
+                response: "TCP is betrouwbaar.".into(),
+                response_ms: 842,
+                tokens: Some(121),
+                cost: Some(0.00042),
+                model: Some("openai/gpt-4o".into()),
+                finish_reason: Some("length".into()),
+                private: false,
+                at: 1733312430000,
+            },
+            json!({
+                "type": "ai",
+                "from": "Bas",
+                "prompt": "Vat TCP en UDP kort samen.",
+                "response": "TCP is betrouwbaar.",
+                "responseMs": 842,
+                "tokens": 121,
+                "cost": 0.00042,
+                "model": "openai/gpt-4o",
+                "finishReason": "length",
+                "at": 1733312430000u64
+            }),
+        );
+    }
+
+    #[test]
+    fn status_serializes_every_documented_field() {
+        let status = ServerMessage::Status {
+            version: Some("0.1.0".into()),
+            rust_version: Some("1.82.0".into()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            os: Some("macos".into()),
+            cpu_cores: Some(10),
+            uptime_seconds: 42,
+            user_count: 3,
+            peak_users: Some(8),
+            connections_total: Some(15),
+            messages_sent: 112,
+            messages_per_second: Some(2.67),
+            memory_mb: Some(18.34),
+            cpu_percent: Some(1.5),
+            total_session_seconds: Some(5400),
+            compression_offered: Some(2),
+            ai_enabled: Some(true),
+            ai_model: Some("openai/gpt-4o".into()),
+            ai_cost_today: Some(0.0123),
+            ai_tokens_today: Some(4821),
+            ai_retries: Some(3),
+        };
+        assert_wire(
+            &status,
+            json!({
+                "type": "status",
+                "version": "0.1.0",
+                "rustVersion": "1.82.0",
+                "protocolVersion": 1,
+                "os": "macos",
+                "cpuCores": 10,
+                "uptimeSeconds": 42,
+                "userCount": 3,
+                "peakUsers": 8,
+                "connectionsTotal": 15,
+                "messagesSent": 112,
+                "messagesPerSecond": 2.67,
+                "memoryMb": 18.34,
+                "cpuPercent": 1.5,
+                "totalSessionSeconds": 5400,
+                "compressionOffered": 2,
+                "aiEnabled": true,
+                "aiModel": "openai/gpt-4o",
+                "aiCostToday": 0.0123,
+                "aiTokensToday": 4821,
+                "aiRetries": 3
+            }),
+        );
+    }
+
+    #[test]
+    fn bun_backend_messages_still_parse() {
+        let status: ServerMessage = serde_json::from_str(
+            r#"{"type":"status","uptimeSeconds":12.7,"userCount":1,"messagesSent":3}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            status,
+            ServerMessage::Status {
+                uptime_seconds: 12,
+                version: None,
+                ..
+            }
+        ));
+
+        let users: ServerMessage = serde_json::from_str(
+            r#"{"type":"listUsers","users":[{"id":"a1b2c3d4","name":"Bas"}]}"#,
+        )
+        .unwrap();
+        let ServerMessage::ListUsers { users, total, .. } = users else {
+            panic!("expected listUsers");
+        };
+        assert_eq!(users[0].name, "Bas");
+        assert_eq!(total, None);
+
+        let chat: ServerMessage = serde_json::from_str(
+            r#"{"type":"chat","from":"Bas","text":"Hello!","at":1733312410000}"#,
+        )
+        .unwrap();
+        assert!(matches!(chat, ServerMessage::Chat { id: None, .. }));
+    }
+
+    #[test]
+    fn event_kinds_round_trip_through_their_names() {
+        for &kind in EventKind::ALL {
+            assert_eq!(EventKind::parse(kind.as_str()), Some(kind));
+            assert_eq!(serde_json::to_value(kind).unwrap(), json!(kind.as_str()));
+        }
+        assert_eq!(EventKind::parse("typing"), None);
+    }
+}
@@ -20,6 +20,7 @@ let socket;
 let currentName = "";
 let reconnectTimeout;
 let pendingPings = new Map(); // token -> timestamp
+let statusRequests = 0; // answers to /status still to show
 
 const generateToken = () => {
   if (crypto.randomUUID) return crypto.randomUUID();
@@ -127,7 +128,7 @@ const appendMessage = (type, text, meta = "", isMine = false, useMarkdown = fals
   scrollToBottom();
 };
 
-const appendUserList = (users) => {
+const appendUserList = (users, total = users.length) => {
   const item = document.createElement("div");
   item.className = "msg system userlist";
   const metaEl = document.createElement("div");
@@ -138,7 +139,10 @@ const appendUserList = (users) => {
 
   const header = document.createElement("div");
   header.className = "userlist-header";
-  header.textContent = `Gebruikers (${users.length})`;
+  // The server sends one page (max 100 by default); total counts everyone
+  header.textContent = total > users.length
+    ? `Gebruikers (${users.length} van ${total})`
+    : `Gebruikers (${users.length})`;
   body.appendChild(header);
 
   const table = document.createElement("table");
@@ -232,6 +236,9 @@ const appendStatus = (payload) => {
   if (payload.memoryMb !== undefined) {
     addRow("Memory", `${payload.memoryMb} MB`);
   }
+  if (payload.cpuPercent !== undefined) {
+    addRow("CPU", `${payload.cpuPercent}%`);
+  }
 
   // AI status
   if (payload.aiEnabled !== undefined) {
@@ -276,6 +283,7 @@ const handleCommand = (raw) => {
     case "name":
       return sendPayload({ type: "setName", name: rest.join(" ") || nicknameInput.value || "" });
     case "status":
+      statusRequests += 1;
       return sendPayload({ type: "status" });
     case "users":
       return sendPayload({ type: "listUsers" });
@@ -302,21 +310,52 @@ const handleMessage = (event) => {
   } catch {
     return appendMessage("error", "Kon bericht niet lezen.", "server");
   }
+  handlePayload(payload);
+};
 
+const handlePayload = (payload) => {
   switch (payload.type) {
+    case "chatBatch":
+      // Chat messages the server sent in one frame (CHAT_BATCH_MS)
+      payload.messages.forEach(handlePayload);
+      break;
     case "chat": {
       const isMine = payload.from === currentName;
       appendMessage("msg", payload.text, `${payload.from} • ${new Date(payload.at).toLocaleTimeString()}`, isMine);
       break;
     }
     case "system": {
+      // Presence texts depend on the server's SERVER_LOCALE (nl or en)
       const isPresence = payload.text.endsWith("heeft de chat betreden.") ||
                          payload.text.endsWith("heeft de chat verlaten.") ||
-                         / heet nu .+\.$/.test(payload.text);
+                         / heet nu .+\.$/.test(payload.text) ||
+                         payload.text.endsWith(" joined the chat.") ||
+                         payload.text.endsWith(" left the chat.") ||
+                         / is now known as .+\.$/.test(payload.text);
       const msgType = isPresence ? "presence" : "system";
       appendMessage(msgType, payload.text, new Date(payload.at).toLocaleTimeString());
       break;
     }
+    case "announcement":
+      appendMessage("announcement", payload.text, `Mededeling • ${new Date(payload.at).toLocaleTimeString()}`);
+      break;
+    case "event":
+      // Only sent to an admin connection that subscribed; the web client never does
+      appendMessage("system", `[${payload.event}] ${payload.name}${payload.text ? `: ${payload.text}` : ""}`, new Date(payload.at).toLocaleTimeString());
+      break;
+    case "fileOffer": {
+      // Plain text, not a link: the URL comes from another user
+      const isMine = payload.from === currentName;
+      const sizeKb = Math.max(1, Math.round(payload.size / 1024));
+      const text = isMine
+        ? `Je bood ${payload.name} (${sizeKb} KB) aan ${payload.to} aan.`
+        : `${payload.from} biedt je ${payload.name} (${sizeKb} KB) aan: ${payload.url}`;
+      appendMessage("system", text, new Date(payload.at).toLocaleTimeString());
+      break;
+    }
+    case "presence":
+      // Structured twin of the join/leave system text; nothing to show
+      break;
     case "ackName":
       currentName = payload.name;
       nicknameInput.value = payload.name;
@@ -325,18 +364,23 @@ const handleMessage = (event) => {
       appendMessage("system", `Je heet nu ${payload.name}.`, new Date(payload.at).toLocaleTimeString());
       break;
     case "status": {
-      appendStatus(payload);
+      // Only show answers to /status, not periodic pushes from the server
+      if (statusRequests > 0) {
+        statusRequests -= 1;
+        appendStatus(payload);
+      }
       break;
     }
     case "listUsers": {
       if (!payload.users?.length) {
         appendMessage("system", "Geen gebruikers online.", "server");
       } else {
-        appendUserList(payload.users);
+        appendUserList(payload.users, payload.total);
       }
       break;
     }
     case "error":
+    case "rateLimited":
       appendMessage("error", payload.message, "server");
       break;
     case "pong": {
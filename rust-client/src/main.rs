@@ -1,17 +1,654 @@
-use std::collections::HashMap;
-use std::io::{self, Write};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use chat_protocol::{ClientMessage as Outgoing, EventKind, ServerMessage as Incoming, SUBPROTOCOL};
 use crossterm::event::{self, Event, KeyCode, KeyModifiers};
 use crossterm::terminal;
 use crossterm::{cursor, execute};
-use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use serde::Deserialize;
 use tokio::sync::mpsc;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        error::ProtocolError,
+        http::{
+            header::{SEC_WEBSOCKET_PROTOCOL, USER_AGENT},
+            HeaderValue,
+        },
+        Message,
+    },
+    Connector, MaybeTlsStream, WebSocketStream,
+};
+
+mod style;
+
+use style::{paint, ColorMode, Style, Theme};
+
+/// Set by `--output json`: stdout then only carries server messages
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Where the prompt, status bar and local notices go: stdout, or stderr when stdout is
+/// reserved for `--output json`
+fn screen() -> Box<dyn Write> {
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+/// `print!` to [`screen`]
+macro_rules! say {
+    ($($arg:tt)*) => {{
+        let _ = write!(screen(), $($arg)*);
+    }};
+}
+
+/// `--output`: how received messages are printed
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// Formatted and colored for people
+    Plain,
+    /// Each message as one line of JSON (NDJSON), for scripts
+    Json,
+}
+
+/// One server message as a line of NDJSON on stdout
+fn print_json(text: &str) {
+    let mut stdout = io::stdout();
+    // The terminal is in raw mode, so it needs the carriage return too
+    let end = if stdout.is_terminal() { "\r\n" } else { "\n" };
+    let _ = write!(stdout, "{}{}", text, end);
+    let _ = stdout.flush();
+}
 
 const MAX_HISTORY: usize = 20;
+/// The history file keeps more than fits in memory before it gets compacted
+const MAX_HISTORY_FILE_LINES: usize = MAX_HISTORY * 10;
+const DEFAULT_URL: &str = "ws://127.0.0.1:3001";
+/// Sent as `User-Agent` when connecting
+const CLIENT_USER_AGENT: &str = concat!("cbxchat-cli/", env!("CARGO_PKG_VERSION"));
+/// Longest user agent shown in /stats; browsers send very long ones
+const MAX_USER_AGENT_WIDTH: usize = 40;
+/// Chat messages remembered so edits and deletes can show the original text
+const MAX_RECENT_MESSAGES: usize = 500;
+/// Users per page of /users
+const USERS_PAGE_SIZE: usize = 50;
+/// Users idle this long are shown as away in /users
+const AWAY_AFTER_SECS: u64 = 10 * 60;
+/// Chat messages held while disconnected; more are dropped
+const MAX_OUTBOX: usize = 20;
+/// Wait before the first reconnect attempt, doubled after every failure
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+struct Args {
+    url: String,
+    bell: bool,
+    /// `--timestamps` or `CHAT_TIMESTAMPS`; `None` leaves it to the config file
+    timestamps: Option<bool>,
+    name: Option<String>,
+    statusbar: bool,
+    cafile: Option<String>,
+    insecure: bool,
+    color: ColorMode,
+    theme: Theme,
+    raw: bool,
+    config: Option<String>,
+    reconnect: bool,
+    /// Disconnect and exit after this long (`--duration`)
+    duration: Option<Duration>,
+    output: OutputFormat,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        url: DEFAULT_URL.to_string(),
+        bell: true,
+        timestamps: std::env::var("CHAT_TIMESTAMPS")
+            .ok()
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1"),
+        name: None,
+        statusbar: true,
+        cafile: None,
+        insecure: false,
+        color: ColorMode::Auto,
+        theme: Theme::Default,
+        raw: false,
+        config: None,
+        reconnect: true,
+        duration: None,
+        output: OutputFormat::Plain,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--name" => match iter.next() {
+                Some(name) => args.name = Some(name),
+                None => {
+                    eprintln!("--name requires a value");
+                    std::process::exit(2);
+                }
+            },
+            s if s.starts_with("--name=") => {
+                args.name = Some(s.trim_start_matches("--name=").to_string());
+            }
+            "--cafile" => match iter.next() {
+                Some(path) => args.cafile = Some(path),
+                None => {
+                    eprintln!("--cafile requires a value");
+                    std::process::exit(2);
+                }
+            },
+            s if s.starts_with("--cafile=") => {
+                args.cafile = Some(s.trim_start_matches("--cafile=").to_string());
+            }
+            "--config" => match iter.next() {
+                Some(path) => args.config = Some(path),
+                None => {
+                    eprintln!("--config requires a value");
+                    std::process::exit(2);
+                }
+            },
+            s if s.starts_with("--config=") => {
+                args.config = Some(s.trim_start_matches("--config=").to_string());
+            }
+            "--duration" => args.duration = Some(parse_duration(iter.next())),
+            s if s.starts_with("--duration=") => {
+                args.duration = Some(parse_duration(Some(
+                    s.trim_start_matches("--duration=").to_string(),
+                )));
+            }
+            "--output" => args.output = parse_output(iter.next()),
+            s if s.starts_with("--output=") => {
+                args.output = parse_output(Some(s.trim_start_matches("--output=").to_string()));
+            }
+            "--color" => args.color = parse_color(iter.next()),
+            s if s.starts_with("--color=") => {
+                args.color = parse_color(Some(s.trim_start_matches("--color=").to_string()));
+            }
+            "--theme" => args.theme = parse_theme(iter.next()),
+            s if s.starts_with("--theme=") => {
+                args.theme = parse_theme(Some(s.trim_start_matches("--theme=").to_string()));
+            }
+            "--insecure" => args.insecure = true,
+            "--no-bell" => args.bell = false,
+            "--timestamps" => args.timestamps = Some(true),
+            "--no-statusbar" => args.statusbar = false,
+            "--raw" => args.raw = true,
+            "--no-reconnect" => args.reconnect = false,
+            "-h" | "--help" => {
+                print_usage();
+                std::process::exit(0);
+            }
+            s if !s.starts_with('-') => args.url = s.to_string(),
+            s => {
+                eprintln!("Unknown option: {}", s);
+                print_usage();
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if args.output == OutputFormat::Json {
+        if args.raw {
+            eprintln!("--raw can't be combined with --output json, which prints the JSON already");
+            std::process::exit(2);
+        }
+        // Its periodic status requests would end up in the output
+        args.statusbar = false;
+    }
+
+    args
+}
+
+fn parse_color(value: Option<String>) -> ColorMode {
+    match value.as_deref().and_then(ColorMode::parse) {
+        Some(mode) => mode,
+        None => {
+            eprintln!("--color requires one of: auto, always, never");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn parse_output(value: Option<String>) -> OutputFormat {
+    match value.as_deref() {
+        Some("plain") => OutputFormat::Plain,
+        Some("json") => OutputFormat::Json,
+        _ => {
+            eprintln!("--output requires one of: plain, json");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn parse_duration(value: Option<String>) -> Duration {
+    match value
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&secs| secs > 0)
+    {
+        Some(secs) => Duration::from_secs(secs),
+        None => {
+            eprintln!("--duration requires a number of seconds greater than 0");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn parse_theme(value: Option<String>) -> Theme {
+    match value.as_deref().and_then(Theme::parse) {
+        Some(theme) => theme,
+        None => {
+            eprintln!("--theme requires one of: default, colorblind, mono");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn print_usage() {
+    eprintln!("Usage: chat [OPTIONS] [URL]");
+    eprintln!();
+    eprintln!("Arguments:");
+    eprintln!("  [URL]  WebSocket server URL (default: {})", DEFAULT_URL);
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --name <NAME> Set your username right after connecting");
+    eprintln!("  --no-bell     Don't ring the terminal bell when you are mentioned");
+    eprintln!("  --timestamps  Prefix messages with local HH:MM:SS (or CHAT_TIMESTAMPS=1, or the config file)");
+    eprintln!("  --no-statusbar Don't show the status bar at the top of the screen");
+    eprintln!("  --cafile <PATH> Trust an extra root certificate (PEM) for wss:// URLs");
+    eprintln!("  --insecure    Skip TLS certificate verification (testing only)");
+    eprintln!(
+        "  --config <PATH> Read settings from PATH instead of ~/.config/chatclient/config.toml"
+    );
+    eprintln!("  --color <WHEN> Use colors: auto (default, off when piped or NO_COLOR is set), always, never");
+    eprintln!("  --theme <NAME> Color palette: default, colorblind, mono");
+    eprintln!(
+        "  --raw         Also print the raw JSON of every received message (toggle with /raw)"
+    );
+    eprintln!("  --no-reconnect Exit when the connection drops instead of reconnecting");
+    eprintln!("  --duration <SECS> Disconnect and exit after SECS seconds");
+    eprintln!(
+        "  --output <FORMAT> Print received messages as plain (default) or json, one per line"
+    );
+    eprintln!("  -h, --help    Show this help");
+}
+
+/// Macro name (without the `/`) to the text it expands to
+type Macros = HashMap<String, String>;
+
+/// Settings from the config file
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Config {
+    /// Extra chat macros; a name that is also built in replaces the built-in one
+    macros: Macros,
+    /// Also recall sent chat messages with ↑/↓, for this session only; the history
+    /// file keeps commands only
+    history_include_chat: bool,
+    /// Prefix messages with local `HH:MM:SS`, unless `--timestamps` or `CHAT_TIMESTAMPS`
+    /// says otherwise
+    timestamps: bool,
+}
+
+/// `$XDG_CONFIG_HOME/chatclient/config.toml`, falling back to `~/.config/chatclient/config.toml`.
+fn config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(config_dir.join("chatclient").join("config.toml"))
+}
+
+/// Read the config file. Only a file named with `--config` has to exist; a broken file
+/// is always an error, so a typo doesn't silently lose settings.
+fn load_config(explicit: Option<&str>) -> Result<Config, String> {
+    let path = match explicit {
+        Some(path) => PathBuf::from(path),
+        None => match config_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+}
+
+/// Built-in macros plus the ones from the config file. Names are matched case-insensitively.
+fn all_macros(config: Config) -> Macros {
+    let mut macros = Macros::from([("shrug".to_string(), r"¯\_(ツ)_/¯".to_string())]);
+    for (name, expansion) in config.macros {
+        macros.insert(name.trim_start_matches('/').to_lowercase(), expansion);
+    }
+    macros
+}
+
+/// TLS connector for `--cafile` / `--insecure`; `None` keeps the default system roots.
+fn tls_connector(cafile: Option<&str>, insecure: bool) -> Result<Option<Connector>, String> {
+    if cafile.is_none() && !insecure {
+        return Ok(None);
+    }
+
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(path) = cafile {
+        let pem = std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        let cert = native_tls::Certificate::from_pem(&pem)
+            .map_err(|e| format!("Invalid certificate in {}: {}", path, e))?;
+        builder.add_root_certificate(cert);
+    }
+    if insecure {
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+
+    let connector = builder
+        .build()
+        .map_err(|e| format!("Cannot set up TLS: {}", e))?;
+    Ok(Some(Connector::NativeTls(connector)))
+}
+
+/// How often the status bar refreshes the user count via a silent status request
+const STATUSBAR_REFRESH: Duration = Duration::from_secs(30);
+/// Refresh interval of `/watch status` without a number
+const DEFAULT_WATCH_SECS: u64 = 5;
+/// Messages `/purge` removes without a count
+const DEFAULT_PURGE: usize = 10;
+
+const PROMPT: &str = "> ";
+/// Shown in front of every extra row of a multi-line input; same width as `PROMPT`
+const CONTINUATION: &str = "… ";
+
+/// The input being typed, possibly spanning several rows.
+///
+/// Shared between the key reader and the receive loop, so incoming messages
+/// can be printed above the prompt without losing what was typed.
+struct Prompt {
+    input: String,
+    /// Cursor position as char index, not byte index
+    cursor: usize,
+    /// Row the terminal cursor is on, relative to the first prompt row
+    row: usize,
+    /// Our unanswered `/ai` question and when it was sent; shown above the input
+    ai_pending: Option<(String, Instant)>,
+    /// When the server's rate limit lets us send again; counted down above the input
+    cooldown_until: Option<Instant>,
+    /// Chat messages waiting for the cooldown to end (see `/queue`)
+    queued: usize,
+    /// Latest server status while `/watch status` runs; kept above the input
+    watch: Option<String>,
+}
+
+impl Prompt {
+    fn new() -> Self {
+        Self {
+            input: String::new(),
+            cursor: 0,
+            row: 0,
+            ai_pending: None,
+            cooldown_until: None,
+            queued: 0,
+            watch: None,
+        }
+    }
+
+    /// Erase all prompt rows and leave the cursor at the start of the first one.
+    fn clear(&mut self) {
+        if self.row > 0 {
+            say!("\x1b[{}A", self.row);
+        }
+        say!("\r\x1b[J");
+        self.row = 0;
+    }
+
+    /// Redraw the prompt and put the terminal cursor at `self.cursor`.
+    fn draw(&mut self) {
+        self.clear();
+        let mut status_rows = 0;
+        if let Some(line) = &self.watch {
+            say!("{}\r\n", paint(Style::Dim, line));
+            status_rows += 1;
+        }
+        if let Some((_, since)) = &self.ai_pending {
+            say!(
+                "{}\r\n",
+                paint(
+                    Style::Dim,
+                    format!("AI is thinking... {}s", since.elapsed().as_secs())
+                )
+            );
+            status_rows += 1;
+        }
+        if let Some(until) = self.cooldown_until {
+            let secs = until
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64()
+                .ceil();
+            let queued = match self.queued {
+                0 => String::new(),
+                n => format!(", {} queued", n),
+            };
+            say!(
+                "{}\r\n",
+                paint(
+                    Style::Warning,
+                    format!("Rate limited, you can send again in {}s{}", secs, queued)
+                )
+            );
+            status_rows += 1;
+        }
+        let rows: Vec<&str> = self.input.split('\n').collect();
+        say!("{}{}", PROMPT, rows.join(&format!("\r\n{}", CONTINUATION)));
+
+        let before: String = self.input.chars().take(self.cursor).collect();
+        let row = before.matches('\n').count();
+        let col = before.rsplit('\n').next().unwrap_or("").chars().count();
+        if rows.len() - 1 > row {
+            say!("\x1b[{}A", rows.len() - 1 - row);
+        }
+        let _ = execute!(screen(), cursor::MoveToColumn((col + PROMPT.len()) as u16));
+        self.row = status_rows + row;
+        let _ = screen().flush();
+    }
+}
+
+/// Client settings that can be flipped at runtime by a command
+struct Toggles {
+    /// Also print the raw JSON of received messages (`--raw`, `/raw`)
+    raw: AtomicBool,
+    /// Hold chat messages while rate limited and send them when the cooldown ends (`/queue`)
+    queue: AtomicBool,
+    /// Seconds between status requests of `/watch status`; 0 when not watching
+    watch_secs: AtomicU64,
+}
+
+/// Persistent top row with connection info.
+///
+/// The rest of the screen is a scroll region below it, so regular output
+/// never overwrites the bar.
+struct StatusBar {
+    enabled: bool,
+    url: String,
+    name: String,
+    users: Option<usize>,
+    /// The server sends `presence` events, so join/leave texts don't need parsing
+    presence: bool,
+    connected: bool,
+    /// Showing the server's event stream instead of the chat (`/tail`)
+    tailing: bool,
+}
+
+impl StatusBar {
+    fn new(enabled: bool, url: String) -> Self {
+        Self {
+            enabled,
+            url,
+            name: String::new(),
+            users: None,
+            presence: false,
+            connected: false,
+            tailing: false,
+        }
+    }
+
+    /// Apply a server message to the bar. Returns true if anything changed.
+    fn update(&mut self, msg: &Incoming) -> bool {
+        match msg {
+            Incoming::AckName { name, .. } => self.name = name.clone(),
+            Incoming::Status { user_count, .. } => self.users = Some(*user_count),
+            Incoming::Presence { user_count, .. } => {
+                self.presence = true;
+                self.users = Some(*user_count);
+            }
+            // Servers without `presence`: join/leave texts depend on the server's
+            // SERVER_LOCALE (nl or en)
+            Incoming::System { text, .. }
+                if !self.presence
+                    && (text.ends_with("heeft de chat betreden.")
+                        || text.ends_with(" joined the chat.")) =>
+            {
+                self.users = self.users.map(|u| u + 1);
+            }
+            Incoming::System { text, .. }
+                if !self.presence
+                    && (text.ends_with("heeft de chat verlaten.")
+                        || text.ends_with(" left the chat.")) =>
+            {
+                self.users = self.users.map(|u| u.saturating_sub(1));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Reserve the top row and move the cursor to the bottom of the scroll region.
+    fn install(&self) {
+        if !self.enabled {
+            return;
+        }
+        let (_, rows) = terminal::size().unwrap_or((80, 24));
+        say!("\x1b[2;{}r", rows);
+        let _ = execute!(screen(), cursor::MoveTo(0, rows.saturating_sub(1)));
+        self.draw();
+    }
+
+    fn draw(&self) {
+        if !self.enabled {
+            return;
+        }
+        let (cols, _) = terminal::size().unwrap_or((80, 24));
+        let state = if self.connected {
+            "● connected"
+        } else {
+            "○ disconnected"
+        };
+        let users = self
+            .users
+            .map(|u| u.to_string())
+            .unwrap_or_else(|| "?".to_string());
+        let mut text = format!(
+            " {} │ {} │ {} │ users: {} ",
+            state, self.url, self.name, users
+        );
+        if self.tailing {
+            text.push_str("│ tail ");
+        }
+        let width = cols as usize;
+        let mut line: String = text.chars().take(width).collect();
+        let len = line.chars().count();
+        line.push_str(&" ".repeat(width - len));
+
+        // Save cursor, draw on row 1, restore cursor
+        say!("\x1b7\x1b[1;1H{}\x1b8", paint(Style::Inverse, line));
+        let _ = screen().flush();
+    }
+}
+
+/// `$XDG_DATA_HOME/chatclient/history`, falling back to `~/.local/share/chatclient/history`.
+fn history_path() -> Option<PathBuf> {
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))?;
+    Some(data_dir.join("chatclient").join("history"))
+}
+
+/// Load the most recent commands from disk. A missing or unreadable file gives an empty history.
+fn load_history() -> Vec<String> {
+    let Some(contents) = history_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+
+    let mut history: Vec<String> = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('/') && history.last().map(String::as_str) != Some(line) {
+            history.push(line.to_string());
+        }
+    }
+    let skip = history.len().saturating_sub(MAX_HISTORY);
+    history.split_off(skip)
+}
+
+/// Append a command to the history file, compacting it when it grows too large.
+fn append_history(command: &str) {
+    let Some(path) = history_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+
+    let appended = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| writeln!(f, "{}", command));
+    if appended.is_err() {
+        return;
+    }
+
+    if let Ok(contents) = fs::read_to_string(&path) {
+        let lines: Vec<&str> = contents.lines().collect();
+        if lines.len() > MAX_HISTORY_FILE_LINES {
+            let keep = &lines[lines.len() - MAX_HISTORY_FILE_LINES..];
+            let _ = fs::write(&path, keep.join("\n") + "\n");
+        }
+    }
+}
+
+/// Disable raw mode and release the status bar scroll region.
+fn restore_terminal() {
+    let _ = terminal::disable_raw_mode();
+    say!("\x1b[r");
+    let _ = screen().flush();
+}
+
+/// Check whether `text` mentions `name` as a whole word (case-insensitive).
+fn is_mention(text: &str, name: &str) -> bool {
+    if name.is_empty() {
+        return false;
+    }
+    let text = text.to_lowercase();
+    let name = name.to_lowercase();
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+
+    text.match_indices(&name).any(|(start, matched)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + matched.len()..].chars().next();
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    })
+}
 
 fn format_uptime(seconds: u64) -> String {
     if seconds < 60 {
@@ -25,106 +662,350 @@ fn format_uptime(seconds: u64) -> String {
     }
 }
 
-#[derive(Debug, Serialize)]
-#[serde(tag = "type", rename_all = "camelCase")]
-enum Outgoing {
-    #[serde(rename = "chat")]
-    Chat { text: String },
-    #[serde(rename = "setName")]
-    SetName { name: String },
-    #[serde(rename = "status")]
-    Status,
-    #[serde(rename = "listUsers")]
-    ListUsers,
-    #[serde(rename = "ping")]
-    Ping { token: Option<String> },
-    #[serde(rename = "ai")]
-    Ai { prompt: String },
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(tag = "type", rename_all = "camelCase")]
-enum Incoming {
-    #[serde(rename = "chat")]
-    Chat { from: String, text: String },
-    #[serde(rename = "system")]
-    System { text: String },
-    #[serde(rename = "ackName")]
-    AckName { name: String },
-    #[serde(rename = "status")]
-    Status {
-        version: String,
-        #[serde(rename = "rustVersion")]
-        rust_version: Option<String>,
-        os: Option<String>,
-        #[serde(rename = "cpuCores")]
-        cpu_cores: Option<usize>,
-        #[serde(rename = "uptimeSeconds")]
-        uptime_seconds: u64,
-        #[serde(rename = "userCount")]
-        user_count: usize,
-        #[serde(rename = "peakUsers")]
-        peak_users: Option<usize>,
-        #[serde(rename = "connectionsTotal")]
-        connections_total: Option<u64>,
-        #[serde(rename = "messagesSent")]
-        messages_sent: u64,
-        #[serde(rename = "messagesPerSecond")]
-        messages_per_second: f64,
-        #[serde(rename = "memoryMb")]
-        memory_mb: f64,
-        #[serde(rename = "aiEnabled")]
-        ai_enabled: Option<bool>,
-        #[serde(rename = "aiModel")]
-        ai_model: Option<String>,
-    },
-    #[serde(rename = "listUsers")]
-    ListUsers { users: Vec<UserInfo> },
-    #[serde(rename = "error")]
-    Error { message: String },
-    #[serde(rename = "pong")]
-    Pong { token: Option<String> },
-    #[serde(rename = "ai")]
-    Ai {
-        from: String,
-        prompt: String,
-        response: String,
-        #[serde(rename = "responseMs")]
-        response_ms: u64,
-        tokens: Option<u32>,
-        cost: Option<f64>,
-    },
+/// One-line summary of a `status` reply for `/watch status`
+fn format_watch_line(status: &Incoming, secs: u64) -> Option<String> {
+    let Incoming::Status {
+        uptime_seconds,
+        user_count,
+        messages_sent,
+        messages_per_second,
+        memory_mb,
+        cpu_percent,
+        ..
+    } = status
+    else {
+        return None;
+    };
+    let mut parts = vec![
+        format!("up {}", format_uptime(*uptime_seconds)),
+        format!("{} users", user_count),
+        format!("{} messages", messages_sent),
+    ];
+    if let Some(rate) = messages_per_second {
+        parts.push(format!("{} msg/s", rate));
+    }
+    if let Some(mb) = memory_mb {
+        parts.push(format!("{:.2} MB", mb));
+    }
+    if let Some(cpu) = cpu_percent {
+        parts.push(format!("{:.1}% CPU", cpu));
+    }
+    Some(format!(
+        "⟳ {} ({}s, {})",
+        parts.join(" │ "),
+        secs,
+        chrono::Local::now().format("%H:%M:%S")
+    ))
 }
 
-#[derive(Debug, Deserialize)]
-struct UserInfo {
-    id: String,
-    name: String,
-    ip: String,
+/// Wait for the next `/watch` refresh; never finishes while not watching.
+async fn next_watch(watch: &mut Option<(u64, tokio::time::Interval)>) {
+    match watch {
+        Some((_, interval)) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Human-readable file size, e.g. `482 KB` or `1.5 MB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {
+        format!("{:.1} {}", size, UNITS[unit])
+    } else {
+        format!("{:.0} {}", size, UNITS[unit])
+    }
+}
+
+/// Parse a size like `4096`, `512K`, `1.5M` or `2GB` into bytes
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim().to_uppercase();
+    let value = value.strip_suffix('B').unwrap_or(&value);
+    let (number, factor) = match value.char_indices().last()? {
+        (i, 'K') => (&value[..i], 1024.0),
+        (i, 'M') => (&value[..i], 1024.0 * 1024.0),
+        (i, 'G') => (&value[..i], 1024.0 * 1024.0 * 1024.0),
+        _ => (value, 1.0),
+    };
+    let bytes = number.parse::<f64>().ok()? * factor;
+    (bytes.is_finite() && bytes >= 1.0).then_some(bytes as u64)
+}
+
+/// Last path segment of a URL, without query or fragment
+fn file_name_from_url(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next()?;
+    let (_, rest) = path.split_once("://")?;
+    let name = rest.split_once('/')?.1.rsplit('/').next()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Put `text` on the clipboard with the OSC 52 escape sequence. Most terminals support
+/// it, also over SSH; the ones that don't ignore it.
+fn copy_to_clipboard(text: &str) {
+    use base64::Engine;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    say!("\x1b]52;c;{}\x07", encoded);
+}
+
+/// The messages in a frame: the ones inside a `chatBatch`, or else the frame itself
+fn unbatch(frame: &str) -> Vec<String> {
+    if frame.contains("\"chatBatch\"") {
+        if let Ok(serde_json::Value::Object(batch)) = serde_json::from_str(frame) {
+            if let (Some("chatBatch"), Some(serde_json::Value::Array(messages))) = (
+                batch.get("type").and_then(|t| t.as_str()),
+                batch.get("messages"),
+            ) {
+                return messages.iter().map(|m| m.to_string()).collect();
+            }
+        }
+    }
+    vec![frame.to_string()]
+}
+
+/// Hand a received frame on as its messages, so a batch reaches the receive loop as
+/// separate chat messages
+fn split_batch(
+    msg: Result<Message, tungstenite::Error>,
+) -> futures_util::stream::Iter<std::vec::IntoIter<Result<Message, tungstenite::Error>>> {
+    let messages = match msg {
+        Ok(Message::Text(frame)) => unbatch(&frame)
+            .into_iter()
+            .map(|text| Ok(Message::text(text)))
+            .collect(),
+        other => vec![other],
+    };
+    futures_util::stream::iter(messages)
+}
+
+/// Render a `[HH:MM:SS] ` prefix in local time; falls back to now if the message has no timestamp.
+fn format_timestamp(at: Option<u64>) -> String {
+    let time = at
+        .and_then(|ms| chrono::DateTime::from_timestamp_millis(ms as i64))
+        .map(|t| t.with_timezone(&chrono::Local))
+        .unwrap_or_else(chrono::Local::now);
+    format!(
+        "{} ",
+        paint(Style::Dim, format!("[{}]", time.format("%H:%M:%S")))
+    )
+}
+
+fn print_help(macros: &Macros) {
+    let help = [
+        "Commands:",
+        "  /name <username>  Change your username",
+        "  /status           Show server status",
+        "  /watch status [secs]  Keep a status line above the input, refreshed every 5s or secs",
+        "  /unwatch          Stop /watch",
+        "  /users [filter]   List connected users; --page N for the next pages",
+        "  /whoami           Show your name, id and IP as the server sees them",
+        "  /version          Show the server's version, commit and build time",
+        "  /ping [token]     Ping server (measures roundtrip)",
+        "  /ai <question>    Ask AI a question",
+        "  /ask <question>   Ask AI a question, only you see the answer",
+        "  /cancel           Cancel your pending AI question",
+        "  /reply <id> <text>  Reply to a message, quoting it",
+        "  /edit <id> <text> Edit one of your messages",
+        "  /delete <id>      Delete one of your messages",
+        "  /auth <token>     Authenticate as admin",
+        "  /stats            Show per-user statistics (admin)",
+        "  /announce <text>  Post an announcement to everyone (admin)",
+        "  /purge <name> [count]  Delete a user's last count (10) messages for everyone (admin)",
+        "  /tail [events]    Show server events instead of the chat (admin)",
+        "  /untail           Back to the chat after /tail",
+        "  /offer <url> <size> <user>  Offer a file to one user (size like 512K or 3.5M)",
+        "  /copy             Copy the link of the last file offered to you",
+        "  /raw              Toggle showing raw JSON of received messages",
+        "  /queue            Toggle auto-sending rate-limited messages after the cooldown",
+        "  /clear            Clear the screen (Ctrl+L)",
+        "  /help             Show this help",
+        "  /quit             Exit the client",
+    ];
+    let mut names: Vec<String> = macros.keys().map(|name| format!("/{}", name)).collect();
+    names.sort();
+    let macro_help = format!(
+        "Macros (send their text, followed by anything you type after them):\r\n  {}",
+        names.join(" ")
+    );
+    say!(
+        "\r\n{}\r\n\r\n{}\r\n\r\n",
+        paint(Style::Dim, help.join("\r\n")),
+        paint(Style::Dim, macro_help)
+    );
+    let _ = screen().flush();
+}
+
+/// Display width of `s`, ignoring ANSI escape sequences
+fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip up to and including the final byte of the CSI sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Break a multi-line message text into terminal rows, indenting continuation rows
+/// so they line up under the first row's text. `head` is what precedes the text.
+fn indent_continuation(head: &str, text: &str) -> String {
+    text.replace('\n', &format!("\r\n{}", " ".repeat(visible_width(head))))
+}
+
+/// Dimmed `#<id> ` prefix so messages can be referenced by /edit and /delete
+fn id_prefix(id: Option<u64>) -> String {
+    id.map(|id| format!("{} ", paint(Style::Dim, format!("#{}", id))))
+        .unwrap_or_default()
+}
+
+/// Longest part of a message quoted above a reply, in characters
+const MAX_QUOTE_WIDTH: usize = 60;
+
+/// The line above a reply: the start of the message it answers, or only its id when
+/// that is no longer in the scrollback
+fn format_reply_quote(id: u64, quoted: Option<&(String, String)>) -> String {
+    let quote = match quoted {
+        Some((from, text)) => {
+            let first_line = text.lines().next().unwrap_or("");
+            let mut snippet: String = first_line.chars().take(MAX_QUOTE_WIDTH).collect();
+            if snippet.len() < text.len() {
+                snippet.push('…');
+            }
+            format!("↪ #{} {}: {}", id, from, snippet)
+        }
+        None => format!("↪ #{}", id),
+    };
+    paint(Style::Dim, format!("  {}", quote))
 }
 
-fn print_help() {
-    print!("\x1b[90m\r\n");
-    print!("Commands:\r\n");
-    print!("  /name <username>  Change your username\r\n");
-    print!("  /status           Show server status\r\n");
-    print!("  /users            List connected users\r\n");
-    print!("  /ping [token]     Ping server (measures roundtrip)\r\n");
-    print!("  /ai <question>    Ask AI a question\r\n");
-    print!("  /help             Show this help\r\n");
-    print!("  /quit             Exit the client\r\n");
-    print!("\x1b[0m\r\n");
-    let _ = io::stdout().flush();
+/// Parse a message id as shown in the scrollback, with or without the leading `#`
+fn parse_message_id(arg: &str) -> Option<u64> {
+    arg.trim_start_matches('#').parse().ok()
 }
 
 fn format_message(msg: &Incoming) -> String {
     match msg {
-        Incoming::Chat { from, text } => format!("\x1b[1m{}\x1b[0m: {}", from, text),
-        Incoming::System { text } => format!("\x1b[33m* {}\x1b[0m", text),
-        Incoming::AckName { name } => format!("\x1b[32m✓ Your name is now: {}\x1b[0m", name),
+        Incoming::Chat { id, from, text, .. } => {
+            let head = format!("{}{}: ", id_prefix(*id), paint(Style::Bold, from));
+            format!("{}{}", head, indent_continuation(&head, text))
+        }
+        // Normally split up by `split_batch` before it gets here
+        Incoming::ChatBatch { messages } => messages
+            .iter()
+            .map(format_message)
+            .collect::<Vec<_>>()
+            .join("\r\n"),
+        Incoming::Backfill {
+            messages,
+            gap: true,
+            ..
+        } if messages.is_empty() => paint(
+            Style::Dim,
+            "↺ some messages from while you were away are no longer available",
+        ),
+        Incoming::Backfill { messages, gap, .. } => {
+            let note = if *gap {
+                "↺ missed messages (some are no longer available):"
+            } else {
+                "↺ missed messages:"
+            };
+            std::iter::once(paint(Style::Dim, note))
+                .chain(messages.iter().map(format_message))
+                .collect::<Vec<_>>()
+                .join("\r\n")
+        }
+        Incoming::Edit { id, text, .. } => {
+            let head = format!("{} ", paint(Style::Dim, format!("✎ #{} edited:", id)));
+            format!("{}{}", head, indent_continuation(&head, text))
+        }
+        Incoming::Delete { id, .. } => paint(Style::Dim, format!("✗ #{} deleted", id)),
+        Incoming::System { text, .. } => paint(Style::Warning, format!("* {}", text)),
+        Incoming::Announcement { text, .. } => {
+            let head = " [Announcement] ";
+            paint(
+                Style::Announcement,
+                format!("{}{} ", head, indent_continuation(head, text)),
+            )
+        }
+        Incoming::Event {
+            event,
+            client_id,
+            name,
+            text,
+            ..
+        } => {
+            // Short id, like the server's guest names, to tell apart clients with one name
+            let who = format!(
+                "{} {}",
+                paint(Style::Bold, name),
+                paint(Style::Dim, &client_id[..6.min(client_id.len())])
+            );
+            let kind = paint(Style::Info, format!("{:<6}", event.as_str()));
+            match (event, text) {
+                (EventKind::Rename, Some(new)) => format!("{} {} → {}", kind, who, new),
+                (EventKind::Error, Some(error)) => {
+                    format!("{} {}: {}", kind, who, paint(Style::Error, error))
+                }
+                (_, Some(text)) => {
+                    let head = format!("{} {}: ", kind, who);
+                    format!("{}{}", head, indent_continuation(&head, text))
+                }
+                (_, None) => format!("{} {}", kind, who),
+            }
+        }
+        Incoming::AckName { name, .. } => {
+            paint(Style::Success, format!("✓ Your name is now: {}", name))
+        }
+        Incoming::FileOffer {
+            from,
+            to,
+            name,
+            size,
+            url,
+            ..
+        } => format!(
+            "{} {}\r\n  {}",
+            paint(Style::Info, format!("📎 {} → {}:", from, to)),
+            paint(Style::Bold, format!("{} ({})", name, format_size(*size))),
+            url
+        ),
+        Incoming::Presence {
+            user_count,
+            joined,
+            left,
+            ..
+        } => {
+            let change = match (joined, left) {
+                (Some(name), _) => format!("+{} ", name),
+                (_, Some(name)) => format!("-{} ", name),
+                _ => String::new(),
+            };
+            paint(
+                Style::Dim,
+                format!("* {}({} users online)", change, user_count),
+            )
+        }
         Incoming::Status {
             version,
             rust_version,
+            protocol_version,
             os,
             cpu_cores,
             uptime_seconds,
@@ -134,8 +1015,14 @@ fn format_message(msg: &Incoming) -> String {
             messages_sent,
             messages_per_second,
             memory_mb,
+            cpu_percent,
+            total_session_seconds,
+            compression_offered,
             ai_enabled,
             ai_model,
+            ai_cost_today,
+            ai_tokens_today,
+            ai_retries,
         } => {
             let mut rows: Vec<(String, String)> = Vec::new();
 
@@ -149,6 +1036,9 @@ fn format_message(msg: &Incoming) -> String {
             if let Some(rust_ver) = rust_version {
                 rows.push(("Rust".to_string(), rust_ver.clone()));
             }
+            if let Some(protocol) = protocol_version {
+                rows.push(("Protocol".to_string(), format!("v{}", protocol)));
+            }
 
             // Runtime stats
             rows.push(("Uptime".to_string(), format_uptime(*uptime_seconds)));
@@ -160,20 +1050,44 @@ fn format_message(msg: &Incoming) -> String {
                 rows.push(("Connections".to_string(), conns.to_string()));
             }
             rows.push(("Messages".to_string(), messages_sent.to_string()));
-            rows.push((
-                "Throughput".to_string(),
-                format!("{} msg/s", messages_per_second),
-            ));
-            rows.push(("Memory".to_string(), format!("{:.2} MB", memory_mb)));
+            if let Some(rate) = messages_per_second {
+                rows.push(("Throughput".to_string(), format!("{} msg/s", rate)));
+            }
+            if let Some(mb) = memory_mb {
+                rows.push(("Memory".to_string(), format!("{:.2} MB", mb)));
+            }
+            if let Some(cpu) = cpu_percent {
+                rows.push(("CPU".to_string(), format!("{:.1}%", cpu)));
+            }
+            if let Some(secs) = total_session_seconds {
+                rows.push((
+                    "Sessions".to_string(),
+                    format!("{} total", format_uptime(*secs)),
+                ));
+            }
+            if let Some(offered) = compression_offered {
+                rows.push((
+                    "Compression".to_string(),
+                    format!("off (offered by {})", offered),
+                ));
+            }
 
             // AI status
             if let Some(enabled) = ai_enabled {
-                let ai_status = if *enabled {
-                    ai_model.as_deref().unwrap_or("enabled").to_string()
-                } else {
-                    "disabled".to_string()
-                };
-                rows.push(("AI".to_string(), ai_status));
+                let ai_status = if *enabled { "enabled" } else { "disabled" };
+                rows.push(("AI".to_string(), ai_status.to_string()));
+            }
+            if let Some(model) = ai_model {
+                rows.push(("AI model".to_string(), model.clone()));
+            }
+            if let Some(cost) = ai_cost_today {
+                let tokens = ai_tokens_today
+                    .map(|t| format!(" ({} tokens)", t))
+                    .unwrap_or_default();
+                rows.push(("AI today".to_string(), format!("${:.4}{}", cost, tokens)));
+            }
+            if let Some(retries) = ai_retries {
+                rows.push(("AI retries".to_string(), retries.to_string()));
             }
 
             // Calculate max value width for box sizing
@@ -185,72 +1099,244 @@ fn format_message(msg: &Incoming) -> String {
                 .max(10);
             // "│ " + 11 (label) + " " + value_width + " │" = 16 + value_width
             let box_width = 16 + value_width;
-            let title = format!(" Server Status v{} ", version);
+            let title = match version {
+                Some(version) => format!(" Server Status v{} ", version),
+                None => " Server Status ".to_string(),
+            };
             let title_padding = box_width.saturating_sub(title.len() + 2);
             let left_pad = title_padding / 2;
             let right_pad = title_padding - left_pad;
 
             let mut lines = Vec::new();
-            lines.push(format!(
-                "\x1b[36m┌{}{}{}┐\x1b[0m",
-                "─".repeat(left_pad),
-                title,
-                "─".repeat(right_pad)
+            lines.push(paint(
+                Style::Info,
+                format!(
+                    "┌{}{}{}┐",
+                    "─".repeat(left_pad),
+                    title,
+                    "─".repeat(right_pad)
+                ),
             ));
 
             for (label, value) in &rows {
                 // Color AI status specially
-                let colored_value = if label == "AI" {
-                    if value == "disabled" {
-                        format!("\x1b[90m{:<width$}\x1b[0m", value, width = value_width)
-                    } else {
-                        format!("\x1b[32m{:<width$}\x1b[0m", value, width = value_width)
-                    }
-                } else {
-                    format!("\x1b[37m{:<width$}\x1b[0m", value, width = value_width)
+                let value_style = match (label.as_str(), value.as_str()) {
+                    ("AI", "disabled") => Style::Dim,
+                    ("AI", _) => Style::Success,
+                    _ => Style::Value,
                 };
+                let colored_value = paint(
+                    value_style,
+                    format!("{:<width$}", value, width = value_width),
+                );
+                let border = paint(Style::Info, "│");
                 lines.push(format!(
-                    "\x1b[36m│\x1b[0m {:<11} {} \x1b[36m│\x1b[0m",
-                    label, colored_value
+                    "{} {:<11} {} {}",
+                    border, label, colored_value, border
                 ));
             }
 
-            lines.push(format!("\x1b[36m└{}┘\x1b[0m", "─".repeat(box_width - 2)));
+            lines.push(paint(
+                Style::Info,
+                format!("└{}┘", "─".repeat(box_width - 2)),
+            ));
             lines.join("\r\n")
         }
-        Incoming::ListUsers { users } => {
+        Incoming::ListUsers {
+            users,
+            total,
+            offset,
+        } => {
             if users.is_empty() {
-                return "\x1b[36m[Users] No users connected\x1b[0m".to_string();
+                return paint(Style::Info, "[Users] No users found");
             }
             // Calculate column widths
             let name_width = users.iter().map(|u| u.name.len()).max().unwrap_or(4).max(4);
             let ip_width = users.iter().map(|u| u.ip.len()).max().unwrap_or(2).max(2);
 
-            let mut output = String::from("\x1b[36m");
-            output.push_str(&format!(
-                "\r\n  {:<name_width$}  {:<ip_width$}  {}\r\n",
-                "NAME", "IP", "ID"
-            ));
+            let msgs_width = users
+                .iter()
+                .map(|u| u.message_count.to_string().len())
+                .max()
+                .unwrap_or(4)
+                .max(4);
+
+            // Servers without idleSeconds (the Bun backend) show "-"
+            let idle: Vec<String> = users
+                .iter()
+                .map(|u| u.idle_seconds.map_or("-".to_string(), format_uptime))
+                .collect();
+            let idle_width = idle.iter().map(String::len).max().unwrap_or(4).max(4);
+
+            let mut output = paint(
+                Style::Info,
+                format!(
+                    "\r\n  {:<name_width$}  {:<ip_width$}  {:>msgs_width$}  {:>idle_width$}  {}\r\n  {:-<name_width$}  {:-<ip_width$}  {:->msgs_width$}  {:->idle_width$}  {:-<36}\r\n",
+                    "NAME", "IP", "MSGS", "IDLE", "ID", "", "", "", "", ""
+                ),
+            );
+            for (u, idle) in users.iter().zip(&idle) {
+                // The tags keep roles readable without colors
+                let away = u.idle_seconds.is_some_and(|secs| secs >= AWAY_AFTER_SECS);
+                let mut tags = String::new();
+                for (tag, set) in [("admin", u.admin), ("bot", u.bot), ("away", away)] {
+                    if set {
+                        tags.push_str(&format!("  [{}]", tag));
+                    }
+                }
+                let style = if u.admin {
+                    Style::Admin
+                } else if u.bot {
+                    Style::Bot
+                } else if away {
+                    Style::Dim
+                } else {
+                    Style::Info
+                };
+                let row = format!(
+                    "  {:<name_width$}  {:<ip_width$}  {:>msgs_width$}  {:>idle_width$}  {}{}",
+                    u.name, u.ip, u.message_count, idle, u.id, tags
+                );
+                output.push_str(&format!("{}\r\n", paint(style, row)));
+            }
+            let offset = offset.unwrap_or(0);
+            let shown = offset + users.len();
+            if let Some(total) = total.filter(|&total| total > shown) {
+                let next_page = offset / USERS_PAGE_SIZE + 2;
+                output.push_str(&paint(
+                    Style::Dim,
+                    format!(
+                        "  {}-{} of {}, /users --page {} for more\r\n",
+                        offset + 1,
+                        shown,
+                        total,
+                        next_page
+                    ),
+                ));
+            }
+            output
+        }
+        Incoming::Stats {
+            uptime_seconds,
+            messages_sent,
+            memory_mb,
+            rate_limit_enabled,
+            rate_limit_per_minute,
+            ai_cost_today,
+            users,
+            at,
+            ..
+        } => {
+            let mut summary = format!(
+                "[Stats] up {} | {} users | {} messages | {:.2} MB",
+                format_uptime(*uptime_seconds),
+                users.len(),
+                messages_sent,
+                memory_mb
+            );
+            if let Some(cost) = ai_cost_today {
+                summary.push_str(&format!(" | AI today ${:.4}", cost));
+            }
+            let limit = if *rate_limit_enabled {
+                format!("/{}", rate_limit_per_minute)
+            } else {
+                String::new()
+            };
+
+            let name_width = users.iter().map(|u| u.name.len()).max().unwrap_or(4).max(4);
+            let ip_width = users.iter().map(|u| u.ip.len()).max().unwrap_or(2).max(2);
+            let flags: Vec<String> = users
+                .iter()
+                .map(|u| {
+                    let mut flags = Vec::new();
+                    if u.admin {
+                        flags.push("admin");
+                    }
+                    if u.bot {
+                        flags.push("bot");
+                    }
+                    if u.rate_limited {
+                        flags.push("rate-limited");
+                    }
+                    flags.join(",")
+                })
+                .collect();
+            let flags_width = flags.iter().map(String::len).max().unwrap_or(5).max(5);
+
+            let mut output = format!("{}\r\n", summary);
             output.push_str(&format!(
-                "  {:-<name_width$}  {:-<ip_width$}  {:-<36}\r\n",
-                "", "", ""
+                "\r\n  {:<name_width$}  {:<ip_width$}  {:>6}  {:>8}  {:>10}  {:<flags_width$}  {}\r\n",
+                "NAME", "IP", "MSGS", "LAST MIN", "ONLINE", "FLAGS", "CLIENT"
             ));
-            for u in users {
+            for (u, flags) in users.iter().zip(&flags) {
+                let online = format_uptime(at.saturating_sub(u.connected_at) / 1000);
+                let client = match &u.user_agent {
+                    Some(agent) if agent.chars().count() > MAX_USER_AGENT_WIDTH => {
+                        let short: String = agent.chars().take(MAX_USER_AGENT_WIDTH - 1).collect();
+                        format!("{}…", short)
+                    }
+                    Some(agent) => agent.clone(),
+                    None => "-".to_string(),
+                };
                 output.push_str(&format!(
-                    "  {:<name_width$}  {:<ip_width$}  {}\r\n",
-                    u.name, u.ip, u.id
+                    "  {:<name_width$}  {:<ip_width$}  {:>6}  {:>8}  {:>10}  {:<flags_width$}  {}\r\n",
+                    u.name,
+                    u.ip,
+                    u.message_count,
+                    format!("{}{}", u.messages_last_minute, limit),
+                    online,
+                    flags,
+                    client
                 ));
             }
-            output.push_str("\x1b[0m");
-            output
+            paint(Style::Info, output)
+        }
+        Incoming::WhoAmI {
+            id,
+            name,
+            ip,
+            admin,
+            ..
+        } => paint(
+            Style::Info,
+            format!(
+                "[Whoami] {} ({}) from {}{}",
+                name,
+                id,
+                ip,
+                if *admin { ", admin" } else { "" }
+            ),
+        ),
+        Incoming::Version {
+            crate_version,
+            rust_version,
+            git_commit,
+            built_at,
+            ..
+        } => {
+            let built = chrono::DateTime::from_timestamp_millis(*built_at as i64)
+                .map(|t| {
+                    t.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_else(|| "unknown".to_string());
+            paint(
+                Style::Info,
+                format!(
+                    "[Version] server {} ({}), Rust {}, built {}",
+                    crate_version, git_commit, rust_version, built
+                ),
+            )
         }
-        Incoming::Error { message } => format!("\x1b[31m✗ Error: {}\x1b[0m", message),
-        Incoming::Pong { token } => {
+        Incoming::Error { message, .. } => paint(Style::Error, format!("✗ Error: {}", message)),
+        Incoming::RateLimited { message, .. } => paint(Style::Error, format!("✗ {}", message)),
+        Incoming::Pong { token, .. } => {
             let token_str = token
                 .as_ref()
                 .map(|t| format!(" (token: {}...)", &t[..8.min(t.len())]))
                 .unwrap_or_default();
-            format!("\x1b[36m[Pong]{}\x1b[0m", token_str)
+            paint(Style::Info, format!("[Pong]{}", token_str))
         }
         Incoming::Ai {
             from,
@@ -259,6 +1345,10 @@ fn format_message(msg: &Incoming) -> String {
             response_ms,
             tokens,
             cost,
+            model,
+            finish_reason,
+            private,
+            ..
         } => {
             let mut stats = vec![format!("{}ms", response_ms)];
             if let Some(t) = tokens {
@@ -267,18 +1357,59 @@ fn format_message(msg: &Incoming) -> String {
             if let Some(c) = cost {
                 stats.push(format!("${:.4}", c));
             }
+            if let Some(m) = model {
+                stats.push(m.clone());
+            }
+            if let Some(r) = finish_reason {
+                stats.push(format!("finish: {}", r));
+            }
+            let tag = if *private { "[AI, private]" } else { "[AI]" };
             format!(
-                "\x1b[35m[AI] {}\x1b[0m asked: {} \x1b[90m({})\x1b[0m\r\n\x1b[36m{}\x1b[0m",
-                from,
+                "{} asked: {} {}\r\n{}",
+                paint(Style::Ai, format!("{} {}", tag, from)),
                 prompt,
-                stats.join(" | "),
-                response
+                paint(Style::Dim, format!("({})", stats.join(" | "))),
+                paint(Style::Info, indent_continuation("", response))
             )
         }
     }
 }
 
-fn parse_command(input: &str) -> Option<Outgoing> {
+/// Clear the terminal and put the cursor where the prompt belongs.
+fn clear_screen(status_bar: &Mutex<StatusBar>) {
+    let _ = execute!(
+        screen(),
+        terminal::Clear(terminal::ClearType::All),
+        cursor::MoveTo(0, 0)
+    );
+    if let Ok(bar) = status_bar.lock() {
+        bar.install();
+    }
+}
+
+/// Split `/users` arguments into a name filter and a 1-based page number
+fn parse_users_args(arg: &str) -> Option<(Option<String>, usize)> {
+    let mut filter = Vec::new();
+    let mut page = 1;
+    let mut words = arg.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "--page" {
+            page = words.next()?.parse().ok().filter(|&p| p > 0)?;
+        } else {
+            filter.push(word);
+        }
+    }
+    let filter = (!filter.is_empty()).then(|| filter.join(" "));
+    Some((filter, page))
+}
+
+fn parse_command(
+    input: &str,
+    status_bar: &Mutex<StatusBar>,
+    toggles: &Toggles,
+    macros: &Macros,
+    last_offer: &Mutex<Option<String>>,
+) -> Option<Outgoing> {
     let input = input.trim();
     if input.is_empty() {
         return None;
@@ -292,8 +1423,8 @@ fn parse_command(input: &str) -> Option<Outgoing> {
         match cmd.as_str() {
             "/name" => {
                 if arg.is_empty() {
-                    print!("\x1b[31mUsage: /name <username>\x1b[0m\r\n");
-                    let _ = io::stdout().flush();
+                    say!("{}\r\n", paint(Style::Error, "Usage: /name <username>"));
+                    let _ = screen().flush();
                     None
                 } else {
                     Some(Outgoing::SetName {
@@ -302,7 +1433,24 @@ fn parse_command(input: &str) -> Option<Outgoing> {
                 }
             }
             "/status" => Some(Outgoing::Status),
-            "/users" => Some(Outgoing::ListUsers),
+            "/users" => match parse_users_args(arg) {
+                Some((filter, page)) => Some(Outgoing::ListUsers {
+                    sort: Some("name".to_string()),
+                    filter,
+                    offset: Some((page - 1) * USERS_PAGE_SIZE),
+                    limit: Some(USERS_PAGE_SIZE),
+                }),
+                None => {
+                    say!(
+                        "{}\r\n",
+                        paint(Style::Error, "Usage: /users [filter] [--page N]")
+                    );
+                    let _ = screen().flush();
+                    None
+                }
+            },
+            "/whoami" => Some(Outgoing::WhoAmI),
+            "/version" => Some(Outgoing::Version),
             "/ping" => {
                 let token = if arg.is_empty() {
                     uuid::Uuid::new_v4().to_string()
@@ -313,70 +1461,447 @@ fn parse_command(input: &str) -> Option<Outgoing> {
             }
             "/ai" => {
                 if arg.is_empty() {
-                    print!("\x1b[31mUsage: /ai <question>\x1b[0m\r\n");
-                    let _ = io::stdout().flush();
+                    say!("{}\r\n", paint(Style::Error, "Usage: /ai <question>"));
+                    let _ = screen().flush();
                     None
                 } else {
-                    print!("\x1b[90mAI is thinking...\x1b[0m\r\n");
-                    let _ = io::stdout().flush();
                     Some(Outgoing::Ai {
                         prompt: arg.to_string(),
                     })
                 }
             }
+            "/ask" => {
+                if arg.is_empty() {
+                    say!("{}\r\n", paint(Style::Error, "Usage: /ask <question>"));
+                    let _ = screen().flush();
+                    None
+                } else {
+                    Some(Outgoing::AiPrivate {
+                        prompt: arg.to_string(),
+                    })
+                }
+            }
+            "/cancel" => Some(Outgoing::AiCancel),
+            "/edit" => {
+                let mut edit_parts = arg.splitn(2, ' ');
+                let id = edit_parts.next().and_then(parse_message_id);
+                let text = edit_parts.next().map(str::trim).unwrap_or("");
+                match id {
+                    Some(id) if !text.is_empty() => Some(Outgoing::Edit {
+                        id,
+                        text: text.to_string(),
+                    }),
+                    _ => {
+                        say!("{}\r\n", paint(Style::Error, "Usage: /edit <id> <text>"));
+                        let _ = screen().flush();
+                        None
+                    }
+                }
+            }
+            "/reply" => {
+                let mut reply_parts = arg.splitn(2, ' ');
+                let id = reply_parts.next().and_then(parse_message_id);
+                let text = reply_parts.next().map(str::trim).unwrap_or("");
+                match id {
+                    Some(id) if !text.is_empty() => Some(Outgoing::Chat {
+                        text: text.to_string(),
+                        reply_to: Some(id),
+                    }),
+                    _ => {
+                        say!("{}\r\n", paint(Style::Error, "Usage: /reply <id> <text>"));
+                        let _ = screen().flush();
+                        None
+                    }
+                }
+            }
+            "/auth" => {
+                if arg.is_empty() {
+                    say!("{}\r\n", paint(Style::Error, "Usage: /auth <token>"));
+                    let _ = screen().flush();
+                    None
+                } else {
+                    Some(Outgoing::Auth {
+                        token: arg.to_string(),
+                    })
+                }
+            }
+            "/stats" => Some(Outgoing::Stats),
+            "/announce" => {
+                if arg.is_empty() {
+                    say!("{}\r\n", paint(Style::Error, "Usage: /announce <text>"));
+                    let _ = screen().flush();
+                    None
+                } else {
+                    Some(Outgoing::Announce {
+                        text: arg.to_string(),
+                    })
+                }
+            }
+            // Names may contain spaces; a number at the end is the count
+            "/purge" => {
+                let (name, last_n) = match arg.rsplit_once(' ') {
+                    Some((name, n)) if n.parse::<usize>().is_ok() => {
+                        (name.trim(), n.parse().unwrap())
+                    }
+                    _ => (arg, DEFAULT_PURGE),
+                };
+                if name.is_empty() {
+                    say!(
+                        "{}\r\n",
+                        paint(Style::Error, "Usage: /purge <name> [count]")
+                    );
+                    let _ = screen().flush();
+                    None
+                } else {
+                    Some(Outgoing::Purge {
+                        name: name.to_string(),
+                        last_n,
+                    })
+                }
+            }
+            // Without arguments, every kind of event
+            "/tail" => {
+                let events = if arg.is_empty() {
+                    EventKind::ALL
+                        .iter()
+                        .map(|e| e.as_str().to_string())
+                        .collect()
+                } else {
+                    arg.split_whitespace().map(str::to_lowercase).collect()
+                };
+                Some(Outgoing::Subscribe { events })
+            }
+            "/untail" => Some(Outgoing::Subscribe { events: Vec::new() }),
+            // The user name goes last, since names can contain spaces
+            "/offer" => {
+                let mut offer_parts = arg.splitn(3, ' ');
+                let url = offer_parts.next().unwrap_or("");
+                let size = offer_parts.next().and_then(parse_size);
+                let to = offer_parts.next().map(str::trim).unwrap_or("");
+                match (file_name_from_url(url), size) {
+                    (Some(name), Some(size)) if !to.is_empty() => Some(Outgoing::FileOffer {
+                        to: to.to_string(),
+                        name,
+                        size,
+                        url: url.to_string(),
+                    }),
+                    _ => {
+                        say!(
+                            "{}\r\n",
+                            paint(Style::Error, "Usage: /offer <url> <size> <user>")
+                        );
+                        let _ = screen().flush();
+                        None
+                    }
+                }
+            }
+            "/copy" => {
+                match last_offer.lock().unwrap().as_deref() {
+                    Some(url) => {
+                        copy_to_clipboard(url);
+                        say!("{}\r\n", paint(Style::Dim, format!("Copied: {}", url)));
+                    }
+                    None => say!(
+                        "{}\r\n",
+                        paint(Style::Error, "Nobody offered you a file yet.")
+                    ),
+                }
+                let _ = screen().flush();
+                None
+            }
+            "/delete" => match parse_message_id(arg) {
+                Some(id) => Some(Outgoing::Delete { id }),
+                None => {
+                    say!("{}\r\n", paint(Style::Error, "Usage: /delete <id>"));
+                    let _ = screen().flush();
+                    None
+                }
+            },
+            "/clear" => {
+                clear_screen(status_bar);
+                None
+            }
             "/help" => {
-                print_help();
+                print_help(macros);
+                None
+            }
+            "/raw" => {
+                let enabled = !toggles.raw.fetch_xor(true, Ordering::Relaxed);
+                let state = if enabled { "on" } else { "off" };
+                say!("{}\r\n", paint(Style::Dim, format!("Raw JSON: {}", state)));
+                let _ = screen().flush();
+                None
+            }
+            "/queue" => {
+                let enabled = !toggles.queue.fetch_xor(true, Ordering::Relaxed);
+                let state = if enabled { "on" } else { "off" };
+                say!(
+                    "{}\r\n",
+                    paint(Style::Dim, format!("Queue while rate limited: {}", state))
+                );
+                let _ = screen().flush();
+                None
+            }
+            "/watch" => {
+                let mut watch_parts = arg.split_whitespace();
+                let secs = match (watch_parts.next(), watch_parts.next(), watch_parts.next()) {
+                    (Some("status"), None, None) => Some(DEFAULT_WATCH_SECS),
+                    (Some("status"), Some(secs), None) => secs.parse().ok().filter(|&s| s > 0),
+                    _ => None,
+                };
+                match secs {
+                    Some(secs) => {
+                        toggles.watch_secs.store(secs, Ordering::Relaxed);
+                        say!(
+                            "{}\r\n",
+                            paint(
+                                Style::Dim,
+                                format!("Watching status every {}s; /unwatch to stop", secs)
+                            )
+                        );
+                    }
+                    None => say!("{}\r\n", paint(Style::Error, "Usage: /watch status [secs]")),
+                }
+                let _ = screen().flush();
+                None
+            }
+            "/unwatch" => {
+                let text = if toggles.watch_secs.swap(0, Ordering::Relaxed) > 0 {
+                    "Stopped watching status"
+                } else {
+                    "Not watching anything"
+                };
+                say!("{}\r\n", paint(Style::Dim, text));
+                let _ = screen().flush();
                 None
             }
             "/quit" | "/exit" | "/q" => {
-                let _ = terminal::disable_raw_mode();
+                restore_terminal();
                 std::process::exit(0);
             }
+            // Built-in commands come first, so a macro can't hide one
+            _ if macros.contains_key(&cmd[1..]) => {
+                let expansion = &macros[&cmd[1..]];
+                let text = if arg.is_empty() {
+                    expansion.clone()
+                } else {
+                    format!("{} {}", expansion, arg)
+                };
+                Some(Outgoing::Chat {
+                    text,
+                    reply_to: None,
+                })
+            }
             _ => {
-                print!("\x1b[31mUnknown command: {}\x1b[0m\r\n", cmd);
-                let _ = io::stdout().flush();
+                say!(
+                    "{}\r\n",
+                    paint(Style::Error, format!("Unknown command: {}", cmd))
+                );
+                let _ = screen().flush();
                 None
             }
         }
     } else {
         Some(Outgoing::Chat {
             text: input.to_string(),
+            reply_to: None,
         })
     }
 }
 
+type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Serialize and send one message. Returns false if the connection is gone.
+async fn send_message(write: &mut SplitSink<WsStream, Message>, msg: &Outgoing) -> bool {
+    let json = serde_json::to_string(msg).unwrap();
+    write.send(Message::Text(json.into())).await.is_ok()
+}
+
+/// A chat message that still has to go out: held by /queue or waiting in the outbox
+#[derive(Clone)]
+struct PendingChat {
+    text: String,
+    reply_to: Option<u64>,
+}
+
+impl PendingChat {
+    fn message(&self) -> Outgoing {
+        Outgoing::Chat {
+            text: self.text.clone(),
+            reply_to: self.reply_to,
+        }
+    }
+}
+
+/// Keep a chat message until the connection is back, or drop it if the outbox is full
+fn hold_in_outbox(outbox: &mut VecDeque<PendingChat>, chat: PendingChat, prompt: &Mutex<Prompt>) {
+    let mut prompt = prompt.lock().unwrap();
+    prompt.clear();
+    if outbox.len() >= MAX_OUTBOX {
+        say!(
+            "{}\r\n",
+            paint(
+                Style::Error,
+                format!(
+                    "✗ Outbox full ({} messages), not sent: {}",
+                    MAX_OUTBOX, chat.text
+                )
+            )
+        );
+    } else {
+        say!("{} {}\r\n", paint(Style::Dim, "(queued)"), chat.text);
+        outbox.push_back(chat);
+    }
+    prompt.draw();
+}
+
+/// Connect requesting the `chat.v1` subprotocol. Servers that don't negotiate subprotocols
+/// (older rust-ws, the Bun backend) fail the handshake check, so retry once without it.
+async fn connect(url: &str, connector: Option<Connector>) -> Result<WsStream, tungstenite::Error> {
+    let mut request = url.into_client_request()?;
+    // Lets server admins see which client a connection uses
+    request
+        .headers_mut()
+        .insert(USER_AGENT, HeaderValue::from_static(CLIENT_USER_AGENT));
+    let plain_request = request.clone();
+    request.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(SUBPROTOCOL),
+    );
+
+    match connect_async_tls_with_config(request, None, false, connector.clone()).await {
+        Ok((stream, _)) => Ok(stream),
+        Err(tungstenite::Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(_))) => {
+            let (stream, _) =
+                connect_async_tls_with_config(plain_request, None, false, connector).await?;
+            Ok(stream)
+        }
+        Err(e) => Err(e),
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let url = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "ws://127.0.0.1:3001".to_string());
+    let args = parse_args();
+    let json_output = args.output == OutputFormat::Json;
+    JSON_OUTPUT.store(json_output, Ordering::Relaxed);
+    style::init(args.color, args.theme);
+    let (history_include_chat, timestamps, macros) = match load_config(args.config.as_deref()) {
+        Ok(config) => (
+            config.history_include_chat,
+            args.timestamps.unwrap_or(config.timestamps),
+            all_macros(config),
+        ),
+        Err(e) => {
+            eprintln!("{}", paint(Style::Error, e));
+            std::process::exit(2);
+        }
+    };
+    let url = args.url.clone();
+
+    // Validate --name up front; mirrors the server's 2-32 character rule
+    let initial_name = args.name.as_deref().map(str::trim).map(str::to_string);
+    if let Some(name) = &initial_name {
+        let len = name.chars().count();
+        if !(2..=32).contains(&len) {
+            eprintln!(
+                "{}",
+                paint(
+                    Style::Error,
+                    format!(
+                        "Invalid --name '{}': must be between 2 and 32 characters",
+                        name
+                    )
+                )
+            );
+            std::process::exit(2);
+        }
+    }
+
+    let connector = match tls_connector(args.cafile.as_deref(), args.insecure) {
+        Ok(connector) => connector,
+        Err(e) => {
+            eprintln!("{}", paint(Style::Error, e));
+            std::process::exit(2);
+        }
+    };
+    if args.insecure {
+        eprintln!(
+            "{}",
+            paint(
+                Style::Warning,
+                "Warning: TLS certificate verification is disabled"
+            )
+        );
+    }
 
-    println!("\x1b[90mConnecting to {}...\x1b[0m", url);
+    say!(
+        "{}\n",
+        paint(Style::Dim, format!("Connecting to {}...", url))
+    );
 
-    let (ws_stream, _) = match connect_async(&url).await {
+    let ws_stream = match connect(&url, connector.clone()).await {
         Ok(conn) => conn,
         Err(e) => {
-            eprintln!("\x1b[31mFailed to connect: {}\x1b[0m", e);
+            eprintln!(
+                "{}",
+                paint(Style::Error, format!("Failed to connect: {}", e))
+            );
             std::process::exit(1);
         }
     };
 
-    println!("\x1b[32mConnected!\x1b[0m Type /help for commands.");
+    say!(
+        "{} Type /help for commands.\n",
+        paint(Style::Success, "Connected!")
+    );
 
-    let (mut write, mut read) = ws_stream.split();
+    let (mut write, read) = ws_stream.split();
+    let mut read = read.flat_map(split_batch);
+
+    // Name requested via --name; cleared once the server confirms or rejects it
+    let mut requested_name: Option<String> = None;
+    if let Some(name) = initial_name {
+        let json = serde_json::to_string(&Outgoing::SetName { name: name.clone() }).unwrap();
+        if write.send(Message::Text(json.into())).await.is_err() {
+            eprintln!("{}", paint(Style::Error, "Failed to send name"));
+            std::process::exit(1);
+        }
+        requested_name = Some(name);
+    }
     let (tx, mut rx) = mpsc::unbounded_channel::<Outgoing>();
     let pending_pings: Arc<Mutex<HashMap<String, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
     let pending_pings_clone = Arc::clone(&pending_pings);
 
+    let status_bar = Arc::new(Mutex::new(StatusBar::new(args.statusbar, url.clone())));
+    if let Ok(mut bar) = status_bar.lock() {
+        bar.connected = true;
+        bar.install();
+    }
+    let status_bar_input = Arc::clone(&status_bar);
+
+    let toggles = Arc::new(Toggles {
+        raw: AtomicBool::new(args.raw),
+        queue: AtomicBool::new(false),
+        watch_secs: AtomicU64::new(0),
+    });
+    let toggles_input = Arc::clone(&toggles);
+
+    // Download link of the last file offered to us, for /copy
+    let last_offer: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let last_offer_input = Arc::clone(&last_offer);
+
     // Spawn stdin reader with command history
+    let prompt = Arc::new(Mutex::new(Prompt::new()));
+    let prompt_input = Arc::clone(&prompt);
     let tx_clone = tx.clone();
     std::thread::spawn(move || {
         let _ = terminal::enable_raw_mode();
 
-        let mut history: Vec<String> = Vec::new();
+        let mut history: Vec<String> = load_history();
         let mut history_idx: Option<usize> = None;
-        let mut input = String::new();
-        let mut cursor_pos: usize = 0; // char index, not byte index
+        // Recalled with Ctrl+P, to send it again with a tweak
+        let mut last_chat: Option<String> = None;
 
         // Helper to get byte index from char index
         let char_to_byte = |s: &str, char_idx: usize| -> usize {
@@ -391,116 +1916,162 @@ async fn main() {
 
         loop {
             if event::poll(std::time::Duration::from_millis(100)).unwrap_or(false) {
-                if let Ok(Event::Key(key_event)) = event::read() {
+                let event = event::read();
+                let Ok(mut prompt) = prompt_input.lock() else {
+                    break;
+                };
+                if let Ok(Event::Resize(_, _)) = event {
+                    if let Ok(bar) = status_bar_input.lock() {
+                        bar.install();
+                    }
+                    prompt.draw();
+                }
+                if let Ok(Event::Key(key_event)) = event {
+                    // Alt+Enter or Ctrl+J inserts a newline instead of submitting
+                    let newline = match key_event.code {
+                        KeyCode::Enter => key_event.modifiers.contains(KeyModifiers::ALT),
+                        KeyCode::Char('j') => key_event.modifiers.contains(KeyModifiers::CONTROL),
+                        _ => false,
+                    };
                     match key_event.code {
+                        _ if newline => {
+                            let byte_pos = char_to_byte(&prompt.input, prompt.cursor);
+                            prompt.input.insert(byte_pos, '\n');
+                            prompt.cursor += 1;
+                            prompt.draw();
+                        }
                         KeyCode::Enter => {
-                            print!("\r\n");
-                            let _ = io::stdout().flush();
+                            // Move below the last row before submitting
+                            prompt.cursor = char_count(&prompt.input);
+                            prompt.draw();
+                            say!("\r\n");
+                            let _ = screen().flush();
 
-                            let trimmed = input.trim().to_string();
+                            let trimmed = prompt.input.trim().to_string();
                             if !trimmed.is_empty() {
-                                // Save commands to history
-                                if trimmed.starts_with('/') && history.last() != Some(&trimmed) {
+                                // Save commands to history (never the admin token, and
+                                // nothing multi-line since the history file is line based)
+                                if trimmed.starts_with('/')
+                                    && !trimmed.to_lowercase().starts_with("/auth")
+                                    && !trimmed.contains('\n')
+                                    && history.last() != Some(&trimmed)
+                                {
                                     history.push(trimmed.clone());
+                                    append_history(&trimmed);
                                     if history.len() > MAX_HISTORY {
                                         history.remove(0);
                                     }
+                                } else if !trimmed.starts_with('/') {
+                                    if history_include_chat && history.last() != Some(&trimmed) {
+                                        history.push(trimmed.clone());
+                                        if history.len() > MAX_HISTORY {
+                                            history.remove(0);
+                                        }
+                                    }
+                                    last_chat = Some(trimmed.clone());
                                 }
 
-                                if let Some(msg) = parse_command(&trimmed) {
+                                if let Some(msg) = parse_command(
+                                    &trimmed,
+                                    &status_bar_input,
+                                    &toggles_input,
+                                    &macros,
+                                    &last_offer_input,
+                                ) {
+                                    match &msg {
+                                        Outgoing::Ai { prompt: question }
+                                        | Outgoing::AiPrivate { prompt: question } => {
+                                            prompt.ai_pending =
+                                                Some((question.clone(), Instant::now()));
+                                        }
+                                        Outgoing::AiCancel => prompt.ai_pending = None,
+                                        _ => {}
+                                    }
                                     if tx_clone.send(msg).is_err() {
                                         break;
                                     }
                                 }
                             }
 
-                            input.clear();
-                            cursor_pos = 0;
+                            prompt.input.clear();
+                            prompt.cursor = 0;
+                            prompt.row = 0;
                             history_idx = None;
-
-                            print!("> ");
-                            let _ = io::stdout().flush();
+                            prompt.draw();
                         }
-                        KeyCode::Backspace => {
-                            if cursor_pos > 0 {
-                                let byte_pos = char_to_byte(&input, cursor_pos - 1);
-                                let next_byte_pos = char_to_byte(&input, cursor_pos);
-                                input.replace_range(byte_pos..next_byte_pos, "");
-                                cursor_pos -= 1;
-                                print!("\r\x1b[K> {}", input);
-                                if cursor_pos < char_count(&input) {
-                                    let _ = execute!(
-                                        io::stdout(),
-                                        cursor::MoveToColumn((cursor_pos + 2) as u16)
-                                    );
-                                }
-                                let _ = io::stdout().flush();
-                            }
+                        KeyCode::Backspace if prompt.cursor > 0 => {
+                            let byte_pos = char_to_byte(&prompt.input, prompt.cursor - 1);
+                            let next_byte_pos = char_to_byte(&prompt.input, prompt.cursor);
+                            prompt.input.replace_range(byte_pos..next_byte_pos, "");
+                            prompt.cursor -= 1;
+                            prompt.draw();
                         }
-                        KeyCode::Left => {
-                            if cursor_pos > 0 {
-                                cursor_pos -= 1;
-                                let _ = execute!(io::stdout(), cursor::MoveLeft(1));
-                                let _ = io::stdout().flush();
-                            }
+                        KeyCode::Left if prompt.cursor > 0 => {
+                            prompt.cursor -= 1;
+                            prompt.draw();
                         }
-                        KeyCode::Right => {
-                            if cursor_pos < char_count(&input) {
-                                cursor_pos += 1;
-                                let _ = execute!(io::stdout(), cursor::MoveRight(1));
-                                let _ = io::stdout().flush();
-                            }
+                        KeyCode::Right if prompt.cursor < char_count(&prompt.input) => {
+                            prompt.cursor += 1;
+                            prompt.draw();
                         }
-                        KeyCode::Up => {
-                            if !history.is_empty() {
-                                let new_idx = match history_idx {
-                                    None => history.len() - 1,
-                                    Some(0) => 0,
-                                    Some(i) => i - 1,
-                                };
-                                history_idx = Some(new_idx);
-                                input = history[new_idx].clone();
-                                cursor_pos = char_count(&input);
-                                print!("\r\x1b[K> {}", input);
-                                let _ = io::stdout().flush();
-                            }
+                        KeyCode::Up if !history.is_empty() => {
+                            let new_idx = match history_idx {
+                                None => history.len() - 1,
+                                Some(0) => 0,
+                                Some(i) => i - 1,
+                            };
+                            history_idx = Some(new_idx);
+                            prompt.input = history[new_idx].clone();
+                            prompt.cursor = char_count(&prompt.input);
+                            prompt.draw();
                         }
                         KeyCode::Down => {
                             match history_idx {
                                 Some(i) if i + 1 < history.len() => {
                                     history_idx = Some(i + 1);
-                                    input = history[i + 1].clone();
-                                    cursor_pos = char_count(&input);
+                                    prompt.input = history[i + 1].clone();
+                                    prompt.cursor = char_count(&prompt.input);
                                 }
                                 Some(_) => {
                                     history_idx = None;
-                                    input.clear();
-                                    cursor_pos = 0;
+                                    prompt.input.clear();
+                                    prompt.cursor = 0;
                                 }
                                 None => {}
                             }
-                            print!("\r\x1b[K> {}", input);
-                            let _ = io::stdout().flush();
+                            prompt.draw();
+                        }
+                        KeyCode::Char('p')
+                            if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+                        {
+                            // Only fills the input; nothing is sent until Enter
+                            if let Some(text) = &last_chat {
+                                prompt.input = text.clone();
+                                prompt.cursor = char_count(&prompt.input);
+                                history_idx = None;
+                                prompt.draw();
+                            }
+                        }
+                        KeyCode::Char('l')
+                            if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+                        {
+                            // Clear the screen but keep the text being typed
+                            clear_screen(&status_bar_input);
+                            prompt.row = 0;
+                            prompt.draw();
                         }
                         KeyCode::Char('c')
                             if key_event.modifiers.contains(KeyModifiers::CONTROL) =>
                         {
-                            let _ = terminal::disable_raw_mode();
-                            println!("\r");
+                            restore_terminal();
+                            say!("\r\n");
                             std::process::exit(0);
                         }
                         KeyCode::Char(c) => {
-                            let byte_pos = char_to_byte(&input, cursor_pos);
-                            input.insert(byte_pos, c);
-                            cursor_pos += 1;
-                            print!("\r\x1b[K> {}", input);
-                            if cursor_pos < char_count(&input) {
-                                let _ = execute!(
-                                    io::stdout(),
-                                    cursor::MoveToColumn((cursor_pos + 2) as u16)
-                                );
-                            }
-                            let _ = io::stdout().flush();
+                            let byte_pos = char_to_byte(&prompt.input, prompt.cursor);
+                            prompt.input.insert(byte_pos, c);
+                            prompt.cursor += 1;
+                            prompt.draw();
                         }
                         _ => {}
                     }
@@ -512,47 +2083,324 @@ async fn main() {
     });
 
     // Print initial prompt
-    print!("> ");
-    let _ = io::stdout().flush();
+    if let Ok(mut prompt) = prompt.lock() {
+        prompt.draw();
+    }
+
+    // Our current name, taken from the last ackName; used for mention detection
+    let mut current_name = String::new();
+    // id -> (from, text) of recent chat messages, for rendering edits and deletes
+    let mut recent_messages: BTreeMap<u64, (String, String)> = BTreeMap::new();
+
+    // Status requests sent by /status. Only that many responses are printed; the rest
+    // answer the status bar refresh or are pushed by the server (STATUS_BROADCAST_SECS).
+    let mut shown_status_pending = 0u32;
+    let mut statusbar_refresh = tokio::time::interval(STATUSBAR_REFRESH);
+    // Interval of /watch status, picked up from the toggles on the next tick
+    let mut watch: Option<(u64, tokio::time::Interval)> = None;
+    // Ticks the "AI is thinking" and rate limit countdown lines
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    // Chat messages held back by /queue until the rate limit cooldown ends
+    let mut queue: VecDeque<PendingChat> = VecDeque::new();
+    // The last chat message sent, re-queued if the server rate limits it
+    let mut last_chat: Option<PendingChat> = None;
+    // Set by /tail until the server confirms or refuses the subscription
+    let mut tail_requested = false;
+    // Chat messages typed while disconnected, sent once the connection is back
+    let mut outbox: VecDeque<PendingChat> = VecDeque::new();
+    // Sent from the outbox; confirmed when the server echoes them back to us
+    let mut unconfirmed: VecDeque<String> = VecDeque::new();
+    // From the last ackName, to get our name back after a reconnect
+    let mut resume_token: Option<String> = None;
+    // Name we are taking back with `resume`; the outbox waits until that is settled
+    let mut resuming: Option<String> = None;
+    // Highest chat message id seen, where `backfill` continues after a reconnect.
+    // Only servers that send `seq` in ackName know `backfill`.
+    let mut last_seen: Option<u64> = None;
+    // Only polled with --duration
+    let deadline = tokio::time::sleep(args.duration.unwrap_or_default());
+    tokio::pin!(deadline);
 
     loop {
+        let mut lost = false;
+        // Answers to what the server sent, e.g. after a resume; sent after the select, so
+        // the prompt isn't locked while sending
+        let mut replies = Vec::new();
         tokio::select! {
+            _ = &mut deadline, if args.duration.is_some() => {
+                prompt.lock().unwrap().clear();
+                say!("{}\r\n", paint(Style::Dim, "Duration reached, disconnecting"));
+                let _ = screen().flush();
+                let _ = write.send(Message::Close(None)).await;
+                break;
+            }
+            // Periodically refresh the user count in the status bar
+            _ = statusbar_refresh.tick(), if args.statusbar => {
+                let _ = send_message(&mut write, &Outgoing::Status).await;
+            }
+            // Replies only update the watch line, they aren't printed
+            _ = next_watch(&mut watch) => {
+                let _ = send_message(&mut write, &Outgoing::Status).await;
+            }
+            _ = tick.tick() => {
+                let wanted = toggles.watch_secs.load(Ordering::Relaxed);
+                if wanted != watch.as_ref().map_or(0, |(secs, _)| *secs) {
+                    // A new interval ticks right away, so the line fills in quickly
+                    watch = (wanted > 0).then(|| (wanted, tokio::time::interval(Duration::from_secs(wanted))));
+                    if watch.is_none() {
+                        let mut prompt = prompt.lock().unwrap();
+                        prompt.watch = None;
+                        prompt.draw();
+                    }
+                }
+                let next = {
+                    let mut prompt = prompt.lock().unwrap();
+                    let expired = prompt.cooldown_until.is_some_and(|until| Instant::now() >= until);
+                    if expired {
+                        prompt.cooldown_until = None;
+                    }
+                    // One queued message per tick; if it's rate limited again it goes back in front
+                    let next = if prompt.cooldown_until.is_none() { queue.pop_front() } else { None };
+                    prompt.queued = queue.len();
+                    if expired || prompt.ai_pending.is_some() || prompt.cooldown_until.is_some() {
+                        prompt.draw();
+                    }
+                    next
+                };
+                if let Some(chat) = next {
+                    last_chat = Some(chat.clone());
+                    if !send_message(&mut write, &chat.message()).await {
+                        outbox.push_front(chat);
+                        lost = true;
+                    }
+                }
+            }
             // Receive from server
             msg = read.next() => {
                 match msg {
                     Some(Ok(Message::Text(text))) => {
-                        // Clear current line and print message
-                        print!("\r\x1b[K");
-                        if let Ok(incoming) = serde_json::from_str::<Incoming>(&text) {
-                            // Handle Pong with roundtrip calculation
-                            if let Incoming::Pong { ref token } = incoming {
-                                let roundtrip = token.as_ref().and_then(|t| {
-                                    pending_pings_clone.lock().ok()?.remove(t).map(|start| start.elapsed())
-                                });
-                                let token_str = token.as_ref().map(|t| format!(" (token: {}...)", &t[..8.min(t.len())])).unwrap_or_default();
-                                if let Some(rtt) = roundtrip {
-                                    print!("\x1b[36m[Pong] roundtrip: {:.2}ms{}\x1b[0m\r\n", rtt.as_secs_f64() * 1000.0, token_str);
-                                } else {
-                                    print!("{}\r\n", format_message(&incoming));
+                        let parsed = serde_json::from_str::<Incoming>(&text);
+                        if let Ok(incoming) = &parsed {
+                            if let Ok(mut bar) = status_bar.lock() {
+                                if bar.update(incoming) {
+                                    bar.draw();
+                                }
+                            }
+                            // Already shown as the join/leave system text; an empty backfill
+                            // means nothing was missed
+                            let nothing_missed = matches!(incoming, Incoming::Backfill { gap: false, messages, .. } if messages.is_empty());
+                            if !json_output && (matches!(incoming, Incoming::Presence { .. }) || nothing_missed) {
+                                continue;
+                            }
+                            if !json_output && matches!(incoming, Incoming::Status { .. }) {
+                                // Any status reply is fresh, whoever asked for it. Skip replies
+                                // that arrive after /unwatch, before the next tick stops the watch.
+                                let watching = toggles.watch_secs.load(Ordering::Relaxed) > 0;
+                                if let Some((secs, _)) = watch.as_ref().filter(|_| watching) {
+                                    let mut prompt = prompt.lock().unwrap();
+                                    prompt.watch = format_watch_line(incoming, *secs);
+                                    prompt.draw();
+                                }
+                                if shown_status_pending == 0 {
+                                    continue;
+                                }
+                                shown_status_pending -= 1;
+                            }
+                        }
+
+                        // Clear the prompt, print the message above it, then redraw the prompt
+                        let mut prompt = prompt.lock().unwrap();
+                        prompt.clear();
+                        if let Ok(incoming) = parsed {
+                            // Our AI question is done once its answer or an error about it comes back
+                            let answered = match &incoming {
+                                Incoming::Ai { from, prompt: question, .. } => {
+                                    *from == current_name
+                                        && prompt.ai_pending.as_ref().is_some_and(|(q, _)| q == question)
+                                }
+                                Incoming::Error { prompt: Some(question), .. } => {
+                                    prompt.ai_pending.as_ref().is_some_and(|(q, _)| q == question)
+                                }
+                                _ => false,
+                            };
+                            if answered {
+                                prompt.ai_pending = None;
+                            }
+                            let chats = match &incoming {
+                                Incoming::Backfill { messages, .. } => messages.iter().collect(),
+                                chat => vec![chat],
+                            };
+                            for chat in chats {
+                                if let Incoming::Chat { id: Some(id), from, text, .. } = chat {
+                                    recent_messages.insert(*id, (from.clone(), text.clone()));
+                                    while recent_messages.len() > MAX_RECENT_MESSAGES {
+                                        recent_messages.pop_first();
+                                    }
+                                    last_seen = last_seen.max(Some(*id));
+                                }
+                            }
+                            if let Incoming::Backfill { seq, .. } = &incoming {
+                                last_seen = last_seen.max(Some(*seq));
+                            }
+                            // Settle a resume before anything else looks at the reply
+                            let resumed = match &incoming {
+                                Incoming::AckName { name, .. } if resuming.as_ref() == Some(name) => Some(Ok(())),
+                                Incoming::Error { message, .. } if resuming.is_some() => Some(Err(message.clone())),
+                                _ => None,
+                            };
+                            let line = match &incoming {
+                                // Handle Pong with roundtrip calculation
+                                Incoming::Pong { token, .. } => {
+                                    let roundtrip = token.as_ref().and_then(|t| {
+                                        pending_pings_clone.lock().ok()?.remove(t).map(|start| start.elapsed())
+                                    });
+                                    let token_str = token.as_ref().map(|t| format!(" (token: {}...)", &t[..8.min(t.len())])).unwrap_or_default();
+                                    match roundtrip {
+                                        Some(rtt) => paint(Style::Info, format!("[Pong] roundtrip: {:.2}ms{}", rtt.as_secs_f64() * 1000.0, token_str)),
+                                        None => format_message(&incoming),
+                                    }
+                                }
+                                Incoming::Chat { id, from, text, .. } if *from != current_name && is_mention(text, &current_name) => {
+                                    // Highlight the whole line and ring the bell
+                                    if args.bell {
+                                        say!("\x07");
+                                    }
+                                    let head = format!("{}{}: ", id_prefix(*id), from);
+                                    format!("{}{}", id_prefix(*id), paint(Style::Mention, format!("{}: {}", from, indent_continuation(&head, text))))
+                                }
+                                // The terminal can't rewrite old lines, so show the original struck through
+                                Incoming::Edit { id, text, .. } if recent_messages.contains_key(id) => {
+                                    let (from, old) = recent_messages.get_mut(id).unwrap();
+                                    let head = format!("{} {}: ", paint(Style::Dim, format!("✎ #{}", id)), paint(Style::Bold, &*from));
+                                    let line = format!("{}{} → {}", head, paint(Style::Strike, indent_continuation(&head, old)), indent_continuation(&head, text));
+                                    *old = text.clone();
+                                    line
+                                }
+                                Incoming::Delete { id, .. } if recent_messages.contains_key(id) => {
+                                    let (from, old) = recent_messages.remove(id).unwrap();
+                                    let head = format!("{} {}: ", paint(Style::Dim, format!("✗ #{}", id)), paint(Style::Bold, &from));
+                                    format!("{}{} {}", head, paint(Style::Strike, indent_continuation(&head, &old)), paint(Style::Dim, "(deleted)"))
+                                }
+                                Incoming::Chat { from, text, .. } if *from == current_name && unconfirmed.contains(text) => {
+                                    // Anything sent before it that didn't come back was refused, with an error
+                                    while unconfirmed.pop_front().is_some_and(|sent| sent != *text) {}
+                                    format!("{} {}", format_message(&incoming), paint(Style::Success, "✓ delivered"))
+                                }
+                                Incoming::FileOffer { from, to, name, size, .. } if *from == current_name => {
+                                    paint(Style::Dim, format!("📎 Offered {} ({}) to {}", name, format_size(*size), to))
+                                }
+                                Incoming::FileOffer { url, .. } => {
+                                    *last_offer.lock().unwrap() = Some(url.clone());
+                                    if args.bell {
+                                        say!("\x07");
+                                    }
+                                    format!("{}\r\n{}", format_message(&incoming), paint(Style::Dim, "  /copy to copy the link"))
+                                }
+                                Incoming::Error { message, .. } if resumed.is_some() => {
+                                    let name = resuming.take().unwrap_or_default();
+                                    // Ask for the name the normal way; it may still be free
+                                    replies.push(Outgoing::SetName { name: name.clone() });
+                                    requested_name = Some(name);
+                                    paint(Style::Error, format!("✗ Could not resume session: {}", message))
+                                }
+                                Incoming::AckName { name, resume_token: token, seq, .. } => {
+                                    if resumed.is_some() {
+                                        resuming = None;
+                                    }
+                                    if token.is_some() {
+                                        resume_token = token.clone();
+                                    }
+                                    // On a reconnect this is already set: the backfill starts where we left off
+                                    if last_seen.is_none() {
+                                        last_seen = *seq;
+                                    }
+                                    current_name = name.clone();
+                                    if requested_name.as_ref() == Some(name) {
+                                        requested_name = None;
+                                    }
+                                    format_message(&incoming)
+                                }
+                                Incoming::RateLimited { retry_after_secs, message, .. } => {
+                                    prompt.cooldown_until = Some(Instant::now() + Duration::from_secs(*retry_after_secs));
+                                    match last_chat.take() {
+                                        Some(chat) if toggles.queue.load(Ordering::Relaxed) => {
+                                            queue.push_front(chat);
+                                            prompt.queued = queue.len();
+                                            format!("{} {}", paint(Style::Error, format!("✗ {}", message)), paint(Style::Dim, "(queued, sent when the cooldown ends)"))
+                                        }
+                                        _ => format_message(&incoming),
+                                    }
+                                }
+                                Incoming::System { .. } if tail_requested => {
+                                    tail_requested = false;
+                                    if let Ok(mut bar) = status_bar.lock() {
+                                        bar.tailing = true;
+                                        bar.draw();
+                                    }
+                                    format!("{}\r\n{}", format_message(&incoming), paint(Style::Dim, "Chat paused; /untail to go back."))
+                                }
+                                Incoming::Error { message, .. } if tail_requested => {
+                                    tail_requested = false;
+                                    format!("{}\r\n{}", paint(Style::Error, format!("✗ Could not tail: {}", message)), paint(Style::Dim, "/tail needs admin rights: use /auth <token> first."))
+                                }
+                                Incoming::Error { message, .. } if requested_name.is_some() => {
+                                    let name = requested_name.take().unwrap_or_default();
+                                    format!("{}\r\n{}", paint(Style::Error, format!("✗ Could not set name '{}': {}", name, message)), paint(Style::Dim, "Use /name <username> to try another name."))
                                 }
+                                _ => format_message(&incoming),
+                            };
+                            let line = match &incoming {
+                                Incoming::Chat { reply_to: Some(reply_to), .. } => {
+                                    format!("{}\r\n{}", format_reply_quote(*reply_to, recent_messages.get(reply_to)), line)
+                                }
+                                _ => line,
+                            };
+                            // Events always get a time; they're a log, not a conversation
+                            let prefix = if timestamps || matches!(incoming, Incoming::Event { .. }) { format_timestamp(incoming.at()) } else { String::new() };
+                            // Keep continuation rows of chat messages aligned under the timestamp too
+                            let line = if !prefix.is_empty() && matches!(incoming, Incoming::Chat { .. } | Incoming::Edit { .. } | Incoming::Delete { .. }) {
+                                line.replace("\r\n", &format!("\r\n{}", " ".repeat(visible_width(&prefix))))
+                            } else {
+                                line
+                            };
+                            if json_output {
+                                print_json(&text);
                             } else {
-                                print!("{}\r\n", format_message(&incoming));
+                                say!("{}{}\r\n", prefix, line);
+                                if toggles.raw.load(Ordering::Relaxed) {
+                                    say!("{}\r\n", paint(Style::Dim, &text));
+                                }
+                            }
+                            if resumed.is_some() {
+                                for chat in outbox.drain(..) {
+                                    replies.push(chat.message());
+                                    unconfirmed.push_back(chat.text);
+                                }
                             }
+                        } else if json_output {
+                            print_json(&text);
                         } else {
-                            print!("\x1b[90m{}\x1b[0m\r\n", text);
+                            say!("{}\r\n", paint(Style::Dim, &text));
                         }
-                        print!("> ");
-                        let _ = io::stdout().flush();
+                        prompt.draw();
                     }
                     Some(Ok(Message::Close(_))) | None => {
-                        print!("\r\n\x1b[33mDisconnected from server\x1b[0m\r\n");
-                        let _ = io::stdout().flush();
-                        break;
+                        if let Ok(mut bar) = status_bar.lock() {
+                            bar.connected = false;
+                            bar.draw();
+                        }
+                        say!("\r\n{}\r\n", paint(Style::Warning, "Disconnected from server"));
+                        let _ = screen().flush();
+                        lost = true;
                     }
                     Some(Err(e)) => {
-                        print!("\r\n\x1b[31mConnection error: {}\x1b[0m\r\n", e);
-                        let _ = io::stdout().flush();
-                        break;
+                        if let Ok(mut bar) = status_bar.lock() {
+                            bar.connected = false;
+                            bar.draw();
+                        }
+                        say!("\r\n{}\r\n", paint(Style::Error, format!("Connection error: {}", e)));
+                        let _ = screen().flush();
+                        lost = true;
                     }
                     _ => {}
                 }
@@ -565,13 +2413,277 @@ async fn main() {
                         pings.insert(t.clone(), Instant::now());
                     }
                 }
-                let json = serde_json::to_string(&msg).unwrap();
-                if write.send(Message::Text(json.into())).await.is_err() {
-                    print!("\r\n\x1b[31mFailed to send message\x1b[0m\r\n");
-                    let _ = io::stdout().flush();
-                    break;
+                if let Outgoing::Status = msg {
+                    shown_status_pending += 1;
+                }
+                if let Outgoing::Subscribe { events } = &msg {
+                    tail_requested = !events.is_empty();
+                    if events.is_empty() {
+                        if let Ok(mut bar) = status_bar.lock() {
+                            bar.tailing = false;
+                            bar.draw();
+                        }
+                    }
+                }
+                if let Outgoing::Chat { text, reply_to } = &msg {
+                    let chat = PendingChat { text: text.clone(), reply_to: *reply_to };
+                    // Until the resume is settled, or the outbox is empty, chat waits its turn
+                    if resuming.is_some() || !outbox.is_empty() {
+                        hold_in_outbox(&mut outbox, chat, &prompt);
+                        continue;
+                    }
+                    let mut prompt = prompt.lock().unwrap();
+                    // Keep the order: while anything is queued, new messages queue up behind it
+                    if toggles.queue.load(Ordering::Relaxed) && (prompt.cooldown_until.is_some() || !queue.is_empty()) {
+                        queue.push_back(chat);
+                        prompt.queued = queue.len();
+                        prompt.draw();
+                        continue;
+                    }
+                    last_chat = Some(chat);
+                }
+                if !send_message(&mut write, &msg).await {
+                    match msg {
+                        Outgoing::Chat { text, reply_to } => outbox.push_back(PendingChat { text, reply_to }),
+                        _ => {
+                            say!("\r\n{}\r\n", paint(Style::Error, "Failed to send message"));
+                            let _ = screen().flush();
+                        }
+                    }
+                    lost = true;
+                }
+            }
+        }
+        for reply in replies {
+            if !send_message(&mut write, &reply).await {
+                if let Outgoing::Chat { text, reply_to } = reply {
+                    outbox.push_back(PendingChat { text, reply_to });
                 }
+                lost = true;
             }
         }
+        if !lost {
+            continue;
+        }
+        // The watch belongs to this connection; don't leave a stale status line behind
+        if watch.take().is_some() {
+            toggles.watch_secs.store(0, Ordering::Relaxed);
+            let mut prompt = prompt.lock().unwrap();
+            prompt.clear();
+            prompt.watch = None;
+            say!("{}\r\n", paint(Style::Dim, "Stopped watching status"));
+            prompt.draw();
+        }
+        if !args.reconnect {
+            break;
+        }
+
+        // Keep taking input while reconnecting: chat goes to the outbox, the rest is dropped
+        let mut delay = RECONNECT_DELAY;
+        let stream = loop {
+            {
+                let mut prompt = prompt.lock().unwrap();
+                prompt.clear();
+                say!(
+                    "{}\r\n",
+                    paint(
+                        Style::Dim,
+                        format!("Reconnecting in {}s...", delay.as_secs())
+                    )
+                );
+                prompt.draw();
+            }
+            let (wait, url, connector) = (delay, &url, connector.clone());
+            let attempt = async move {
+                tokio::time::sleep(wait).await;
+                connect(url, connector).await
+            };
+            tokio::pin!(attempt);
+            let result = loop {
+                tokio::select! {
+                    _ = &mut deadline, if args.duration.is_some() => {
+                        restore_terminal();
+                        return;
+                    }
+                    result = &mut attempt => break result,
+                    Some(msg) = rx.recv() => match msg {
+                        Outgoing::Chat { text, reply_to } => hold_in_outbox(&mut outbox, PendingChat { text, reply_to }, &prompt),
+                        _ => {
+                            let mut prompt = prompt.lock().unwrap();
+                            prompt.clear();
+                            say!("{}\r\n", paint(Style::Error, "✗ Not connected; only chat messages are kept until the connection is back"));
+                            prompt.draw();
+                        }
+                    },
+                }
+            };
+            match result {
+                Ok(stream) => break stream,
+                Err(e) => {
+                    let mut prompt = prompt.lock().unwrap();
+                    prompt.clear();
+                    say!(
+                        "{}\r\n",
+                        paint(Style::Error, format!("Connection error: {}", e))
+                    );
+                    prompt.draw();
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        };
+        let (new_write, new_read) = stream.split();
+        (write, read) = (new_write, new_read.flat_map(split_batch));
+
+        // Admin rights and /tail belong to the old connection
+        shown_status_pending = 0;
+        tail_requested = false;
+        if let Ok(mut bar) = status_bar.lock() {
+            bar.connected = true;
+            bar.tailing = false;
+            bar.draw();
+        }
+        {
+            let mut prompt = prompt.lock().unwrap();
+            prompt.clear();
+            say!("{}\r\n", paint(Style::Success, "Reconnected!"));
+            prompt.draw();
+        }
+        match resume_token.take() {
+            Some(token) => {
+                resuming = Some(current_name.clone());
+                let _ = send_message(&mut write, &Outgoing::Resume { token }).await;
+            }
+            None => {
+                for chat in outbox.drain(..) {
+                    let _ = send_message(&mut write, &chat.message()).await;
+                    unconfirmed.push_back(chat.text);
+                }
+            }
+        }
+        if let Some(since) = last_seen {
+            let _ = send_message(&mut write, &Outgoing::Backfill { since }).await;
+        }
+    }
+    restore_terminal();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mention_needs_the_whole_name() {
+        assert!(is_mention("hoi bas", "bas"));
+        assert!(!is_mention("hoi basil", "bas"));
+        assert!(!is_mention("hoi sebas", "bas"));
+        assert!(!is_mention("hoi bas_2", "bas"));
+        assert!(!is_mention("hoi bas-jan", "bas"));
+        assert!(!is_mention("hoi", ""));
+    }
+
+    #[test]
+    fn mention_ignores_punctuation_and_case() {
+        assert!(is_mention("Bas, kijk even", "bas"));
+        assert!(is_mention("@bas: klaar?", "Bas"));
+        assert!(is_mention("is het goed, BAS?", "bas"));
+        assert!(is_mention("(bas)", "bas"));
+        // The second occurrence counts when the first is part of a longer word
+        assert!(is_mention("basil en bas", "bas"));
+    }
+
+    #[test]
+    fn sizes_parse_with_and_without_units() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("512K"), Some(512 * 1024));
+        assert_eq!(parse_size("512kb"), Some(512 * 1024));
+        assert_eq!(parse_size(" 1.5M "), Some(1536 * 1024));
+        assert_eq!(parse_size("2GB"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("0"), None);
+        assert_eq!(parse_size("-1K"), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("veel"), None);
+    }
+
+    #[test]
+    fn formatted_sizes_parse_back() {
+        for bytes in [1023, 1024, 1536, 10 * 1024, 5 * 1024 * 1024, 3 << 30] {
+            let formatted = format_size(bytes);
+            let parsed = parse_size(&formatted.replace(' ', "")).unwrap();
+            // One decimal below 10 and none above, so allow for the rounding
+            let error = parsed.abs_diff(bytes) as f64 / bytes as f64;
+            assert!(error < 0.05, "{bytes} → {formatted} → {parsed}");
+        }
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(482 * 1024), "482 KB");
+    }
+
+    #[test]
+    fn unbatch_splits_only_chat_batches() {
+        let batch = r#"{"type":"chatBatch","messages":[{"type":"chat","text":"a"},{"type":"chat","text":"b"}]}"#;
+        let messages: Vec<serde_json::Value> = unbatch(batch)
+            .iter()
+            .map(|m| serde_json::from_str(m).unwrap())
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["text"], "a");
+        assert_eq!(messages[1]["text"], "b");
+
+        // Anything else is passed on as it is, even when it mentions a batch
+        let chat = r#"{"type":"chat","text":"\"chatBatch\""}"#;
+        assert_eq!(unbatch(chat), [chat]);
+        assert_eq!(
+            unbatch("geen json \"chatBatch\""),
+            ["geen json \"chatBatch\""]
+        );
+    }
+
+    #[test]
+    fn config_file_settings_default_to_off() {
+        let config: Config = toml::from_str("timestamps = true").unwrap();
+        assert!(config.timestamps);
+        assert!(!config.history_include_chat);
+
+        let config: Config = toml::from_str("[macros]\nhi = \"Hallo\"").unwrap();
+        assert!(!config.timestamps);
+        assert_eq!(config.macros["hi"], "Hallo");
+    }
+
+    /// Text of the chat message `input` turns into, if any
+    fn chat_text(input: &str, macros: &Macros) -> Option<String> {
+        let status_bar = Mutex::new(StatusBar::new(false, String::new()));
+        let toggles = Toggles {
+            raw: AtomicBool::new(false),
+            queue: AtomicBool::new(false),
+            watch_secs: AtomicU64::new(0),
+        };
+        match parse_command(input, &status_bar, &toggles, macros, &Mutex::new(None))? {
+            Outgoing::Chat { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn macros_expand_with_their_arguments() {
+        let macros = all_macros(Config {
+            macros: Macros::from([
+                ("/Hi".to_string(), "Hallo allemaal".to_string()),
+                ("status".to_string(), "niet gebruikt".to_string()),
+            ]),
+            ..Config::default()
+        });
+
+        assert_eq!(chat_text("/shrug", &macros).unwrap(), r"¯\_(ツ)_/¯");
+        assert_eq!(
+            chat_text("/shrug  geen idee ", &macros).unwrap(),
+            r"¯\_(ツ)_/¯ geen idee"
+        );
+        // Names are matched case-insensitively, with or without the leading `/`
+        assert_eq!(
+            chat_text("/HI en welkom", &macros).unwrap(),
+            "Hallo allemaal en welkom"
+        );
+        // A built-in command wins over a macro with the same name
+        assert_eq!(chat_text("/status", &macros), None);
     }
 }
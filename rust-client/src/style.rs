@@ -0,0 +1,156 @@
+//! Terminal colors.
+//!
+//! Every SGR color code the client prints goes through [`paint`], so a theme can
+//! swap the palette and `--color never` can strip it entirely. Cursor movement
+//! and screen control codes are not colors and stay where they are used.
+
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+/// `--color` setting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Color only when stdout is a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto" => Some(ColorMode::Auto),
+            "always" => Some(ColorMode::Always),
+            "never" => Some(ColorMode::Never),
+            _ => None,
+        }
+    }
+
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                !no_color && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+/// `--theme` palette
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Theme {
+    Default,
+    /// Blue/orange instead of green/red, safe for the common kinds of color blindness
+    Colorblind,
+    /// No hues at all, only bold, faint, strikethrough and reverse video
+    Mono,
+}
+
+impl Theme {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "default" => Some(Theme::Default),
+            "colorblind" => Some(Theme::Colorblind),
+            "mono" => Some(Theme::Mono),
+            _ => None,
+        }
+    }
+
+    fn code(self, style: Style) -> &'static str {
+        match (self, style) {
+            (Theme::Mono, Style::Strike) => "9",
+            (_, Style::Bold) => "1",
+            (_, Style::Strike) => "9;90",
+            (_, Style::Inverse) => "7",
+
+            (Theme::Default, Style::Dim) => "90",
+            (Theme::Default, Style::Error) => "31",
+            (Theme::Default, Style::Success) => "32",
+            (Theme::Default, Style::Warning) => "33",
+            (Theme::Default, Style::Info) => "36",
+            (Theme::Default, Style::Value) => "37",
+            (Theme::Default, Style::Ai) => "35",
+            (Theme::Default, Style::Mention) => "1;30;103",
+            (Theme::Default, Style::Announcement) => "1;97;44",
+            (Theme::Default, Style::Admin) => "31",
+            (Theme::Default, Style::Bot) => "34",
+
+            (Theme::Colorblind, Style::Dim) => "90",
+            (Theme::Colorblind, Style::Error) => "1;38;5;166",
+            (Theme::Colorblind, Style::Success) => "38;5;32",
+            (Theme::Colorblind, Style::Warning) => "38;5;214",
+            (Theme::Colorblind, Style::Info) => "38;5;74",
+            (Theme::Colorblind, Style::Value) => "37",
+            (Theme::Colorblind, Style::Ai) => "38;5;175",
+            (Theme::Colorblind, Style::Mention) => "1;30;48;5;221",
+            (Theme::Colorblind, Style::Announcement) => "1;97;48;5;25",
+            (Theme::Colorblind, Style::Admin) => "38;5;208",
+            (Theme::Colorblind, Style::Bot) => "38;5;27",
+
+            (Theme::Mono, Style::Dim) => "2",
+            (Theme::Mono, Style::Error) => "1",
+            (Theme::Mono, Style::Success) => "",
+            (Theme::Mono, Style::Warning) => "1",
+            (Theme::Mono, Style::Info) => "",
+            (Theme::Mono, Style::Value) => "",
+            (Theme::Mono, Style::Ai) => "1",
+            (Theme::Mono, Style::Mention) => "7",
+            (Theme::Mono, Style::Announcement) => "1;7",
+            (Theme::Mono, Style::Admin) => "1",
+            (Theme::Mono, Style::Bot) => "",
+        }
+    }
+}
+
+/// What a piece of text is, not what it looks like; the theme decides that
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// Secondary info: ids, hints, timestamps
+    Dim,
+    /// Sender names
+    Bold,
+    Error,
+    Success,
+    /// Warnings and system messages
+    Warning,
+    /// Command output like /status, /users and pong
+    Info,
+    /// Values in the /status table
+    Value,
+    Ai,
+    /// Chat messages that mention you
+    Mention,
+    /// Admin announcements, as a banner
+    Announcement,
+    /// Admins in /users
+    Admin,
+    /// Bots in /users
+    Bot,
+    /// Original text of edited and deleted messages
+    Strike,
+    /// Status bar
+    Inverse,
+}
+
+static PALETTE: OnceLock<Option<Theme>> = OnceLock::new();
+
+/// Pick the palette for the rest of the process. Only the first call has effect.
+pub fn init(mode: ColorMode, theme: Theme) {
+    let _ = PALETTE.set(mode.enabled().then_some(theme));
+}
+
+/// Wrap `text` in the theme's color for `style`, or return it as is when colors are off.
+pub fn paint(style: Style, text: impl Display) -> String {
+    match PALETTE
+        .get()
+        .copied()
+        .flatten()
+        .map(|theme| theme.code(style))
+    {
+        Some(code) if !code.is_empty() => format!("\x1b[{}m{}\x1b[0m", code, text),
+        _ => text.to_string(),
+    }
+}
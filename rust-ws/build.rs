@@ -1,20 +1,60 @@
-use std::process::Command;
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 fn main() {
-    // Get rustc version
-    let output = Command::new("rustc")
-        .arg("--version")
-        .output()
-        .expect("Failed to execute rustc");
+    // Ask the compiler cargo is actually using (toolchain overrides, wrappers), not
+    // whatever `rustc` happens to be first on PATH
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(rustc).arg("--version").output();
 
-    let version = String::from_utf8_lossy(&output.stdout);
     // Extract version like "1.75.0" from "rustc 1.75.0 (..."
-    let version = version
-        .split_whitespace()
-        .nth(1)
-        .unwrap_or("unknown")
-        .to_string();
+    let version = output
+        .ok()
+        .and_then(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .split_whitespace()
+                .nth(1)
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // Commit the binary is built from; "unknown" outside a git checkout, such as a
+    // Docker build that only copies the sources
+    let commit = git(&["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+
+    // SOURCE_DATE_EPOCH keeps reproducible builds reproducible
+    let built_at_ms = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .map(|secs| secs * 1000)
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0)
+        });
 
     println!("cargo:rustc-env=RUSTC_VERSION={}", version);
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=BUILT_AT={}", built_at_ms);
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    // Pick up a new commit: HEAD changes on checkout, the branch ref on commit
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={}/{}", git_dir, branch);
+        }
+    }
+}
+
+/// Trimmed output of a successful git command; `None` without git or a repository
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !stdout.is_empty()).then_some(stdout)
 }
@@ -1,25 +1,67 @@
+mod audit;
+mod ollama;
+mod openrouter;
+
+use async_trait::async_trait;
 use dashmap::DashMap;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
 use std::time::{Duration, Instant};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+use crate::clock::{Clock, SystemClock};
+use crate::messages::{self, Msg};
+use crate::utils::now_ms;
 
-const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+pub use audit::{AuditEntry, AuditLog};
+pub use ollama::OllamaProvider;
+pub use openrouter::OpenRouterProvider;
 
 /// Default timeout for AI requests in seconds
 const DEFAULT_TIMEOUT_SECS: u64 = 30;
 /// Default max tokens for AI responses
 const DEFAULT_MAX_TOKENS: u32 = 1024;
+/// Default max prompt length in characters
+const DEFAULT_MAX_PROMPT_CHARS: usize = 1000;
+/// Default number of retries on transient provider failures
+const DEFAULT_MAX_RETRIES: u32 = 2;
+/// First retry delay; doubles on every following attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Upper bound for a single retry delay, including server-provided Retry-After
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+/// Default model on OpenRouter
+const DEFAULT_OPENROUTER_MODEL: &str = "openai/gpt-4o";
+/// Default model on Ollama; an OpenRouter model name wouldn't exist locally
+const DEFAULT_OLLAMA_MODEL: &str = "llama3.2";
+/// Milliseconds per day, used to bucket spend into UTC days
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
 
 #[derive(Clone)]
 pub struct AiConfig {
     pub enabled: bool,
+    pub provider: String, // "openrouter" or "ollama"
     pub api_key: String,
+    pub ollama_url: String,
     pub model: String,
-    pub rate_limit: u32,   // requests per minute per user
-    pub timeout_secs: u64, // timeout for API requests
-    pub max_tokens: u32,   // max tokens in AI response
+    pub rate_limit: u32,                     // requests per minute per user
+    pub timeout_secs: u64,                   // timeout for API requests
+    pub max_tokens: u32,                     // max tokens in AI response
+    pub max_retries: u32,                    // retries on timeouts, 429 and 5xx
+    pub max_prompt_chars: usize,             // prompt length in characters, not bytes
+    pub daily_cost_limit: Option<f64>,       // server-wide USD per UTC day
+    pub daily_token_limit: Option<u64>,      // server-wide tokens per UTC day
+    pub user_daily_cost_limit: Option<f64>,  // per client IP, USD per UTC day
+    pub user_daily_token_limit: Option<u64>, // per client IP, tokens per UTC day
+    pub cost_per_minute: Option<f64>,        // per client IP, USD in the trailing minute
+    pub cost_per_token: f64,                 // fallback price when the API reports no cost
+    pub system_on_error: bool,               // tell the room when a request fails
+    pub refund_on_error: bool,               // failed requests don't count towards rate_limit
+    pub treat_empty_as_error: bool, // empty or filtered answers go to the requester as an error
+    pub audit_log: Option<String>,  // JSON lines file with every prompt and answer
 }
 
 impl AiConfig {
@@ -28,9 +70,17 @@ impl AiConfig {
             .map(|v| v.to_lowercase() == "true")
             .unwrap_or(false);
 
+        let provider = std::env::var("AI_PROVIDER")
+            .map(|v| v.to_lowercase())
+            .unwrap_or_else(|_| "openrouter".to_string());
+
         let api_key = std::env::var("OPENROUTER_API_KEY").unwrap_or_default();
 
-        let model = std::env::var("AI_MODEL").unwrap_or_else(|_| "openai/gpt-4o".to_string());
+        let ollama_url =
+            std::env::var("OLLAMA_URL").unwrap_or_else(|_| "http://localhost:11434".to_string());
+
+        let model =
+            std::env::var("AI_MODEL").unwrap_or_else(|_| default_model(&provider).to_string());
 
         let rate_limit = std::env::var("AI_RATE_LIMIT")
             .ok()
@@ -47,64 +97,201 @@ impl AiConfig {
             .and_then(|v| v.parse().ok())
             .unwrap_or(DEFAULT_MAX_TOKENS);
 
-        if enabled && api_key.is_empty() {
+        let max_retries = std::env::var("AI_MAX_RETRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_RETRIES);
+
+        let max_prompt_chars = std::env::var("AI_MAX_PROMPT_CHARS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&max| max > 0)
+            .unwrap_or(DEFAULT_MAX_PROMPT_CHARS);
+
+        let daily_cost_limit = env_limit("AI_DAILY_COST_LIMIT");
+        let daily_token_limit = env_limit("AI_DAILY_TOKEN_LIMIT");
+        let user_daily_cost_limit = env_limit("AI_USER_DAILY_COST_LIMIT");
+        let user_daily_token_limit = env_limit("AI_USER_DAILY_TOKEN_LIMIT");
+        let cost_per_minute = env_limit("AI_COST_PER_MINUTE");
+
+        let cost_per_token = std::env::var("AI_COST_PER_TOKEN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0.0);
+
+        let system_on_error = std::env::var("AI_SYSTEM_ON_ERROR")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+        let refund_on_error = std::env::var("AI_REFUND_ON_ERROR")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+        let treat_empty_as_error = std::env::var("AI_TREAT_EMPTY_AS_ERROR")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+
+        let audit_log = std::env::var("AI_AUDIT_LOG")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+
+        if enabled && provider == "openrouter" && api_key.is_empty() {
             error!("AI_ENABLED=true but OPENROUTER_API_KEY is not set");
         }
 
         info!(
             enabled,
+            provider,
             model,
             rate_limit,
             timeout_secs,
             max_tokens,
+            max_retries,
+            max_prompt_chars,
+            ?daily_cost_limit,
+            ?daily_token_limit,
+            ?user_daily_cost_limit,
+            ?user_daily_token_limit,
+            ?cost_per_minute,
+            system_on_error,
+            refund_on_error,
+            treat_empty_as_error,
+            ?audit_log,
             has_api_key = !api_key.is_empty(),
             "AI configuration loaded"
         );
 
         Self {
             enabled,
+            provider,
             api_key,
+            ollama_url,
             model,
             rate_limit,
             timeout_secs,
             max_tokens,
+            max_retries,
+            max_prompt_chars,
+            daily_cost_limit,
+            daily_token_limit,
+            user_daily_cost_limit,
+            user_daily_token_limit,
+            cost_per_minute,
+            cost_per_token,
+            system_on_error,
+            refund_on_error,
+            treat_empty_as_error,
+            audit_log,
         }
     }
 }
 
-#[derive(Serialize)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<ChatMessage>,
-    max_tokens: u32,
+/// `AI_MODEL` when it isn't set, which depends on the provider
+fn default_model(provider: &str) -> &'static str {
+    match provider {
+        "ollama" => DEFAULT_OLLAMA_MODEL,
+        _ => DEFAULT_OPENROUTER_MODEL,
+    }
 }
 
-#[derive(Serialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
+/// Parse an optional positive limit; unset, invalid or zero means unlimited.
+fn env_limit<T: std::str::FromStr + PartialOrd + Default>(key: &str) -> Option<T> {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > T::default())
 }
 
-#[derive(Deserialize)]
-struct ChatResponse {
-    choices: Vec<Choice>,
-    usage: Option<Usage>,
+/// A single message in a chat completion conversation
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
 }
 
-#[derive(Deserialize)]
-struct Choice {
-    message: ResponseMessage,
+/// Provider-agnostic request parameters
+#[derive(Debug, Clone)]
+pub struct CompletionParams {
+    pub model: String,
+    pub max_tokens: u32,
 }
 
-#[derive(Deserialize)]
-struct ResponseMessage {
-    content: String,
+/// Error from an AI provider, with enough detail to decide whether to retry
+#[derive(Debug)]
+pub struct ProviderError {
+    /// User-facing error message
+    pub message: String,
+    /// Timeouts, 429 and 5xx responses are worth another attempt
+    pub retryable: bool,
+    /// Delay requested by the server via `Retry-After`
+    pub retry_after: Option<Duration>,
 }
 
-#[derive(Deserialize)]
-struct Usage {
-    total_tokens: Option<u32>,
-    cost: Option<f64>,
+impl ProviderError {
+    pub fn fatal(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            retryable: false,
+            retry_after: None,
+        }
+    }
+
+    pub fn transient(message: impl Into<String>, retry_after: Option<Duration>) -> Self {
+        Self {
+            message: message.into(),
+            retryable: true,
+            retry_after,
+        }
+    }
+
+    /// Classify a non-success HTTP response.
+    pub fn from_status(status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap) -> Self {
+        let message = messages::text(Msg::AiServiceError, &[("status", &status)]);
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            let retry_after = headers
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            Self::transient(message, retry_after)
+        } else {
+            Self::fatal(message)
+        }
+    }
+}
+
+/// Why an AI query produced no answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum AiError {
+    /// Turned down before the provider was asked: AI disabled, rate limit, budget, bad prompt
+    Rejected(String),
+    /// The provider was asked and failed, after any retries
+    Failed(String),
+}
+
+impl AiError {
+    /// User-facing error message
+    pub fn message(&self) -> &str {
+        match self {
+            AiError::Rejected(message) | AiError::Failed(message) => message,
+        }
+    }
+}
+
+/// A backend that can answer chat completion requests.
+///
+/// Rate limiting and budgets live in `AiClient`, so providers only deal with the API call.
+#[async_trait]
+pub trait AiProvider: Send + Sync {
+    /// Short provider name for logging
+    fn name(&self) -> &'static str;
+
+    /// Whether the provider has everything it needs (e.g. an API key)
+    fn is_configured(&self) -> bool;
+
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        params: &CompletionParams,
+    ) -> Result<AiResponse, ProviderError>;
 }
 
 /// Result of an AI query including response content and stats
@@ -114,6 +301,88 @@ pub struct AiResponse {
     pub response_ms: u64,
     pub tokens: Option<u32>,
     pub cost: Option<f64>,
+    /// Model that answered, when the provider says so
+    pub model: Option<String>,
+    /// Why the model stopped, as reported by the provider (`stop`, `length`, `content_filter`, ...)
+    pub finish_reason: Option<String>,
+}
+
+impl AiResponse {
+    /// The provider blocked (part of) the answer
+    pub fn is_filtered(&self) -> bool {
+        self.finish_reason.as_deref() == Some("content_filter")
+    }
+}
+
+/// Accumulated AI spend for a single UTC day
+#[derive(Default)]
+struct DailyUsage {
+    day: u64,
+    cost: f64,
+    tokens: u64,
+}
+
+impl DailyUsage {
+    /// Reset the counters when the UTC day has rolled over.
+    fn roll_over(&mut self, today: u64) {
+        if self.day != today {
+            *self = DailyUsage {
+                day: today,
+                ..Default::default()
+            };
+        }
+    }
+
+    /// Take back usage counted ahead of an answer, unless the day has rolled over since.
+    fn release(&mut self, held: Held) {
+        if self.day == held.day {
+            self.cost = (self.cost - held.cost).max(0.0);
+            self.tokens = self.tokens.saturating_sub(held.tokens);
+        }
+    }
+}
+
+/// Estimated usage of a request in flight, already counted in the daily totals
+#[derive(Clone, Copy, Default)]
+struct Held {
+    day: u64,
+    cost: f64,
+    tokens: u64,
+}
+
+/// Budget reserved for one request by `reserve_budget`. Concurrent requests see each
+/// other's reservations, so they can't all pass the daily limits at once. Settled with
+/// the real usage when the answer arrives; a failed or cancelled request just drops it,
+/// which gives the estimate back.
+struct Reservation<'a> {
+    ai: &'a AiClient,
+    user_key: &'a str,
+    held: Held,
+}
+
+impl Reservation<'_> {
+    /// Replace the estimate with what the request used; returns the cost like `record_usage`
+    fn settle(mut self, tokens: Option<u32>, cost: Option<f64>) -> Option<f64> {
+        let held = std::mem::take(&mut self.held);
+        self.ai.record_usage(self.user_key, held, tokens, cost)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let held = std::mem::take(&mut self.held);
+        if held.tokens == 0 && held.cost == 0.0 {
+            return;
+        }
+        self.ai.usage_global.lock().unwrap().release(held);
+        if let Some(mut user) = self.ai.usage_per_user.get_mut(self.user_key) {
+            user.release(held);
+        }
+    }
+}
+
+fn utc_day() -> u64 {
+    now_ms() / MS_PER_DAY
 }
 
 struct RateLimitEntry {
@@ -122,34 +391,234 @@ struct RateLimitEntry {
     last_seen: Instant,
 }
 
+/// Window over which `AI_COST_PER_MINUTE` is enforced
+const COST_WINDOW: Duration = Duration::from_secs(60);
+
 pub struct AiClient {
     config: AiConfig,
-    http: Client,
+    provider: Box<dyn AiProvider>,
     rate_limits: Arc<DashMap<String, RateLimitEntry>>,
+    usage_global: Mutex<DailyUsage>,
+    usage_per_user: DashMap<String, DailyUsage>,
+    /// Cost of each answered request in the trailing `COST_WINDOW`, oldest first
+    recent_costs: DashMap<String, VecDeque<(Instant, f64)>>,
+    retries_total: AtomicU64,
+    clock: Arc<dyn Clock>,
+    audit: Option<AuditLog>,
 }
 
 impl AiClient {
     pub fn new(config: AiConfig) -> Self {
-        let http = Client::builder()
-            .timeout(Duration::from_secs(config.timeout_secs))
-            .build()
-            .expect("Failed to create HTTP client");
+        let provider: Box<dyn AiProvider> = match config.provider.as_str() {
+            "ollama" => Box::new(OllamaProvider::new(
+                config.ollama_url.clone(),
+                config.timeout_secs,
+            )),
+            "openrouter" => Box::new(OpenRouterProvider::new(
+                config.api_key.clone(),
+                config.timeout_secs,
+            )),
+            other => {
+                warn!(
+                    provider = other,
+                    "Unknown AI_PROVIDER, falling back to openrouter"
+                );
+                Box::new(OpenRouterProvider::new(
+                    config.api_key.clone(),
+                    config.timeout_secs,
+                ))
+            }
+        };
+
+        Self::with_provider(config, provider)
+    }
+
+    pub fn with_provider(config: AiConfig, provider: Box<dyn AiProvider>) -> Self {
+        let audit = config
+            .audit_log
+            .as_ref()
+            .and_then(|path| match AuditLog::open(path) {
+                Ok(log) => Some(log),
+                Err(e) => {
+                    error!(path, error = %e, "Cannot open AI_AUDIT_LOG, AI queries won't be audited");
+                    None
+                }
+            });
 
         Self {
             config,
-            http,
+            provider,
             rate_limits: Arc::new(DashMap::new()),
+            usage_global: Mutex::new(DailyUsage::default()),
+            usage_per_user: DashMap::new(),
+            recent_costs: DashMap::new(),
+            retries_total: AtomicU64::new(0),
+            clock: Arc::new(SystemClock),
+            audit,
         }
     }
 
+    /// Use `clock` for rate limiting instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn is_enabled(&self) -> bool {
-        self.config.enabled && !self.config.api_key.is_empty()
+        self.config.enabled && self.provider.is_configured()
+    }
+
+    /// Whether failed requests should be announced to the room
+    pub fn system_on_error(&self) -> bool {
+        self.config.system_on_error
     }
 
     pub fn model(&self) -> &str {
         &self.config.model
     }
 
+    /// Append a query to the audit log, if `AI_AUDIT_LOG` is set
+    pub async fn audit(&self, entry: &AuditEntry<'_>) {
+        if let Some(log) = &self.audit {
+            if let Err(e) = log.write(entry).await {
+                error!(error = %e, "Failed to write AI audit log");
+            }
+        }
+    }
+
+    /// Total AI cost (USD) spent today across all users.
+    pub fn cost_today(&self) -> f64 {
+        let mut usage = self.usage_global.lock().unwrap();
+        usage.roll_over(utc_day());
+        usage.cost
+    }
+
+    /// Total AI tokens used today across all users.
+    pub fn tokens_today(&self) -> u64 {
+        let mut usage = self.usage_global.lock().unwrap();
+        usage.roll_over(utc_day());
+        usage.tokens
+    }
+
+    /// Check the daily budgets and reserve this request's estimated usage under the same
+    /// locks. The estimate is `AI_MAX_TOKENS` at `AI_COST_PER_TOKEN`; the prompt isn't
+    /// counted, and without a per-token price only the token limits see the reservation.
+    fn reserve_budget<'a>(&'a self, user_key: &'a str) -> Result<Reservation<'a>, String> {
+        let today = utc_day();
+        let tokens = self.config.max_tokens as u64;
+        let held = Held {
+            day: today,
+            cost: tokens as f64 * self.config.cost_per_token,
+            tokens,
+        };
+
+        let mut global = self.usage_global.lock().unwrap();
+        global.roll_over(today);
+        if self
+            .config
+            .daily_cost_limit
+            .is_some_and(|limit| global.cost >= limit)
+            || self
+                .config
+                .daily_token_limit
+                .is_some_and(|limit| global.tokens >= limit)
+        {
+            warn!(
+                cost = global.cost,
+                tokens = global.tokens,
+                "AI daily budget exhausted"
+            );
+            return Err(messages::text(Msg::AiServerBudget, &[]));
+        }
+
+        let mut user = self.usage_per_user.entry(user_key.to_string()).or_default();
+        user.roll_over(today);
+        if self
+            .config
+            .user_daily_cost_limit
+            .is_some_and(|limit| user.cost >= limit)
+            || self
+                .config
+                .user_daily_token_limit
+                .is_some_and(|limit| user.tokens >= limit)
+        {
+            return Err(messages::text(Msg::AiUserBudget, &[]));
+        }
+
+        global.cost += held.cost;
+        global.tokens += held.tokens;
+        user.cost += held.cost;
+        user.tokens += held.tokens;
+        Ok(Reservation {
+            ai: self,
+            user_key,
+            held,
+        })
+    }
+
+    /// Add a request's usage to the totals, taking back what was `held` for it.
+    fn record_usage(
+        &self,
+        user_key: &str,
+        held: Held,
+        tokens: Option<u32>,
+        cost: Option<f64>,
+    ) -> Option<f64> {
+        // OpenRouter doesn't always report cost; fall back to the configured per-token price.
+        let cost = cost.or_else(|| {
+            tokens
+                .filter(|_| self.config.cost_per_token > 0.0)
+                .map(|t| t as f64 * self.config.cost_per_token)
+        });
+        let tokens = tokens.unwrap_or(0) as u64;
+        let spent = cost.unwrap_or(0.0);
+        let today = utc_day();
+
+        {
+            let mut global = self.usage_global.lock().unwrap();
+            global.release(held);
+            global.roll_over(today);
+            global.cost += spent;
+            global.tokens += tokens;
+        }
+
+        // Drop entries from previous days so the map stays bounded.
+        self.usage_per_user.retain(|_, usage| usage.day == today);
+        let mut user = self.usage_per_user.entry(user_key.to_string()).or_default();
+        user.release(held);
+        user.roll_over(today);
+        user.cost += spent;
+        user.tokens += tokens;
+        drop(user);
+
+        if self.config.cost_per_minute.is_some() && spent > 0.0 {
+            let now = self.clock.now();
+            self.recent_costs.retain(|_, costs| {
+                costs
+                    .back()
+                    .is_some_and(|&(at, _)| now.duration_since(at) < COST_WINDOW)
+            });
+            self.recent_costs
+                .entry(user_key.to_string())
+                .or_default()
+                .push_back((now, spent));
+        }
+
+        cost
+    }
+
+    /// Number of provider retries since startup.
+    pub fn retries_total(&self) -> u64 {
+        self.retries_total.load(Ordering::Relaxed)
+    }
+
+    /// Give back a rate-limit slot, e.g. when the request was cancelled.
+    pub fn refund_rate_limit(&self, user_key: &str) {
+        if let Some(mut entry) = self.rate_limits.get_mut(user_key) {
+            entry.count = entry.count.saturating_sub(1);
+        }
+    }
+
     fn purge_stale_rate_limits(&self, now: Instant) {
         // Keep short-lived, inactive keys bounded to prevent unbounded memory growth.
         let ttl = Duration::from_secs(60 * 10);
@@ -158,7 +627,7 @@ impl AiClient {
     }
 
     fn check_rate_limit(&self, user_key: &str) -> Result<(), String> {
-        let now = Instant::now();
+        let now = self.clock.now();
         let window = Duration::from_secs(60);
         self.purge_stale_rate_limits(now);
 
@@ -182,10 +651,12 @@ impl AiClient {
             let remaining = window
                 .checked_sub(now.duration_since(entry.window_start))
                 .unwrap_or(Duration::ZERO);
-            return Err(format!(
-                "Rate limit bereikt (max {}/min). Probeer over {} seconden.",
-                self.config.rate_limit,
-                remaining.as_secs().max(1)
+            return Err(messages::text(
+                Msg::AiRateLimited,
+                &[
+                    ("max", &self.config.rate_limit),
+                    ("secs", &remaining.as_secs().max(1)),
+                ],
             ));
         }
 
@@ -193,92 +664,596 @@ impl AiClient {
         Ok(())
     }
 
-    pub async fn query(&self, user_key: &str, prompt: &str) -> Result<AiResponse, String> {
+    /// Reject when the user's answers of the last minute cost `AI_COST_PER_MINUTE` or more.
+    ///
+    /// Cost is only known afterwards, so a single request can overshoot the budget;
+    /// the next one then waits until enough of it has left the window.
+    fn check_cost_rate(&self, user_key: &str) -> Result<(), String> {
+        let Some(max) = self.config.cost_per_minute else {
+            return Ok(());
+        };
+        let Some(mut costs) = self.recent_costs.get_mut(user_key) else {
+            return Ok(());
+        };
+
+        let now = self.clock.now();
+        while costs
+            .front()
+            .is_some_and(|&(at, _)| now.duration_since(at) >= COST_WINDOW)
+        {
+            costs.pop_front();
+        }
+
+        let mut spent: f64 = costs.iter().map(|&(_, cost)| cost).sum();
+        if spent < max {
+            return Ok(());
+        }
+
+        // Wait until the oldest costs have left the window and the rest is under budget.
+        let total = spent;
+        let mut wait = Duration::ZERO;
+        for &(at, cost) in costs.iter() {
+            spent -= cost;
+            wait = COST_WINDOW.saturating_sub(now.duration_since(at));
+            if spent < max {
+                break;
+            }
+        }
+        Err(messages::text(
+            Msg::AiCostLimited,
+            &[
+                ("spent", &format!("{total:.4}")),
+                ("max", &format!("{max:.4}")),
+                ("secs", &wait.as_secs().max(1)),
+            ],
+        ))
+    }
+
+    pub async fn query(&self, user_key: &str, prompt: &str) -> Result<AiResponse, AiError> {
         if !self.is_enabled() {
-            return Err("AI is niet geactiveerd op deze server.".to_string());
+            return Err(AiError::Rejected(messages::text(Msg::AiDisabled, &[])));
         }
 
         // Check rate limit
-        self.check_rate_limit(user_key)?;
+        self.check_rate_limit(user_key).map_err(AiError::Rejected)?;
 
+        let result = self.query_within_rate_limit(user_key, prompt).await;
+        if result.is_err() && self.config.refund_on_error {
+            self.refund_rate_limit(user_key);
+        }
+        result
+    }
+
+    async fn query_within_rate_limit(
+        &self,
+        user_key: &str,
+        prompt: &str,
+    ) -> Result<AiResponse, AiError> {
         // Validate prompt
         let prompt = prompt.trim();
         if prompt.is_empty() {
-            return Err("Geef een vraag op. Gebruik: /ai <vraag>".to_string());
+            return Err(AiError::Rejected(messages::text(Msg::AiPromptEmpty, &[])));
         }
-        if prompt.len() > 1000 {
-            return Err("Vraag is te lang (max 1000 tekens).".to_string());
+        if prompt.chars().count() > self.config.max_prompt_chars {
+            return Err(AiError::Rejected(messages::text(
+                Msg::AiPromptTooLong,
+                &[("max", &self.config.max_prompt_chars)],
+            )));
         }
 
-        debug!(user_key, prompt_len = prompt.len(), "Sending AI request");
+        self.check_cost_rate(user_key).map_err(AiError::Rejected)?;
+        let reservation = self.reserve_budget(user_key).map_err(AiError::Rejected)?;
+
+        debug!(
+            user_key,
+            provider = self.provider.name(),
+            prompt_len = prompt.len(),
+            "Sending AI request"
+        );
 
-        let request = ChatRequest {
+        let messages = [ChatMessage {
+            role: "user".to_string(),
+            content: prompt.to_string(),
+        }];
+        let params = CompletionParams {
             model: self.config.model.clone(),
-            messages: vec![ChatMessage {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            }],
             max_tokens: self.config.max_tokens,
         };
 
-        let start = Instant::now();
-
-        let response = self
-            .http
-            .post(OPENROUTER_API_URL)
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
+        let mut response = self
+            .complete_with_retry(&messages, &params)
             .await
-            .map_err(|e| {
-                error!(?e, "OpenRouter request failed");
-                if e.is_timeout() {
-                    format!(
-                        "AI request timed out after {} seconds.",
-                        self.config.timeout_secs
-                    )
-                } else {
-                    "AI service tijdelijk niet beschikbaar.".to_string()
+            .map_err(AiError::Failed)?;
+        // An empty or blocked answer was still paid for
+        response.cost = reservation.settle(response.tokens, response.cost);
+
+        debug!(
+            response_len = response.content.len(),
+            response_ms = response.response_ms,
+            tokens = ?response.tokens,
+            cost = ?response.cost,
+            model = ?response.model,
+            finish_reason = ?response.finish_reason,
+            "AI response received"
+        );
+
+        let empty = response.content.trim().is_empty();
+        if self.config.treat_empty_as_error && (empty || response.is_filtered()) {
+            let key = if response.is_filtered() {
+                Msg::AiFiltered
+            } else {
+                Msg::AiNoAnswer
+            };
+            return Err(AiError::Failed(messages::text(key, &[])));
+        }
+        if empty {
+            response.content = messages::text(Msg::AiNoAnswer, &[]);
+        }
+
+        Ok(response)
+    }
+
+    /// Call the provider, retrying transient failures with exponential backoff.
+    ///
+    /// All attempts share a deadline of twice the configured timeout, so a client
+    /// never waits much longer than a single slow request would take.
+    async fn complete_with_retry(
+        &self,
+        messages: &[ChatMessage],
+        params: &CompletionParams,
+    ) -> Result<AiResponse, String> {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let deadline = Instant::now() + timeout * 2;
+        let mut attempt = 0u32;
+
+        loop {
+            attempt += 1;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let result = tokio::time::timeout(remaining, self.provider.complete(messages, params))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(ProviderError::fatal(messages::text(
+                        Msg::AiTimeout,
+                        &[("secs", &self.config.timeout_secs)],
+                    )))
+                });
+
+            let err = match result {
+                Ok(response) => {
+                    debug!(attempt, "AI request succeeded");
+                    return Ok(response);
                 }
-            })?;
+                Err(err) => err,
+            };
+
+            if !err.retryable || attempt > self.config.max_retries {
+                debug!(attempt, error = %err.message, "AI request failed");
+                return Err(err.message);
+            }
 
-        let response_ms = start.elapsed().as_millis() as u64;
+            let backoff = RETRY_BASE_DELAY * 2u32.saturating_pow(attempt - 1);
+            let wait = err.retry_after.unwrap_or(backoff).min(RETRY_MAX_DELAY);
+            if Instant::now() + wait >= deadline {
+                debug!(attempt, error = %err.message, "AI retry would exceed deadline");
+                return Err(err.message);
+            }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            error!(%status, %body, "OpenRouter error response");
-            return Err(format!("AI service error: {}", status));
+            self.retries_total.fetch_add(1, Ordering::Relaxed);
+            warn!(attempt, wait_ms = wait.as_millis() as u64, error = %err.message, "Retrying AI request");
+            tokio::time::sleep(wait).await;
         }
+    }
+}
 
-        let chat_response: ChatResponse = response.json().await.map_err(|e| {
-            error!(?e, "Failed to parse OpenRouter response");
-            "Kon AI antwoord niet verwerken.".to_string()
-        })?;
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::state::tests::ai_config;
 
-        let content = chat_response
-            .choices
-            .first()
-            .map(|c| c.message.content.clone())
-            .unwrap_or_else(|| "Geen antwoord ontvangen.".to_string());
+    /// Provider whose every request fails for good, as after exhausted retries
+    pub(crate) struct FailingProvider;
 
-        let tokens = chat_response.usage.as_ref().and_then(|u| u.total_tokens);
-        let cost = chat_response.usage.as_ref().and_then(|u| u.cost);
+    #[async_trait]
+    impl AiProvider for FailingProvider {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
 
-        debug!(
-            response_len = content.len(),
-            response_ms,
-            ?tokens,
-            ?cost,
-            "AI response received"
+        fn is_configured(&self) -> bool {
+            true
+        }
+
+        async fn complete(
+            &self,
+            _messages: &[ChatMessage],
+            _params: &CompletionParams,
+        ) -> Result<AiResponse, ProviderError> {
+            Err(ProviderError::fatal("kapot"))
+        }
+    }
+
+    /// Provider that answers every question with the question itself
+    pub(crate) struct EchoProvider;
+
+    #[async_trait]
+    impl AiProvider for EchoProvider {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn is_configured(&self) -> bool {
+            true
+        }
+
+        async fn complete(
+            &self,
+            messages: &[ChatMessage],
+            _params: &CompletionParams,
+        ) -> Result<AiResponse, ProviderError> {
+            Ok(AiResponse {
+                content: messages[0].content.clone(),
+                response_ms: 1,
+                tokens: None,
+                cost: None,
+                model: Some("echo-1".into()),
+                finish_reason: Some("stop".into()),
+            })
+        }
+    }
+
+    /// Provider whose every answer costs the same
+    struct PricedProvider(f64);
+
+    #[async_trait]
+    impl AiProvider for PricedProvider {
+        fn name(&self) -> &'static str {
+            "priced"
+        }
+
+        fn is_configured(&self) -> bool {
+            true
+        }
+
+        async fn complete(
+            &self,
+            _messages: &[ChatMessage],
+            _params: &CompletionParams,
+        ) -> Result<AiResponse, ProviderError> {
+            // Let concurrent queries run up to this point, as a real request would
+            tokio::task::yield_now().await;
+            Ok(AiResponse {
+                content: "ok".into(),
+                response_ms: 1,
+                tokens: Some(100),
+                cost: Some(self.0),
+                model: None,
+                finish_reason: Some("stop".into()),
+            })
+        }
+    }
+
+    /// Provider that always gives the same answer and finish reason
+    struct CannedProvider(&'static str, &'static str);
+
+    #[async_trait]
+    impl AiProvider for CannedProvider {
+        fn name(&self) -> &'static str {
+            "canned"
+        }
+
+        fn is_configured(&self) -> bool {
+            true
+        }
+
+        async fn complete(
+            &self,
+            _messages: &[ChatMessage],
+            _params: &CompletionParams,
+        ) -> Result<AiResponse, ProviderError> {
+            Ok(AiResponse {
+                content: self.0.into(),
+                response_ms: 1,
+                tokens: Some(10),
+                cost: Some(0.001),
+                model: None,
+                finish_reason: Some(self.1.into()),
+            })
+        }
+    }
+
+    fn canned_ai(content: &'static str, finish_reason: &'static str, as_error: bool) -> AiClient {
+        AiClient::with_provider(
+            AiConfig {
+                enabled: true,
+                treat_empty_as_error: as_error,
+                ..ai_config()
+            },
+            Box::new(CannedProvider(content, finish_reason)),
+        )
+        .with_clock(Arc::new(MockClock::new()))
+    }
+
+    #[tokio::test]
+    async fn empty_answer_is_replaced_by_default() {
+        let ai = canned_ai(" \n", "stop", false);
+        let response = ai.query("alice", "hoi").await.unwrap();
+        assert_eq!(response.content, messages::text(Msg::AiNoAnswer, &[]));
+
+        // Whatever got through a filter is still shown
+        let ai = canned_ai("Deels", "content_filter", false);
+        assert_eq!(ai.query("alice", "hoi").await.unwrap().content, "Deels");
+    }
+
+    #[tokio::test]
+    async fn empty_or_filtered_answer_can_be_an_error() {
+        let ai = canned_ai("", "stop", true);
+        assert_eq!(
+            ai.query("alice", "hoi").await.unwrap_err(),
+            AiError::Failed(messages::text(Msg::AiNoAnswer, &[]))
         );
+        // It was paid for all the same
+        assert!(ai.cost_today() > 0.0);
 
-        Ok(AiResponse {
-            content,
-            response_ms,
-            tokens,
-            cost,
+        let ai = canned_ai("Deels", "content_filter", true);
+        assert_eq!(
+            ai.query("alice", "hoi").await.unwrap_err(),
+            AiError::Failed(messages::text(Msg::AiFiltered, &[]))
+        );
+
+        let ai = canned_ai("Hallo", "length", true);
+        assert_eq!(ai.query("alice", "hoi").await.unwrap().content, "Hallo");
+    }
+
+    fn failing_ai(refund_on_error: bool) -> AiClient {
+        AiClient::with_provider(
+            AiConfig {
+                enabled: true,
+                rate_limit: 1,
+                refund_on_error,
+                ..ai_config()
+            },
+            Box::new(FailingProvider),
+        )
+        .with_clock(Arc::new(MockClock::new()))
+    }
+
+    #[tokio::test]
+    async fn failed_request_keeps_its_rate_limit_slot_by_default() {
+        let ai = failing_ai(false);
+
+        assert_eq!(
+            ai.query("alice", "hoi").await.unwrap_err(),
+            AiError::Failed("kapot".into())
+        );
+        assert!(matches!(
+            ai.query("alice", "hoi").await,
+            Err(AiError::Rejected(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_request_is_refunded_when_configured() {
+        let ai = failing_ai(true);
+
+        for _ in 0..3 {
+            assert_eq!(
+                ai.query("alice", "hoi").await.unwrap_err(),
+                AiError::Failed("kapot".into())
+            );
+        }
+        // An invalid prompt doesn't use up the slot either
+        assert!(matches!(
+            ai.query("alice", " ").await,
+            Err(AiError::Rejected(_))
+        ));
+        assert_eq!(
+            ai.query("alice", "hoi").await.unwrap_err(),
+            AiError::Failed("kapot".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn prompt_length_is_counted_in_characters() {
+        let ai = AiClient::with_provider(
+            AiConfig {
+                enabled: true,
+                max_prompt_chars: 10,
+                ..ai_config()
+            },
+            Box::new(EchoProvider),
+        )
+        .with_clock(Arc::new(MockClock::new()));
+
+        // 10 characters but 30 bytes
+        let prompt = "日本語のテキストです";
+        assert_eq!(ai.query("alice", prompt).await.unwrap().content, prompt);
+        assert_eq!(
+            ai.query("alice", &format!("{prompt}!")).await.unwrap_err(),
+            AiError::Rejected(messages::text(Msg::AiPromptTooLong, &[("max", &10)]))
+        );
+    }
+
+    fn rate_limited(secs: u64) -> Result<(), String> {
+        Err(messages::text(
+            Msg::AiRateLimited,
+            &[("max", &2), ("secs", &secs)],
+        ))
+    }
+
+    #[test]
+    fn default_model_depends_on_the_provider() {
+        assert_eq!(default_model("openrouter"), "openai/gpt-4o");
+        assert_eq!(default_model("ollama"), "llama3.2");
+    }
+
+    #[test]
+    fn rate_limit_window_resets_after_60_seconds() {
+        let clock = Arc::new(MockClock::new());
+        let ai = AiClient::new(AiConfig {
+            rate_limit: 2,
+            ..ai_config()
         })
+        .with_clock(clock.clone());
+
+        assert_eq!(ai.check_rate_limit("alice"), Ok(()));
+        assert_eq!(ai.check_rate_limit("alice"), Ok(()));
+        assert_eq!(ai.check_rate_limit("alice"), rate_limited(60));
+        // Other users have their own window
+        assert_eq!(ai.check_rate_limit("bob"), Ok(()));
+
+        clock.advance(Duration::from_secs(45));
+        assert_eq!(ai.check_rate_limit("alice"), rate_limited(15));
+        clock.advance(Duration::from_millis(14_500));
+        assert_eq!(ai.check_rate_limit("alice"), rate_limited(1));
+
+        // The window is fixed: at exactly 60 seconds it starts over
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(ai.check_rate_limit("alice"), Ok(()));
+        assert_eq!(ai.check_rate_limit("alice"), Ok(()));
+        assert_eq!(ai.check_rate_limit("alice"), rate_limited(60));
+    }
+
+    #[test]
+    fn refunded_slot_can_be_used_again() {
+        let ai = AiClient::new(AiConfig {
+            rate_limit: 1,
+            ..ai_config()
+        })
+        .with_clock(Arc::new(MockClock::new()));
+
+        assert_eq!(ai.check_rate_limit("alice"), Ok(()));
+        assert!(ai.check_rate_limit("alice").is_err());
+        ai.refund_rate_limit("alice");
+        assert_eq!(ai.check_rate_limit("alice"), Ok(()));
+    }
+
+    fn cost_limited(spent: &str, secs: u64) -> Result<(), String> {
+        Err(messages::text(
+            Msg::AiCostLimited,
+            &[("spent", &spent), ("max", &"0.0100"), ("secs", &secs)],
+        ))
+    }
+
+    #[test]
+    fn cost_per_minute_is_enforced_over_the_trailing_window() {
+        let clock = Arc::new(MockClock::new());
+        let ai = AiClient::new(AiConfig {
+            cost_per_minute: Some(0.01),
+            ..ai_config()
+        })
+        .with_clock(clock.clone());
+
+        ai.record_usage("alice", Held::default(), Some(400), Some(0.004));
+        assert_eq!(ai.check_cost_rate("alice"), Ok(()));
+
+        clock.advance(Duration::from_secs(20));
+        ai.record_usage("alice", Held::default(), Some(700), Some(0.007));
+        assert_eq!(ai.check_cost_rate("alice"), cost_limited("0.0110", 40));
+        // Other users have their own budget
+        assert_eq!(ai.check_cost_rate("bob"), Ok(()));
+
+        // Once the first answer has left the window the rest is under budget
+        clock.advance(Duration::from_secs(39));
+        assert_eq!(ai.check_cost_rate("alice"), cost_limited("0.0110", 1));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(ai.check_cost_rate("alice"), Ok(()));
+    }
+
+    #[test]
+    fn one_expensive_answer_blocks_until_it_leaves_the_window() {
+        let clock = Arc::new(MockClock::new());
+        let ai = AiClient::new(AiConfig {
+            cost_per_minute: Some(0.01),
+            ..ai_config()
+        })
+        .with_clock(clock.clone());
+
+        ai.record_usage("alice", Held::default(), Some(100), Some(0.001));
+        clock.advance(Duration::from_secs(10));
+        ai.record_usage("alice", Held::default(), Some(5000), Some(0.05));
+        // Dropping the cheap answer isn't enough; the expensive one has to expire
+        assert_eq!(ai.check_cost_rate("alice"), cost_limited("0.0510", 60));
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(ai.check_cost_rate("alice"), Ok(()));
+    }
+
+    #[test]
+    fn estimated_cost_counts_when_the_api_reports_none() {
+        let ai = AiClient::new(AiConfig {
+            cost_per_minute: Some(0.01),
+            cost_per_token: 0.00001,
+            ..ai_config()
+        })
+        .with_clock(Arc::new(MockClock::new()));
+
+        ai.record_usage("alice", Held::default(), Some(1000), None);
+        assert_eq!(ai.check_cost_rate("alice"), cost_limited("0.0100", 60));
+    }
+
+    #[tokio::test]
+    async fn query_is_rejected_when_the_cost_budget_is_spent() {
+        let ai = AiClient::with_provider(
+            AiConfig {
+                enabled: true,
+                cost_per_minute: Some(0.01),
+                ..ai_config()
+            },
+            Box::new(PricedProvider(0.006)),
+        )
+        .with_clock(Arc::new(MockClock::new()));
+
+        assert!(ai.query("alice", "hoi").await.is_ok());
+        assert!(ai.query("alice", "hoi").await.is_ok());
+        assert_eq!(
+            ai.query("alice", "hoi").await.unwrap_err(),
+            AiError::Rejected(cost_limited("0.0120", 60).unwrap_err())
+        );
+        assert!(ai.query("bob", "hoi").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn concurrent_queries_cannot_all_pass_the_daily_budget() {
+        let ai = AiClient::with_provider(
+            AiConfig {
+                enabled: true,
+                daily_token_limit: Some(100),
+                ..ai_config()
+            },
+            Box::new(PricedProvider(0.001)),
+        );
+
+        let (first, second) = tokio::join!(ai.query("alice", "hoi"), ai.query("bob", "hoi"));
+        assert!(first.is_ok());
+        assert_eq!(
+            second.unwrap_err(),
+            AiError::Rejected(messages::text(Msg::AiServerBudget, &[]))
+        );
+        // The estimate was replaced by what the answer actually used
+        assert_eq!(ai.tokens_today(), 100);
+        assert_eq!(ai.cost_today(), 0.001);
+    }
+
+    #[tokio::test]
+    async fn failed_query_gives_its_reservation_back() {
+        let ai = AiClient::with_provider(
+            AiConfig {
+                enabled: true,
+                daily_token_limit: Some(100),
+                cost_per_token: 0.00001,
+                ..ai_config()
+            },
+            Box::new(FailingProvider),
+        );
+
+        for _ in 0..2 {
+            assert!(matches!(
+                ai.query("alice", "hoi").await,
+                Err(AiError::Failed(_))
+            ));
+        }
+        assert_eq!(ai.tokens_today(), 0);
+        assert_eq!(ai.cost_today(), 0.0);
     }
 }
@@ -0,0 +1,63 @@
+//! Audit log of AI questions and answers.
+//!
+//! One JSON object per line, appended to the file in `AI_AUDIT_LOG`. This is a record
+//! for compliance, separate from the tracing output. The server never rotates or
+//! truncates the file; because it is opened in append mode, external rotation with
+//! `copytruncate` works without a restart.
+
+use std::path::Path;
+
+use serde::Serialize;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Mutex;
+
+/// One AI query, as written to the audit log
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry<'a> {
+    pub at: u64,
+    pub user_id: String,
+    pub name: &'a str,
+    pub ip: &'a str,
+    pub model: &'a str,
+    pub prompt: &'a str,
+    pub private: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+pub struct AuditLog {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl AuditLog {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(File::from_std(file))),
+        })
+    }
+
+    /// Append `entry` as a single line. The line is flushed right away, so a crash
+    /// doesn't lose queries that were already answered.
+    pub async fn write(&self, entry: &AuditEntry<'_>) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+}
@@ -0,0 +1,136 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+use crate::messages::{self, Msg};
+
+use super::{AiProvider, AiResponse, ChatMessage, CompletionParams, ProviderError};
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    stream: bool,
+    options: Options,
+}
+
+#[derive(Serialize)]
+struct Options {
+    num_predict: u32,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    model: Option<String>,
+    message: ResponseMessage,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: String,
+}
+
+/// Local Ollama instance via its native `/api/chat` endpoint
+pub struct OllamaProvider {
+    http: Client,
+    base_url: String,
+    timeout_secs: u64,
+}
+
+impl OllamaProvider {
+    pub fn new(base_url: String, timeout_secs: u64) -> Self {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(timeout_secs))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            timeout_secs,
+        }
+    }
+}
+
+#[async_trait]
+impl AiProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn is_configured(&self) -> bool {
+        !self.base_url.is_empty()
+    }
+
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        params: &CompletionParams,
+    ) -> Result<AiResponse, ProviderError> {
+        let request = ChatRequest {
+            model: &params.model,
+            messages,
+            stream: false,
+            options: Options {
+                num_predict: params.max_tokens,
+            },
+        };
+
+        let start = Instant::now();
+
+        let response = self
+            .http
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "Ollama request failed");
+                if e.is_timeout() {
+                    ProviderError::transient(
+                        messages::text(Msg::AiTimeout, &[("secs", &self.timeout_secs)]),
+                        None,
+                    )
+                } else if e.is_connect() {
+                    ProviderError::transient(messages::text(Msg::AiUnavailable, &[]), None)
+                } else {
+                    ProviderError::fatal(messages::text(Msg::AiUnavailable, &[]))
+                }
+            })?;
+
+        let response_ms = start.elapsed().as_millis() as u64;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let err = ProviderError::from_status(status, response.headers());
+            let body = response.text().await.unwrap_or_default();
+            error!(%status, %body, "Ollama error response");
+            return Err(err);
+        }
+
+        let chat_response: ChatResponse = response.json().await.map_err(|e| {
+            error!(?e, "Failed to parse Ollama response");
+            ProviderError::fatal(messages::text(Msg::AiInvalidResponse, &[]))
+        })?;
+
+        let tokens = match (chat_response.prompt_eval_count, chat_response.eval_count) {
+            (None, None) => None,
+            (prompt, completion) => Some(prompt.unwrap_or(0) + completion.unwrap_or(0)),
+        };
+
+        // Local models have no per-request cost.
+        Ok(AiResponse {
+            content: chat_response.message.content,
+            response_ms,
+            tokens,
+            cost: Some(0.0),
+            model: chat_response.model,
+            finish_reason: chat_response.done_reason,
+        })
+    }
+}
@@ -0,0 +1,143 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+use crate::messages::{self, Msg};
+
+use super::{AiProvider, AiResponse, ChatMessage, CompletionParams, ProviderError};
+
+const OPENROUTER_API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    max_tokens: u32,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    model: Option<String>,
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    // null when the answer was blocked
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    total_tokens: Option<u32>,
+    cost: Option<f64>,
+}
+
+/// OpenRouter chat completions API (OpenAI-compatible)
+pub struct OpenRouterProvider {
+    http: Client,
+    api_key: String,
+    timeout_secs: u64,
+}
+
+impl OpenRouterProvider {
+    pub fn new(api_key: String, timeout_secs: u64) -> Self {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(timeout_secs))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Self {
+            http,
+            api_key,
+            timeout_secs,
+        }
+    }
+}
+
+#[async_trait]
+impl AiProvider for OpenRouterProvider {
+    fn name(&self) -> &'static str {
+        "openrouter"
+    }
+
+    fn is_configured(&self) -> bool {
+        !self.api_key.is_empty()
+    }
+
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        params: &CompletionParams,
+    ) -> Result<AiResponse, ProviderError> {
+        let request = ChatRequest {
+            model: &params.model,
+            messages,
+            max_tokens: params.max_tokens,
+        };
+
+        let start = Instant::now();
+
+        let response = self
+            .http
+            .post(OPENROUTER_API_URL)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header("Content-Type", "application/json")
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "OpenRouter request failed");
+                if e.is_timeout() {
+                    ProviderError::transient(
+                        messages::text(Msg::AiTimeout, &[("secs", &self.timeout_secs)]),
+                        None,
+                    )
+                } else if e.is_connect() {
+                    ProviderError::transient(messages::text(Msg::AiUnavailable, &[]), None)
+                } else {
+                    ProviderError::fatal(messages::text(Msg::AiUnavailable, &[]))
+                }
+            })?;
+
+        let response_ms = start.elapsed().as_millis() as u64;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let err = ProviderError::from_status(status, response.headers());
+            let body = response.text().await.unwrap_or_default();
+            error!(%status, %body, "OpenRouter error response");
+            return Err(err);
+        }
+
+        let chat_response: ChatResponse = response.json().await.map_err(|e| {
+            error!(?e, "Failed to parse OpenRouter response");
+            ProviderError::fatal(messages::text(Msg::AiInvalidResponse, &[]))
+        })?;
+
+        let choice = chat_response.choices.into_iter().next();
+        let finish_reason = choice.as_ref().and_then(|c| c.finish_reason.clone());
+        let content = choice.and_then(|c| c.message.content).unwrap_or_default();
+
+        let tokens = chat_response.usage.as_ref().and_then(|u| u.total_tokens);
+        let cost = chat_response.usage.as_ref().and_then(|u| u.cost);
+
+        Ok(AiResponse {
+            content,
+            response_ms,
+            tokens,
+            cost,
+            model: chat_response.model,
+            finish_reason,
+        })
+    }
+}
@@ -0,0 +1,84 @@
+//! Chat batching for very busy rooms.
+//!
+//! Normally every chat message is serialized and fanned out on its own. With
+//! `CHAT_BATCH_MS` set, chat messages are held for that long and go out together as one
+//! `chatBatch`, so each client gets one frame for many messages. That costs up to the
+//! window in latency. Only chat is batched; other broadcasts go out right away. Edits and
+//! deletes flush the batch first, so they never arrive before the message they are about.
+
+use std::{mem, sync::Mutex, time::Duration};
+
+use tracing::{debug, info};
+
+use crate::handlers::broadcast;
+use crate::protocol::Outgoing;
+use crate::state::AppState;
+
+#[derive(Clone, Default)]
+pub struct ChatBatchConfig {
+    /// How long a chat message may wait for others; `None` sends every message on its own
+    pub window: Option<Duration>,
+}
+
+impl ChatBatchConfig {
+    pub fn from_env() -> Self {
+        let window_ms = std::env::var("CHAT_BATCH_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|&ms| ms > 0);
+
+        if let Some(window_ms) = window_ms {
+            info!(window_ms, "Chat batching enabled");
+        }
+
+        Self {
+            window: window_ms.map(Duration::from_millis),
+        }
+    }
+}
+
+/// Chat messages waiting for the current window to end
+pub struct ChatBatcher {
+    window: Duration,
+    pending: Mutex<Vec<Outgoing>>,
+}
+
+impl ChatBatcher {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Queue a chat message. The first message of a window schedules the flush, so no
+    /// task runs while the room is quiet.
+    pub fn push(&self, state: &AppState, message: Outgoing) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.push(message);
+        if pending.len() == 1 {
+            let state = state.clone();
+            let window = self.window;
+            tokio::spawn(async move {
+                tokio::time::sleep(window).await;
+                if let Some(batcher) = &state.chat_batch {
+                    batcher.flush(&state);
+                }
+            });
+        }
+    }
+
+    /// Broadcast everything queued: a single message as a plain `chat`, more as a `chatBatch`.
+    /// The lock is held while broadcasting, so a flush that finds nothing waiting returns
+    /// only after an earlier one has sent its messages.
+    pub fn flush(&self, state: &AppState) {
+        let mut pending = self.pending.lock().unwrap();
+        let mut messages = mem::take(&mut *pending);
+        debug!(count = messages.len(), "Flushing chat batch");
+        match messages.len() {
+            0 => {}
+            1 => broadcast(state, &messages.remove(0), None),
+            _ => broadcast(state, &Outgoing::ChatBatch { messages }, None),
+        }
+    }
+}
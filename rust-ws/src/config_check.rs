@@ -0,0 +1,450 @@
+//! `--check-config`: show the configuration the server would run with, and stop.
+//!
+//! Everything is read with the same `from_env` constructors as a normal start, so the
+//! summary is what the server would actually use. Secrets are only reported as set or
+//! not set. Values those constructors would silently replace by a default (a port that
+//! isn't a number, `AI_ENABLED=yes`) are reported as problems, as is an AI setup that
+//! can't work.
+
+use std::path::Path;
+
+use crate::ai::AiConfig;
+use crate::batch::ChatBatchConfig;
+use crate::env_file::EnvFile;
+use crate::guest_names::GuestNameStyle;
+use crate::history::HistoryConfig;
+use crate::listeners::TlsConfig;
+use crate::messages::Locale;
+use crate::resume::ResumeConfig;
+use crate::state::{
+    AdminConfig, ConnectionLimitConfig, FloodCollapseConfig, JoinLeaveConfig, RateLimitConfig,
+    SlowClientConfig, SpectatorConfig,
+};
+use crate::validation::ValidationConfig;
+
+/// What a variable must look like to be used as given
+#[derive(Clone, Copy)]
+enum Kind {
+    Port,
+    Number,
+    Decimal,
+    Flag,
+    OneOf(&'static [&'static str]),
+}
+
+const VARIABLES: &[(&str, Kind)] = &[
+    ("WS_PORT", Kind::Port),
+    ("SERVER_LOCALE", Kind::OneOf(&["nl", "en"])),
+    ("NAME_CHARS", Kind::OneOf(&["printable", "strict"])),
+    ("GUEST_NAME_STYLE", Kind::OneOf(&["plain", "friendly"])),
+    ("CHAT_CONTROL_CHARS", Kind::OneOf(&["strip", "reject"])),
+    ("MAX_MESSAGE_LENGTH", Kind::Number),
+    ("AI_ENABLED", Kind::OneOf(&["true", "false"])),
+    ("AI_PROVIDER", Kind::OneOf(&["openrouter", "ollama"])),
+    ("AI_RATE_LIMIT", Kind::Number),
+    ("AI_TIMEOUT_SECS", Kind::Number),
+    ("AI_MAX_TOKENS", Kind::Number),
+    ("AI_MAX_RETRIES", Kind::Number),
+    ("AI_MAX_PROMPT_CHARS", Kind::Number),
+    ("AI_DAILY_COST_LIMIT", Kind::Decimal),
+    ("AI_DAILY_TOKEN_LIMIT", Kind::Number),
+    ("AI_USER_DAILY_COST_LIMIT", Kind::Decimal),
+    ("AI_USER_DAILY_TOKEN_LIMIT", Kind::Number),
+    ("AI_COST_PER_MINUTE", Kind::Decimal),
+    ("AI_COST_PER_TOKEN", Kind::Decimal),
+    ("AI_SYSTEM_ON_ERROR", Kind::Flag),
+    ("AI_REFUND_ON_ERROR", Kind::Flag),
+    ("AI_TREAT_EMPTY_AS_ERROR", Kind::Flag),
+    ("RATE_LIMIT_ENABLED", Kind::Flag),
+    ("RATE_LIMIT_MSG_PER_MIN", Kind::Number),
+    ("RATE_LIMIT_PLAIN_ERROR", Kind::Flag),
+    ("BOT_RATE_LIMIT_MSG_PER_MIN", Kind::Number),
+    ("PING_RATE_LIMIT_PER_SEC", Kind::Number),
+    ("FLOOD_COLLAPSE_ENABLED", Kind::Flag),
+    ("DEDUP_WINDOW_MS", Kind::Number),
+    ("SLOW_CLIENT_DISCONNECT", Kind::Flag),
+    ("HISTORY_SIZE", Kind::Number),
+    ("EDIT_WINDOW_SECS", Kind::Number),
+    ("RESUME_TTL_SECS", Kind::Number),
+    ("JOIN_LEAVE_NOTICES", Kind::Flag),
+    ("JOIN_LEAVE_NOTICES_MAX_USERS", Kind::Number),
+    ("MAX_CONNECTIONS", Kind::Number),
+    ("SPECTATORS_COUNTED", Kind::Flag),
+    ("CHAT_BATCH_MS", Kind::Number),
+    ("SYSTEM_STATS_INTERVAL_SECS", Kind::Number),
+    ("STATUS_BROADCAST_SECS", Kind::Number),
+    ("TRUST_PROXY_HEADERS", Kind::Flag),
+];
+
+/// Print the resolved configuration and any problems. Returns the process exit code:
+/// 0 when the configuration is usable, 1 otherwise.
+pub fn run(env_file: Option<&EnvFile>) -> i32 {
+    let ai = AiConfig::from_env();
+    println!("Configuration:");
+    for (label, value) in summary(env_file, &ai) {
+        println!("  {:<20}{}", format!("{label}:"), value);
+    }
+
+    let problems = problems(&ai, |key| std::env::var(key).ok());
+    if problems.is_empty() {
+        println!("\nConfiguration OK");
+        return 0;
+    }
+    eprintln!();
+    for problem in &problems {
+        eprintln!("Error: {problem}");
+    }
+    eprintln!("{} problem(s) found", problems.len());
+    1
+}
+
+fn on_off(enabled: bool) -> String {
+    if enabled { "on" } else { "off" }.to_string()
+}
+
+fn secret(value: Option<&str>) -> String {
+    match value {
+        Some(_) => "set (redacted)".to_string(),
+        None => "not set".to_string(),
+    }
+}
+
+fn limit<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "none".to_string(), |v| v.to_string())
+}
+
+fn summary(env_file: Option<&EnvFile>, ai: &AiConfig) -> Vec<(&'static str, String)> {
+    let validation = ValidationConfig::from_env();
+    let rate_limit = RateLimitConfig::from_env();
+    let flood_collapse = FloodCollapseConfig::from_env();
+    let admin = AdminConfig::from_env();
+    let slow_client = SlowClientConfig::from_env();
+    let history = HistoryConfig::from_env();
+    let resume = ResumeConfig::from_env();
+    let join_leave = JoinLeaveConfig::from_env();
+    let connection_limit = ConnectionLimitConfig::from_env();
+    let spectators = SpectatorConfig::from_env();
+    let chat_batch = ChatBatchConfig::from_env();
+    let status_broadcast_secs = crate::status_broadcast_secs();
+
+    let mut rows = vec![
+        (
+            "Env file",
+            env_file.map_or_else(
+                || "none".to_string(),
+                |file| {
+                    format!(
+                        "{} ({} loaded, {} overridden by the environment)",
+                        file.path.display(),
+                        file.loaded.len(),
+                        file.overridden.len()
+                    )
+                },
+            ),
+        ),
+        (
+            "TLS listener",
+            match TlsConfig::from_vars(|key| std::env::var(key).ok()) {
+                Ok(Some(tls)) => format!("port {} ({})", tls.port, tls.cert_file),
+                Ok(None) => "off".to_string(),
+                Err(_) => "invalid".to_string(),
+            },
+        ),
+        ("Port", crate::port().to_string()),
+        (
+            "Locale",
+            match Locale::from_env() {
+                Locale::Nl => "nl".to_string(),
+                Locale::En => "en".to_string(),
+            },
+        ),
+        (
+            "Validation",
+            format!(
+                "names {:?}, control chars {:?}, max {} chars",
+                validation.name_chars, validation.control_chars, validation.max_message_length
+            )
+            .to_lowercase(),
+        ),
+        (
+            "Guest names",
+            format!("{:?}", GuestNameStyle::from_env()).to_lowercase(),
+        ),
+        (
+            "Rate limit",
+            if rate_limit.enabled {
+                format!(
+                    "{}/min, bots {}, plain errors {}",
+                    rate_limit.messages_per_minute,
+                    rate_limit
+                        .bot_messages_per_minute
+                        .map_or_else(|| "unlimited".to_string(), |max| format!("{max}/min")),
+                    on_off(rate_limit.plain_error)
+                )
+            } else {
+                "off".to_string()
+            },
+        ),
+        ("Ping limit", format!("{}/s", rate_limit.pings_per_second)),
+        (
+            "Flood collapse",
+            if flood_collapse.enabled {
+                format!("{}ms window", flood_collapse.window.as_millis())
+            } else {
+                "off".to_string()
+            },
+        ),
+        ("Admin token", secret(admin.token.as_deref())),
+        ("Bot token", secret(admin.bot_token.as_deref())),
+        (
+            "Slow clients",
+            if slow_client.disconnect {
+                "disconnect"
+            } else {
+                "drop messages"
+            }
+            .to_string(),
+        ),
+        (
+            "History",
+            format!(
+                "{} messages, editable for {}s",
+                history.size, history.edit_window_secs
+            ),
+        ),
+        ("Resume", format!("{}s", resume.ttl.as_secs())),
+        (
+            "Join/leave notices",
+            match (join_leave.enabled, join_leave.max_users) {
+                (true, Some(max)) => format!("on, up to {max} users"),
+                (enabled, _) => on_off(enabled),
+            },
+        ),
+        ("Max connections", limit(connection_limit.max_connections)),
+        (
+            "Spectators",
+            if spectators.counted {
+                "counted as users"
+            } else {
+                "not counted"
+            }
+            .to_string(),
+        ),
+        (
+            "Chat batching",
+            chat_batch
+                .window
+                .map_or_else(|| "off".to_string(), |w| format!("{}ms", w.as_millis())),
+        ),
+        (
+            "System stats",
+            format!("every {}s", crate::system_stats_secs()),
+        ),
+        (
+            "Status broadcast",
+            if status_broadcast_secs > 0 {
+                format!("every {status_broadcast_secs}s")
+            } else {
+                "off".to_string()
+            },
+        ),
+        (
+            "MOTD",
+            match (std::env::var("MOTD_FILE"), std::env::var("MOTD")) {
+                (Ok(path), _) => format!("from {path}"),
+                (_, Ok(_)) => "from MOTD".to_string(),
+                _ => "none".to_string(),
+            },
+        ),
+        ("AI", on_off(ai.enabled)),
+    ];
+
+    if ai.enabled {
+        rows.push(("AI provider", ai.provider.clone()));
+        rows.push(("AI model", ai.model.clone()));
+        if ai.provider == "ollama" {
+            rows.push(("Ollama URL", ai.ollama_url.clone()));
+        } else {
+            rows.push((
+                "OpenRouter key",
+                secret(Some(ai.api_key.as_str()).filter(|key| !key.is_empty())),
+            ));
+        }
+        rows.push((
+            "AI limits",
+            format!(
+                "{}/min per user, {}s timeout, {} max tokens, {} retries, prompts up to {} characters",
+                ai.rate_limit, ai.timeout_secs, ai.max_tokens, ai.max_retries, ai.max_prompt_chars
+            ),
+        ));
+        rows.push((
+            "AI daily budget",
+            format!(
+                "server {} / {} tokens, per IP {} / {} tokens",
+                limit(ai.daily_cost_limit.map(|usd| format!("${usd}"))),
+                limit(ai.daily_token_limit),
+                limit(ai.user_daily_cost_limit.map(|usd| format!("${usd}"))),
+                limit(ai.user_daily_token_limit)
+            ),
+        ));
+        rows.push((
+            "AI cost per minute",
+            limit(ai.cost_per_minute.map(|usd| format!("${usd} per IP"))),
+        ));
+        rows.push(("AI audit log", limit(ai.audit_log.as_deref())));
+    }
+    rows
+}
+
+/// Everything that would make the server run with something else than was asked for
+fn problems(ai: &AiConfig, var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for &(key, kind) in VARIABLES {
+        let Some(value) = var(key) else {
+            continue;
+        };
+        let valid = match kind {
+            Kind::Port => value.parse::<u16>().is_ok(),
+            Kind::Number => value.parse::<u64>().is_ok(),
+            Kind::Decimal => value.parse::<f64>().is_ok_and(|v| v >= 0.0),
+            Kind::Flag => ["true", "false", "1", "0"]
+                .iter()
+                .any(|flag| value.eq_ignore_ascii_case(flag)),
+            Kind::OneOf(options) => options
+                .iter()
+                .any(|option| value.eq_ignore_ascii_case(option)),
+        };
+        if !valid {
+            let expected = match kind {
+                Kind::Port => "a port number".to_string(),
+                Kind::Number => "a whole number".to_string(),
+                Kind::Decimal => "a number".to_string(),
+                Kind::Flag => "true or false".to_string(),
+                Kind::OneOf(options) => options.join(" or "),
+            };
+            problems.push(format!("{key}={value:?} is not {expected}"));
+        }
+    }
+
+    if ai.enabled && ai.provider == "openrouter" && ai.api_key.is_empty() {
+        problems.push("AI_ENABLED=true but OPENROUTER_API_KEY is not set".to_string());
+    }
+    if let Some(path) = &ai.audit_log {
+        let dir = Path::new(path)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty());
+        if dir.is_some_and(|dir| !dir.is_dir()) {
+            problems.push(format!("AI_AUDIT_LOG directory of {path} does not exist"));
+        }
+    }
+    if let Some(path) = var("MOTD_FILE") {
+        if let Err(err) = std::fs::read_to_string(&path) {
+            problems.push(format!("MOTD_FILE {path} cannot be read: {err}"));
+        }
+    }
+    match TlsConfig::from_vars(&var) {
+        Ok(Some(tls)) => {
+            for (key, path) in [
+                ("TLS_CERT_FILE", tls.cert_file),
+                ("TLS_KEY_FILE", tls.key_file),
+            ] {
+                if let Err(err) = std::fs::read(&path) {
+                    problems.push(format!("{key} {path} cannot be read: {err}"));
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(err) => problems.push(err),
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::ai_config;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn defaults_have_no_problems() {
+        assert!(problems(&ai_config(), env(&[])).is_empty());
+        assert!(problems(
+            &ai_config(),
+            env(&[
+                ("WS_PORT", "8080"),
+                ("AI_ENABLED", "TRUE"),
+                ("RATE_LIMIT_ENABLED", "1"),
+                ("AI_DAILY_COST_LIMIT", "2.50"),
+                ("SERVER_LOCALE", "en"),
+            ])
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn values_that_would_fall_back_to_a_default_are_problems() {
+        let problems = problems(
+            &ai_config(),
+            env(&[
+                ("WS_PORT", "70000"),
+                ("HISTORY_SIZE", "lots"),
+                ("AI_ENABLED", "yes"),
+                ("SERVER_LOCALE", "de"),
+            ]),
+        );
+        assert_eq!(
+            problems,
+            [
+                "WS_PORT=\"70000\" is not a port number",
+                "SERVER_LOCALE=\"de\" is not nl or en",
+                "AI_ENABLED=\"yes\" is not true or false",
+                "HISTORY_SIZE=\"lots\" is not a whole number",
+            ]
+        );
+    }
+
+    #[test]
+    fn openrouter_needs_a_key() {
+        let mut ai = AiConfig {
+            enabled: true,
+            provider: "openrouter".into(),
+            ..ai_config()
+        };
+        assert_eq!(
+            problems(&ai, env(&[])),
+            ["AI_ENABLED=true but OPENROUTER_API_KEY is not set"]
+        );
+        ai.api_key = "sk-test".into();
+        assert!(problems(&ai, env(&[])).is_empty());
+    }
+
+    #[test]
+    fn file_settings_must_be_usable() {
+        let ai = AiConfig {
+            audit_log: Some("/nonexistent/dir/audit.jsonl".into()),
+            ..ai_config()
+        };
+        let problems = problems(
+            &ai,
+            env(&[
+                ("MOTD_FILE", "/nonexistent/motd.txt"),
+                ("WSS_PORT", "3443"),
+                ("TLS_CERT_FILE", "/nonexistent/cert.pem"),
+                ("TLS_KEY_FILE", "/nonexistent/key.pem"),
+            ]),
+        );
+        assert_eq!(problems.len(), 4);
+        assert!(problems[0].starts_with("AI_AUDIT_LOG"));
+        assert!(problems[1].starts_with("MOTD_FILE"));
+        assert!(problems[2].starts_with("TLS_CERT_FILE"));
+        assert!(problems[3].starts_with("TLS_KEY_FILE"));
+    }
+}
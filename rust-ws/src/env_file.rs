@@ -0,0 +1,156 @@
+//! Configuration from a `.env` file.
+//!
+//! The file is read before anything else so every setting (`WS_PORT`, `AI_*`,
+//! `RATE_LIMIT_*`, even `RUST_LOG`) can live in it. Variables that are already set in
+//! the environment win over the file. Logging isn't set up yet at that point, so the
+//! outcome is kept in an [`EnvFile`] and logged afterwards. The command line is parsed
+//! here as well, since it is read at the same moment.
+
+use std::path::{Path, PathBuf};
+
+use tracing::info;
+
+const DEFAULT_ENV_FILE: &str = ".env";
+
+/// Command line of the server
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    /// `--env-file <path>`; `None` means `.env` in the working directory, if any
+    pub env_file: Option<PathBuf>,
+    /// `--check-config`: print and validate the configuration, then exit
+    pub check_config: bool,
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut args = args.into_iter();
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        if arg == "--env-file" {
+            let value = args.next().ok_or("--env-file needs a path")?;
+            parsed.env_file = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--env-file=") {
+            parsed.env_file = Some(PathBuf::from(value));
+        } else if arg == "--check-config" {
+            parsed.check_config = true;
+        } else {
+            return Err(format!(
+                "Unknown argument '{arg}'. Usage: rust-ws [--env-file <path>] [--check-config]"
+            ));
+        }
+    }
+    Ok(parsed)
+}
+
+/// Which variables a loaded file provided
+#[derive(Debug)]
+pub struct EnvFile {
+    pub path: PathBuf,
+    /// Set from the file
+    pub loaded: Vec<String>,
+    /// In the file, but already set in the environment
+    pub overridden: Vec<String>,
+}
+
+impl EnvFile {
+    /// Log the file and variable names. Values are never logged, they include API keys.
+    pub fn log(&self) {
+        info!(
+            path = %self.path.display(),
+            loaded = ?self.loaded,
+            overridden = ?self.overridden,
+            "Loaded env file"
+        );
+    }
+}
+
+/// Load `explicit`, or `.env` if it exists. An explicit file that can't be read is an
+/// error; a missing default file just means there is none.
+pub fn load(explicit: Option<&Path>) -> Result<Option<EnvFile>, String> {
+    let path = match explicit {
+        Some(path) => path.to_path_buf(),
+        None if Path::new(DEFAULT_ENV_FILE).is_file() => PathBuf::from(DEFAULT_ENV_FILE),
+        None => return Ok(None),
+    };
+    let entries = dotenvy::from_path_iter(&path)
+        .map_err(|e| format!("Cannot read env file {}: {e}", path.display()))?;
+
+    let mut env_file = EnvFile {
+        path,
+        loaded: Vec::new(),
+        overridden: Vec::new(),
+    };
+    for entry in entries {
+        let (key, value) =
+            entry.map_err(|e| format!("Invalid env file {}: {e}", env_file.path.display()))?;
+        if std::env::var_os(&key).is_some() {
+            env_file.overridden.push(key);
+        } else {
+            std::env::set_var(&key, value);
+            env_file.loaded.push(key);
+        }
+    }
+    Ok(Some(env_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn env_file_arg(list: &[&str]) -> Result<Option<PathBuf>, String> {
+        parse_args(args(list)).map(|args| args.env_file)
+    }
+
+    #[test]
+    fn env_file_comes_from_the_arguments() {
+        assert_eq!(env_file_arg(&[]), Ok(None));
+        assert_eq!(
+            env_file_arg(&["--env-file", "/etc/chat.env"]),
+            Ok(Some(PathBuf::from("/etc/chat.env")))
+        );
+        assert_eq!(
+            env_file_arg(&["--env-file=prod.env"]),
+            Ok(Some(PathBuf::from("prod.env")))
+        );
+        assert!(env_file_arg(&["--env-file"]).is_err());
+        assert!(env_file_arg(&["--port", "80"]).is_err());
+    }
+
+    #[test]
+    fn check_config_goes_with_any_env_file() {
+        assert!(!parse_args(args(&[])).unwrap().check_config);
+        assert_eq!(
+            parse_args(args(&["--check-config", "--env-file", "prod.env"])),
+            Ok(Args {
+                env_file: Some(PathBuf::from("prod.env")),
+                check_config: true,
+            })
+        );
+    }
+
+    #[test]
+    fn environment_wins_over_the_file() {
+        let path = std::env::temp_dir().join(format!("rust-ws-{}.env", uuid::Uuid::new_v4()));
+        // Cargo sets CARGO_PKG_NAME for the test binary, so the test doesn't have to
+        std::fs::write(&path, "ENV_FILE_TEST_FROM_FILE=file\nCARGO_PKG_NAME=file\n").unwrap();
+
+        let env_file = load(Some(&path)).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(env_file.loaded, ["ENV_FILE_TEST_FROM_FILE"]);
+        assert_eq!(env_file.overridden, ["CARGO_PKG_NAME"]);
+        assert_eq!(std::env::var("ENV_FILE_TEST_FROM_FILE").unwrap(), "file");
+        assert_eq!(
+            std::env::var("CARGO_PKG_NAME").unwrap(),
+            env!("CARGO_PKG_NAME")
+        );
+    }
+
+    #[test]
+    fn explicit_file_must_exist() {
+        assert!(load(Some(Path::new("/nonexistent/rust-ws.env"))).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Event stream for observers such as moderation dashboards.
+//!
+//! Handlers report what happens (joins, chat, renames, ...) through [`AppState::emit`].
+//! An admin that sends `subscribe` stops receiving the chat and gets these events as
+//! `event` messages instead.
+
+use std::sync::atomic::Ordering;
+
+use axum::extract::ws::Message;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc::{self, error::TrySendError},
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::protocol::Encoding;
+use crate::state::AppState;
+
+pub use chat_protocol::EventKind;
+
+/// Events an observer may lag behind before it misses some
+pub const EVENT_BUFFER: usize = 1024;
+
+/// A serialized `event` message, sent on [`AppState::events`]; in both encodings, as
+/// observers may use either
+#[derive(Clone)]
+pub struct ServerEvent {
+    pub kind: EventKind,
+    pub json: Option<Message>,
+    pub msgpack: Option<Message>,
+}
+
+impl ServerEvent {
+    fn frame(&self, encoding: Encoding) -> Option<Message> {
+        match encoding {
+            Encoding::Json => self.json.clone(),
+            Encoding::MsgPack => self.msgpack.clone(),
+        }
+    }
+}
+
+/// Make client `id` an observer of `kinds`, replacing an earlier subscription. With no
+/// kinds it goes back to the normal chat stream.
+pub fn observe(state: &AppState, id: Uuid, kinds: Vec<EventKind>) {
+    let Some(client) = state.clients.get(&id).map(|entry| entry.value().clone()) else {
+        return;
+    };
+    let mut observer = client.observer.lock().unwrap();
+    if let Some(previous) = observer.take() {
+        previous.abort();
+    }
+    client.observing.store(!kinds.is_empty(), Ordering::Relaxed);
+    if !kinds.is_empty() {
+        let task = tokio::spawn(forward_events(
+            id,
+            state.events.subscribe(),
+            kinds,
+            client.encoding,
+            client.tx.clone(),
+        ));
+        *observer = Some(task.abort_handle());
+    }
+}
+
+/// Copy the events of the given kinds into the client's outbound queue. Like other
+/// direct messages, an event that doesn't fit in a full queue is dropped.
+async fn forward_events(
+    id: Uuid,
+    mut events: broadcast::Receiver<ServerEvent>,
+    kinds: Vec<EventKind>,
+    encoding: Encoding,
+    tx: mpsc::Sender<Message>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(id = %id, missed, "Observer fell behind, events dropped");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if !kinds.contains(&event.kind) {
+            continue;
+        }
+        let Some(frame) = event.frame(encoding) else {
+            continue;
+        };
+        if let Err(TrySendError::Closed(_)) = tx.try_send(frame) {
+            break;
+        }
+    }
+    debug!(id = %id, "Event forwarding finished");
+}
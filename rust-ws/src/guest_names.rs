@@ -0,0 +1,113 @@
+//! Names for clients that haven't picked one yet.
+//!
+//! By default a guest is `guest-` plus the start of its id. With
+//! `GUEST_NAME_STYLE=friendly` it gets a name like `BlueFox42` instead; those can
+//! collide, so the caller says which names are taken and another one is drawn.
+
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Draws before giving up on a free friendly name and falling back to a plain one
+const MAX_ATTEMPTS: usize = 10;
+
+const ADJECTIVES: &[&str] = &[
+    "Blue", "Brave", "Calm", "Clever", "Cosy", "Eager", "Fuzzy", "Gentle", "Golden", "Happy",
+    "Jolly", "Lucky", "Mellow", "Nimble", "Proud", "Quick", "Quiet", "Red", "Shy", "Silver",
+    "Sunny", "Swift", "Witty", "Zesty",
+];
+
+const ANIMALS: &[&str] = &[
+    "Badger", "Bear", "Beaver", "Crow", "Deer", "Dolphin", "Eagle", "Ferret", "Fox", "Frog",
+    "Gecko", "Hare", "Heron", "Koala", "Lynx", "Moose", "Otter", "Owl", "Panda", "Puffin", "Robin",
+    "Seal", "Tiger", "Wolf",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuestNameStyle {
+    /// `guest-a1b2c3`
+    Plain,
+    /// `BlueFox42`
+    Friendly,
+}
+
+impl GuestNameStyle {
+    pub fn from_env() -> Self {
+        let style = match std::env::var("GUEST_NAME_STYLE") {
+            Ok(v) if v.eq_ignore_ascii_case("friendly") => GuestNameStyle::Friendly,
+            Ok(v) if v.eq_ignore_ascii_case("plain") => GuestNameStyle::Plain,
+            Ok(v) => {
+                warn!(guest_name_style = %v, "Unknown GUEST_NAME_STYLE, falling back to plain");
+                GuestNameStyle::Plain
+            }
+            Err(_) => GuestNameStyle::Plain,
+        };
+        info!(?style, "Guest names");
+        style
+    }
+}
+
+/// Name for the new client `id`. `taken` tells whether another client already has a name.
+pub fn guest_name(id: Uuid, style: GuestNameStyle, taken: impl Fn(&str) -> bool) -> String {
+    if style == GuestNameStyle::Friendly {
+        // The id is random already; later attempts draw fresh randomness
+        let seeds = std::iter::once(id.as_u128())
+            .chain(std::iter::repeat_with(|| Uuid::new_v4().as_u128()))
+            .take(MAX_ATTEMPTS);
+        for seed in seeds {
+            let name = friendly_name(seed);
+            if !taken(&name) {
+                return name;
+            }
+        }
+    }
+    format!("guest-{}", &id.to_string()[..6])
+}
+
+/// Adjective, animal and a number below 100, all picked from `seed`
+fn friendly_name(seed: u128) -> String {
+    let adjective = ADJECTIVES[(seed % ADJECTIVES.len() as u128) as usize];
+    let seed = seed / ADJECTIVES.len() as u128;
+    let animal = ANIMALS[(seed % ANIMALS.len() as u128) as usize];
+    let number = (seed / ANIMALS.len() as u128) % 100;
+    format!("{adjective}{animal}{number}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::{validate_name, NameChars};
+
+    #[test]
+    fn every_friendly_name_is_a_valid_strict_name() {
+        for adjective in ADJECTIVES {
+            for animal in ANIMALS {
+                for number in [0, 99] {
+                    let name = format!("{adjective}{animal}{number}");
+                    assert!((2..=32).contains(&name.chars().count()), "{name}");
+                    assert_eq!(validate_name(&name, NameChars::Strict), Ok(name));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn friendly_name_is_drawn_again_when_taken() {
+        let id = Uuid::new_v4();
+        let first = guest_name(id, GuestNameStyle::Friendly, |_| false);
+        assert_eq!(first, friendly_name(id.as_u128()));
+
+        let second = guest_name(id, GuestNameStyle::Friendly, |name| name == first);
+        assert_ne!(second, first);
+        assert!(!second.starts_with("guest-"), "{second}");
+    }
+
+    #[test]
+    fn falls_back_to_a_plain_name() {
+        let id = Uuid::new_v4();
+        let expected = format!("guest-{}", &id.to_string()[..6]);
+
+        assert_eq!(guest_name(id, GuestNameStyle::Plain, |_| false), expected);
+        // When every draw collides
+        assert_eq!(guest_name(id, GuestNameStyle::Friendly, |_| true), expected);
+    }
+}
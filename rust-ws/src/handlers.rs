@@ -1,21 +1,34 @@
-use std::net::SocketAddr;
+use std::{
+    future::Future, net::SocketAddr, panic::AssertUnwindSafe, sync::atomic::Ordering,
+    time::Duration,
+};
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        ConnectInfo, State,
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Query, State,
+    },
+    http::{
+        header::{SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL, USER_AGENT},
+        HeaderMap, StatusCode,
     },
-    http::HeaderMap,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
-use futures::{stream::StreamExt, SinkExt};
-use tracing::{debug, error, info};
+use futures::{stream::StreamExt, FutureExt, SinkExt};
+use serde::Deserialize;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    protocol::{Incoming, Outgoing, UserInfo},
-    state::{AppState, Client},
+    ai::{AiError, AuditEntry},
+    events::{self, EventKind},
+    history::MAX_PURGE,
+    messages::{self, Msg},
+    protocol::{Encoding, Frames, Incoming, Outgoing, SUBPROTOCOLS},
+    state::{AiTask, AppState, Client, UserQuery, UserSort, DEFAULT_USER_PAGE, MAX_USER_PAGE},
     utils::now_ms,
+    validation,
 };
 
 fn trust_proxy_headers_configured() -> bool {
@@ -24,6 +37,25 @@ fn trust_proxy_headers_configured() -> bool {
         .unwrap_or(false)
 }
 
+/// Message of the day, sent to each client on join. `MOTD_FILE` wins over `MOTD` and is
+/// re-read on every connect, so it can be changed without a restart.
+fn motd() -> Option<String> {
+    let from_file = std::env::var("MOTD_FILE")
+        .ok()
+        .and_then(|path| match std::fs::read_to_string(&path) {
+            Ok(text) => Some(text),
+            Err(err) => {
+                error!(path = %path, ?err, "Could not read MOTD_FILE");
+                None
+            }
+        });
+    // Allow `\n` in the variable for multi-line messages
+    let motd = from_file.or_else(|| std::env::var("MOTD").ok().map(|m| m.replace("\\n", "\n")))?;
+
+    let motd = motd.trim_end();
+    (!motd.trim().is_empty()).then(|| motd.to_string())
+}
+
 fn should_trust_proxy_headers(addr: SocketAddr) -> bool {
     // Bun reverse proxy on the same host appears as loopback; trust its forwarded headers.
     // Non-loopback peers must opt in explicitly via TRUST_PROXY_HEADERS=true.
@@ -50,26 +82,131 @@ fn extract_client_ip(headers: &HeaderMap, addr: SocketAddr, trust_proxy: bool) -
         .unwrap_or_else(|| addr.ip().to_string())
 }
 
+/// Longest `User-Agent` kept for a connection; it is only for telling clients apart
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// The `User-Agent` header, if present and readable, cut to [`MAX_USER_AGENT_LENGTH`]
+fn extract_user_agent(headers: &HeaderMap) -> Option<String> {
+    let user_agent = headers.get(USER_AGENT)?.to_str().ok()?.trim();
+    (!user_agent.is_empty()).then(|| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect())
+}
+
+/// Query string of the upgrade request
+#[derive(Deserialize)]
+pub struct ConnectParams {
+    /// `spectator`: receive the chat but don't post
+    mode: Option<String>,
+}
+
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     headers: HeaderMap,
+    Query(params): Query<ConnectParams>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+) -> Response {
     let client_ip = extract_client_ip(&headers, addr, should_trust_proxy_headers(addr));
+    let user_agent = extract_user_agent(&headers);
+    let spectator = match params.mode.as_deref() {
+        None => false,
+        Some(mode) if mode.eq_ignore_ascii_case("spectator") => true,
+        Some(mode) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown mode '{mode}', expected spectator"),
+            )
+                .into_response();
+        }
+    };
+    // Counted for status only: the WebSocket stack can't negotiate compression
+    let deflate_offered = headers
+        .get_all(SEC_WEBSOCKET_EXTENSIONS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("permessage-deflate"));
 
-    ws.on_upgrade(move |socket| handle_socket(state, socket, client_ip))
+    // No subprotocol requested: plain connection on the current protocol.
+    // Requested but none supported: refuse instead of guessing.
+    let ws = ws.protocols(SUBPROTOCOLS.iter().copied());
+    if headers.contains_key(SEC_WEBSOCKET_PROTOCOL) && ws.selected_protocol().is_none() {
+        debug!(ip = %client_ip, requested = ?headers.get(SEC_WEBSOCKET_PROTOCOL), "Unsupported subprotocol");
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unsupported WebSocket subprotocol, expected one of: {}",
+                SUBPROTOCOLS.join(", ")
+            ),
+        )
+            .into_response();
+    }
+
+    ws.on_upgrade(move |socket| {
+        handle_socket(
+            state,
+            socket,
+            client_ip,
+            user_agent,
+            deflate_offered,
+            spectator,
+        )
+    })
 }
 
 /// Buffer size for outbound messages per client.
-/// If a client can't keep up, messages will be dropped to prevent memory exhaustion.
+/// A client that fills it during a broadcast is handled per `SLOW_CLIENT_DISCONNECT`.
 const CLIENT_CHANNEL_BUFFER: usize = 256;
 
-async fn handle_socket(state: AppState, socket: WebSocket, client_ip: String) {
+/// Tell a client why it can't join and close the connection
+async fn reject<S>(sender: &mut S, encoding: Encoding, message: String)
+where
+    S: futures::Sink<Message> + Unpin,
+{
+    if let Some(frame) = encoding.encode(&Outgoing::Error {
+        message: message.clone(),
+        prompt: None,
+    }) {
+        let _ = sender.send(frame).await;
+    }
+    let _ = sender
+        .send(Message::Close(Some(CloseFrame {
+            code: close_code::AGAIN,
+            reason: message.into(),
+        })))
+        .await;
+}
+
+async fn handle_socket(
+    state: AppState,
+    socket: WebSocket,
+    client_ip: String,
+    user_agent: Option<String>,
+    deflate_offered: bool,
+    spectator: bool,
+) {
     let id = Uuid::new_v4();
-    let name = format!("guest-{}", &id.to_string()[..6]);
+    let encoding = Encoding::for_protocol(socket.protocol().and_then(|p| p.to_str().ok()));
     let (mut sender, mut receiver) = socket.split();
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(CLIENT_CHANNEL_BUFFER);
+    let (tx, mut rx) = mpsc::channel::<Message>(CLIENT_CHANNEL_BUFFER);
+
+    // Named by `register_client`
+    let mut client = Client::new(String::new(), client_ip.clone(), user_agent, tx);
+    client.deflate_offered = deflate_offered;
+    client.is_spectator = spectator;
+    client.encoding = encoding;
+    // Spectators that aren't counted come and go without anyone noticing
+    let visible = state.is_visible(&client);
+
+    if !state.register_client(id, &mut client) {
+        warn!(
+            ip = %client_ip,
+            max = ?state.connection_limit.max_connections,
+            "Server full, connection refused"
+        );
+        reject(&mut sender, encoding, messages::text(Msg::ServerFull, &[])).await;
+        return;
+    }
+    let name = client.name.clone();
+    state.increment_connections();
 
     // Send loop
     let send_task = tokio::spawn(async move {
@@ -82,30 +219,68 @@ async fn handle_socket(state: AppState, socket: WebSocket, client_ip: String) {
         debug!("WS send loop finished");
     });
 
-    let client = Client::new(name.clone(), client_ip.clone(), tx);
+    state.emit(EventKind::Join, id, &name, None);
 
-    // Register client and update stats
-    state.clients.insert(id, client.clone());
-    state.increment_connections();
-
-    info!(id = %id, name = %name, ip = %client_ip, "Client connected");
+    info!(
+        id = %id,
+        name = %name,
+        ip = %client_ip,
+        user_agent = client.user_agent.as_deref().unwrap_or("-"),
+        spectator,
+        "Client connected"
+    );
 
     // Send welcome messages
     client.send(&Outgoing::AckName {
         name: name.clone(),
+        resume_token: Some(client.resume_token.clone()),
+        seq: Some(state.history.seq()),
         at: now_ms(),
     });
-    broadcast(
-        &state,
-        &Outgoing::System {
-            text: format!("{name} heeft de chat betreden."),
-            at: now_ms(),
-        },
-        Some(id),
-    );
+    if let Some(motd) = motd() {
+        // One system message per line, so clients don't have to handle embedded newlines
+        for line in motd.lines() {
+            client.send(&Outgoing::System {
+                text: line.to_string(),
+                at: now_ms(),
+            });
+        }
+    }
+    if visible {
+        if state.join_leave.announce(state.user_count()) {
+            broadcast(
+                &state,
+                &Outgoing::System {
+                    text: messages::text(Msg::Joined, &[("name", &name)]),
+                    at: now_ms(),
+                },
+                Some(id),
+            );
+        }
+        broadcast(
+            &state,
+            &Outgoing::Presence {
+                user_count: state.user_count(),
+                joined: Some(name.clone()),
+                left: None,
+                at: now_ms(),
+            },
+            None,
+        );
+    }
 
-    // Receive loop
-    while let Some(msg) = receiver.next().await {
+    // Receive loop; also ends when a broadcast finds this client too slow
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.next() => msg,
+            _ = client.disconnect.notified() => {
+                info!(id = %id, "Dropping slow client");
+                break;
+            }
+        };
+        let Some(msg) = msg else {
+            break;
+        };
         debug!(id = %id, raw = ?msg, "Ontvangen WS bericht");
         let msg = match msg {
             Ok(m) => m,
@@ -114,19 +289,41 @@ async fn handle_socket(state: AppState, socket: WebSocket, client_ip: String) {
                 break;
             }
         };
-        match msg {
-            Message::Text(text) => {
-                if let Err(err) = process_message(&state, id, text.to_string()).await {
-                    if let Some(entry) = state.clients.get(&id) {
-                        entry.value().send(&Outgoing::Error { message: err });
-                    }
-                }
+        // JSON text frames work on every connection, binary frames only with MessagePack
+        let incoming = match msg {
+            Message::Text(text) => serde_json::from_str::<Incoming>(&text)
+                .map_err(|_| messages::text(Msg::InvalidJson, &[])),
+            Message::Binary(bytes) if encoding == Encoding::MsgPack => {
+                rmp_serde::from_slice::<Incoming>(&bytes)
+                    .map_err(|_| messages::text(Msg::InvalidMsgPack, &[]))
             }
+            Message::Binary(_) => continue,
             Message::Close(_) => break,
             Message::Ping(p) => {
+                client.touch(state.clock.now());
                 let _ = client.tx.try_send(Message::Pong(p));
+                continue;
+            }
+            // The answer to a WebSocket-level ping: no payload to handle, but proof
+            // that the connection is still alive
+            Message::Pong(_) => {
+                client.touch(state.clock.now());
+                continue;
+            }
+        };
+        client.touch(state.clock.now());
+        let result = match incoming {
+            Ok(incoming) => isolate_panic(id, process_message(&state, id, incoming)).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            if let Some(entry) = state.clients.get(&id) {
+                state.emit(EventKind::Error, id, &entry.name, Some(&err));
+                entry.value().send(&Outgoing::Error {
+                    message: err,
+                    prompt: None,
+                });
             }
-            _ => {}
         }
     }
 
@@ -137,208 +334,899 @@ async fn handle_socket(state: AppState, socket: WebSocket, client_ip: String) {
         .map(|entry| entry.value().name.clone())
         .unwrap_or_else(|| name.clone());
 
+    state.cancel_ai_task(id);
+    events::observe(&state, id, Vec::new());
     state.clients.remove(&id);
+    state.emit(EventKind::Leave, id, &final_name, None);
+    state
+        .resume
+        .park(&client.resume_token, final_name.clone(), state.clock.now());
 
-    broadcast(
-        &state,
-        &Outgoing::System {
-            text: format!("{final_name} heeft de chat verlaten."),
-            at: now_ms(),
-        },
-        Some(id),
-    );
+    let session = client.connected_at.elapsed().unwrap_or_default();
+    let avg_session_secs = state.record_session(session);
+
+    if visible {
+        if state.join_leave.announce(state.user_count()) {
+            broadcast(
+                &state,
+                &Outgoing::System {
+                    text: messages::text(Msg::Left, &[("name", &final_name)]),
+                    at: now_ms(),
+                },
+                Some(id),
+            );
+        }
+        broadcast(
+            &state,
+            &Outgoing::Presence {
+                user_count: state.user_count(),
+                joined: None,
+                left: Some(final_name.clone()),
+                at: now_ms(),
+            },
+            Some(id),
+        );
+    }
 
     send_task.abort();
-    info!(id = %id, name = %final_name, ip = %client_ip, "Client disconnected");
+    info!(
+        id = %id,
+        name = %final_name,
+        ip = %client_ip,
+        session_secs = session.as_secs(),
+        idle_secs = client.idle_for(state.clock.now()).as_secs(),
+        avg_session_secs = (avg_session_secs * 10.0).round() / 10.0,
+        "Client disconnected"
+    );
 }
 
-async fn process_message(state: &AppState, id: Uuid, text: String) -> Result<(), String> {
-    let incoming: Incoming =
-        serde_json::from_str(&text).map_err(|_| "Bericht moet geldig JSON zijn.".to_string())?;
+/// Run the handling of one message so that a panic in it (a bug) fails only that
+/// message: it is logged and the client gets an error, but the connection stays up.
+async fn isolate_panic<F>(id: Uuid, handling: F) -> Result<(), String>
+where
+    F: Future<Output = Result<(), String>>,
+{
+    match AssertUnwindSafe(handling).catch_unwind().await {
+        Ok(result) => result,
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown");
+            error!(id = %id, reason, "Panic while processing message");
+            Err(messages::text(Msg::InternalError, &[]))
+        }
+    }
+}
 
+async fn process_message(state: &AppState, id: Uuid, incoming: Incoming) -> Result<(), String> {
     match incoming {
-        Incoming::Chat { text } => {
-            let trimmed = text.trim();
-            if trimmed.is_empty() {
-                return Err("Message cannot be empty.".into());
-            }
-            if trimmed.len() > 500 {
-                return Err("Message is too long (max 500 characters).".into());
+        Incoming::Chat { text, reply_to } => {
+            require_participant(state, id)?;
+            let text = validation::validate_chat_text(&text, validation::config())?;
+            if let Some(reply_to) = reply_to.filter(|&id| !state.history.contains(id)) {
+                return Err(messages::text(Msg::ReplyNotFound, &[("id", &reply_to)]));
             }
 
             // Check rate limit
-            let (name, ip) = {
+            let (name, ip, bot) = {
                 let entry = state
                     .clients
                     .get(&id)
-                    .ok_or_else(|| "Unknown user".to_string())?;
-
-                // Check rate limit before allowing the message
-                if let Err(wait_secs) = entry.value().check_rate_limit(&state.rate_limit) {
-                    return Err(format!(
-                        "Rate limit exceeded. Please wait {} seconds.",
-                        wait_secs
-                    ));
+                    .ok_or_else(|| messages::text(Msg::UnknownUser, &[]))?;
+                if !admit(state, id, entry.value(), Some(&text))? {
+                    return Ok(());
                 }
 
-                (entry.value().name.clone(), entry.value().ip.clone())
+                entry.value().increment_messages(state.clock.now());
+                let client = entry.value();
+                (client.name.clone(), client.ip.clone(), client.is_bot)
             };
 
             state.increment_messages();
+            let at = now_ms();
+            let msg_id = state.history.push(id, &name, &text, reply_to, at);
+            state.emit(EventKind::Chat, id, &name, Some(&text));
+            let chat = Outgoing::Chat {
+                id: Some(msg_id),
+                from: name.clone(),
+                text,
+                reply_to,
+                at,
+            };
+            match &state.chat_batch {
+                Some(batcher) => batcher.push(state, chat),
+                None => broadcast(state, &chat, None),
+            }
+            debug!(from = %name, id = %id, ip = %ip, bot, msg_id, "Bericht verzonden");
+        }
+        Incoming::Edit { id: msg_id, text } => {
+            require_participant(state, id)?;
+            let text = validation::validate_chat_text(&text, validation::config())?;
+            let name = {
+                let entry = state
+                    .clients
+                    .get(&id)
+                    .ok_or_else(|| messages::text(Msg::UnknownUser, &[]))?;
+                // Only an edit that may go through counts as a post
+                let admitted = state.history.edit(msg_id, id, &text, now_ms(), || {
+                    admit(state, id, entry.value(), None)
+                })?;
+                if !admitted {
+                    return Ok(());
+                }
+                entry.value().name.clone()
+            };
+            state.emit(
+                EventKind::Edit,
+                id,
+                &name,
+                Some(&format!("#{msg_id} {text}")),
+            );
+            flush_chat_batch(state);
             broadcast(
                 state,
-                &Outgoing::Chat {
-                    from: name.clone(),
-                    text: trimmed.to_string(),
+                &Outgoing::Edit {
+                    id: msg_id,
+                    text,
                     at: now_ms(),
                 },
                 None,
             );
-            debug!(from = %name, id = %id, ip = %ip, "Bericht verzonden");
+            debug!(id = %id, msg_id, "Bericht bewerkt");
         }
-        Incoming::SetName { name } => {
-            let trimmed = name.trim();
-            if trimmed.len() < 2 || trimmed.len() > 32 {
-                return Err("Naam moet tussen 2 en 32 tekens zijn.".into());
-            }
-            if !trimmed
-                .chars()
-                .all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
-            {
-                return Err("Naam mag alleen letters, cijfers, spaties, - en _ bevatten.".into());
-            }
-
-            let rename_info = {
-                if let Some(mut entry) = state.clients.get_mut(&id) {
-                    let old = entry.value().name.clone();
-                    entry.name = trimmed.to_string();
-                    entry.send(&Outgoing::AckName {
-                        name: entry.name.clone(),
-                        at: now_ms(),
-                    });
-                    Some((old, entry.name.clone(), entry.ip.clone()))
-                } else {
-                    None
+        Incoming::Delete { id: msg_id } => {
+            require_participant(state, id)?;
+            let name = {
+                let entry = state
+                    .clients
+                    .get(&id)
+                    .ok_or_else(|| messages::text(Msg::UnknownUser, &[]))?;
+                let admitted = state
+                    .history
+                    .delete(msg_id, id, || admit(state, id, entry.value(), None))?;
+                if !admitted {
+                    return Ok(());
+                }
+                entry.value().name.clone()
+            };
+            state.emit(EventKind::Delete, id, &name, Some(&format!("#{msg_id}")));
+            flush_chat_batch(state);
+            broadcast(
+                state,
+                &Outgoing::Delete {
+                    id: msg_id,
+                    at: now_ms(),
+                },
+                None,
+            );
+            debug!(id = %id, msg_id, "Bericht verwijderd");
+        }
+        Incoming::FileOffer {
+            to,
+            name: file_name,
+            size,
+            url,
+        } => {
+            require_participant(state, id)?;
+            let (file_name, url) = validation::validate_file_offer(&file_name, size, &url)?;
+            let Some(target) = state
+                .clients
+                .iter()
+                .find(|entry| entry.value().name == to)
+                .map(|entry| *entry.key())
+            else {
+                return Err(messages::text(Msg::UserNotFound, &[("name", &to)]));
+            };
+            let from = {
+                let entry = state
+                    .clients
+                    .get(&id)
+                    .ok_or_else(|| messages::text(Msg::UnknownUser, &[]))?;
+                if target == id {
+                    return Err(messages::text(Msg::FileOfferSelf, &[]));
                 }
+                // Offers count as chat messages, so they can't be used to get around the limit
+                if !admit(state, id, entry.value(), None)? {
+                    return Ok(());
+                }
+                entry.value().name.clone()
+            };
+
+            let offer = Outgoing::FileOffer {
+                from: from.clone(),
+                to,
+                name: file_name,
+                size,
+                url,
+                at: now_ms(),
             };
+            // The sender gets it too, as confirmation that it was delivered
+            for recipient in [target, id] {
+                if let Some(entry) = state.clients.get(&recipient) {
+                    entry.value().send(&offer);
+                }
+            }
+            debug!(from = %from, id = %id, size, "File offer relayed");
+        }
+        Incoming::SetName { name } => {
+            let new_name = validation::validate_name(&name, validation::config().name_chars)?;
 
-            if let Some((old, new_name, ip)) = rename_info {
+            let Some(old) = state.rename_client(id, &new_name)? else {
+                return Ok(());
+            };
+            let Some(visible) = state.clients.get(&id).map(|entry| {
+                entry.send(&Outgoing::AckName {
+                    name: new_name.clone(),
+                    resume_token: Some(entry.resume_token.clone()),
+                    seq: Some(state.history.seq()),
+                    at: now_ms(),
+                });
+                debug!(old = %old, new = %new_name, id = %id, ip = %entry.ip, "Gebruikersnaam gewijzigd");
+                state.is_visible(&entry)
+            }) else {
+                return Ok(());
+            };
+            state.emit(EventKind::Rename, id, &old, Some(&new_name));
+            // Hidden spectators rename as quietly as they came in
+            if visible {
+                broadcast(
+                    state,
+                    &Outgoing::System {
+                        text: messages::text(Msg::Renamed, &[("old", &old), ("new", &new_name)]),
+                        at: now_ms(),
+                    },
+                    Some(id),
+                );
+            }
+        }
+        Incoming::Resume { token } => {
+            let Some(name) = state.resume.take(&token, state.clock.now()) else {
+                return Err(messages::text(Msg::ResumeInvalid, &[]));
+            };
+            // Someone may have picked the name while its owner was away
+            let Some(old) = state.rename_client(id, &name)? else {
+                return Ok(());
+            };
+            let Some(visible) = state.clients.get(&id).map(|entry| {
+                entry.send(&Outgoing::AckName {
+                    name: name.clone(),
+                    resume_token: Some(entry.resume_token.clone()),
+                    seq: Some(state.history.seq()),
+                    at: now_ms(),
+                });
+                state.is_visible(&entry)
+            }) else {
+                return Ok(());
+            };
+            state.emit(EventKind::Rename, id, &old, Some(&name));
+            if visible {
                 broadcast(
                     state,
                     &Outgoing::System {
-                        text: format!("{old} heet nu {new_name}."),
+                        text: messages::text(Msg::Resumed, &[("name", &name), ("old", &old)]),
                         at: now_ms(),
                     },
                     Some(id),
                 );
-                debug!(old = %old, new = %new_name, id = %id, ip = %ip, "Gebruikersnaam gewijzigd");
+            }
+            info!(id = %id, name = %name, old = %old, "Session resumed");
+        }
+        Incoming::Backfill { since } => {
+            let backfill = state.history.since(since);
+            let messages = backfill
+                .messages
+                .into_iter()
+                .map(|m| Outgoing::Chat {
+                    id: Some(m.id),
+                    from: m.from,
+                    text: m.text,
+                    reply_to: m.reply_to,
+                    at: m.at,
+                })
+                .collect::<Vec<_>>();
+            debug!(id = %id, since, count = messages.len(), gap = backfill.gap, "Backfill");
+            if let Some(entry) = state.clients.get(&id) {
+                entry.value().send(&Outgoing::Backfill {
+                    messages,
+                    seq: backfill.seq,
+                    gap: backfill.gap,
+                });
             }
         }
         Incoming::Status => {
-            let uptime_secs = state.uptime_seconds();
-            let count = state.user_count();
-            let messages = state.messages_sent();
-            let msgs_per_sec = if uptime_secs > 0 {
-                messages as f64 / uptime_secs as f64
-            } else {
-                0.0
+            let status = state.build_status();
+            if let Some(entry) = state.clients.get(&id) {
+                entry.value().send(&status);
+            }
+        }
+        Incoming::ListUsers {
+            sort,
+            filter,
+            offset,
+            limit,
+        } => {
+            let sort = match sort.as_deref() {
+                None => None,
+                Some(value) => Some(
+                    UserSort::parse(value)
+                        .ok_or_else(|| messages::text(Msg::UnknownUserSort, &[("sort", &value)]))?,
+                ),
             };
-            let memory_mb = state.memory_mb().await;
-            let cpu_cores = std::thread::available_parallelism()
-                .map(|p| p.get())
-                .unwrap_or(1);
-
+            let query = UserQuery {
+                sort,
+                filter: filter
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty()),
+                offset: offset.unwrap_or(0),
+                limit: limit.unwrap_or(DEFAULT_USER_PAGE).min(MAX_USER_PAGE),
+            };
+            let page = state.list_users(&query);
+            if let Some(entry) = state.clients.get(&id) {
+                entry.value().send(&Outgoing::ListUsers {
+                    users: page.users,
+                    total: Some(page.total),
+                    offset: Some(query.offset),
+                });
+            }
+        }
+        Incoming::WhoAmI => {
+            if let Some(entry) = state.clients.get(&id) {
+                let client = entry.value();
+                client.send(&Outgoing::WhoAmI {
+                    id: id.to_string(),
+                    name: client.name.clone(),
+                    ip: client.ip.clone(),
+                    admin: client.is_admin,
+                    at: now_ms(),
+                });
+            }
+        }
+        Incoming::Version => {
+            if let Some(entry) = state.clients.get(&id) {
+                entry.send(&Outgoing::Version {
+                    crate_version: env!("CARGO_PKG_VERSION").to_string(),
+                    rust_version: env!("RUSTC_VERSION").to_string(),
+                    git_commit: env!("GIT_COMMIT").to_string(),
+                    built_at: env!("BUILT_AT").parse().unwrap_or(0),
+                    at: now_ms(),
+                });
+            }
+        }
+        Incoming::Auth { token } => {
+            if state.admin.token.is_none() && state.admin.bot_token.is_none() {
+                return Err(messages::text(Msg::AdminNotConfigured, &[]));
+            }
+            let admin = state.admin.verify(&token);
+            if !admin && !state.admin.verify_bot(&token) {
+                warn!(id = %id, "Invalid admin token");
+                return Err(messages::text(Msg::AdminInvalidToken, &[]));
+            }
+            if let Some(mut entry) = state.clients.get_mut(&id) {
+                let granted = if admin {
+                    entry.is_admin = true;
+                    info!(id = %id, name = %entry.name, ip = %entry.ip, "Admin authenticated");
+                    Msg::AdminGranted
+                } else {
+                    entry.is_bot = true;
+                    info!(id = %id, name = %entry.name, ip = %entry.ip, "Bot authenticated");
+                    Msg::BotGranted
+                };
+                entry.send(&Outgoing::System {
+                    text: messages::text(granted, &[]),
+                    at: now_ms(),
+                });
+            }
+        }
+        Incoming::Announce { text } => {
+            require_admin(state, id)?;
+            let text = validation::validate_chat_text(&text, validation::config())?;
             if let Some(entry) = state.clients.get(&id) {
-                entry.value().send(&Outgoing::Status {
-                    version: env!("CARGO_PKG_VERSION"),
-                    rust_version: env!("RUSTC_VERSION"),
-                    os: std::env::consts::OS,
-                    cpu_cores,
-                    uptime_seconds: uptime_secs,
-                    user_count: count,
-                    peak_users: state.peak_users() as usize,
-                    connections_total: state.connections_total(),
-                    messages_sent: messages,
-                    messages_per_second: (msgs_per_sec * 100.0).round() / 100.0,
-                    memory_mb: (memory_mb * 100.0).round() / 100.0,
-                    ai_enabled: state.ai.is_enabled(),
-                    ai_model: if state.ai.is_enabled() {
-                        Some(state.ai.model().to_string())
-                    } else {
-                        None
+                info!(id = %id, name = %entry.name, text_len = text.len(), "Admin announcement");
+            }
+            broadcast(state, &Outgoing::Announcement { text, at: now_ms() }, None);
+        }
+        Incoming::Purge { name, last_n } => {
+            require_admin(state, id)?;
+            if !(1..=MAX_PURGE).contains(&last_n) {
+                return Err(messages::text(Msg::PurgeCount, &[("max", &MAX_PURGE)]));
+            }
+            // Messages sent under the name, and those of whoever has it now under an
+            // earlier name; a spammer who already left is still found by name
+            let online = state
+                .clients
+                .iter()
+                .find(|entry| entry.value().name == name)
+                .map(|entry| *entry.key());
+            let purged = state
+                .history
+                .purge(last_n, |m| m.from == name || Some(m.author) == online);
+            flush_chat_batch(state);
+            for &msg_id in &purged {
+                broadcast(
+                    state,
+                    &Outgoing::Delete {
+                        id: msg_id,
+                        at: now_ms(),
                     },
+                    None,
+                );
+            }
+            if let Some(entry) = state.clients.get(&id) {
+                info!(id = %id, admin = %entry.name, target = %name, count = purged.len(), ids = ?purged, "Admin purge");
+                entry.send(&Outgoing::System {
+                    text: messages::text(Msg::Purged, &[("count", &purged.len()), ("name", &name)]),
+                    at: now_ms(),
                 });
             }
         }
-        Incoming::ListUsers => {
-            let users: Vec<UserInfo> = state.list_users();
+        Incoming::Subscribe { events: names } => {
+            require_admin(state, id)?;
+            let mut kinds = Vec::new();
+            for name in &names {
+                let kind = EventKind::parse(name)
+                    .ok_or_else(|| messages::text(Msg::UnknownEvent, &[("event", name)]))?;
+                if !kinds.contains(&kind) {
+                    kinds.push(kind);
+                }
+            }
+            let reply = if kinds.is_empty() {
+                messages::text(Msg::ObservingStopped, &[])
+            } else {
+                messages::text(Msg::Observing, &[("events", &names.join(", "))])
+            };
+            events::observe(state, id, kinds);
+            if let Some(entry) = state.clients.get(&id) {
+                info!(id = %id, name = %entry.name, events = ?names, "Admin event subscription");
+                entry.send(&Outgoing::System {
+                    text: reply,
+                    at: now_ms(),
+                });
+            }
+        }
+        Incoming::Stats => {
+            require_admin(state, id)?;
+
+            let ai_enabled = state.ai.is_enabled();
+            let stats = Outgoing::Stats {
+                uptime_seconds: state.uptime_seconds(),
+                user_count: state.user_count(),
+                peak_users: state.peak_users() as usize,
+                connections_total: state.connections_total(),
+                messages_sent: state.messages_sent(),
+                memory_mb: (state.memory_mb() * 100.0).round() / 100.0,
+                rate_limit_enabled: state.rate_limit.enabled,
+                rate_limit_per_minute: state.rate_limit.messages_per_minute,
+                ai_enabled,
+                ai_cost_today: ai_enabled
+                    .then(|| (state.ai.cost_today() * 10000.0).round() / 10000.0),
+                ai_tokens_today: ai_enabled.then(|| state.ai.tokens_today()),
+                ai_retries: ai_enabled.then(|| state.ai.retries_total()),
+                users: state.user_stats(),
+                at: now_ms(),
+            };
             if let Some(entry) = state.clients.get(&id) {
-                entry.value().send(&Outgoing::ListUsers { users });
+                entry.value().send(&stats);
             }
         }
         Incoming::Ping { token } => {
             if let Some(entry) = state.clients.get(&id) {
+                // Dropped without a reply: an error would be as much traffic as the pong
+                if !entry
+                    .value()
+                    .allow_ping(&state.rate_limit, state.clock.now())
+                {
+                    debug!(id = %id, "Ping dropped, over the ping rate limit");
+                    return Ok(());
+                }
                 entry.value().send(&Outgoing::Pong {
                     token,
                     at: now_ms(),
                 });
             }
         }
-        Incoming::Ai { prompt } => {
-            let (name, rate_limit_key) = state
-                .clients
-                .get(&id)
-                .map(|e| (e.value().name.clone(), e.value().ip.clone()))
-                .unwrap_or_else(|| ("unknown".to_string(), id.to_string()));
+        Incoming::Ai { prompt } => ask_ai(state, id, prompt, false),
+        Incoming::AiPrivate { prompt } => ask_ai(state, id, prompt, true),
+        Incoming::AiCancel => {
+            if !state.cancel_ai_task(id) {
+                return Err(messages::text(Msg::NoAiInProgress, &[]));
+            }
+            if let Some(entry) = state.clients.get(&id) {
+                entry.value().send(&Outgoing::System {
+                    text: messages::text(Msg::AiCancelled, &[]),
+                    at: now_ms(),
+                });
+            }
+            debug!(id = %id, "AI request cancelled");
+        }
+    }
+
+    Ok(())
+}
+
+/// Start an AI question. A refusal goes to the asker as an error naming the question,
+/// so the client can tell it from errors about anything else.
+fn ask_ai(state: &AppState, id: Uuid, prompt: String, private: bool) {
+    let started = require_participant(state, id)
+        .and_then(|()| start_ai_query(state, id, prompt.clone(), private));
+    if let Err(message) = started {
+        if let Some(entry) = state.clients.get(&id) {
+            state.emit(EventKind::Error, id, &entry.name, Some(&message));
+            entry.value().send(&Outgoing::Error {
+                message,
+                prompt: Some(prompt),
+            });
+        }
+    }
+}
+
+/// Run an AI query in the background. The answer goes to the room, or only to the
+/// requester when `private`; both kinds share the requester's AI rate limit.
+fn start_ai_query(state: &AppState, id: Uuid, prompt: String, private: bool) -> Result<(), String> {
+    let (name, rate_limit_key) = state
+        .clients
+        .get(&id)
+        .map(|e| (e.value().name.clone(), e.value().ip.clone()))
+        .unwrap_or_else(|| ("unknown".to_string(), id.to_string()));
+
+    if state
+        .ai_tasks
+        .get(&id)
+        .is_some_and(|task| !task.handle.is_finished())
+    {
+        return Err(messages::text(Msg::AiInProgress, &[]));
+    }
+
+    // Run the query in the background (this may take a few seconds) so the
+    // receive loop stays responsive and the request can be cancelled.
+    let task_state = state.clone();
+    let task_key = rate_limit_key.clone();
+    let handle = tokio::spawn(async move {
+        let state = task_state;
+        let result = state.ai.query(&task_key, &prompt).await;
+
+        // Answers and provider failures go to the audit log; a request cancelled with
+        // /cancel is aborted before it gets here
+        let (response, error) = match &result {
+            Ok(r) => (Some(r.content.as_str()), None),
+            Err(AiError::Failed(e)) => (None, Some(e.as_str())),
+            Err(AiError::Rejected(_)) => (None, None),
+        };
+        if response.is_some() || error.is_some() {
+            let answer = result.as_ref().ok();
+            state
+                .ai
+                .audit(&AuditEntry {
+                    at: now_ms(),
+                    user_id: id.to_string(),
+                    name: &name,
+                    ip: &task_key,
+                    model: answer
+                        .and_then(|r| r.model.as_deref())
+                        .unwrap_or(state.ai.model()),
+                    prompt: prompt.trim(),
+                    private,
+                    response,
+                    error,
+                    response_ms: answer.map(|r| r.response_ms),
+                    tokens: answer.and_then(|r| r.tokens),
+                    cost: answer.and_then(|r| r.cost),
+                })
+                .await;
+        }
 
-            // Query AI (this may take a few seconds)
-            match state.ai.query(&rate_limit_key, &prompt).await {
-                Ok(ai_response) => {
+        match result {
+            Ok(ai_response) => {
+                let answer = Outgoing::Ai {
+                    from: name.clone(),
+                    prompt: prompt.clone(),
+                    response: ai_response.content,
+                    response_ms: ai_response.response_ms,
+                    tokens: ai_response.tokens,
+                    cost: ai_response.cost,
+                    model: ai_response.model,
+                    finish_reason: ai_response.finish_reason,
+                    private,
+                    at: now_ms(),
+                };
+                state.emit(EventKind::Ai, id, &name, Some(prompt.trim()));
+                if private {
+                    if let Some(entry) = state.clients.get(&id) {
+                        entry.value().send(&answer);
+                    }
+                } else {
+                    broadcast(&state, &answer, None);
+                }
+                debug!(from = %name, id = %id, private, prompt_len = prompt.len(), "AI response sent");
+            }
+            // Only the requester hears the details; nothing of the failed request
+            // reaches the room unless AI_SYSTEM_ON_ERROR asks for a short notice, and
+            // never for a private question
+            Err(err) => {
+                state.emit(EventKind::Error, id, &name, Some(err.message()));
+                if let Some(entry) = state.clients.get(&id) {
+                    entry.value().send(&Outgoing::Error {
+                        message: err.message().to_string(),
+                        prompt: Some(prompt.clone()),
+                    });
+                }
+                if matches!(err, AiError::Failed(_)) && !private && state.ai.system_on_error() {
                     broadcast(
-                        state,
-                        &Outgoing::Ai {
-                            from: name.clone(),
-                            prompt: prompt.clone(),
-                            response: ai_response.content,
-                            response_ms: ai_response.response_ms,
-                            tokens: ai_response.tokens,
-                            cost: ai_response.cost,
+                        &state,
+                        &Outgoing::System {
+                            text: messages::text(Msg::AiFailedNotice, &[("name", &name)]),
                             at: now_ms(),
                         },
-                        None,
+                        Some(id),
                     );
-                    debug!(from = %name, id = %id, prompt_len = prompt.len(), "AI response sent");
-                }
-                Err(err) => {
-                    if let Some(entry) = state.clients.get(&id) {
-                        entry.value().send(&Outgoing::Error { message: err });
-                    }
                 }
             }
         }
-    }
+        let task_id = tokio::task::id();
+        state
+            .ai_tasks
+            .remove_if(&id, |_, task| task.handle.id() == task_id);
+    });
 
+    state.ai_tasks.insert(
+        id,
+        AiTask {
+            handle: handle.abort_handle(),
+            rate_limit_key,
+        },
+    );
     Ok(())
 }
 
+/// Flood collapse and rate limit for everything a client posts: chat, edits, deletes and
+/// file offers. `Ok(false)` when the message is dropped without an error, i.e. a repeated
+/// duplicate or a rate limit reported with `rateLimited`.
+fn admit(state: &AppState, id: Uuid, client: &Client, text: Option<&str>) -> Result<bool, String> {
+    let now = state.clock.now();
+    // Drop repeats of the previous message before they count against the rate limit
+    if let Some(Err(warn)) =
+        text.map(|text| client.check_duplicate(text, &state.flood_collapse, now))
+    {
+        debug!(id = %id, "Duplicate message dropped");
+        if warn {
+            return Err(messages::text(Msg::DuplicateIgnored, &[]));
+        }
+        return Ok(false);
+    }
+
+    if let Err(wait_secs) = client.check_rate_limit(&state.rate_limit, now) {
+        let message = messages::text(Msg::RateLimited, &[("secs", &wait_secs)]);
+        if state.rate_limit.plain_error {
+            return Err(message);
+        }
+        client.send(&Outgoing::RateLimited {
+            retry_after_secs: wait_secs,
+            message,
+            at: now_ms(),
+        });
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Send chat still waiting for its batch, so an edit or delete can't overtake it
+fn flush_chat_batch(state: &AppState) {
+    if let Some(batcher) = &state.chat_batch {
+        batcher.flush(state);
+    }
+}
+
+fn require_admin(state: &AppState, id: Uuid) -> Result<(), String> {
+    if state.clients.get(&id).is_some_and(|entry| entry.is_admin) {
+        Ok(())
+    } else {
+        Err(messages::text(Msg::AdminRequired, &[]))
+    }
+}
+
+/// Spectators only watch: no chat, AI questions or file offers
+fn require_participant(state: &AppState, id: Uuid) -> Result<(), String> {
+    if state
+        .clients
+        .get(&id)
+        .is_some_and(|entry| entry.is_spectator)
+    {
+        Err(messages::text(Msg::SpectatorReadOnly, &[]))
+    } else {
+        Ok(())
+    }
+}
+
+/// Push `status` to all clients every `interval`, so dashboards don't have to poll.
+/// It is not a chat message and doesn't count towards `messagesSent`.
+pub fn spawn_status_broadcast(state: AppState, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately; nobody is connected yet anyway
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if state.clients.is_empty() {
+                continue;
+            }
+            let status = state.build_status();
+            broadcast(&state, &status, None);
+        }
+    });
+}
+
 pub fn broadcast(state: &AppState, payload: &Outgoing, except: Option<Uuid>) {
-    let text = serde_json::to_string(payload)
-        .unwrap_or_else(|_| r#"{"type":"error","message":"serialize"}"#.into());
+    // Serialize once per encoding; every client using it shares one buffer
+    let frames = Frames::new(payload);
 
     let targets = state.clients.len();
     debug!(targets, except = ?except, kind = %payload.kind(), "Broadcast payload");
 
+    // Use try_send so one slow client can't hold up the others; deal with the ones that
+    // fell behind after the loop, so no DashMap shard stays locked while doing so
+    let mut slow = Vec::new();
     for entry in state.clients.iter() {
         if except.is_some_and(|ex| ex == *entry.key()) {
             continue;
         }
-        // Use try_send to avoid blocking on slow clients
-        if let Err(err) = entry
-            .value()
-            .tx
-            .try_send(Message::Text(text.clone().into()))
-        {
-            error!(id = %entry.key(), ?err, "Send to client failed (slow client or disconnected)");
+        // Observers get events instead of the chat
+        if entry.value().observing.load(Ordering::Relaxed) {
+            continue;
+        }
+        let Some(frame) = frames.get(entry.value().encoding) else {
+            continue;
+        };
+        match entry.value().tx.try_send(frame) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => slow.push(*entry.key()),
+            Err(TrySendError::Closed(_)) => {
+                debug!(id = %entry.key(), "Broadcast to client that is disconnecting");
+            }
+        }
+    }
+
+    if !slow.is_empty() {
+        handle_slow_clients(state, &slow);
+    }
+}
+
+/// Apply the slow client policy to clients whose outbound buffer was full
+fn handle_slow_clients(state: &AppState, ids: &[Uuid]) {
+    for id in ids {
+        let Some(disconnect) = state.clients.get(id).map(|c| c.disconnect.clone()) else {
+            continue;
+        };
+        if state.slow_client.disconnect {
+            warn!(id = %id, "Client can't keep up with broadcasts, disconnecting");
+            disconnect.notify_one();
+        } else {
+            error!(id = %id, "Send to client failed (buffer full), message dropped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::state::{tests::app_state, SlowClientConfig};
+
+    #[tokio::test]
+    async fn panic_in_message_handling_becomes_an_error() {
+        let id = Uuid::new_v4();
+
+        let result = isolate_panic(id, async {
+            let empty: Vec<u64> = Vec::new();
+            // Index out of bounds, like a bug in a handler would
+            let _ = empty[0];
+            Ok(())
+        })
+        .await;
+        assert_eq!(result, Err(messages::text(Msg::InternalError, &[])));
+
+        // Regular results pass through untouched
+        assert_eq!(isolate_panic(id, async { Ok(()) }).await, Ok(()));
+        assert_eq!(
+            isolate_panic(id, async { Err("nee".to_string()) }).await,
+            Err("nee".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn broadcast_disconnects_client_with_full_buffer() {
+        let mut state = app_state();
+        state.slow_client = SlowClientConfig { disconnect: true };
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let slow = Client::new("slow".into(), "127.0.0.1".into(), None, tx);
+        state.clients.insert(Uuid::new_v4(), slow.clone());
+        let payload = Outgoing::System {
+            text: "hi".into(),
+            at: now_ms(),
+        };
+
+        broadcast(&state, &payload, None);
+        broadcast(&state, &payload, None);
+
+        tokio::time::timeout(Duration::from_secs(1), slow.disconnect.notified())
+            .await
+            .expect("slow client was not told to disconnect");
+    }
+
+    #[tokio::test]
+    async fn broadcast_skips_the_excluded_client_and_observers() {
+        let state = app_state();
+        let mut receivers = Vec::new();
+        for name in ["sender", "observer", "reader"] {
+            let (tx, rx) = tokio::sync::mpsc::channel(CLIENT_CHANNEL_BUFFER);
+            let client = Client::new(name.into(), "127.0.0.1".into(), None, tx);
+            client
+                .observing
+                .store(name == "observer", Ordering::Relaxed);
+            let id = Uuid::new_v4();
+            state.clients.insert(id, client);
+            receivers.push((id, rx));
+        }
+        let payload = Outgoing::System {
+            text: "hi".into(),
+            at: now_ms(),
+        };
+
+        broadcast(&state, &payload, Some(receivers[0].0));
+
+        let received: Vec<usize> = receivers
+            .iter_mut()
+            .map(|(_, rx)| std::iter::from_fn(|| rx.try_recv().ok()).count())
+            .collect();
+        assert_eq!(received, [0, 0, 1]);
+    }
+
+    /// Fan-out cost of one chat broadcast to 1k clients. Not a correctness test; run with
+    /// `cargo test --release broadcast_fanout -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn broadcast_fanout_1k_clients() {
+        const CLIENTS: usize = 1000;
+        const ROUNDS: u32 = 500;
+
+        let state = app_state();
+        let mut receivers = Vec::with_capacity(CLIENTS);
+        for i in 0..CLIENTS {
+            let (tx, rx) = tokio::sync::mpsc::channel(CLIENT_CHANNEL_BUFFER);
+            state.clients.insert(
+                Uuid::new_v4(),
+                Client::new(format!("bench-{i}"), "127.0.0.1".into(), None, tx),
+            );
+            receivers.push(rx);
+        }
+
+        // A typical chat line and a long AI answer
+        for size in [200, 4000] {
+            let payload = Outgoing::Chat {
+                id: Some(1),
+                from: "bench".into(),
+                text: "x".repeat(size),
+                reply_to: None,
+                at: now_ms(),
+            };
+
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..ROUNDS {
+                let start = Instant::now();
+                broadcast(&state, &payload, None);
+                elapsed += start.elapsed();
+                for rx in &mut receivers {
+                    while rx.try_recv().is_ok() {}
+                }
+            }
+
+            let per_broadcast = elapsed / ROUNDS;
+            println!(
+                "broadcast {size} bytes to {CLIENTS} clients: {per_broadcast:?} per broadcast, {:?} per client",
+                per_broadcast / CLIENTS as u32
+            );
         }
+        assert_eq!(state.user_count(), CLIENTS);
     }
 }
@@ -0,0 +1,307 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use tracing::info;
+use uuid::Uuid;
+
+use crate::messages::{self, Msg};
+
+const DEFAULT_HISTORY_SIZE: usize = 200;
+const DEFAULT_EDIT_WINDOW_SECS: u64 = 300;
+/// Most messages a single `backfill` returns
+pub const MAX_BACKFILL: usize = 100;
+/// Most messages a single `purge` removes
+pub const MAX_PURGE: usize = 50;
+
+#[derive(Clone)]
+pub struct HistoryConfig {
+    /// Number of recent chat messages kept for edit/delete
+    pub size: usize,
+    /// How long after sending a message can still be edited
+    pub edit_window_secs: u64,
+}
+
+impl HistoryConfig {
+    pub fn from_env() -> Self {
+        let size = std::env::var("HISTORY_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_SIZE);
+        let edit_window_secs = std::env::var("EDIT_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_EDIT_WINDOW_SECS);
+
+        info!(size, edit_window_secs, "Message history configured");
+
+        Self {
+            size,
+            edit_window_secs,
+        }
+    }
+}
+
+/// A chat message as remembered by the server
+#[derive(Clone)]
+pub struct StoredMessage {
+    pub id: u64,
+    /// Connection id of the author; names can change, ids can't
+    pub author: Uuid,
+    /// Name of the author when it was sent
+    pub from: String,
+    pub text: String,
+    /// Id of the message this answers
+    pub reply_to: Option<u64>,
+    pub at: u64,
+}
+
+/// Messages after a sequence number, for a client catching up
+pub struct Backfill {
+    pub messages: Vec<StoredMessage>,
+    /// Latest id when the messages were collected
+    pub seq: u64,
+    /// Some messages after the requested sequence are no longer available
+    pub gap: bool,
+}
+
+/// Ring buffer of recent chat messages with server-assigned ids.
+///
+/// Ids only go up, so they double as the sequence number a reconnecting client
+/// catches up from.
+pub struct MessageHistory {
+    config: HistoryConfig,
+    next_id: AtomicU64,
+    messages: Mutex<VecDeque<StoredMessage>>,
+    /// Highest id that fell out of the buffer; anything up to it is gone
+    evicted: AtomicU64,
+}
+
+impl MessageHistory {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::with_capacity(config.size)),
+            config,
+            next_id: AtomicU64::new(1),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    /// Id of the latest message, 0 before the first one
+    pub fn seq(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed) - 1
+    }
+
+    /// Whether message `id` is still kept, e.g. to reply to
+    pub fn contains(&self, id: u64) -> bool {
+        self.messages.lock().unwrap().iter().any(|m| m.id == id)
+    }
+
+    /// Store a new message and return its id
+    pub fn push(
+        &self,
+        author: Uuid,
+        from: &str,
+        text: &str,
+        reply_to: Option<u64>,
+        at: u64,
+    ) -> u64 {
+        let mut history = self.messages.lock().unwrap();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if self.config.size == 0 {
+            self.evicted.store(id, Ordering::Relaxed);
+            return id;
+        }
+
+        while history.len() >= self.config.size {
+            if let Some(dropped) = history.pop_front() {
+                self.evicted.store(dropped.id, Ordering::Relaxed);
+            }
+        }
+        history.push_back(StoredMessage {
+            id,
+            author,
+            from: from.to_string(),
+            text: text.to_string(),
+            reply_to,
+            at,
+        });
+        id
+    }
+
+    /// Replace the text of a message sent by `author` within the edit window. `allow` has
+    /// the last word once the edit would go through; `Ok(false)` when it said no.
+    pub fn edit(
+        &self,
+        id: u64,
+        author: Uuid,
+        text: &str,
+        now: u64,
+        allow: impl FnOnce() -> Result<bool, String>,
+    ) -> Result<bool, String> {
+        let mut history = self.messages.lock().unwrap();
+        let message = find_own(&mut history, id, author)?;
+
+        let window_ms = self.config.edit_window_secs * 1000;
+        if now.saturating_sub(message.at) > window_ms {
+            return Err(messages::text(
+                Msg::EditTooOld,
+                &[("id", &id), ("secs", &self.config.edit_window_secs)],
+            ));
+        }
+        if !allow()? {
+            return Ok(false);
+        }
+
+        message.text = text.to_string();
+        Ok(true)
+    }
+
+    /// Remove a message sent by `author`, if `allow` agrees; `Ok(false)` when it didn't
+    pub fn delete(
+        &self,
+        id: u64,
+        author: Uuid,
+        allow: impl FnOnce() -> Result<bool, String>,
+    ) -> Result<bool, String> {
+        let mut history = self.messages.lock().unwrap();
+        find_own(&mut history, id, author)?;
+        if !allow()? {
+            return Ok(false);
+        }
+        history.retain(|m| m.id != id);
+        Ok(true)
+    }
+
+    /// Remove the latest `last_n` messages that `matches` selects, whoever asks.
+    /// Returns their ids, oldest first.
+    pub fn purge(&self, last_n: usize, matches: impl Fn(&StoredMessage) -> bool) -> Vec<u64> {
+        let mut history = self.messages.lock().unwrap();
+        let mut ids: Vec<u64> = history
+            .iter()
+            .rev()
+            .filter(|m| matches(m))
+            .take(last_n)
+            .map(|m| m.id)
+            .collect();
+        ids.reverse();
+        history.retain(|m| !ids.contains(&m.id));
+        ids
+    }
+
+    /// The most recent messages after `since`, at most [`MAX_BACKFILL`]. Deleted
+    /// messages are skipped and edited ones come with their current text.
+    pub fn since(&self, since: u64) -> Backfill {
+        let history = self.messages.lock().unwrap();
+        // A sequence from the future was handed out before a restart; ids start over
+        // then, so everything we have is new to the client
+        let seq = self.seq();
+        let restarted = since > seq;
+        let since = if restarted { 0 } else { since };
+
+        let newer: Vec<&StoredMessage> = history.iter().filter(|m| m.id > since).collect();
+        let skip = newer.len().saturating_sub(MAX_BACKFILL);
+        Backfill {
+            seq,
+            gap: restarted || skip > 0 || self.evicted.load(Ordering::Relaxed) > since,
+            messages: newer.into_iter().skip(skip).cloned().collect(),
+        }
+    }
+}
+
+fn find_own(
+    history: &mut VecDeque<StoredMessage>,
+    id: u64,
+    author: Uuid,
+) -> Result<&mut StoredMessage, String> {
+    let message = history
+        .iter_mut()
+        .find(|m| m.id == id)
+        .ok_or_else(|| messages::text(Msg::MessageNotFound, &[("id", &id)]))?;
+    if message.author != author {
+        return Err(messages::text(Msg::NotOwnMessage, &[]));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(size: usize) -> MessageHistory {
+        MessageHistory::new(HistoryConfig {
+            size,
+            edit_window_secs: 300,
+        })
+    }
+
+    fn ids(backfill: &Backfill) -> Vec<u64> {
+        backfill.messages.iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn backfill_returns_only_newer_messages() {
+        let history = history(10);
+        let author = Uuid::new_v4();
+        assert_eq!(history.seq(), 0);
+        for text in ["een", "twee", "drie"] {
+            history.push(author, "Bas", text, None, 0);
+        }
+        history.delete(2, author, || Ok(true)).unwrap();
+
+        assert_eq!(history.seq(), 3);
+        let backfill = history.since(1);
+        assert_eq!(ids(&backfill), [3]);
+        assert!(!backfill.gap);
+        assert!(history.since(3).messages.is_empty());
+    }
+
+    #[test]
+    fn backfill_reports_messages_that_fell_out() {
+        let history = history(2);
+        let author = Uuid::new_v4();
+        for _ in 0..5 {
+            history.push(author, "Bas", "hoi", None, 0);
+        }
+
+        let backfill = history.since(1);
+        assert_eq!(ids(&backfill), [4, 5]);
+        assert!(backfill.gap);
+        assert!(!history.since(3).gap);
+        // Sequence from before a restart
+        assert!(history.since(99).gap);
+        assert_eq!(ids(&history.since(99)), [4, 5]);
+    }
+
+    #[test]
+    fn purge_removes_the_latest_matching_messages() {
+        let history = history(10);
+        let (spammer, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for author in [spammer, other, spammer, spammer, other] {
+            history.push(author, "Bas", "hoi", None, 0);
+        }
+
+        assert_eq!(history.purge(2, |m| m.author == spammer), [3, 4]);
+        assert_eq!(ids(&history.since(0)), [1, 2, 5]);
+        assert_eq!(history.purge(5, |m| m.author == spammer), [1]);
+        assert!(history.purge(5, |m| m.author == spammer).is_empty());
+    }
+
+    #[test]
+    fn backfill_is_capped() {
+        let history = history(MAX_BACKFILL + 10);
+        let author = Uuid::new_v4();
+        for _ in 0..MAX_BACKFILL + 5 {
+            history.push(author, "Bas", "hoi", None, 0);
+        }
+
+        let backfill = history.since(0);
+        assert_eq!(backfill.messages.len(), MAX_BACKFILL);
+        assert_eq!(backfill.messages[0].id, 6);
+        assert!(backfill.gap);
+    }
+}
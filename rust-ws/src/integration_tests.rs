@@ -0,0 +1,1250 @@
+//! End-to-end tests: the real router on an ephemeral port, driven by WebSocket clients.
+
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::ai::{
+    tests::{EchoProvider, FailingProvider},
+    AiClient, AiConfig, AiProvider,
+};
+use crate::batch::ChatBatchConfig;
+use crate::clock::MockClock;
+use crate::guest_names::GuestNameStyle;
+use crate::history::MAX_PURGE;
+use crate::listeners::{self, Listener};
+use crate::messages::{self, Msg};
+use crate::protocol::{PROTOCOL_VERSION, SUBPROTOCOLS, SUBPROTOCOL_MSGPACK};
+use crate::state::tests::{ai_config, app_state};
+use crate::state::{AdminConfig, AppState, ConnectionLimitConfig, JoinLeaveConfig};
+
+type Ws = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serve the app with AI disabled and rate limiting off
+async fn start_server() -> SocketAddr {
+    start_server_with(app_state()).await
+}
+
+async fn start_server_with(state: AppState) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = crate::app(state).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    addr
+}
+
+/// Connect and consume the `ackName` greeting and our own `presence`; returns the
+/// assigned name
+async fn connect(addr: SocketAddr) -> (Ws, String) {
+    let (mut ws, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+    let ack = recv(&mut ws).await;
+    assert_eq!(ack["type"], "ackName");
+    let name = ack["name"].as_str().unwrap().to_string();
+    let presence = recv_type(&mut ws, "presence").await;
+    assert_eq!(presence["joined"], name.as_str());
+    (ws, name)
+}
+
+async fn send(ws: &mut Ws, payload: Value) {
+    ws.send(Message::text(payload.to_string())).await.unwrap();
+}
+
+/// Next text frame as JSON
+async fn recv(ws: &mut Ws) -> Value {
+    loop {
+        let frame = tokio::time::timeout(Duration::from_secs(2), ws.next())
+            .await
+            .expect("no message within 2 seconds")
+            .expect("connection closed")
+            .unwrap();
+        if let Message::Text(text) = frame {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+/// Next message of the given type, skipping others (e.g. join notices)
+async fn recv_type(ws: &mut Ws, kind: &str) -> Value {
+    loop {
+        let msg = recv(ws).await;
+        if msg["type"] == kind {
+            return msg;
+        }
+    }
+}
+
+#[tokio::test]
+async fn connect_is_greeted_with_ack_name() {
+    let addr = start_server().await;
+    let (_ws, name) = connect(addr).await;
+
+    assert!(name.starts_with("guest-"), "unexpected name {name}");
+}
+
+#[tokio::test]
+async fn friendly_guest_names_are_unique() {
+    let addr = start_server_with(app_state().with_guest_names(GuestNameStyle::Friendly)).await;
+    let mut names = HashSet::new();
+    let mut sockets = Vec::new();
+    for _ in 0..20 {
+        let (ws, name) = connect(addr).await;
+        assert!(!name.starts_with("guest-"), "unexpected name {name}");
+        assert!(names.insert(name));
+        sockets.push(ws);
+    }
+}
+
+#[tokio::test]
+async fn chat_subprotocol_is_negotiated() {
+    let addr = start_server().await;
+    let mut request = format!("ws://{addr}").into_client_request().unwrap();
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static(SUBPROTOCOLS[0]),
+    );
+
+    let (_ws, response) = connect_async(request).await.unwrap();
+
+    assert_eq!(
+        response.headers()["Sec-WebSocket-Protocol"],
+        SUBPROTOCOLS[0]
+    );
+}
+
+/// Next binary frame, decoded from MessagePack
+async fn recv_msgpack(ws: &mut Ws) -> Value {
+    loop {
+        let frame = tokio::time::timeout(Duration::from_secs(2), ws.next())
+            .await
+            .expect("no message within 2 seconds")
+            .expect("connection closed")
+            .unwrap();
+        match frame {
+            Message::Binary(bytes) => return rmp_serde::from_slice(&bytes).unwrap(),
+            Message::Text(text) => panic!("expected MessagePack, got {text}"),
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn msgpack_clients_get_binary_frames() {
+    let addr = start_server().await;
+    let mut request = format!("ws://{addr}").into_client_request().unwrap();
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static(SUBPROTOCOL_MSGPACK),
+    );
+    let (mut bot, response) = connect_async(request).await.unwrap();
+    assert_eq!(
+        response.headers()["Sec-WebSocket-Protocol"],
+        SUBPROTOCOL_MSGPACK
+    );
+    let bot_name = recv_msgpack(&mut bot).await["name"].clone();
+    let (mut bob, _) = connect(addr).await;
+
+    let chat = rmp_serde::to_vec_named(&json!({ "type": "chat", "text": "bliep" })).unwrap();
+    bot.send(Message::binary(chat)).await.unwrap();
+    let echo = loop {
+        let msg = recv_msgpack(&mut bot).await;
+        if msg["type"] == "chat" {
+            break msg;
+        }
+    };
+    assert_eq!(echo["text"], "bliep");
+    // Everyone else still gets JSON
+    let chat = recv_type(&mut bob, "chat").await;
+    assert_eq!(chat["from"], bot_name);
+    assert_eq!(chat["text"], "bliep");
+    // And the other way around
+    send(&mut bob, json!({ "type": "chat", "text": "hoi bot" })).await;
+    let chat = loop {
+        let msg = recv_msgpack(&mut bot).await;
+        if msg["type"] == "chat" {
+            break msg;
+        }
+    };
+    assert_eq!(chat["text"], "hoi bot");
+
+    // JSON text frames keep working, and errors come back as MessagePack
+    send(&mut bot, json!({ "type": "ping", "token": "t" })).await;
+    assert_eq!(recv_msgpack(&mut bot).await["type"], "pong");
+    bot.send(Message::binary(vec![0xc1])).await.unwrap();
+    assert_eq!(
+        recv_msgpack(&mut bot).await["message"],
+        messages::text(Msg::InvalidMsgPack, &[])
+    );
+}
+
+#[tokio::test]
+async fn listeners_share_one_chat_and_stop_together() {
+    let local = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let public = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (local_addr, public_addr) = (local.local_addr().unwrap(), public.local_addr().unwrap());
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(listeners::serve(
+        vec![Listener::Plain(local), Listener::Plain(public)],
+        crate::app(app_state()),
+        async {
+            let _ = stopped.await;
+        },
+    ));
+
+    let (mut alice, _) = connect(local_addr).await;
+    let (mut bob, bob_name) = connect(public_addr).await;
+    assert_eq!(recv_type(&mut alice, "presence").await["joined"], bob_name);
+
+    send(
+        &mut bob,
+        json!({ "type": "chat", "text": "via de andere poort" }),
+    )
+    .await;
+    let chat = recv_type(&mut alice, "chat").await;
+    assert_eq!(chat["from"], bob_name);
+    assert_eq!(chat["text"], "via de andere poort");
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .expect("listeners did not stop")
+        .unwrap()
+        .unwrap();
+    assert!(connect_async(format!("ws://{local_addr}")).await.is_err());
+    assert!(connect_async(format!("ws://{public_addr}")).await.is_err());
+}
+
+#[tokio::test]
+async fn chat_is_broadcast_to_other_clients() {
+    let addr = start_server().await;
+    let (mut alice, alice_name) = connect(addr).await;
+    let (mut bob, bob_name) = connect(addr).await;
+
+    let joined = recv_type(&mut alice, "system").await;
+    assert!(joined["text"].as_str().unwrap().contains(&bob_name));
+
+    send(
+        &mut alice,
+        json!({ "type": "chat", "text": "  hallo bob  " }),
+    )
+    .await;
+
+    let chat = recv_type(&mut bob, "chat").await;
+    assert_eq!(chat["from"], alice_name);
+    assert_eq!(chat["text"], "hallo bob");
+    assert!(chat["id"].is_u64());
+    // The sender gets its own message back, with the same id
+    let echo = recv_type(&mut alice, "chat").await;
+    assert_eq!(echo["id"], chat["id"]);
+}
+
+#[tokio::test]
+async fn edits_and_deletes_count_as_posts() {
+    let mut state = app_state();
+    state.admin = AdminConfig {
+        token: Some("geheim".to_string()),
+        bot_token: None,
+    };
+    state.rate_limit.enabled = true;
+    state.rate_limit.messages_per_minute = 3;
+    let addr = start_server_with(state).await;
+    let (mut observer, _) = connect(addr).await;
+    send(&mut observer, json!({ "type": "auth", "token": "geheim" })).await;
+    recv_type(&mut observer, "system").await;
+    send(
+        &mut observer,
+        json!({ "type": "subscribe", "events": ["edit", "delete"] }),
+    )
+    .await;
+    recv_type(&mut observer, "system").await;
+    let (mut alice, alice_name) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "chat", "text": "hallo" })).await;
+    let id = recv_type(&mut alice, "chat").await["id"].clone();
+    send(
+        &mut alice,
+        json!({ "type": "edit", "id": id, "text": "hoi" }),
+    )
+    .await;
+    recv_type(&mut alice, "edit").await;
+    send(&mut alice, json!({ "type": "delete", "id": id })).await;
+    recv_type(&mut alice, "delete").await;
+    // An edit that fails on its own doesn't count
+    send(
+        &mut alice,
+        json!({ "type": "edit", "id": id, "text": "weg" }),
+    )
+    .await;
+    assert_eq!(
+        recv_type(&mut alice, "error").await["message"],
+        messages::text(Msg::MessageNotFound, &[("id", &id)])
+    );
+    // The fourth post within a minute, whatever kind, is over the limit
+    send(&mut alice, json!({ "type": "chat", "text": "nog eens" })).await;
+    recv_type(&mut alice, "rateLimited").await;
+
+    let edit = recv(&mut observer).await;
+    assert_eq!(edit["event"], "edit");
+    assert_eq!(edit["name"], alice_name.as_str());
+    assert_eq!(edit["text"], format!("#{id} hoi"));
+    let delete = recv(&mut observer).await;
+    assert_eq!(delete["event"], "delete");
+    assert_eq!(delete["text"], format!("#{id}"));
+
+    // Spectators can't change anything either
+    let (mut spectator, _) = connect_async(format!("ws://{addr}/?mode=spectator"))
+        .await
+        .unwrap();
+    send(&mut spectator, json!({ "type": "delete", "id": id })).await;
+    assert_eq!(
+        recv_type(&mut spectator, "error").await["message"],
+        messages::text(Msg::SpectatorReadOnly, &[])
+    );
+}
+
+#[tokio::test]
+async fn set_name_is_validated() {
+    let addr = start_server().await;
+    let (mut ws, _) = connect(addr).await;
+
+    send(&mut ws, json!({ "type": "setName", "name": "a" })).await;
+    assert_eq!(recv(&mut ws).await["type"], "error");
+
+    send(
+        &mut ws,
+        json!({ "type": "setName", "name": "x".repeat(33) }),
+    )
+    .await;
+    assert_eq!(recv(&mut ws).await["type"], "error");
+
+    send(
+        &mut ws,
+        json!({ "type": "setName", "name": "x".repeat(32) }),
+    )
+    .await;
+    let ack = recv(&mut ws).await;
+    assert_eq!(ack["type"], "ackName");
+    assert_eq!(ack["name"], "x".repeat(32));
+}
+
+#[tokio::test]
+async fn status_counts_connections_and_peak_users() {
+    let addr = start_server().await;
+    let (mut alice, _) = connect(addr).await;
+    let (_bob, _) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "status" })).await;
+    let status = recv_type(&mut alice, "status").await;
+    assert_eq!(status["connectionsTotal"], 2);
+    assert_eq!(status["peakUsers"], 2);
+}
+
+#[tokio::test]
+async fn status_counts_clients_offering_compression() {
+    let addr = start_server().await;
+    let mut request = format!("ws://{addr}").into_client_request().unwrap();
+    request.headers_mut().insert(
+        "Sec-WebSocket-Extensions",
+        HeaderValue::from_static("permessage-deflate; client_max_window_bits"),
+    );
+    // Not negotiated: the handshake answers without the extension
+    let (mut ws, response) = connect_async(request).await.unwrap();
+    assert!(response.headers().get("Sec-WebSocket-Extensions").is_none());
+    let (_plain, _) = connect(addr).await;
+
+    send(&mut ws, json!({ "type": "status" })).await;
+    let status = recv_type(&mut ws, "status").await;
+    assert_eq!(status["userCount"], 2);
+    assert_eq!(status["compressionOffered"], 1);
+}
+
+#[tokio::test]
+async fn status_reports_all_fields() {
+    let addr = start_server().await;
+    let (mut ws, _) = connect(addr).await;
+
+    send(&mut ws, json!({ "type": "status" })).await;
+    let status = recv(&mut ws).await;
+
+    assert_eq!(status["type"], "status");
+    for field in [
+        "version",
+        "rustVersion",
+        "os",
+        "cpuCores",
+        "uptimeSeconds",
+        "peakUsers",
+        "connectionsTotal",
+        "messagesSent",
+        "messagesPerSecond",
+        "memoryMb",
+        "cpuPercent",
+        "totalSessionSeconds",
+        "compressionOffered",
+        "aiEnabled",
+    ] {
+        assert!(status.get(field).is_some(), "status is missing {field}");
+    }
+    assert_eq!(status["protocolVersion"], PROTOCOL_VERSION);
+    assert_eq!(status["userCount"], 1);
+    // AI is disabled, so no model is reported
+    assert!(status.get("aiModel").is_none());
+}
+
+#[tokio::test]
+async fn ping_echoes_token() {
+    let addr = start_server().await;
+    let (mut ws, _) = connect(addr).await;
+
+    send(&mut ws, json!({ "type": "ping", "token": "abc-123" })).await;
+    let pong = recv(&mut ws).await;
+
+    assert_eq!(pong["type"], "pong");
+    assert_eq!(pong["token"], "abc-123");
+    assert!(pong["at"].is_u64());
+}
+
+#[tokio::test]
+async fn whoami_returns_own_identity_only_to_requester() {
+    let addr = start_server().await;
+    let (mut alice, alice_name) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+    recv_type(&mut alice, "presence").await;
+
+    send(&mut alice, json!({ "type": "whoami" })).await;
+    let me = recv(&mut alice).await;
+
+    assert_eq!(me["type"], "whoami");
+    assert_eq!(me["name"], alice_name);
+    assert_eq!(me["ip"], "127.0.0.1");
+    assert_eq!(me["admin"], false);
+    assert!(!me["id"].as_str().unwrap().is_empty());
+
+    // Bob sees nothing of it; the next thing he gets is his own pong
+    send(&mut bob, json!({ "type": "ping" })).await;
+    assert_eq!(recv(&mut bob).await["type"], "pong");
+}
+
+#[tokio::test]
+async fn version_reports_the_build() {
+    let addr = start_server().await;
+    let (mut ws, _) = connect(addr).await;
+
+    send(&mut ws, json!({ "type": "version" })).await;
+    let version = recv_type(&mut ws, "version").await;
+    assert_eq!(version["crateVersion"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(version["rustVersion"], env!("RUSTC_VERSION"));
+    assert!(!version["gitCommit"].as_str().unwrap().is_empty());
+    assert!(version["builtAt"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn resume_token_restores_name_after_reconnect() {
+    let addr = start_server().await;
+    let (mut watcher, _) = connect(addr).await;
+
+    let (mut ws, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+    let token = recv(&mut ws).await["resumeToken"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    send(&mut ws, json!({ "type": "setName", "name": "Bas" })).await;
+    assert_eq!(recv_type(&mut ws, "ackName").await["name"], "Bas");
+    ws.close(None).await.unwrap();
+    // The name is parked once the server has seen the disconnect
+    let left = messages::text(Msg::Left, &[("name", &"Bas")]);
+    while recv_type(&mut watcher, "system").await["text"] != left.as_str() {}
+
+    let (mut ws, guest) = connect(addr).await;
+    send(&mut ws, json!({ "type": "resume", "token": token })).await;
+    let ack = recv_type(&mut ws, "ackName").await;
+    assert_eq!(ack["name"], "Bas");
+    assert_ne!(ack["resumeToken"], token.as_str());
+    assert_ne!(guest, "Bas");
+
+    // A token works only once
+    let (mut other, _) = connect(addr).await;
+    send(&mut other, json!({ "type": "resume", "token": token })).await;
+    assert_eq!(recv_type(&mut other, "error").await["type"], "error");
+}
+
+#[tokio::test]
+async fn backfill_returns_messages_missed_while_away() {
+    let addr = start_server().await;
+    let (mut alice, _) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "chat", "text": "een" })).await;
+    let seen = recv_type(&mut bob, "chat").await["id"].as_u64().unwrap();
+    drop(bob);
+    for text in ["twee", "drie"] {
+        send(&mut alice, json!({ "type": "chat", "text": text })).await;
+        recv_type(&mut alice, "chat").await;
+    }
+
+    let (mut bob, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+    let ack = recv_type(&mut bob, "ackName").await;
+    assert_eq!(ack["seq"], seen + 2);
+    send(&mut bob, json!({ "type": "backfill", "since": seen })).await;
+    let backfill = recv_type(&mut bob, "backfill").await;
+    let texts: Vec<_> = backfill["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, ["twee", "drie"]);
+    assert_eq!(backfill["seq"], seen + 2);
+    assert_eq!(backfill["gap"], false);
+}
+
+#[tokio::test]
+async fn replies_must_point_at_a_recent_message() {
+    let addr = start_server().await;
+    let (mut alice, _) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "chat", "text": "Pizza?" })).await;
+    let question = recv_type(&mut alice, "chat").await["id"].as_u64().unwrap();
+    send(
+        &mut alice,
+        json!({ "type": "chat", "text": "Ja!", "replyTo": question }),
+    )
+    .await;
+    let reply = recv_type(&mut alice, "chat").await;
+    assert_eq!(reply["replyTo"], question);
+
+    send(
+        &mut alice,
+        json!({ "type": "chat", "text": "Nee", "replyTo": 999 }),
+    )
+    .await;
+    let error = recv_type(&mut alice, "error").await;
+    assert!(error["message"].as_str().unwrap().contains("999"));
+}
+
+#[tokio::test]
+async fn presence_reports_joins_and_leaves_with_user_count() {
+    let addr = start_server().await;
+    let (mut alice, _) = connect(addr).await;
+
+    let (bob, bob_name) = connect(addr).await;
+    let joined = recv_type(&mut alice, "presence").await;
+    assert_eq!(joined["userCount"], 2);
+    assert_eq!(joined["joined"], bob_name.as_str());
+    assert!(joined.get("left").is_none());
+
+    drop(bob);
+    let left = recv_type(&mut alice, "presence").await;
+    assert_eq!(left["userCount"], 1);
+    assert_eq!(left["left"], bob_name.as_str());
+    assert!(left.get("joined").is_none());
+}
+
+#[tokio::test]
+async fn spectators_receive_the_chat_but_cannot_post() {
+    let addr = start_server().await;
+    let (mut alice, alice_name) = connect(addr).await;
+    let (mut spectator, _) = connect_async(format!("ws://{addr}/?mode=spectator"))
+        .await
+        .unwrap();
+    // Greeted like anyone else, but nobody hears about the join
+    assert_eq!(recv(&mut spectator).await["type"], "ackName");
+
+    send(&mut spectator, json!({ "type": "chat", "text": "mag ik?" })).await;
+    let error = recv_type(&mut spectator, "error").await;
+    assert_eq!(
+        error["message"],
+        messages::text(Msg::SpectatorReadOnly, &[])
+    );
+    assert!(error.get("prompt").is_none());
+    send(&mut spectator, json!({ "type": "ai", "prompt": "en nu?" })).await;
+    let error = recv_type(&mut spectator, "error").await;
+    assert_eq!(
+        error["message"],
+        messages::text(Msg::SpectatorReadOnly, &[])
+    );
+    // Names the question it answers
+    assert_eq!(error["prompt"], "en nu?");
+
+    send(&mut alice, json!({ "type": "chat", "text": "hallo" })).await;
+    let chat = recv_type(&mut spectator, "chat").await;
+    assert_eq!(chat["from"], alice_name);
+    assert_eq!(chat["text"], "hallo");
+    // Alice's next message is her own chat: no join notice and not in the count
+    assert_eq!(recv(&mut alice).await["type"], "chat");
+    send(&mut alice, json!({ "type": "status" })).await;
+    assert_eq!(recv_type(&mut alice, "status").await["userCount"], 1);
+}
+
+#[tokio::test]
+async fn hidden_spectators_rename_and_resume_quietly() {
+    let addr = start_server().await;
+    let (mut alice, _) = connect(addr).await;
+
+    let (mut ws, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+    let token = recv(&mut ws).await["resumeToken"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    send(&mut ws, json!({ "type": "setName", "name": "Bas" })).await;
+    assert_eq!(recv_type(&mut ws, "ackName").await["name"], "Bas");
+    ws.close(None).await.unwrap();
+    let left = messages::text(Msg::Left, &[("name", &"Bas")]);
+    while recv_type(&mut alice, "system").await["text"] != left.as_str() {}
+
+    let (mut spectator, _) = connect_async(format!("ws://{addr}/?mode=spectator"))
+        .await
+        .unwrap();
+    assert_eq!(recv(&mut spectator).await["type"], "ackName");
+    send(
+        &mut spectator,
+        json!({ "type": "setName", "name": "Kijker" }),
+    )
+    .await;
+    assert_eq!(recv_type(&mut spectator, "ackName").await["name"], "Kijker");
+    send(&mut spectator, json!({ "type": "resume", "token": token })).await;
+    assert_eq!(recv_type(&mut spectator, "ackName").await["name"], "Bas");
+
+    // No rename or resume notice before Alice's own chat comes back
+    send(&mut alice, json!({ "type": "chat", "text": "hallo" })).await;
+    loop {
+        let msg = recv(&mut alice).await;
+        assert_ne!(msg["type"], "system", "{msg}");
+        if msg["type"] == "chat" {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn unknown_connect_modes_are_refused() {
+    let addr = start_server().await;
+    assert!(connect_async(format!("ws://{addr}/?mode=admin"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn connections_over_the_limit_are_refused() {
+    let state = app_state().with_connection_limit(ConnectionLimitConfig {
+        max_connections: Some(2),
+    });
+    let addr = start_server_with(state).await;
+    let (_alice, _) = connect(addr).await;
+    let (bob, _) = connect(addr).await;
+
+    let (mut carol, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+    let error = recv(&mut carol).await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(
+        error["message"],
+        messages::text(Msg::ServerFull, &[]).as_str()
+    );
+    assert!(matches!(carol.next().await, Some(Ok(Message::Close(_)))));
+
+    // A free slot can be taken again
+    drop(bob);
+    let mut joined = false;
+    for _ in 0..50 {
+        let (mut dave, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+        if recv(&mut dave).await["type"] == "ackName" {
+            joined = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(joined);
+}
+
+#[tokio::test]
+async fn presence_is_sent_without_join_leave_notices() {
+    let state = app_state().with_join_leave(JoinLeaveConfig {
+        enabled: false,
+        max_users: None,
+    });
+    let addr = start_server_with(state).await;
+    let (mut alice, _) = connect(addr).await;
+
+    // The first message after a join or leave is the presence update, not a system text
+    let (bob, bob_name) = connect(addr).await;
+    let joined = recv(&mut alice).await;
+    assert_eq!(joined["type"], "presence");
+    assert_eq!(joined["joined"], bob_name.as_str());
+
+    drop(bob);
+    let left = recv(&mut alice).await;
+    assert_eq!(left["type"], "presence");
+    assert_eq!(left["left"], bob_name.as_str());
+}
+
+#[tokio::test]
+async fn chat_messages_in_one_window_arrive_as_a_batch() {
+    let state = app_state().with_chat_batch(ChatBatchConfig {
+        window: Some(Duration::from_millis(200)),
+    });
+    let addr = start_server_with(state).await;
+    let (mut alice, _) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "chat", "text": "een" })).await;
+    send(&mut alice, json!({ "type": "chat", "text": "twee" })).await;
+    let batch = recv_type(&mut bob, "chatBatch").await;
+    let messages = batch["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["type"], "chat");
+    assert_eq!(messages[0]["text"], "een");
+    assert_eq!(messages[1]["text"], "twee");
+    assert!(messages[0]["id"].as_u64() < messages[1]["id"].as_u64());
+
+    // Alone in its window, a message goes out as a plain chat
+    send(&mut alice, json!({ "type": "chat", "text": "drie" })).await;
+    assert_eq!(recv_type(&mut bob, "chat").await["text"], "drie");
+}
+
+#[tokio::test]
+async fn edits_and_deletes_do_not_overtake_batched_chat() {
+    let state = app_state().with_chat_batch(ChatBatchConfig {
+        window: Some(Duration::from_secs(5)),
+    });
+    let addr = start_server_with(state).await;
+    let (mut alice, _) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+    recv_type(&mut alice, "presence").await;
+
+    send(&mut alice, json!({ "type": "chat", "text": "een" })).await;
+    send(&mut alice, json!({ "type": "chat", "text": "twee" })).await;
+    // Long before the window ends: the delete takes the waiting messages with it
+    send(&mut alice, json!({ "type": "delete", "id": 1 })).await;
+    let batch = recv(&mut bob).await;
+    assert_eq!(batch["type"], "chatBatch");
+    assert_eq!(batch["messages"][0]["id"], 1);
+    let delete = recv(&mut bob).await;
+    assert_eq!(delete["type"], "delete");
+    assert_eq!(delete["id"], 1);
+
+    send(&mut alice, json!({ "type": "chat", "text": "drie" })).await;
+    send(
+        &mut alice,
+        json!({ "type": "edit", "id": 3, "text": "vier" }),
+    )
+    .await;
+    assert_eq!(recv(&mut bob).await["text"], "drie");
+    assert_eq!(recv(&mut bob).await["type"], "edit");
+}
+
+/// Server with AI enabled on the given provider; time stands still for its rate limit
+async fn start_ai_server(config: AiConfig, provider: Box<dyn AiProvider>) -> SocketAddr {
+    let mut state = app_state();
+    let ai = AiClient::with_provider(
+        AiConfig {
+            enabled: true,
+            ..config
+        },
+        provider,
+    )
+    .with_clock(std::sync::Arc::new(MockClock::new()));
+    state.ai = std::sync::Arc::new(ai);
+    start_server_with(state).await
+}
+
+/// Server whose AI requests always fail
+async fn start_failing_ai_server(system_on_error: bool) -> SocketAddr {
+    let config = AiConfig {
+        system_on_error,
+        ..ai_config()
+    };
+    start_ai_server(config, Box::new(FailingProvider)).await
+}
+
+#[tokio::test]
+async fn ai_failure_is_only_reported_to_the_requester_by_default() {
+    let addr = start_failing_ai_server(false).await;
+    let (mut alice, _) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "ai", "prompt": "hoi" })).await;
+    let error = recv_type(&mut alice, "error").await;
+    assert_eq!(error["message"], "kapot");
+    assert_eq!(error["prompt"], "hoi");
+
+    // Bob's next message is the answer to his own ping, not an AI notice
+    send(&mut bob, json!({ "type": "ping" })).await;
+    assert_eq!(recv(&mut bob).await["type"], "pong");
+}
+
+#[tokio::test]
+async fn ai_failure_can_be_announced_to_the_room() {
+    let addr = start_failing_ai_server(true).await;
+    let (mut alice, alice_name) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "ai", "prompt": "hoi" })).await;
+    assert_eq!(recv_type(&mut alice, "error").await["message"], "kapot");
+
+    let notice = recv_type(&mut bob, "system").await;
+    assert_eq!(
+        notice["text"],
+        messages::text(Msg::AiFailedNotice, &[("name", &alice_name)])
+    );
+    assert!(!notice["text"].as_str().unwrap().contains("kapot"));
+}
+
+#[tokio::test]
+async fn private_ai_answer_goes_only_to_the_requester() {
+    let addr = start_ai_server(ai_config(), Box::new(EchoProvider)).await;
+    let (mut alice, alice_name) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+
+    send(
+        &mut alice,
+        json!({ "type": "aiPrivate", "prompt": "geheim" }),
+    )
+    .await;
+    let answer = recv_type(&mut alice, "ai").await;
+    assert_eq!(answer["from"], alice_name);
+    assert_eq!(answer["response"], "geheim");
+    assert_eq!(answer["private"], true);
+    assert_eq!(answer["model"], "echo-1");
+    assert_eq!(answer["finishReason"], "stop");
+
+    send(&mut bob, json!({ "type": "ping" })).await;
+    assert_eq!(recv(&mut bob).await["type"], "pong");
+
+    // A room question goes to everyone and isn't marked private
+    send(&mut alice, json!({ "type": "ai", "prompt": "openbaar" })).await;
+    let answer = recv_type(&mut bob, "ai").await;
+    assert_eq!(answer["response"], "openbaar");
+    assert!(answer.get("private").is_none());
+}
+
+#[tokio::test]
+async fn private_and_room_ai_share_the_rate_limit() {
+    let config = AiConfig {
+        rate_limit: 1,
+        ..ai_config()
+    };
+    let addr = start_ai_server(config, Box::new(EchoProvider)).await;
+    let (mut alice, _) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "ai", "prompt": "een" })).await;
+    recv_type(&mut alice, "ai").await;
+    // Let the finished AI task deregister itself
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    send(&mut alice, json!({ "type": "aiPrivate", "prompt": "twee" })).await;
+    let error = recv_type(&mut alice, "error").await;
+    assert_eq!(
+        error["message"],
+        messages::text(Msg::AiRateLimited, &[("max", &1), ("secs", &60)])
+    );
+}
+
+#[tokio::test]
+async fn answered_ai_questions_are_audited() {
+    let path = std::env::temp_dir().join(format!("ai-audit-{}.jsonl", uuid::Uuid::new_v4()));
+    let config = AiConfig {
+        rate_limit: 1,
+        audit_log: Some(path.to_string_lossy().into_owned()),
+        ..ai_config()
+    };
+    let addr = start_ai_server(config, Box::new(EchoProvider)).await;
+    let (mut alice, alice_name) = connect(addr).await;
+
+    send(
+        &mut alice,
+        json!({ "type": "aiPrivate", "prompt": " hoi " }),
+    )
+    .await;
+    recv_type(&mut alice, "ai").await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Rate limited: never reaches the provider, so it isn't audited
+    send(&mut alice, json!({ "type": "ai", "prompt": "nog een" })).await;
+    recv_type(&mut alice, "error").await;
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let lines: Vec<Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["name"], alice_name);
+    assert_eq!(lines[0]["ip"], "127.0.0.1");
+    // The model that answered, not the configured one
+    assert_eq!(lines[0]["model"], "echo-1");
+    assert_eq!(lines[0]["prompt"], "hoi");
+    assert_eq!(lines[0]["response"], "hoi");
+    assert_eq!(lines[0]["private"], true);
+    assert!(lines[0]["userId"].is_string());
+}
+
+#[tokio::test]
+async fn ping_flood_is_throttled() {
+    let addr = start_server().await;
+    let (mut alice, _) = connect(addr).await;
+
+    for i in 0..25 {
+        send(
+            &mut alice,
+            json!({ "type": "ping", "token": i.to_string() }),
+        )
+        .await;
+    }
+    send(&mut alice, json!({ "type": "whoami" })).await;
+
+    // Pings over the limit (20 per second in tests) are dropped without an answer
+    let mut pongs = 0;
+    loop {
+        let msg = recv(&mut alice).await;
+        match msg["type"].as_str() {
+            Some("pong") => pongs += 1,
+            Some("whoami") => break,
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+    assert_eq!(pongs, 20);
+}
+
+#[tokio::test]
+async fn admin_announcement_reaches_everyone() {
+    let mut state = app_state();
+    state.admin = AdminConfig {
+        token: Some("geheim".to_string()),
+        bot_token: None,
+    };
+    let addr = start_server_with(state).await;
+    let (mut admin, _) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+
+    // Without auth it's refused
+    send(
+        &mut bob,
+        json!({ "type": "announce", "text": "ik ben de baas" }),
+    )
+    .await;
+    assert_eq!(
+        recv_type(&mut bob, "error").await["message"],
+        messages::text(Msg::AdminRequired, &[])
+    );
+
+    send(&mut admin, json!({ "type": "auth", "token": "geheim" })).await;
+    recv_type(&mut admin, "system").await;
+    send(
+        &mut admin,
+        json!({ "type": "announce", "text": "Onderhoud om 22:00" }),
+    )
+    .await;
+
+    for ws in [&mut admin, &mut bob] {
+        let announcement = recv_type(ws, "announcement").await;
+        assert_eq!(announcement["text"], "Onderhoud om 22:00");
+        assert!(announcement["at"].is_number());
+    }
+}
+
+#[tokio::test]
+async fn admin_purge_deletes_a_users_latest_messages() {
+    let mut state = app_state();
+    state.admin = AdminConfig {
+        token: Some("geheim".to_string()),
+        bot_token: None,
+    };
+    let addr = start_server_with(state).await;
+    let (mut admin, _) = connect(addr).await;
+    let (mut spammer, spammer_name) = connect(addr).await;
+    recv_type(&mut admin, "presence").await;
+
+    let mut ids = Vec::new();
+    for text in ["koop nu", "koop nu!", "koop nu!!"] {
+        send(&mut spammer, json!({ "type": "chat", "text": text })).await;
+        ids.push(recv_type(&mut admin, "chat").await["id"].as_u64().unwrap());
+    }
+
+    let purge = json!({ "type": "purge", "name": spammer_name, "lastN": 2 });
+    send(&mut spammer, purge.clone()).await;
+    assert_eq!(
+        recv_type(&mut spammer, "error").await["message"],
+        messages::text(Msg::AdminRequired, &[])
+    );
+
+    send(&mut admin, json!({ "type": "auth", "token": "geheim" })).await;
+    recv_type(&mut admin, "system").await;
+    send(
+        &mut admin,
+        json!({ "type": "purge", "name": spammer_name, "lastN": 1000 }),
+    )
+    .await;
+    assert_eq!(
+        recv_type(&mut admin, "error").await["message"],
+        messages::text(Msg::PurgeCount, &[("max", &MAX_PURGE)])
+    );
+
+    send(&mut admin, purge).await;
+    assert_eq!(recv_type(&mut spammer, "delete").await["id"], ids[1]);
+    assert_eq!(recv_type(&mut spammer, "delete").await["id"], ids[2]);
+    // The confirmation is sent directly, so it may overtake the broadcast deletes
+    let (mut deleted, mut confirmation) = (Vec::new(), None);
+    while deleted.len() < 2 || confirmation.is_none() {
+        let msg = recv(&mut admin).await;
+        match msg["type"].as_str() {
+            Some("delete") => deleted.push(msg["id"].as_u64().unwrap()),
+            Some("system") => confirmation = Some(msg["text"].clone()),
+            _ => {}
+        }
+    }
+    assert_eq!(deleted, ids[1..]);
+    assert_eq!(
+        confirmation.unwrap(),
+        messages::text(Msg::Purged, &[("count", &2), ("name", &spammer_name)])
+    );
+
+    // Gone from the history too
+    send(&mut admin, json!({ "type": "backfill", "since": 0 })).await;
+    let backfill = recv_type(&mut admin, "backfill").await;
+    let texts: Vec<&Value> = backfill["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| &m["text"])
+        .collect();
+    assert_eq!(texts, [&json!("koop nu")]);
+}
+
+#[tokio::test]
+async fn list_users_marks_admins() {
+    let mut state = app_state();
+    state.admin = AdminConfig {
+        token: Some("geheim".to_string()),
+        bot_token: None,
+    };
+    let addr = start_server_with(state).await;
+    let (mut admin, admin_name) = connect(addr).await;
+    let (mut bob, bob_name) = connect(addr).await;
+
+    send(&mut admin, json!({ "type": "auth", "token": "geheim" })).await;
+    recv_type(&mut admin, "system").await;
+
+    for (name, expected) in [(admin_name, Some(true)), (bob_name, None)] {
+        send(&mut bob, json!({ "type": "listUsers", "filter": name })).await;
+        let users = recv_type(&mut bob, "listUsers").await;
+        assert_eq!(users["users"][0]["admin"].as_bool(), expected);
+    }
+}
+
+#[tokio::test]
+async fn subscribed_admin_gets_events_instead_of_chat() {
+    let mut state = app_state();
+    state.admin = AdminConfig {
+        token: Some("geheim".to_string()),
+        bot_token: None,
+    };
+    let addr = start_server_with(state).await;
+    let (mut observer, _) = connect(addr).await;
+
+    send(
+        &mut observer,
+        json!({ "type": "subscribe", "events": ["chat"] }),
+    )
+    .await;
+    assert_eq!(
+        recv_type(&mut observer, "error").await["message"],
+        messages::text(Msg::AdminRequired, &[])
+    );
+
+    send(&mut observer, json!({ "type": "auth", "token": "geheim" })).await;
+    recv_type(&mut observer, "system").await;
+    send(
+        &mut observer,
+        json!({ "type": "subscribe", "events": ["join", "chat", "typo"] }),
+    )
+    .await;
+    assert_eq!(
+        recv_type(&mut observer, "error").await["message"],
+        messages::text(Msg::UnknownEvent, &[("event", &"typo")])
+    );
+    send(
+        &mut observer,
+        json!({ "type": "subscribe", "events": ["join", "chat"] }),
+    )
+    .await;
+    recv_type(&mut observer, "system").await;
+
+    let (mut alice, alice_name) = connect(addr).await;
+    send(&mut alice, json!({ "type": "chat", "text": "hallo" })).await;
+    recv_type(&mut alice, "chat").await;
+
+    // Only events arrive: no join notice, presence or chat message
+    let join = recv(&mut observer).await;
+    assert_eq!(join["type"], "event");
+    assert_eq!(join["event"], "join");
+    assert_eq!(join["name"], alice_name.as_str());
+    assert!(join["clientId"].is_string());
+    let chat = recv(&mut observer).await;
+    assert_eq!(chat["type"], "event");
+    assert_eq!(chat["event"], "chat");
+    assert_eq!(chat["text"], "hallo");
+
+    // An empty list goes back to the chat
+    send(&mut observer, json!({ "type": "subscribe", "events": [] })).await;
+    assert_eq!(
+        recv_type(&mut observer, "system").await["text"],
+        messages::text(Msg::ObservingStopped, &[])
+    );
+    send(&mut alice, json!({ "type": "chat", "text": "weer terug" })).await;
+    assert_eq!(recv_type(&mut observer, "chat").await["text"], "weer terug");
+}
+
+#[tokio::test]
+async fn set_name_to_a_taken_name_is_refused() {
+    let addr = start_server().await;
+    let (mut alice, _) = connect(addr).await;
+    let (mut bob, _) = connect(addr).await;
+
+    send(&mut alice, json!({ "type": "setName", "name": "Bas" })).await;
+    recv_type(&mut alice, "ackName").await;
+
+    send(&mut bob, json!({ "type": "setName", "name": "Bas" })).await;
+    assert_eq!(
+        recv_type(&mut bob, "error").await["message"],
+        messages::text(Msg::NameTaken, &[("name", &"Bas")])
+    );
+}
+
+#[tokio::test]
+async fn bots_skip_the_chat_rate_limit() {
+    let mut state = app_state();
+    state.admin.bot_token = Some("robot".to_string());
+    state.rate_limit.enabled = true;
+    state.rate_limit.messages_per_minute = 1;
+    let addr = start_server_with(state).await;
+    let (mut bot, bot_name) = connect(addr).await;
+
+    send(&mut bot, json!({ "type": "auth", "token": "robot" })).await;
+    assert_eq!(
+        recv_type(&mut bot, "system").await["text"],
+        messages::text(Msg::BotGranted, &[])
+    );
+    let (mut human, _) = connect(addr).await;
+    for i in 0..3 {
+        send(
+            &mut bot,
+            json!({ "type": "chat", "text": format!("relay {i}") }),
+        )
+        .await;
+        assert_eq!(
+            recv_type(&mut bot, "chat").await["text"],
+            format!("relay {i}")
+        );
+    }
+
+    // Regular users still get the normal limit
+    send(&mut human, json!({ "type": "chat", "text": "een" })).await;
+    send(&mut human, json!({ "type": "chat", "text": "twee" })).await;
+    recv_type(&mut human, "rateLimited").await;
+
+    send(
+        &mut human,
+        json!({ "type": "listUsers", "filter": bot_name }),
+    )
+    .await;
+    let users = recv_type(&mut human, "listUsers").await;
+    assert_eq!(users["users"][0]["bot"], true);
+}
+
+#[tokio::test]
+async fn file_offer_goes_only_to_the_target_and_the_sender() {
+    let addr = start_server().await;
+    let (mut alice, alice_name) = connect(addr).await;
+    let (mut bob, bob_name) = connect(addr).await;
+    let (mut carol, _) = connect(addr).await;
+
+    let offer = json!({
+        "type": "fileOffer",
+        "to": bob_name,
+        "name": "notulen.pdf",
+        "size": 482133,
+        "url": "https://files.example.com/notulen.pdf"
+    });
+    send(&mut alice, offer).await;
+    for ws in [&mut bob, &mut alice] {
+        let received = recv_type(ws, "fileOffer").await;
+        assert_eq!(received["from"], alice_name);
+        assert_eq!(received["to"], bob_name);
+        assert_eq!(received["name"], "notulen.pdf");
+        assert_eq!(received["size"], 482133);
+        assert_eq!(received["url"], "https://files.example.com/notulen.pdf");
+    }
+
+    // An offer to Carol would arrive before the reply to her later ping
+    send(&mut carol, json!({ "type": "ping" })).await;
+    loop {
+        let msg = recv(&mut carol).await;
+        assert_ne!(msg["type"], "fileOffer");
+        if msg["type"] == "pong" {
+            break;
+        }
+    }
+
+    send(
+        &mut alice,
+        json!({ "type": "fileOffer", "to": "niemand", "name": "a.txt", "size": 1, "url": "https://x.nl/a.txt" }),
+    )
+    .await;
+    assert_eq!(
+        recv_type(&mut alice, "error").await["message"],
+        messages::text(Msg::UserNotFound, &[("name", &"niemand")])
+    );
+}
+
+#[tokio::test]
+async fn file_offers_count_against_the_rate_limit() {
+    let mut state = app_state();
+    state.rate_limit.enabled = true;
+    state.rate_limit.messages_per_minute = 1;
+    let addr = start_server_with(state).await;
+    let (mut alice, _) = connect(addr).await;
+    let (_bob, bob_name) = connect(addr).await;
+    let offer = |to: &str| json!({ "type": "fileOffer", "to": to, "name": "a.txt", "size": 1, "url": "https://x.nl/a.txt" });
+
+    // An offer to nobody doesn't use up the limit
+    send(&mut alice, offer("niemand")).await;
+    recv_type(&mut alice, "error").await;
+    send(&mut alice, offer(&bob_name)).await;
+    recv_type(&mut alice, "fileOffer").await;
+    send(&mut alice, offer(&bob_name)).await;
+    recv_type(&mut alice, "rateLimited").await;
+}
+
+#[tokio::test]
+async fn admin_stats_show_the_user_agent() {
+    let mut state = app_state();
+    state.admin = AdminConfig {
+        token: Some("geheim".to_string()),
+        bot_token: None,
+    };
+    let addr = start_server_with(state).await;
+    let mut request = format!("ws://{addr}").into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("User-Agent", HeaderValue::from_static("cbxchat-cli/0.1.1"));
+    let (mut admin, _) = connect_async(request).await.unwrap();
+    let admin_name = recv_type(&mut admin, "ackName").await["name"].clone();
+    let (_plain, plain_name) = connect(addr).await;
+
+    send(&mut admin, json!({ "type": "auth", "token": "geheim" })).await;
+    recv_type(&mut admin, "system").await;
+    send(&mut admin, json!({ "type": "stats" })).await;
+    let stats = recv_type(&mut admin, "stats").await;
+    let users = stats["users"].as_array().unwrap();
+    let user = |name: &Value| users.iter().find(|u| u["name"] == *name).unwrap();
+    assert_eq!(user(&admin_name)["userAgent"], "cbxchat-cli/0.1.1");
+    // tungstenite sends no User-Agent of its own
+    assert!(user(&json!(plain_name)).get("userAgent").is_none());
+}
@@ -0,0 +1,150 @@
+//! The sockets the server accepts connections on.
+//!
+//! There is always a plaintext listener on `WS_PORT`. With `WSS_PORT` a TLS listener
+//! runs next to it, for example plaintext on a local port for health checks and WSS on
+//! the public one. Every listener serves the same router and `AppState`, so clients
+//! on either are in the same chat.
+
+use std::{future::Future, io, net::SocketAddr, time::Duration};
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use tokio::{net::TcpListener, task::JoinSet};
+use tracing::info;
+
+/// How long open connections get to finish once shutdown starts
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// The TLS listener, when `WSS_PORT` is set
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsConfig {
+    pub port: u16,
+    /// PEM certificate chain
+    pub cert_file: String,
+    /// PEM private key
+    pub key_file: String,
+}
+
+impl TlsConfig {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let tls = Self::from_vars(|key| std::env::var(key).ok())?;
+        if let Some(tls) = &tls {
+            info!(port = tls.port, cert_file = %tls.cert_file, "TLS listener configured");
+        }
+        Ok(tls)
+    }
+
+    /// `None` without `WSS_PORT`; an error when it is invalid or the certificate or
+    /// key is missing
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let Some(port) = var("WSS_PORT") else {
+            return Ok(None);
+        };
+        let port = port
+            .parse()
+            .map_err(|_| format!("WSS_PORT={port:?} is not a port number"))?;
+        let file = |key| var(key).ok_or_else(|| format!("WSS_PORT is set but {key} is not"));
+        Ok(Some(Self {
+            port,
+            cert_file: file("TLS_CERT_FILE")?,
+            key_file: file("TLS_KEY_FILE")?,
+        }))
+    }
+}
+
+pub enum Listener {
+    Plain(TcpListener),
+    Tls(TcpListener, RustlsConfig),
+}
+
+impl Listener {
+    pub async fn plain(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Listener::Plain(TcpListener::bind(addr).await?))
+    }
+
+    /// Binds `0.0.0.0` on the configured port, after loading the certificate and key
+    pub async fn tls(tls: &TlsConfig) -> io::Result<Self> {
+        let config = RustlsConfig::from_pem_file(&tls.cert_file, &tls.key_file).await?;
+        let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], tls.port))).await?;
+        Ok(Listener::Tls(listener, config))
+    }
+}
+
+/// Serve `app` on all listeners until `shutdown` completes or one of them fails, then
+/// shut all of them down
+pub async fn serve(
+    listeners: Vec<Listener>,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let mut handles = Vec::new();
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let handle = Handle::new();
+        handles.push(handle.clone());
+        let service = app
+            .clone()
+            .into_make_service_with_connect_info::<SocketAddr>();
+        match listener {
+            Listener::Plain(listener) => {
+                let server = axum_server::from_tcp(listener.into_std()?).handle(handle);
+                servers.spawn(server.serve(service));
+            }
+            Listener::Tls(listener, config) => {
+                let server =
+                    axum_server::from_tcp_rustls(listener.into_std()?, config).handle(handle);
+                servers.spawn(server.serve(service));
+            }
+        }
+    }
+
+    let result = tokio::select! {
+        _ = shutdown => Ok(()),
+        Some(stopped) = servers.join_next() => stopped.map_err(io::Error::other).and_then(|r| r),
+    };
+    for handle in &handles {
+        handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+    }
+    while let Some(stopped) = servers.join_next().await {
+        stopped.map_err(io::Error::other)??;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn tls_needs_a_port_certificate_and_key() {
+        assert_eq!(TlsConfig::from_vars(vars(&[])), Ok(None));
+        assert_eq!(
+            TlsConfig::from_vars(vars(&[
+                ("WSS_PORT", "3443"),
+                ("TLS_CERT_FILE", "cert.pem"),
+                ("TLS_KEY_FILE", "key.pem"),
+            ])),
+            Ok(Some(TlsConfig {
+                port: 3443,
+                cert_file: "cert.pem".to_string(),
+                key_file: "key.pem".to_string(),
+            }))
+        );
+        assert_eq!(
+            TlsConfig::from_vars(vars(&[("WSS_PORT", "3443"), ("TLS_CERT_FILE", "cert.pem")])),
+            Err("WSS_PORT is set but TLS_KEY_FILE is not".to_string())
+        );
+        assert_eq!(
+            TlsConfig::from_vars(vars(&[("WSS_PORT", "https")])),
+            Err("WSS_PORT=\"https\" is not a port number".to_string())
+        );
+    }
+}
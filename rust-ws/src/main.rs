@@ -1,24 +1,61 @@
 mod ai;
+mod batch;
+mod clock;
+mod config_check;
+mod env_file;
+mod events;
+mod guest_names;
 mod handlers;
+mod history;
+#[cfg(test)]
+mod integration_tests;
+mod listeners;
+mod messages;
 mod protocol;
+mod resume;
 mod state;
 mod utils;
+mod validation;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{routing::get, Router};
-use tokio::net::TcpListener;
 use tracing::info;
 
 use ai::{AiClient, AiConfig};
-use handlers::ws_handler;
-use state::{AppState, RateLimitConfig};
-
-#[tokio::main]
-async fn main() {
-    // Load .env file if present
-    let _ = dotenvy::dotenv();
+use batch::ChatBatchConfig;
+use clock::SystemClock;
+use env_file::{Args, EnvFile};
+use guest_names::GuestNameStyle;
+use handlers::{spawn_status_broadcast, ws_handler};
+use history::HistoryConfig;
+use listeners::{Listener, TlsConfig};
+use resume::ResumeConfig;
+use state::{
+    AdminConfig, AppState, ConnectionLimitConfig, FloodCollapseConfig, JoinLeaveConfig,
+    RateLimitConfig, SlowClientConfig, SpectatorConfig,
+};
+
+fn main() {
+    // Settings from a .env file (or --env-file), before anything reads the environment.
+    // This sets environment variables, so it runs before the runtime starts its threads.
+    let args = env_file::parse_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(2);
+    });
+    let env_file = env_file::load(args.env_file.as_deref()).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(2);
+    });
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("start tokio runtime")
+        .block_on(run(args, env_file));
+}
 
+async fn run(args: Args, env_file: Option<EnvFile>) {
     // Default: no logging (warn level). Use RUST_LOG=info or RUST_LOG=debug for output.
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -28,11 +65,27 @@ async fn main() {
         .with_target(false)
         .init();
 
-    let port = std::env::var("WS_PORT")
-        .ok()
-        .and_then(|p| p.parse::<u16>().ok())
-        .unwrap_or(3001);
+    if let Some(env_file) = &env_file {
+        env_file.log();
+    }
+
+    // Dry run: validate and print the configuration without binding the listener
+    let check_config = std::env::var("CHECK_CONFIG")
+        .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+        .unwrap_or(false);
+    if args.check_config || check_config {
+        std::process::exit(config_check::run(env_file.as_ref()));
+    }
+
+    messages::init(messages::Locale::from_env());
+    validation::init(validation::ValidationConfig::from_env());
+
+    let port = port();
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let tls = TlsConfig::from_env().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(2);
+    });
 
     // Initialize AI client
     let ai_config = AiConfig::from_env();
@@ -40,23 +93,83 @@ async fn main() {
 
     // Initialize rate limiting
     let rate_limit = RateLimitConfig::from_env();
+    let flood_collapse = FloodCollapseConfig::from_env();
+    let admin = AdminConfig::from_env();
+    let slow_client = SlowClientConfig::from_env();
+
+    // Recent messages kept for edit/delete
+    let history = HistoryConfig::from_env();
+
+    let state = AppState::new(
+        ai_client,
+        rate_limit,
+        flood_collapse,
+        admin,
+        slow_client,
+        history,
+        Arc::new(SystemClock),
+    )
+    .with_resume(ResumeConfig::from_env())
+    .with_join_leave(JoinLeaveConfig::from_env())
+    .with_connection_limit(ConnectionLimitConfig::from_env())
+    .with_spectators(SpectatorConfig::from_env())
+    .with_guest_names(GuestNameStyle::from_env())
+    .with_chat_batch(ChatBatchConfig::from_env());
+    state.spawn_resume_cleanup(Duration::from_secs(60));
+
+    let system_stats_secs = system_stats_secs();
+    info!(system_stats_secs, "System stats refresh interval");
+    state.spawn_system_refresh(Duration::from_secs(system_stats_secs));
+
+    let status_broadcast_secs = status_broadcast_secs();
+    if status_broadcast_secs > 0 {
+        info!(status_broadcast_secs, "Periodic status broadcast enabled");
+        spawn_status_broadcast(state.clone(), Duration::from_secs(status_broadcast_secs));
+    }
 
-    let state = AppState::new(ai_client, rate_limit);
+    let mut listeners = vec![Listener::plain(addr).await.expect("bind to address")];
+    if let Some(tls) = &tls {
+        listeners.push(Listener::tls(tls).await.expect("start TLS listener"));
+    }
+    info!(
+        port,
+        wss_port = tls.map(|tls| tls.port),
+        "Rust WS server start"
+    );
 
-    let app = Router::new().route("/", get(ws_handler)).with_state(state);
+    listeners::serve(listeners, app(state), shutdown_signal())
+        .await
+        .expect("run ws server");
 
-    let listener = TcpListener::bind(addr).await.expect("bind to address");
-    info!(port, "Rust WS server start");
+    info!("Server shut down gracefully");
+}
 
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .with_graceful_shutdown(shutdown_signal())
-    .await
-    .expect("start ws server");
+fn port() -> u16 {
+    std::env::var("WS_PORT")
+        .ok()
+        .and_then(|p| p.parse::<u16>().ok())
+        .unwrap_or(3001)
+}
 
-    info!("Server shut down gracefully");
+/// Memory and CPU in status are sampled on this interval instead of per request
+fn system_stats_secs() -> u64 {
+    std::env::var("SYSTEM_STATS_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(5)
+}
+
+/// Optional periodic status push; 0 (default) disables it
+fn status_broadcast_secs() -> u64 {
+    std::env::var("STATUS_BROADCAST_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
+fn app(state: AppState) -> Router {
+    Router::new().route("/", get(ws_handler)).with_state(state)
 }
 
 async fn shutdown_signal() {
@@ -0,0 +1,513 @@
+//! User-facing server texts per locale.
+//!
+//! Templates use `{name}`-style placeholders that are filled in by [`text`].
+
+use std::{fmt::Display, sync::OnceLock};
+
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    Nl,
+}
+
+impl Locale {
+    pub fn from_env() -> Self {
+        let locale = match std::env::var("SERVER_LOCALE") {
+            Ok(v) if v.eq_ignore_ascii_case("en") => Locale::En,
+            Ok(v) if v.eq_ignore_ascii_case("nl") => Locale::Nl,
+            Ok(v) => {
+                warn!(locale = %v, "Unknown SERVER_LOCALE, falling back to nl");
+                Locale::Nl
+            }
+            Err(_) => Locale::Nl,
+        };
+        info!(?locale, "Server locale");
+        locale
+    }
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// Set the locale for all server texts. Only the first call has effect.
+pub fn init(locale: Locale) {
+    let _ = LOCALE.set(locale);
+}
+
+fn locale() -> Locale {
+    LOCALE.get().copied().unwrap_or(Locale::Nl)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Msg {
+    Joined,
+    Left,
+    Renamed,
+    InvalidJson,
+    InvalidMsgPack,
+    InternalError,
+    UnknownUser,
+    MessageEmpty,
+    MessageTooLong,
+    MessageControlChars,
+    DuplicateIgnored,
+    RateLimited,
+    NameLength,
+    NameChars,
+    NameInvisible,
+    NameTaken,
+    ResumeInvalid,
+    Resumed,
+    UnknownUserSort,
+    MessageNotFound,
+    ReplyNotFound,
+    NotOwnMessage,
+    EditTooOld,
+    AiDisabled,
+    AiInProgress,
+    AiCancelled,
+    NoAiInProgress,
+    AiPromptEmpty,
+    AiPromptTooLong,
+    AiRateLimited,
+    AiServerBudget,
+    AiUserBudget,
+    AiCostLimited,
+    AiTimeout,
+    AiUnavailable,
+    AiServiceError,
+    AiInvalidResponse,
+    AiNoAnswer,
+    AiFiltered,
+    AiFailedNotice,
+    AdminGranted,
+    BotGranted,
+    AdminInvalidToken,
+    AdminNotConfigured,
+    AdminRequired,
+    SpectatorReadOnly,
+    UnknownEvent,
+    Observing,
+    ObservingStopped,
+    PurgeCount,
+    Purged,
+    UserNotFound,
+    FileOfferSelf,
+    FileNameInvalid,
+    FileSizeInvalid,
+    FileUrlInvalid,
+    ServerFull,
+}
+
+impl Msg {
+    #[cfg(test)]
+    const ALL: &'static [Msg] = &[
+        Msg::Joined,
+        Msg::Left,
+        Msg::Renamed,
+        Msg::InvalidJson,
+        Msg::InvalidMsgPack,
+        Msg::InternalError,
+        Msg::UnknownUser,
+        Msg::MessageEmpty,
+        Msg::MessageTooLong,
+        Msg::MessageControlChars,
+        Msg::DuplicateIgnored,
+        Msg::RateLimited,
+        Msg::NameLength,
+        Msg::NameChars,
+        Msg::NameInvisible,
+        Msg::NameTaken,
+        Msg::ResumeInvalid,
+        Msg::Resumed,
+        Msg::UnknownUserSort,
+        Msg::MessageNotFound,
+        Msg::ReplyNotFound,
+        Msg::NotOwnMessage,
+        Msg::EditTooOld,
+        Msg::AiDisabled,
+        Msg::AiInProgress,
+        Msg::AiCancelled,
+        Msg::NoAiInProgress,
+        Msg::AiPromptEmpty,
+        Msg::AiPromptTooLong,
+        Msg::AiRateLimited,
+        Msg::AiServerBudget,
+        Msg::AiUserBudget,
+        Msg::AiCostLimited,
+        Msg::AiTimeout,
+        Msg::AiUnavailable,
+        Msg::AiServiceError,
+        Msg::AiInvalidResponse,
+        Msg::AiNoAnswer,
+        Msg::AiFiltered,
+        Msg::AiFailedNotice,
+        Msg::AdminGranted,
+        Msg::BotGranted,
+        Msg::AdminInvalidToken,
+        Msg::AdminNotConfigured,
+        Msg::AdminRequired,
+        Msg::SpectatorReadOnly,
+        Msg::UnknownEvent,
+        Msg::Observing,
+        Msg::ObservingStopped,
+        Msg::PurgeCount,
+        Msg::Purged,
+        Msg::UserNotFound,
+        Msg::FileOfferSelf,
+        Msg::FileNameInvalid,
+        Msg::FileSizeInvalid,
+        Msg::FileUrlInvalid,
+        Msg::ServerFull,
+    ];
+}
+
+const NL: &[(Msg, &str)] = &[
+    (Msg::Joined, "{name} heeft de chat betreden."),
+    (Msg::Left, "{name} heeft de chat verlaten."),
+    (Msg::Renamed, "{old} heet nu {new}."),
+    (Msg::InvalidJson, "Bericht moet geldig JSON zijn."),
+    (Msg::InvalidMsgPack, "Bericht moet geldig MessagePack zijn."),
+    (
+        Msg::InternalError,
+        "Er ging iets mis bij het verwerken van je bericht.",
+    ),
+    (Msg::UnknownUser, "Onbekende gebruiker."),
+    (Msg::MessageEmpty, "Bericht mag niet leeg zijn."),
+    (
+        Msg::MessageTooLong,
+        "Bericht is te lang (max {max} tekens).",
+    ),
+    (
+        Msg::MessageControlChars,
+        "Bericht mag geen stuurtekens of escape-codes bevatten.",
+    ),
+    (Msg::DuplicateIgnored, "Dubbel bericht genegeerd."),
+    (
+        Msg::RateLimited,
+        "Rate limit overschreden. Wacht {secs} seconden.",
+    ),
+    (Msg::NameLength, "Naam moet tussen 2 en 32 tekens zijn."),
+    (
+        Msg::NameChars,
+        "Naam mag alleen letters, cijfers, spaties, - en _ bevatten.",
+    ),
+    (
+        Msg::NameInvisible,
+        "Naam mag geen stuurtekens of onzichtbare tekens bevatten.",
+    ),
+    (Msg::NameTaken, "De naam {name} is al in gebruik."),
+    (
+        Msg::ResumeInvalid,
+        "Sessie kan niet worden hervat: het token is onbekend of verlopen.",
+    ),
+    (Msg::Resumed, "{name} is terug (was {old})."),
+    (
+        Msg::UnknownUserSort,
+        "Onbekende sortering '{sort}'. Gebruik name of connected.",
+    ),
+    (Msg::MessageNotFound, "Bericht {id} niet gevonden."),
+    (
+        Msg::ReplyNotFound,
+        "Bericht {id} om op te reageren is niet (meer) beschikbaar.",
+    ),
+    (
+        Msg::NotOwnMessage,
+        "Je kunt alleen je eigen berichten wijzigen.",
+    ),
+    (
+        Msg::EditTooOld,
+        "Bericht {id} is te oud om te bewerken (max {secs} seconden).",
+    ),
+    (Msg::AiDisabled, "AI is niet geactiveerd op deze server."),
+    (
+        Msg::AiInProgress,
+        "Er loopt al een AI vraag. Gebruik /cancel om die af te breken.",
+    ),
+    (Msg::AiCancelled, "AI vraag geannuleerd."),
+    (Msg::NoAiInProgress, "Er loopt geen AI vraag."),
+    (
+        Msg::AiPromptEmpty,
+        "Geef een vraag op. Gebruik: /ai <vraag>",
+    ),
+    (Msg::AiPromptTooLong, "Vraag is te lang (max {max} tekens)."),
+    (
+        Msg::AiRateLimited,
+        "Rate limit bereikt (max {max}/min). Probeer over {secs} seconden.",
+    ),
+    (
+        Msg::AiServerBudget,
+        "Het dagelijkse AI budget van de server is bereikt. Probeer het morgen opnieuw.",
+    ),
+    (
+        Msg::AiUserBudget,
+        "Je dagelijkse AI budget is bereikt. Probeer het morgen opnieuw.",
+    ),
+    (
+        Msg::AiCostLimited,
+        "AI budget per minuut op (${spent} van ${max}). Probeer over {secs} seconden.",
+    ),
+    (Msg::AiTimeout, "AI vraag verlopen na {secs} seconden."),
+    (Msg::AiUnavailable, "AI service tijdelijk niet beschikbaar."),
+    (Msg::AiServiceError, "AI service fout: {status}"),
+    (Msg::AiInvalidResponse, "Kon AI antwoord niet verwerken."),
+    (Msg::AiNoAnswer, "Geen antwoord ontvangen."),
+    (
+        Msg::AiFiltered,
+        "Het AI antwoord is tegengehouden door een contentfilter.",
+    ),
+    (Msg::AiFailedNotice, "AI vraag van {name} is mislukt."),
+    (Msg::AdminGranted, "Je bent nu admin."),
+    (Msg::BotGranted, "Je bent nu aangemeld als bot."),
+    (Msg::AdminInvalidToken, "Ongeldig admin token."),
+    (
+        Msg::AdminNotConfigured,
+        "Admin toegang is niet geconfigureerd op deze server.",
+    ),
+    (Msg::AdminRequired, "Alleen voor admins."),
+    (
+        Msg::SpectatorReadOnly,
+        "Je kijkt mee als toeschouwer en kunt niets versturen.",
+    ),
+    (
+        Msg::UnknownEvent,
+        "Onbekend event '{event}'. Gebruik join, leave, chat, edit, delete, rename, ai of error.",
+    ),
+    (Msg::Observing, "Je ontvangt nu events: {events}."),
+    (
+        Msg::ObservingStopped,
+        "Events gestopt, je ontvangt weer de chat.",
+    ),
+    (Msg::PurgeCount, "lastN moet tussen 1 en {max} liggen."),
+    (Msg::Purged, "{count} bericht(en) van {name} verwijderd."),
+    (Msg::UserNotFound, "Gebruiker '{name}' is niet online."),
+    (
+        Msg::FileOfferSelf,
+        "Je kunt geen bestand aan jezelf aanbieden.",
+    ),
+    (
+        Msg::FileNameInvalid,
+        "Bestandsnaam moet 1-{max} tekens zijn, zonder pad of onzichtbare tekens.",
+    ),
+    (
+        Msg::FileSizeInvalid,
+        "Bestandsgrootte moet tussen 1 byte en {max} GB liggen.",
+    ),
+    (
+        Msg::FileUrlInvalid,
+        "Download URL moet een geldige http(s) URL zijn.",
+    ),
+    (Msg::ServerFull, "Server vol, probeer het later opnieuw."),
+];
+
+const EN: &[(Msg, &str)] = &[
+    (Msg::Joined, "{name} joined the chat."),
+    (Msg::Left, "{name} left the chat."),
+    (Msg::Renamed, "{old} is now known as {new}."),
+    (Msg::InvalidJson, "Message must be valid JSON."),
+    (Msg::InvalidMsgPack, "Message must be valid MessagePack."),
+    (
+        Msg::InternalError,
+        "Something went wrong while processing your message.",
+    ),
+    (Msg::UnknownUser, "Unknown user."),
+    (Msg::MessageEmpty, "Message cannot be empty."),
+    (
+        Msg::MessageTooLong,
+        "Message is too long (max {max} characters).",
+    ),
+    (
+        Msg::MessageControlChars,
+        "Message may not contain control characters or escape codes.",
+    ),
+    (Msg::DuplicateIgnored, "Duplicate message ignored."),
+    (
+        Msg::RateLimited,
+        "Rate limit exceeded. Please wait {secs} seconds.",
+    ),
+    (Msg::NameLength, "Name must be between 2 and 32 characters."),
+    (
+        Msg::NameChars,
+        "Name may only contain letters, digits, spaces, - and _.",
+    ),
+    (
+        Msg::NameInvisible,
+        "Name may not contain control or invisible characters.",
+    ),
+    (Msg::NameTaken, "The name {name} is already in use."),
+    (
+        Msg::ResumeInvalid,
+        "Session can't be resumed: the token is unknown or expired.",
+    ),
+    (Msg::Resumed, "{name} is back (was {old})."),
+    (
+        Msg::UnknownUserSort,
+        "Unknown sort '{sort}'. Use name or connected.",
+    ),
+    (Msg::MessageNotFound, "Message {id} not found."),
+    (
+        Msg::ReplyNotFound,
+        "Message {id} to reply to is no longer available.",
+    ),
+    (Msg::NotOwnMessage, "You can only change your own messages."),
+    (
+        Msg::EditTooOld,
+        "Message {id} is too old to edit (max {secs} seconds).",
+    ),
+    (Msg::AiDisabled, "AI is not enabled on this server."),
+    (
+        Msg::AiInProgress,
+        "An AI request is already in progress. Use /cancel to abort it.",
+    ),
+    (Msg::AiCancelled, "AI request cancelled."),
+    (Msg::NoAiInProgress, "No AI request in progress."),
+    (
+        Msg::AiPromptEmpty,
+        "Please ask a question. Usage: /ai <question>",
+    ),
+    (
+        Msg::AiPromptTooLong,
+        "Question is too long (max {max} characters).",
+    ),
+    (
+        Msg::AiRateLimited,
+        "Rate limit reached (max {max}/min). Try again in {secs} seconds.",
+    ),
+    (
+        Msg::AiServerBudget,
+        "The server's daily AI budget has been reached. Try again tomorrow.",
+    ),
+    (
+        Msg::AiUserBudget,
+        "Your daily AI budget has been reached. Try again tomorrow.",
+    ),
+    (
+        Msg::AiCostLimited,
+        "AI budget per minute used up (${spent} of ${max}). Try again in {secs} seconds.",
+    ),
+    (Msg::AiTimeout, "AI request timed out after {secs} seconds."),
+    (Msg::AiUnavailable, "AI service temporarily unavailable."),
+    (Msg::AiServiceError, "AI service error: {status}"),
+    (Msg::AiInvalidResponse, "Could not process AI response."),
+    (Msg::AiNoAnswer, "No answer received."),
+    (Msg::AiFiltered, "The AI response was filtered."),
+    (Msg::AiFailedNotice, "AI request failed for {name}."),
+    (Msg::AdminGranted, "You are now an admin."),
+    (Msg::BotGranted, "You are now signed in as a bot."),
+    (Msg::AdminInvalidToken, "Invalid admin token."),
+    (
+        Msg::AdminNotConfigured,
+        "Admin access is not configured on this server.",
+    ),
+    (Msg::AdminRequired, "Admins only."),
+    (
+        Msg::SpectatorReadOnly,
+        "You are watching as a spectator and can't post.",
+    ),
+    (
+        Msg::UnknownEvent,
+        "Unknown event '{event}'. Use join, leave, chat, edit, delete, rename, ai or error.",
+    ),
+    (Msg::Observing, "You now receive events: {events}."),
+    (
+        Msg::ObservingStopped,
+        "Events stopped, you receive the chat again.",
+    ),
+    (Msg::PurgeCount, "lastN must be between 1 and {max}."),
+    (Msg::Purged, "Removed {count} message(s) from {name}."),
+    (Msg::UserNotFound, "User '{name}' is not online."),
+    (Msg::FileOfferSelf, "You can't offer a file to yourself."),
+    (
+        Msg::FileNameInvalid,
+        "File name must be 1-{max} characters, without a path or invisible characters.",
+    ),
+    (
+        Msg::FileSizeInvalid,
+        "File size must be between 1 byte and {max} GB.",
+    ),
+    (
+        Msg::FileUrlInvalid,
+        "Download URL must be a valid http(s) URL.",
+    ),
+    (Msg::ServerFull, "Server full, try again later."),
+];
+
+fn template(locale: Locale, key: Msg) -> Option<&'static str> {
+    let table = match locale {
+        Locale::En => EN,
+        Locale::Nl => NL,
+    };
+    table.iter().find(|(k, _)| *k == key).map(|(_, t)| *t)
+}
+
+/// Render `key` in the server locale, replacing `{name}` placeholders with `args`.
+/// One pass over the template, so braces in a value are left as they are.
+pub fn text(key: Msg, args: &[(&str, &dyn Display)]) -> String {
+    let mut rest = template(locale(), key)
+        .or_else(|| template(Locale::Nl, key))
+        .unwrap_or_default();
+    let mut text = String::with_capacity(rest.len());
+    while let Some(open) = rest.find('{') {
+        text.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest.find('}').and_then(|close| {
+            let (_, value) = args.iter().find(|(name, _)| *name == &rest[1..close])?;
+            Some((close, value))
+        });
+        match value {
+            Some((close, value)) => {
+                text.push_str(&value.to_string());
+                rest = &rest[close + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholders(template: &str) -> Vec<&str> {
+        let mut found: Vec<&str> = template
+            .split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+            .collect();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn every_key_exists_in_both_locales() {
+        for key in Msg::ALL {
+            let nl = template(Locale::Nl, *key);
+            let en = template(Locale::En, *key);
+            assert!(nl.is_some(), "{key:?} missing in nl");
+            assert!(en.is_some(), "{key:?} missing in en");
+            assert_eq!(
+                placeholders(nl.unwrap()),
+                placeholders(en.unwrap()),
+                "{key:?} has different placeholders per locale"
+            );
+        }
+        assert_eq!(NL.len(), Msg::ALL.len());
+        assert_eq!(EN.len(), Msg::ALL.len());
+    }
+
+    #[test]
+    fn placeholders_in_values_are_left_alone() {
+        let template = template(locale(), Msg::Renamed).unwrap();
+        let renamed = text(Msg::Renamed, &[("old", &"{new}"), ("new", &"Bas")]);
+        assert_eq!(
+            renamed,
+            template.replace("{new}", "Bas").replace("{old}", "{new}")
+        );
+    }
+}
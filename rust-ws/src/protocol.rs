@@ -1,101 +1,112 @@
-use serde::{Deserialize, Serialize};
+//! The wire format lives in the shared `chat-protocol` crate; the server reads
+//! [`ClientMessage`]s and writes [`ServerMessage`]s.
 
-#[derive(Debug, Deserialize)]
-#[serde(tag = "type")]
-pub enum Incoming {
-    #[serde(rename = "chat")]
-    Chat { text: String },
-    #[serde(rename = "setName")]
-    SetName { name: String },
-    #[serde(rename = "status")]
-    Status,
-    #[serde(rename = "listUsers")]
-    ListUsers,
-    #[serde(rename = "ping")]
-    Ping { token: Option<String> },
-    #[serde(rename = "ai")]
-    Ai { prompt: String },
+use std::cell::OnceCell;
+
+use axum::extract::ws::Message;
+use tracing::error;
+
+pub use chat_protocol::{
+    ClientMessage as Incoming, ServerMessage as Outgoing, UserInfo, UserStats, PROTOCOL_VERSION,
+    SUBPROTOCOLS, SUBPROTOCOL_MSGPACK,
+};
+
+/// How messages to a client are framed, chosen by the subprotocol it asked for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// JSON in text frames
+    Json,
+    /// MessagePack maps in binary frames (`chat.msgpack`)
+    MsgPack,
 }
 
-#[derive(Debug, Serialize, Clone)]
-#[serde(tag = "type", rename_all = "camelCase")]
-pub enum Outgoing {
-    #[serde(rename = "chat")]
-    Chat {
-        from: String,
-        text: String,
-        at: u128,
-    },
-    #[serde(rename = "system")]
-    System { text: String, at: u128 },
-    #[serde(rename = "ackName")]
-    AckName { name: String, at: u128 },
-    #[serde(rename = "status")]
-    Status {
-        version: &'static str,
-        #[serde(rename = "rustVersion")]
-        rust_version: &'static str,
-        os: &'static str,
-        #[serde(rename = "cpuCores")]
-        cpu_cores: usize,
-        #[serde(rename = "uptimeSeconds")]
-        uptime_seconds: u64,
-        #[serde(rename = "userCount")]
-        user_count: usize,
-        #[serde(rename = "peakUsers")]
-        peak_users: usize,
-        #[serde(rename = "connectionsTotal")]
-        connections_total: u64,
-        #[serde(rename = "messagesSent")]
-        messages_sent: u64,
-        #[serde(rename = "messagesPerSecond")]
-        messages_per_second: f64,
-        #[serde(rename = "memoryMb")]
-        memory_mb: f64,
-        #[serde(rename = "aiEnabled")]
-        ai_enabled: bool,
-        #[serde(rename = "aiModel", skip_serializing_if = "Option::is_none")]
-        ai_model: Option<String>,
-    },
-    #[serde(rename = "listUsers")]
-    ListUsers { users: Vec<UserInfo> },
-    #[serde(rename = "error")]
-    Error { message: String },
-    #[serde(rename = "pong")]
-    Pong { token: Option<String>, at: u128 },
-    #[serde(rename = "ai")]
-    Ai {
-        from: String,
-        prompt: String,
-        response: String,
-        #[serde(rename = "responseMs")]
-        response_ms: u64,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        tokens: Option<u32>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        cost: Option<f64>,
-        at: u128,
-    },
+impl Encoding {
+    pub fn for_protocol(protocol: Option<&str>) -> Self {
+        if protocol == Some(SUBPROTOCOL_MSGPACK) {
+            Encoding::MsgPack
+        } else {
+            Encoding::Json
+        }
+    }
+
+    /// Serialize `payload` into a frame in this encoding; `None` (logged) if that fails
+    pub fn encode(self, payload: &Outgoing) -> Option<Message> {
+        let frame = match self {
+            Encoding::Json => serde_json::to_string(payload)
+                .map(|text| Message::Text(text.into()))
+                .map_err(|err| err.to_string()),
+            Encoding::MsgPack => rmp_serde::to_vec_named(payload)
+                .map(|bytes| Message::Binary(bytes.into()))
+                .map_err(|err| err.to_string()),
+        };
+        frame
+            .inspect_err(
+                |err| error!(%err, kind = %payload.kind(), encoding = ?self, "Serializing failed"),
+            )
+            .ok()
+    }
 }
 
-#[derive(Debug, Serialize, Clone)]
-pub struct UserInfo {
-    pub id: String,
-    pub name: String,
-    pub ip: String,
+/// One message for many clients. Each encoding is serialized once, when the first client
+/// using it needs it; cloning the frame after that only bumps a refcount.
+pub struct Frames<'a> {
+    payload: &'a Outgoing,
+    json: OnceCell<Option<Message>>,
+    msgpack: OnceCell<Option<Message>>,
 }
 
-impl Outgoing {
-    pub fn kind(&self) -> &'static str {
-        match self {
-            Outgoing::Chat { .. } => "chat",
-            Outgoing::System { .. } => "system",
-            Outgoing::AckName { .. } => "ackName",
-            Outgoing::Status { .. } => "status",
-            Outgoing::ListUsers { .. } => "listUsers",
-            Outgoing::Error { .. } => "error",
-            Outgoing::Pong { .. } => "pong",
-            Outgoing::Ai { .. } => "ai",
+impl<'a> Frames<'a> {
+    pub fn new(payload: &'a Outgoing) -> Self {
+        Self {
+            payload,
+            json: OnceCell::new(),
+            msgpack: OnceCell::new(),
         }
     }
+
+    /// The frame for a client using `encoding`
+    pub fn get(&self, encoding: Encoding) -> Option<Message> {
+        let cell = match encoding {
+            Encoding::Json => &self.json,
+            Encoding::MsgPack => &self.msgpack,
+        };
+        cell.get_or_init(|| encoding.encode(self.payload)).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msgpack_carries_the_same_message() {
+        let outgoing = Outgoing::Chat {
+            id: Some(7),
+            from: "Bas".into(),
+            text: "Hallo".into(),
+            reply_to: None,
+            at: 1733312400000,
+        };
+        let frames = Frames::new(&outgoing);
+
+        let Some(Message::Binary(bytes)) = frames.get(Encoding::MsgPack) else {
+            panic!("expected a binary frame");
+        };
+        let decoded: Outgoing = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&outgoing).unwrap()
+        );
+        assert_eq!(
+            frames.get(Encoding::Json),
+            Some(Message::Text(
+                serde_json::to_string(&outgoing).unwrap().into()
+            ))
+        );
+        // Serialized once: later clients share the buffer
+        let Some(Message::Binary(again)) = frames.get(Encoding::MsgPack) else {
+            panic!("expected a binary frame");
+        };
+        assert_eq!(again.as_ptr(), bytes.as_ptr());
+    }
 }
@@ -0,0 +1,154 @@
+//! Resume tokens, so a client that reconnects can take back its name.
+//!
+//! Every connection gets a token in `ackName`. When the connection ends, its name is
+//! parked under that token for a while; a new connection that sends `resume` with the
+//! token gets the name back instead of staying a guest.
+
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tracing::info;
+
+const DEFAULT_RESUME_TTL_SECS: u64 = 300;
+
+#[derive(Clone)]
+pub struct ResumeConfig {
+    /// How long after a disconnect the token can still be used
+    pub ttl: Duration,
+}
+
+impl ResumeConfig {
+    pub fn from_env() -> Self {
+        let ttl_secs = std::env::var("RESUME_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_RESUME_TTL_SECS);
+
+        info!(ttl_secs, "Session resume configured");
+
+        Self {
+            ttl: Duration::from_secs(ttl_secs),
+        }
+    }
+}
+
+impl Default for ResumeConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(DEFAULT_RESUME_TTL_SECS),
+        }
+    }
+}
+
+/// A name waiting to be resumed
+struct ParkedSession {
+    name: String,
+    expires_at: Instant,
+}
+
+/// Names of recently disconnected clients, by resume token
+pub struct ResumeTokens {
+    ttl: Duration,
+    sessions: DashMap<String, ParkedSession>,
+}
+
+impl ResumeTokens {
+    pub fn new(config: ResumeConfig) -> Self {
+        Self {
+            ttl: config.ttl,
+            sessions: DashMap::new(),
+        }
+    }
+
+    /// A fresh, unguessable token for a new connection
+    pub fn new_token() -> String {
+        uuid::Uuid::new_v4().simple().to_string()
+    }
+
+    /// Keep `name` for the token's owner until the TTL runs out
+    pub fn park(&self, token: &str, name: String, now: Instant) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.sessions.insert(
+            token.to_string(),
+            ParkedSession {
+                name,
+                expires_at: now + self.ttl,
+            },
+        );
+    }
+
+    /// The parked name for `token`, if it hasn't expired. A token works only once.
+    pub fn take(&self, token: &str, now: Instant) -> Option<String> {
+        let (_, session) = self.sessions.remove(token)?;
+        (now < session.expires_at).then_some(session.name)
+    }
+
+    /// Drop expired sessions; returns how many were removed
+    pub fn remove_expired(&self, now: Instant) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| now < session.expires_at);
+        before - self.sessions.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(ttl_secs: u64) -> ResumeTokens {
+        ResumeTokens::new(ResumeConfig {
+            ttl: Duration::from_secs(ttl_secs),
+        })
+    }
+
+    #[test]
+    fn parked_name_can_be_taken_once() {
+        let resume = tokens(60);
+        let now = Instant::now();
+        resume.park("abc", "Bas".to_string(), now);
+
+        assert_eq!(resume.take("abc", now), Some("Bas".to_string()));
+        assert_eq!(resume.take("abc", now), None);
+        assert_eq!(resume.take("onbekend", now), None);
+    }
+
+    #[test]
+    fn expired_token_is_refused() {
+        let resume = tokens(60);
+        let now = Instant::now();
+        resume.park("abc", "Bas".to_string(), now);
+
+        assert_eq!(resume.take("abc", now + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn remove_expired_keeps_live_sessions() {
+        let resume = tokens(60);
+        let now = Instant::now();
+        resume.park("oud", "Eva".to_string(), now);
+        resume.park("nieuw", "Bas".to_string(), now + Duration::from_secs(30));
+
+        assert_eq!(resume.remove_expired(now + Duration::from_secs(61)), 1);
+        assert_eq!(resume.len(), 1);
+        assert_eq!(
+            resume.take("nieuw", now + Duration::from_secs(61)),
+            Some("Bas".to_string())
+        );
+    }
+
+    #[test]
+    fn zero_ttl_disables_resume() {
+        let resume = tokens(0);
+        let now = Instant::now();
+        resume.park("abc", "Bas".to_string(), now);
+
+        assert_eq!(resume.len(), 0);
+        assert_eq!(resume.take("abc", now), None);
+    }
+}
@@ -1,23 +1,47 @@
 use std::{
-    collections::VecDeque,
-    sync::{atomic::AtomicU64, Arc, Mutex},
-    time::{Instant, SystemTime},
+    collections::{BinaryHeap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use axum::extract::ws::Message;
 use dashmap::DashMap;
 use sysinfo::{ProcessesToUpdate, System};
-use tokio::sync::{mpsc, RwLock};
-use tracing::info;
+use tokio::{
+    sync::{broadcast, mpsc, Notify},
+    task::AbortHandle,
+};
+use tracing::{debug, info};
 use uuid::Uuid;
 
 use crate::ai::AiClient;
-use crate::protocol::{Outgoing, UserInfo};
+use crate::batch::{ChatBatchConfig, ChatBatcher};
+use crate::clock::Clock;
+use crate::events::{EventKind, ServerEvent, EVENT_BUFFER};
+use crate::guest_names::{self, GuestNameStyle};
+use crate::history::{HistoryConfig, MessageHistory};
+use crate::messages::{self, Msg};
+use crate::protocol::{Encoding, Outgoing, UserInfo, UserStats, PROTOCOL_VERSION};
+use crate::resume::{ResumeConfig, ResumeTokens};
+use crate::utils::now_ms;
+
+/// Room for a monitor pinging every 100ms, far below what a flood would send
+const DEFAULT_PINGS_PER_SECOND: u32 = 20;
 
 #[derive(Clone)]
 pub struct RateLimitConfig {
     pub enabled: bool,
     pub messages_per_minute: u32,
+    /// Send a plain `error` instead of `rateLimited`, for clients that predate it
+    pub plain_error: bool,
+    /// Pings answered per client per second; excess pings are dropped. 0 means no
+    /// limit. Applies even when chat rate limiting is off.
+    pub pings_per_second: u32,
+    /// Limit for clients authenticated with `BOT_TOKEN`; `None` means bots aren't limited
+    pub bot_messages_per_minute: Option<u32>,
 }
 
 impl RateLimitConfig {
@@ -29,48 +53,488 @@ impl RateLimitConfig {
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(60);
+        let plain_error = std::env::var("RATE_LIMIT_PLAIN_ERROR")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+        let bot_messages_per_minute = std::env::var("BOT_RATE_LIMIT_MSG_PER_MIN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&max| max > 0);
+        let pings_per_second = std::env::var("PING_RATE_LIMIT_PER_SEC")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_PINGS_PER_SECOND);
 
         if enabled {
-            info!(messages_per_minute, "Rate limiting enabled");
+            info!(
+                messages_per_minute,
+                ?bot_messages_per_minute,
+                plain_error,
+                "Rate limiting enabled"
+            );
         }
+        info!(pings_per_second, "Ping rate limit");
 
         Self {
             enabled,
             messages_per_minute,
+            plain_error,
+            pings_per_second,
+            bot_messages_per_minute,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FloodCollapseConfig {
+    pub enabled: bool,
+    /// Identical messages within this window of the previous one are dropped
+    pub window: Duration,
+}
+
+impl FloodCollapseConfig {
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("FLOOD_COLLAPSE_ENABLED")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+        let window_ms = std::env::var("DEDUP_WINDOW_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1500);
+
+        if enabled {
+            info!(window_ms, "Flood collapse enabled");
+        }
+
+        Self {
+            enabled,
+            window: Duration::from_millis(window_ms),
+        }
+    }
+}
+
+/// Order of the `listUsers` reply
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserSort {
+    /// Case-insensitive, by name
+    Name,
+    /// Longest connected first
+    Connected,
+}
+
+impl UserSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(UserSort::Name),
+            "connected" => Some(UserSort::Connected),
+            _ => None,
+        }
+    }
+}
+
+/// Page size for `listUsers` when the client doesn't ask for one
+pub const DEFAULT_USER_PAGE: usize = 100;
+/// Largest page `listUsers` returns, whatever the client asks for
+pub const MAX_USER_PAGE: usize = 500;
+
+/// What a `listUsers` request asks for
+pub struct UserQuery {
+    pub sort: Option<UserSort>,
+    /// Lowercased when matching
+    pub filter: Option<String>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// A page of the user list
+pub struct UserPage {
+    pub users: Vec<UserInfo>,
+    /// Users matching the filter, on all pages
+    pub total: usize,
+}
+
+#[derive(Clone)]
+pub struct AdminConfig {
+    /// Shared secret for `auth`; admin features are off when unset
+    pub token: Option<String>,
+    /// Shared secret for `auth` as a bot (service account with its own rate limit)
+    pub bot_token: Option<String>,
+}
+
+impl AdminConfig {
+    pub fn from_env() -> Self {
+        let token = std::env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|t| !t.trim().is_empty());
+        let bot_token = std::env::var("BOT_TOKEN")
+            .ok()
+            .filter(|t| !t.trim().is_empty());
+
+        if token.is_some() {
+            info!("Admin access enabled");
+        }
+        if bot_token.is_some() {
+            info!("Bot accounts enabled");
+        }
+
+        Self { token, bot_token }
+    }
+
+    pub fn verify(&self, candidate: &str) -> bool {
+        self.token
+            .as_deref()
+            .is_some_and(|token| tokens_match(token, candidate))
+    }
+
+    pub fn verify_bot(&self, candidate: &str) -> bool {
+        self.bot_token
+            .as_deref()
+            .is_some_and(|token| tokens_match(token, candidate))
+    }
+}
+
+/// Compare without short-circuiting so the check doesn't leak how much of the token matched
+fn tokens_match(token: &str, candidate: &str) -> bool {
+    if token.len() != candidate.len() {
+        return false;
+    }
+    token
+        .bytes()
+        .zip(candidate.bytes())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+#[derive(Clone)]
+pub struct SlowClientConfig {
+    /// Disconnect a client whose outbound buffer is full during a broadcast, instead of
+    /// only dropping the message for it
+    pub disconnect: bool,
+}
+
+impl SlowClientConfig {
+    pub fn from_env() -> Self {
+        let disconnect = std::env::var("SLOW_CLIENT_DISCONNECT")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+
+        info!(disconnect, "Slow client policy");
+
+        Self { disconnect }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SpectatorConfig {
+    /// Count spectators (`?mode=spectator`) in `userCount`, list them in `listUsers` and
+    /// announce their joins and leaves like anyone else's
+    pub counted: bool,
+}
+
+impl SpectatorConfig {
+    pub fn from_env() -> Self {
+        let counted = std::env::var("SPECTATORS_COUNTED")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+
+        info!(counted, "Spectators");
+
+        Self { counted }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ConnectionLimitConfig {
+    /// Most clients connected at once; `None` for no limit
+    pub max_connections: Option<usize>,
+}
+
+impl ConnectionLimitConfig {
+    pub fn from_env() -> Self {
+        let max_connections = std::env::var("MAX_CONNECTIONS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&max| max > 0);
+
+        info!(?max_connections, "Connection limit");
+
+        Self { max_connections }
+    }
+}
+
+#[derive(Clone)]
+pub struct JoinLeaveConfig {
+    /// Announce joins and leaves with a `system` message; `presence` is always sent
+    pub enabled: bool,
+    /// Stop announcing once more users than this are online
+    pub max_users: Option<usize>,
+}
+
+impl JoinLeaveConfig {
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("JOIN_LEAVE_NOTICES")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(true);
+        let max_users = std::env::var("JOIN_LEAVE_NOTICES_MAX_USERS")
+            .ok()
+            .and_then(|v| v.parse().ok());
+
+        info!(enabled, ?max_users, "Join/leave notices");
+
+        Self { enabled, max_users }
+    }
+
+    /// Whether a join or leave should get a `system` message with `user_count` users online
+    pub fn announce(&self, user_count: usize) -> bool {
+        self.enabled && self.max_users.is_none_or(|max| user_count <= max)
+    }
+}
+
+impl Default for JoinLeaveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_users: None,
         }
     }
 }
 
+/// Memory and CPU usage of this process. Refreshing them through sysinfo is slow, so it
+/// happens on an interval (see [`AppState::spawn_system_refresh`]) and readers only load
+/// the last values.
+pub struct SystemStats {
+    system: Mutex<System>,
+    memory_bytes: AtomicU64,
+    /// `f64` bits; 100.0 means one full core
+    cpu_percent: AtomicU64,
+}
+
+impl SystemStats {
+    fn new() -> Self {
+        let stats = Self {
+            system: Mutex::new(System::new()),
+            memory_bytes: AtomicU64::new(0),
+            cpu_percent: AtomicU64::new(0),
+        };
+        stats.refresh();
+        stats
+    }
+
+    /// CPU usage is measured since the previous refresh, so the first one reports 0
+    pub fn refresh(&self) {
+        let mut sys = self.system.lock().unwrap();
+        let pid = sysinfo::Pid::from_u32(std::process::id());
+        sys.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+        if let Some(process) = sys.process(pid) {
+            self.memory_bytes
+                .store(process.memory(), std::sync::atomic::Ordering::Relaxed);
+            self.cpu_percent.store(
+                (process.cpu_usage() as f64).to_bits(),
+                std::sync::atomic::Ordering::Relaxed,
+            );
+        }
+    }
+
+    pub fn memory_mb(&self) -> f64 {
+        self.memory_bytes.load(std::sync::atomic::Ordering::Relaxed) as f64 / 1024.0 / 1024.0
+    }
+
+    pub fn cpu_percent(&self) -> f64 {
+        f64::from_bits(self.cpu_percent.load(std::sync::atomic::Ordering::Relaxed))
+    }
+}
+
 pub type Clients = Arc<DashMap<Uuid, Client>>;
 
+/// An AI query running in the background for a single client
+pub struct AiTask {
+    pub handle: AbortHandle,
+    /// Key the request was rate limited under, so the slot can be refunded on cancel
+    pub rate_limit_key: String,
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub clients: Clients,
+    /// What happens on the server, for admins that `subscribe`; see [`AppState::emit`]
+    pub events: broadcast::Sender<ServerEvent>,
     pub started_at: Instant,
     pub messages_sent: Arc<AtomicU64>,
     pub connections_total: Arc<AtomicU64>,
     pub peak_users: Arc<AtomicU64>,
-    pub system_info: Arc<RwLock<System>>,
+    /// Summed duration of all ended sessions
+    pub session_seconds_total: Arc<AtomicU64>,
+    pub sessions_ended: Arc<AtomicU64>,
+    pub system_stats: Arc<SystemStats>,
     pub ai: Arc<AiClient>,
+    pub ai_tasks: Arc<DashMap<Uuid, AiTask>>,
     pub rate_limit: RateLimitConfig,
+    pub flood_collapse: FloodCollapseConfig,
+    pub admin: AdminConfig,
+    pub slow_client: SlowClientConfig,
+    pub join_leave: JoinLeaveConfig,
+    pub connection_limit: ConnectionLimitConfig,
+    pub spectators: SpectatorConfig,
+    pub guest_names: GuestNameStyle,
+    /// Set when chat messages are batched (`CHAT_BATCH_MS`)
+    pub chat_batch: Option<Arc<ChatBatcher>>,
+    pub history: Arc<MessageHistory>,
+    /// Names of recently disconnected clients, for `resume`
+    pub resume: Arc<ResumeTokens>,
+    /// Time source for rate limits and resume token expiry
+    pub clock: Arc<dyn Clock>,
+    /// Held across the "is this name free" check and the rename, see [`AppState::rename_client`]
+    name_lock: Arc<Mutex<()>>,
+    /// Held across the "is there room" check and the insert, see [`AppState::register_client`]
+    register_lock: Arc<Mutex<()>>,
 }
 
 impl AppState {
-    pub fn new(ai_client: AiClient, rate_limit: RateLimitConfig) -> Self {
+    pub fn new(
+        ai_client: AiClient,
+        rate_limit: RateLimitConfig,
+        flood_collapse: FloodCollapseConfig,
+        admin: AdminConfig,
+        slow_client: SlowClientConfig,
+        history: HistoryConfig,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         Self {
             clients: Arc::new(DashMap::new()),
+            events: broadcast::channel(EVENT_BUFFER).0,
             started_at: Instant::now(),
             messages_sent: Arc::new(AtomicU64::new(0)),
             connections_total: Arc::new(AtomicU64::new(0)),
             peak_users: Arc::new(AtomicU64::new(0)),
-            system_info: Arc::new(RwLock::new(System::new())),
-            ai: Arc::new(ai_client),
+            session_seconds_total: Arc::new(AtomicU64::new(0)),
+            sessions_ended: Arc::new(AtomicU64::new(0)),
+            system_stats: Arc::new(SystemStats::new()),
+            ai: Arc::new(ai_client.with_clock(clock.clone())),
+            ai_tasks: Arc::new(DashMap::new()),
             rate_limit,
+            flood_collapse,
+            admin,
+            slow_client,
+            join_leave: JoinLeaveConfig::default(),
+            connection_limit: ConnectionLimitConfig::default(),
+            spectators: SpectatorConfig::default(),
+            guest_names: GuestNameStyle::Plain,
+            chat_batch: None,
+            history: Arc::new(MessageHistory::new(history)),
+            resume: Arc::new(ResumeTokens::new(ResumeConfig::default())),
+            clock,
+            name_lock: Arc::new(Mutex::new(())),
+            register_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Report an event to observers. `text` depends on the kind: the message for chat,
+    /// the new name for rename (`name` is the old one), the prompt for ai and the error
+    /// for error.
+    pub fn emit(&self, kind: EventKind, id: Uuid, name: &str, text: Option<&str>) {
+        // Nothing to serialize when no admin is watching, the usual case
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        let event = Outgoing::Event {
+            event: kind,
+            client_id: id.to_string(),
+            name: name.to_string(),
+            text: text.map(str::to_string),
+            at: now_ms(),
+        };
+        let _ = self.events.send(ServerEvent {
+            kind,
+            json: Encoding::Json.encode(&event),
+            msgpack: Encoding::MsgPack.encode(&event),
+        });
+    }
+
+    pub fn with_resume(mut self, config: ResumeConfig) -> Self {
+        self.resume = Arc::new(ResumeTokens::new(config));
+        self
+    }
+
+    pub fn with_join_leave(mut self, config: JoinLeaveConfig) -> Self {
+        self.join_leave = config;
+        self
+    }
+
+    pub fn with_connection_limit(mut self, config: ConnectionLimitConfig) -> Self {
+        self.connection_limit = config;
+        self
+    }
+
+    pub fn with_spectators(mut self, config: SpectatorConfig) -> Self {
+        self.spectators = config;
+        self
+    }
+
+    pub fn with_guest_names(mut self, style: GuestNameStyle) -> Self {
+        self.guest_names = style;
+        self
+    }
+
+    pub fn with_chat_batch(mut self, config: ChatBatchConfig) -> Self {
+        self.chat_batch = config
+            .window
+            .map(|window| Arc::new(ChatBatcher::new(window)));
+        self
+    }
+
+    /// Add a client under a guest name different from everyone online, unless
+    /// `MAX_CONNECTIONS` clients are already connected. The count and the insert happen
+    /// under one lock, so a burst of connections can't overshoot the limit, and the name
+    /// is picked under the rename lock, so two guests can't draw the same one. Returns
+    /// `false` when the server is full.
+    pub fn register_client(&self, id: Uuid, client: &mut Client) -> bool {
+        let _register = self.register_lock.lock().unwrap();
+        if let Some(max) = self.connection_limit.max_connections {
+            if self.clients.len() >= max {
+                return false;
+            }
+        }
+        let _names = self.name_lock.lock().unwrap();
+        client.name = guest_names::guest_name(id, self.guest_names, |name| {
+            self.clients.iter().any(|entry| entry.value().name == name)
+        });
+        self.clients.insert(id, client.clone());
+        true
+    }
+
+    /// Give client `id` the name `name`, unless another client already has it. Two
+    /// clients renaming to the same name at once can't both succeed: the check and the
+    /// assignment happen under one lock. Returns the old name, or `None` if the client
+    /// is gone.
+    pub fn rename_client(&self, id: Uuid, name: &str) -> Result<Option<String>, String> {
+        let _names = self.name_lock.lock().unwrap();
+        let taken = self
+            .clients
+            .iter()
+            .any(|entry| *entry.key() != id && entry.value().name == name);
+        if taken {
+            return Err(messages::text(Msg::NameTaken, &[("name", &name)]));
         }
+        Ok(self
+            .clients
+            .get_mut(&id)
+            .map(|mut entry| std::mem::replace(&mut entry.name, name.to_string())))
     }
 
+    /// Clients in `userCount` and `listUsers`; uncounted spectators are left out
     pub fn user_count(&self) -> usize {
-        self.clients.len()
+        if self.spectators.counted {
+            return self.clients.len();
+        }
+        self.clients
+            .iter()
+            .filter(|entry| !entry.value().is_spectator)
+            .count()
+    }
+
+    /// Whether others see `client`: in the user count, the user list and join/leave messages
+    pub fn is_visible(&self, client: &Client) -> bool {
+        self.spectators.counted || !client.is_spectator
     }
 
     pub fn uptime_seconds(&self) -> u64 {
@@ -91,8 +555,8 @@ impl AppState {
         self.connections_total
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
-        // Update peak users if current count is higher
-        let current = self.clients.len() as u64;
+        // Update peak users if current count is higher; hidden spectators don't count
+        let current = self.user_count() as u64;
         self.peak_users
             .fetch_max(current, std::sync::atomic::Ordering::Relaxed);
     }
@@ -106,57 +570,370 @@ impl AppState {
         self.peak_users.load(std::sync::atomic::Ordering::Relaxed)
     }
 
-    pub async fn memory_mb(&self) -> f64 {
-        let mut sys = self.system_info.write().await;
-        let pid = sysinfo::Pid::from_u32(std::process::id());
-        sys.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
-        sys.process(pid)
-            .map(|p| p.memory() as f64 / 1024.0 / 1024.0)
-            .unwrap_or(0.0)
+    /// Record an ended session. Returns the average session length in seconds so far.
+    pub fn record_session(&self, duration: Duration) -> f64 {
+        let total = self
+            .session_seconds_total
+            .fetch_add(duration.as_secs(), std::sync::atomic::Ordering::Relaxed)
+            + duration.as_secs();
+        let ended = self
+            .sessions_ended
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            + 1;
+        total as f64 / ended as f64
+    }
+
+    pub fn session_seconds_total(&self) -> u64 {
+        self.session_seconds_total
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Memory use as of the last background refresh
+    pub fn memory_mb(&self) -> f64 {
+        self.system_stats.memory_mb()
+    }
+
+    /// Keep [`SystemStats`] up to date; without this they keep their startup values
+    pub fn spawn_system_refresh(&self, interval: Duration) {
+        let stats = self.system_stats.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately; SystemStats::new just refreshed
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let stats = stats.clone();
+                // sysinfo reads /proc synchronously
+                let _ = tokio::task::spawn_blocking(move || stats.refresh()).await;
+            }
+        });
+    }
+
+    /// Forget resume tokens that have expired, so names of clients that never come back
+    /// don't pile up
+    pub fn spawn_resume_cleanup(&self, interval: Duration) {
+        let resume = self.resume.clone();
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let removed = resume.remove_expired(clock.now());
+                if removed > 0 {
+                    debug!(
+                        removed,
+                        remaining = resume.len(),
+                        "Expired resume tokens removed"
+                    );
+                }
+            }
+        });
+    }
+
+    /// Fully populated `status` message: reply to `status` and the periodic broadcast
+    pub fn build_status(&self) -> Outgoing {
+        let uptime_secs = self.uptime_seconds();
+        let messages = self.messages_sent();
+        let msgs_per_sec = if uptime_secs > 0 {
+            messages as f64 / uptime_secs as f64
+        } else {
+            0.0
+        };
+        let memory_mb = self.memory_mb();
+        let cpu_percent = self.system_stats.cpu_percent();
+        let cpu_cores = std::thread::available_parallelism()
+            .map(|p| p.get())
+            .unwrap_or(1);
+
+        Outgoing::Status {
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            rust_version: Some(env!("RUSTC_VERSION").to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            os: Some(std::env::consts::OS.to_string()),
+            cpu_cores: Some(cpu_cores),
+            uptime_seconds: uptime_secs,
+            user_count: self.user_count(),
+            peak_users: Some(self.peak_users() as usize),
+            connections_total: Some(self.connections_total()),
+            messages_sent: messages,
+            messages_per_second: Some((msgs_per_sec * 100.0).round() / 100.0),
+            memory_mb: Some((memory_mb * 100.0).round() / 100.0),
+            cpu_percent: Some((cpu_percent * 10.0).round() / 10.0),
+            total_session_seconds: Some(self.session_seconds_total()),
+            compression_offered: Some(
+                self.clients
+                    .iter()
+                    .filter(|entry| entry.value().deflate_offered)
+                    .count(),
+            ),
+            ai_enabled: Some(self.ai.is_enabled()),
+            ai_model: if self.ai.is_enabled() {
+                Some(self.ai.model().to_string())
+            } else {
+                None
+            },
+            ai_cost_today: self
+                .ai
+                .is_enabled()
+                .then(|| (self.ai.cost_today() * 10000.0).round() / 10000.0),
+            ai_tokens_today: self.ai.is_enabled().then(|| self.ai.tokens_today()),
+            ai_retries: self.ai.is_enabled().then(|| self.ai.retries_total()),
+        }
+    }
+
+    /// Abort the client's in-flight AI request, if any. Returns true if a request was cancelled.
+    pub fn cancel_ai_task(&self, id: Uuid) -> bool {
+        let Some((_, task)) = self.ai_tasks.remove(&id) else {
+            return false;
+        };
+        if task.handle.is_finished() {
+            return false;
+        }
+        task.handle.abort();
+        self.ai.refund_rate_limit(&task.rate_limit_key);
+        true
+    }
+
+    /// One page of the users whose name contains the filter (case-insensitive), in the
+    /// requested order or map order, plus how many users match in total. Only the page is
+    /// built; sorting keeps at most `offset + limit` keys in memory.
+    pub fn list_users(&self, query: &UserQuery) -> UserPage {
+        let now = self.clock.now();
+        let filter = query.filter.as_deref().map(str::to_lowercase);
+        let matches = |client: &Client| {
+            self.is_visible(client)
+                && filter
+                    .as_ref()
+                    .is_none_or(|f| client.name.to_lowercase().contains(f))
+        };
+        let end = query.offset.saturating_add(query.limit);
+
+        let (ids, total) = match query.sort {
+            None => {
+                let mut users = Vec::new();
+                let mut total = 0;
+                for entry in self.clients.iter().filter(|e| matches(e.value())) {
+                    if (query.offset..end).contains(&total) {
+                        users.push(user_info(*entry.key(), entry.value(), now));
+                    }
+                    total += 1;
+                }
+                return UserPage { users, total };
+            }
+            Some(UserSort::Name) => smallest(
+                self.clients
+                    .iter()
+                    .filter(|e| matches(e.value()))
+                    .map(|e| (e.value().name.to_lowercase(), *e.key())),
+                end,
+            ),
+            Some(UserSort::Connected) => smallest(
+                self.clients
+                    .iter()
+                    .filter(|e| matches(e.value()))
+                    .map(|e| (e.value().connected_at, *e.key())),
+                end,
+            ),
+        };
+        // A client that left in the meantime just makes the page one shorter
+        let users = ids
+            .into_iter()
+            .skip(query.offset)
+            .filter_map(|id| {
+                self.clients
+                    .get(&id)
+                    .map(|entry| user_info(id, entry.value(), now))
+            })
+            .collect();
+        UserPage { users, total }
     }
 
-    pub fn list_users(&self) -> Vec<UserInfo> {
+    /// Per-user breakdown for the admin stats response
+    pub fn user_stats(&self) -> Vec<UserStats> {
         self.clients
             .iter()
-            .map(|entry| UserInfo {
-                id: entry.key().to_string(),
-                name: entry.value().name.clone(),
-                ip: entry.value().ip.clone(),
+            .map(|entry| {
+                let client = entry.value();
+                let messages_last_minute = client.messages_last_minute(self.clock.now());
+                UserStats {
+                    id: entry.key().to_string(),
+                    name: client.name.clone(),
+                    ip: client.ip.clone(),
+                    message_count: client
+                        .message_count
+                        .load(std::sync::atomic::Ordering::Relaxed),
+                    connected_at: client
+                        .connected_at
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|d| d.as_millis() as u64)
+                        .unwrap_or(0),
+                    admin: client.is_admin,
+                    bot: client.is_bot,
+                    user_agent: client.user_agent.clone(),
+                    messages_last_minute,
+                    rate_limited: client
+                        .message_limit(&self.rate_limit)
+                        .is_some_and(|limit| messages_last_minute >= limit as usize),
+                }
             })
             .collect()
     }
 }
 
+fn user_info(id: Uuid, client: &Client, now: Instant) -> UserInfo {
+    UserInfo {
+        id: id.to_string(),
+        name: client.name.clone(),
+        ip: client.ip.clone(),
+        message_count: client
+            .message_count
+            .load(std::sync::atomic::Ordering::Relaxed),
+        bot: client.is_bot,
+        admin: client.is_admin,
+        idle_seconds: Some(client.chat_idle_for(now).as_secs()),
+    }
+}
+
+/// Ids of the `n` smallest `(key, id)` pairs in order, holding no more than `n` at a
+/// time, and the number of pairs seen
+fn smallest<K: Ord>(items: impl Iterator<Item = (K, Uuid)>, n: usize) -> (Vec<Uuid>, usize) {
+    let mut heap = BinaryHeap::new();
+    let mut total = 0;
+    for item in items {
+        total += 1;
+        if heap.len() < n {
+            heap.push(item);
+        } else if heap.peek().is_some_and(|largest| item < *largest) {
+            heap.pop();
+            heap.push(item);
+        }
+    }
+    let ids = heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|(_, id)| id)
+        .collect();
+    (ids, total)
+}
+
+/// The previous chat message of a client, for flood collapse
+struct LastMessage {
+    text: String,
+    at: Instant,
+    /// Whether the sender was already told about a dropped duplicate
+    warned: bool,
+}
+
 #[derive(Clone)]
 pub struct Client {
     pub name: String,
     pub ip: String,
+    /// `User-Agent` of the WebSocket upgrade request, for telling client programs apart
+    pub user_agent: Option<String>,
     pub tx: mpsc::Sender<Message>,
-    #[allow(dead_code)]
     pub connected_at: SystemTime,
+    /// Authenticated with `ADMIN_TOKEN` during this session
+    pub is_admin: bool,
+    /// Authenticated with `BOT_TOKEN`: rate limited by `BOT_RATE_LIMIT_MSG_PER_MIN` instead
+    pub is_bot: bool,
+    /// The upgrade request offered permessage-deflate (which isn't negotiated)
+    pub deflate_offered: bool,
+    /// Connected with `?mode=spectator`: gets the chat but can't post
+    pub is_spectator: bool,
+    /// Framing of everything sent to this client, from its subprotocol
+    pub encoding: Encoding,
     /// Timestamps of recent messages for rate limiting (sliding window)
     pub message_timestamps: Arc<Mutex<VecDeque<Instant>>>,
+    /// Start of the current one-second ping window and the pings answered in it
+    ping_window: Arc<Mutex<Option<(Instant, u32)>>>,
+    /// Chat messages sent during this session
+    pub message_count: Arc<AtomicU64>,
+    last_message: Arc<Mutex<Option<LastMessage>>>,
+    /// Wakes the receive loop to drop the connection, e.g. when it can't keep up with broadcasts
+    pub disconnect: Arc<Notify>,
+    /// Sent in `ackName`; lets the next connection take this one's name after a disconnect
+    pub resume_token: String,
+    /// When the last frame of any kind (including a WebSocket pong) came in
+    last_seen: Arc<Mutex<Instant>>,
+    /// When the last chat message was sent; the connect time until there is one
+    last_chat: Arc<Mutex<Instant>>,
+    /// Subscribed to the event stream: gets events instead of the chat
+    pub observing: Arc<AtomicBool>,
+    /// The task forwarding events to this client, see [`crate::events::observe`]
+    pub observer: Arc<Mutex<Option<AbortHandle>>>,
 }
 
 impl Client {
-    pub fn new(name: String, ip: String, tx: mpsc::Sender<Message>) -> Self {
+    pub fn new(
+        name: String,
+        ip: String,
+        user_agent: Option<String>,
+        tx: mpsc::Sender<Message>,
+    ) -> Self {
         Self {
             name,
             ip,
+            user_agent,
             tx,
             connected_at: SystemTime::now(),
+            is_admin: false,
+            is_bot: false,
+            deflate_offered: false,
+            is_spectator: false,
+            encoding: Encoding::Json,
             message_timestamps: Arc::new(Mutex::new(VecDeque::new())),
+            ping_window: Arc::new(Mutex::new(None)),
+            message_count: Arc::new(AtomicU64::new(0)),
+            last_message: Arc::new(Mutex::new(None)),
+            disconnect: Arc::new(Notify::new()),
+            resume_token: ResumeTokens::new_token(),
+            last_seen: Arc::new(Mutex::new(Instant::now())),
+            last_chat: Arc::new(Mutex::new(Instant::now())),
+            observing: Arc::new(AtomicBool::new(false)),
+            observer: Arc::new(Mutex::new(None)),
         }
     }
 
-    /// Check if this client is rate limited. Returns Ok(()) if allowed, Err with seconds until next allowed message if rate limited.
-    pub fn check_rate_limit(&self, config: &RateLimitConfig) -> Result<(), u64> {
+    /// Record that the connection is alive
+    pub fn touch(&self, now: Instant) {
+        *self.last_seen.lock().unwrap() = now;
+    }
+
+    /// Time since the client last sent anything
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(*self.last_seen.lock().unwrap())
+    }
+
+    /// Count a chat message sent at `now`
+    pub fn increment_messages(&self, now: Instant) {
+        self.message_count
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        *self.last_chat.lock().unwrap() = now;
+    }
+
+    /// Time since the client last chatted, or since it connected if it never did
+    pub fn chat_idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(*self.last_chat.lock().unwrap())
+    }
+
+    /// Chat messages per minute this client may send, `None` when it isn't limited
+    pub fn message_limit(&self, config: &RateLimitConfig) -> Option<u32> {
         if !config.enabled {
-            return Ok(());
+            None
+        } else if self.is_bot {
+            config.bot_messages_per_minute
+        } else {
+            Some(config.messages_per_minute)
         }
+    }
+
+    /// Check if this client is rate limited. Returns Ok(()) if allowed, Err with seconds until next allowed message if rate limited.
+    pub fn check_rate_limit(&self, config: &RateLimitConfig, now: Instant) -> Result<(), u64> {
+        let Some(limit) = self.message_limit(config) else {
+            return Ok(());
+        };
 
         let mut timestamps = self.message_timestamps.lock().unwrap();
-        let now = Instant::now();
         let window = std::time::Duration::from_secs(60);
 
         // Remove timestamps older than 1 minute
@@ -168,7 +945,7 @@ impl Client {
             }
         }
 
-        if timestamps.len() >= config.messages_per_minute as usize {
+        if timestamps.len() >= limit as usize {
             // Calculate how long until the oldest message expires
             if let Some(oldest) = timestamps.front() {
                 let elapsed = now.duration_since(*oldest);
@@ -182,13 +959,635 @@ impl Client {
         Ok(())
     }
 
+    /// Whether a ping should still be answered in the current one-second window
+    pub fn allow_ping(&self, config: &RateLimitConfig, now: Instant) -> bool {
+        if config.pings_per_second == 0 {
+            return true;
+        }
+        let mut window = self.ping_window.lock().unwrap();
+        match window.as_mut() {
+            Some((start, count)) if now.duration_since(*start) < Duration::from_secs(1) => {
+                if *count >= config.pings_per_second {
+                    return false;
+                }
+                *count += 1;
+            }
+            _ => *window = Some((now, 1)),
+        }
+        true
+    }
+
+    /// Messages within the current rate-limit window (only tracked while rate limiting is enabled)
+    pub fn messages_last_minute(&self, now: Instant) -> usize {
+        let timestamps = self.message_timestamps.lock().unwrap();
+        let window = std::time::Duration::from_secs(60);
+        timestamps
+            .iter()
+            .filter(|t| now.duration_since(**t) <= window)
+            .count()
+    }
+
+    /// Check if `text` repeats this client's previous message within the flood collapse window.
+    /// Returns Ok(()) if allowed, Err(warn) if it should be dropped; `warn` is only true for the
+    /// first dropped duplicate in a row, so the sender is told once.
+    pub fn check_duplicate(
+        &self,
+        text: &str,
+        config: &FloodCollapseConfig,
+        now: Instant,
+    ) -> Result<(), bool> {
+        if !config.enabled {
+            return Ok(());
+        }
+
+        let mut last = self.last_message.lock().unwrap();
+
+        if let Some(prev) = last.as_mut() {
+            if prev.text == text && now.duration_since(prev.at) < config.window {
+                // Keep extending the window while the flood continues
+                prev.at = now;
+                let warn = !prev.warned;
+                prev.warned = true;
+                return Err(warn);
+            }
+        }
+
+        *last = Some(LastMessage {
+            text: text.to_string(),
+            at: now,
+            warned: false,
+        });
+        Ok(())
+    }
+
     /// Send a message to this client. Uses try_send to avoid blocking.
     /// Returns false if the client's buffer is full (slow client) or channel closed.
     pub fn send(&self, payload: &Outgoing) -> bool {
-        if let Ok(text) = serde_json::to_string(payload) {
-            self.tx.try_send(Message::Text(text.into())).is_ok()
-        } else {
-            false
+        self.encoding
+            .encode(payload)
+            .is_some_and(|frame| self.tx.try_send(frame).is_ok())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ai::AiConfig;
+    use crate::clock::{MockClock, SystemClock};
+
+    pub(crate) fn client() -> Client {
+        let (tx, _rx) = mpsc::channel(1);
+        Client::new("tester".into(), "127.0.0.1".into(), None, tx)
+    }
+
+    pub(crate) fn ai_config() -> AiConfig {
+        AiConfig {
+            enabled: false,
+            provider: "ollama".into(),
+            api_key: String::new(),
+            ollama_url: String::new(),
+            model: "test-model".into(),
+            rate_limit: 10,
+            timeout_secs: 5,
+            max_tokens: 100,
+            max_retries: 0,
+            max_prompt_chars: 1000,
+            daily_cost_limit: None,
+            daily_token_limit: None,
+            user_daily_cost_limit: None,
+            user_daily_token_limit: None,
+            cost_per_minute: None,
+            cost_per_token: 0.0,
+            system_on_error: false,
+            refund_on_error: false,
+            treat_empty_as_error: false,
+            audit_log: None,
+        }
+    }
+
+    pub(crate) fn app_state() -> AppState {
+        app_state_with_clock(Arc::new(SystemClock))
+    }
+
+    pub(crate) fn app_state_with_clock(clock: Arc<dyn Clock>) -> AppState {
+        AppState::new(
+            AiClient::new(ai_config()),
+            RateLimitConfig {
+                enabled: false,
+                messages_per_minute: 60,
+                plain_error: false,
+                pings_per_second: 20,
+                bot_messages_per_minute: None,
+            },
+            collapse(1500),
+            AdminConfig {
+                token: None,
+                bot_token: None,
+            },
+            SlowClientConfig { disconnect: false },
+            HistoryConfig {
+                size: 10,
+                edit_window_secs: 60,
+            },
+            clock,
+        )
+    }
+
+    fn collapse(window_ms: u64) -> FloodCollapseConfig {
+        FloodCollapseConfig {
+            enabled: true,
+            window: Duration::from_millis(window_ms),
+        }
+    }
+
+    fn rate_limit(messages_per_minute: u32) -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            messages_per_minute,
+            plain_error: false,
+            pings_per_second: 20,
+            bot_messages_per_minute: Some(messages_per_minute * 10),
+        }
+    }
+
+    #[test]
+    fn bots_get_their_own_rate_limit() {
+        let clock = MockClock::new();
+        let config = rate_limit(3);
+        let mut bot = client();
+        bot.is_bot = true;
+
+        for _ in 0..30 {
+            assert_eq!(bot.check_rate_limit(&config, clock.now()), Ok(()));
+        }
+        assert_eq!(bot.check_rate_limit(&config, clock.now()), Err(60));
+
+        let unlimited = RateLimitConfig {
+            bot_messages_per_minute: None,
+            ..config
+        };
+        let bot = {
+            let mut bot = client();
+            bot.is_bot = true;
+            bot
+        };
+        for _ in 0..1000 {
+            assert_eq!(bot.check_rate_limit(&unlimited, clock.now()), Ok(()));
+        }
+    }
+
+    #[test]
+    fn join_leave_notices_stop_above_the_threshold() {
+        let config = JoinLeaveConfig {
+            enabled: true,
+            max_users: Some(100),
+        };
+        assert!(config.announce(100));
+        assert!(!config.announce(101));
+        assert!(JoinLeaveConfig::default().announce(100_000));
+
+        let off = JoinLeaveConfig {
+            enabled: false,
+            max_users: None,
+        };
+        assert!(!off.announce(1));
+    }
+
+    #[test]
+    fn concurrent_renames_to_one_name_have_a_single_winner() {
+        const CLIENTS: usize = 64;
+        let state = app_state();
+        // Bystanders make every name check a long scan, so unguarded checks would overlap
+        for i in 0..5000 {
+            let mut c = client();
+            c.name = format!("bystander-{i}");
+            state.clients.insert(Uuid::new_v4(), c);
+        }
+        let ids: Vec<Uuid> = (0..CLIENTS)
+            .map(|i| {
+                let id = Uuid::new_v4();
+                let mut c = client();
+                c.name = format!("guest-{i}");
+                state.clients.insert(id, c);
+                id
+            })
+            .collect();
+
+        for round in 0..20 {
+            let target = format!("doel-{round}");
+            let barrier = std::sync::Barrier::new(CLIENTS);
+            let winners = std::thread::scope(|s| {
+                let handles: Vec<_> = ids
+                    .iter()
+                    .map(|&id| {
+                        let (state, barrier, target) = (&state, &barrier, &target);
+                        s.spawn(move || {
+                            barrier.wait();
+                            state.rename_client(id, target).is_ok()
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().unwrap())
+                    .filter(|won| *won)
+                    .count()
+            });
+            assert_eq!(winners, 1, "round {round}");
+            let holders = state
+                .clients
+                .iter()
+                .filter(|entry| entry.value().name == target)
+                .count();
+            assert_eq!(holders, 1, "round {round}");
+        }
+    }
+
+    #[test]
+    fn connection_burst_cannot_overshoot_the_limit() {
+        const CLIENTS: usize = 32;
+        const MAX: usize = 10;
+        let state = app_state().with_connection_limit(ConnectionLimitConfig {
+            max_connections: Some(MAX),
+        });
+
+        let barrier = std::sync::Barrier::new(CLIENTS);
+        let admitted = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..CLIENTS)
+                .map(|_| {
+                    let (state, barrier) = (&state, &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        state.register_client(Uuid::new_v4(), &mut client())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|admitted| *admitted)
+                .count()
+        });
+        assert_eq!(admitted, MAX);
+        assert_eq!(state.clients.len(), MAX);
+    }
+
+    #[test]
+    fn guests_connecting_at_once_get_different_names() {
+        const CLIENTS: u128 = 16;
+        let state = app_state().with_guest_names(GuestNameStyle::Friendly);
+        // Ids a whole number of name cycles apart all draw the same friendly name first
+        let cycle = 24 * 24 * 100;
+
+        let barrier = std::sync::Barrier::new(CLIENTS as usize);
+        std::thread::scope(|s| {
+            for i in 0..CLIENTS {
+                let (state, barrier) = (&state, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    assert!(state.register_client(Uuid::from_u128(i * cycle + 7), &mut client()));
+                });
+            }
+        });
+        let names: std::collections::HashSet<String> = state
+            .clients
+            .iter()
+            .map(|entry| entry.value().name.clone())
+            .collect();
+        assert_eq!(names.len(), CLIENTS as usize);
+    }
+
+    #[test]
+    fn renaming_to_your_own_name_is_allowed() {
+        let state = app_state();
+        let id = Uuid::new_v4();
+        state.clients.insert(id, client());
+        let name = state.clients.get(&id).unwrap().name.clone();
+
+        assert_eq!(state.rename_client(id, &name), Ok(Some(name.clone())));
+        assert_eq!(state.rename_client(Uuid::new_v4(), "ander"), Ok(None));
+    }
+
+    #[test]
+    fn touch_resets_idle_time() {
+        let client = client();
+        let clock = MockClock::new();
+        client.touch(clock.now());
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(client.idle_for(clock.now()), Duration::from_secs(30));
+        client.touch(clock.now());
+        assert_eq!(client.idle_for(clock.now()), Duration::ZERO);
+    }
+
+    #[test]
+    fn pings_beyond_the_limit_are_dropped_until_the_next_second() {
+        let client = client();
+        let clock = MockClock::new();
+        let config = rate_limit(60);
+
+        for _ in 0..20 {
+            assert!(client.allow_ping(&config, clock.now()));
+        }
+        assert!(!client.allow_ping(&config, clock.now()));
+
+        clock.advance(Duration::from_millis(999));
+        assert!(!client.allow_ping(&config, clock.now()));
+        clock.advance(Duration::from_millis(1));
+        assert!(client.allow_ping(&config, clock.now()));
+    }
+
+    #[test]
+    fn a_ping_every_100ms_is_never_dropped() {
+        let client = client();
+        let clock = MockClock::new();
+        let config = rate_limit(60);
+
+        for _ in 0..100 {
+            assert!(client.allow_ping(&config, clock.now()));
+            clock.advance(Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn rate_limit_allows_the_limit_and_reports_the_wait() {
+        let client = client();
+        let clock = MockClock::new();
+        let config = rate_limit(3);
+
+        // The last message that fits is allowed, the next one isn't
+        for _ in 0..3 {
+            assert_eq!(client.check_rate_limit(&config, clock.now()), Ok(()));
+        }
+        assert_eq!(client.check_rate_limit(&config, clock.now()), Err(60));
+
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(client.check_rate_limit(&config, clock.now()), Err(40));
+        clock.advance(Duration::from_millis(39_500));
+        assert_eq!(client.check_rate_limit(&config, clock.now()), Err(1));
+    }
+
+    #[test]
+    fn rate_limit_window_empties_after_60_seconds() {
+        let client = client();
+        let clock = MockClock::new();
+        let config = rate_limit(3);
+        for _ in 0..3 {
+            assert_eq!(client.check_rate_limit(&config, clock.now()), Ok(()));
+        }
+
+        // Messages exactly 60 seconds old still count
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(client.check_rate_limit(&config, clock.now()), Err(1));
+        assert_eq!(client.messages_last_minute(clock.now()), 3);
+
+        // Rejected messages weren't recorded, so the whole quota is back
+        clock.advance(Duration::from_millis(1));
+        for _ in 0..3 {
+            assert_eq!(client.check_rate_limit(&config, clock.now()), Ok(()));
+        }
+        assert_eq!(client.messages_last_minute(clock.now()), 3);
+        assert!(client.check_rate_limit(&config, clock.now()).is_err());
+    }
+
+    #[test]
+    fn identical_messages_within_window_are_dropped() {
+        let now = Instant::now();
+        let client = client();
+        let config = collapse(60_000);
+
+        assert_eq!(client.check_duplicate("hallo", &config, now), Ok(()));
+        assert_eq!(client.check_duplicate("hallo", &config, now), Err(true));
+        // Only the first drop in a row warns
+        assert_eq!(client.check_duplicate("hallo", &config, now), Err(false));
+    }
+
+    #[test]
+    fn slightly_different_messages_pass() {
+        let now = Instant::now();
+        let client = client();
+        let config = collapse(60_000);
+
+        assert_eq!(client.check_duplicate("hallo", &config, now), Ok(()));
+        assert_eq!(client.check_duplicate("hallo!", &config, now), Ok(()));
+        assert_eq!(client.check_duplicate("Hallo!", &config, now), Ok(()));
+        // Back to an earlier text is fine: only the previous message counts
+        assert_eq!(client.check_duplicate("hallo", &config, now), Ok(()));
+    }
+
+    #[test]
+    fn identical_message_after_window_passes() {
+        let clock = MockClock::new();
+        let client = client();
+        let config = collapse(60_000);
+        let check = || client.check_duplicate("hallo", &config, clock.now());
+
+        assert_eq!(check(), Ok(()));
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(check(), Err(true));
+        // Each drop extends the window, so only a full minute of silence lets it through
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(check(), Err(false));
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(check(), Ok(()));
+    }
+
+    #[test]
+    fn disabled_never_drops() {
+        let now = Instant::now();
+        let client = client();
+        let config = FloodCollapseConfig {
+            enabled: false,
+            window: Duration::from_millis(60_000),
+        };
+
+        assert_eq!(client.check_duplicate("hallo", &config, now), Ok(()));
+        assert_eq!(client.check_duplicate("hallo", &config, now), Ok(()));
+    }
+
+    #[test]
+    fn build_status_reports_counters_and_build_info() {
+        let state = app_state();
+        state.clients.insert(
+            Uuid::new_v4(),
+            Client {
+                deflate_offered: true,
+                ..client()
+            },
+        );
+        state.increment_connections();
+        state.increment_messages();
+        state.increment_messages();
+        state.record_session(Duration::from_secs(90));
+
+        let Outgoing::Status {
+            version,
+            rust_version,
+            protocol_version,
+            os,
+            cpu_cores,
+            user_count,
+            peak_users,
+            connections_total,
+            messages_sent,
+            memory_mb,
+            total_session_seconds,
+            compression_offered,
+            ai_enabled,
+            ai_model,
+            ai_cost_today,
+            ai_tokens_today,
+            ai_retries,
+            ..
+        } = state.build_status()
+        else {
+            panic!("build_status must return a status message");
+        };
+
+        assert_eq!(version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert!(!rust_version.unwrap().is_empty());
+        assert_eq!(protocol_version, Some(PROTOCOL_VERSION));
+        assert_eq!(os.as_deref(), Some(std::env::consts::OS));
+        assert!(cpu_cores.unwrap() >= 1);
+        assert_eq!(user_count, 1);
+        assert_eq!(peak_users, Some(1));
+        assert_eq!(connections_total, Some(1));
+        assert_eq!(messages_sent, 2);
+        assert!(memory_mb.unwrap() > 0.0);
+        assert_eq!(total_session_seconds, Some(90));
+        assert_eq!(compression_offered, Some(1));
+        // AI details are only reported when AI is enabled
+        assert_eq!(ai_enabled, Some(false));
+        assert_eq!(ai_model, None);
+        assert_eq!(ai_cost_today, None);
+        assert_eq!(ai_tokens_today, None);
+        assert_eq!(ai_retries, None);
+    }
+
+    fn add_user(state: &AppState, name: &str, connected_secs_ago: u64) {
+        let mut client = client();
+        client.name = name.to_string();
+        client.connected_at = SystemTime::now() - Duration::from_secs(connected_secs_ago);
+        state.clients.insert(Uuid::new_v4(), client);
+    }
+
+    fn names(page: UserPage) -> Vec<String> {
+        page.users.into_iter().map(|u| u.name).collect()
+    }
+
+    fn query(sort: Option<UserSort>, filter: Option<&str>) -> UserQuery {
+        UserQuery {
+            sort,
+            filter: filter.map(str::to_string),
+            offset: 0,
+            limit: DEFAULT_USER_PAGE,
         }
     }
+
+    #[test]
+    fn list_users_sorts_and_filters() {
+        let state = app_state();
+        add_user(&state, "eva", 10);
+        add_user(&state, "Bas", 30);
+        add_user(&state, "alice", 20);
+        add_user(&state, "Malice", 5);
+
+        assert_eq!(
+            names(state.list_users(&query(Some(UserSort::Name), None))),
+            ["alice", "Bas", "eva", "Malice"]
+        );
+        assert_eq!(
+            names(state.list_users(&query(Some(UserSort::Connected), None))),
+            ["Bas", "alice", "eva", "Malice"]
+        );
+        let page = state.list_users(&query(Some(UserSort::Name), Some("ALICE")));
+        assert_eq!(page.total, 2);
+        assert_eq!(names(page), ["alice", "Malice"]);
+        assert_eq!(state.list_users(&query(None, None)).users.len(), 4);
+        assert!(state.list_users(&query(None, Some("zoe"))).users.is_empty());
+    }
+
+    #[test]
+    fn spectators_are_only_counted_when_configured() {
+        let mut spectator = client();
+        spectator.is_spectator = true;
+        let state = app_state();
+        add_user(&state, "alice", 10);
+        state.clients.insert(Uuid::new_v4(), spectator);
+        state.increment_connections();
+
+        assert_eq!(state.user_count(), 1);
+        assert_eq!(state.list_users(&query(None, None)).total, 1);
+        assert_eq!(state.peak_users(), 1);
+
+        let state = state.with_spectators(SpectatorConfig { counted: true });
+        state.increment_connections();
+        assert_eq!(state.user_count(), 2);
+        assert_eq!(state.list_users(&query(None, None)).total, 2);
+        assert_eq!(state.peak_users(), 2);
+    }
+
+    #[test]
+    fn list_users_shows_time_since_the_last_chat_message() {
+        // Clients take the real time on connect; start the clock after that
+        let (lurker, chatter) = (Uuid::new_v4(), Uuid::new_v4());
+        let clients = [(lurker, client()), (chatter, client())];
+        let clock = Arc::new(MockClock::new());
+        let state = app_state_with_clock(clock.clone());
+        for (id, client) in clients {
+            state.clients.insert(id, client);
+        }
+        let idle = |id: Uuid| {
+            state
+                .list_users(&query(None, None))
+                .users
+                .into_iter()
+                .find(|u| u.id == id.to_string())
+                .and_then(|u| u.idle_seconds)
+        };
+
+        clock.advance(Duration::from_secs(90));
+        state
+            .clients
+            .get(&chatter)
+            .unwrap()
+            .increment_messages(clock.now());
+        clock.advance(Duration::from_secs(5));
+
+        assert_eq!(idle(lurker), Some(95));
+        assert_eq!(idle(chatter), Some(5));
+    }
+
+    #[test]
+    fn list_users_pages_through_all_users() {
+        let state = app_state();
+        for i in 0..25 {
+            add_user(&state, &format!("user-{i:02}"), 0);
+        }
+        let page = |sort, offset| {
+            state.list_users(&UserQuery {
+                sort,
+                filter: None,
+                offset,
+                limit: 10,
+            })
+        };
+
+        let second = page(Some(UserSort::Name), 10);
+        assert_eq!(second.total, 25);
+        assert_eq!(
+            names(second),
+            (10..20).map(|i| format!("user-{i:02}")).collect::<Vec<_>>()
+        );
+        assert_eq!(page(Some(UserSort::Name), 20).users.len(), 5);
+        assert!(page(Some(UserSort::Name), 30).users.is_empty());
+
+        // Unsorted pages still cover every user exactly once
+        let mut seen: Vec<String> = (0..3).flat_map(|p| names(page(None, p * 10))).collect();
+        seen.sort();
+        assert_eq!(
+            seen,
+            (0..25).map(|i| format!("user-{i:02}")).collect::<Vec<_>>()
+        );
+    }
 }
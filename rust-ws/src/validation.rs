@@ -0,0 +1,437 @@
+//! Checks on user-supplied names and chat text.
+//!
+//! The policy is process-wide like the locale in [`crate::messages`]; the check itself
+//! takes it as an argument so tests don't depend on the environment.
+
+use std::sync::OnceLock;
+
+use tracing::{info, warn};
+
+use crate::messages::{self, Msg};
+
+const DEFAULT_MAX_MESSAGE_LENGTH: usize = 500;
+/// Longest file name in a file offer, in characters
+const MAX_FILE_NAME_LENGTH: usize = 255;
+/// Largest file that can be offered, in GB; the server never sees the bytes, this only
+/// catches nonsense sizes
+const MAX_FILE_OFFER_GB: u64 = 10;
+/// Longest download URL in a file offer
+const MAX_FILE_URL_LENGTH: usize = 2048;
+
+/// Which characters a name may contain, on top of the control character check
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameChars {
+    /// Letters, digits, space, `-` and `_`
+    Strict,
+    /// Anything printable, including punctuation and emoji
+    Printable,
+}
+
+/// What to do with control characters and terminal escape sequences in chat text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlChars {
+    /// Remove them and send the rest
+    Strip,
+    /// Refuse the whole message
+    Reject,
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationConfig {
+    pub name_chars: NameChars,
+    pub control_chars: ControlChars,
+    /// Longest chat message in characters (Unicode scalar values), not bytes
+    pub max_message_length: usize,
+}
+
+impl ValidationConfig {
+    pub fn from_env() -> Self {
+        let name_chars = match std::env::var("NAME_CHARS") {
+            Ok(v) if v.eq_ignore_ascii_case("strict") => NameChars::Strict,
+            Ok(v) if v.eq_ignore_ascii_case("printable") => NameChars::Printable,
+            Ok(v) => {
+                warn!(name_chars = %v, "Unknown NAME_CHARS, falling back to printable");
+                NameChars::Printable
+            }
+            Err(_) => NameChars::Printable,
+        };
+        let control_chars = match std::env::var("CHAT_CONTROL_CHARS") {
+            Ok(v) if v.eq_ignore_ascii_case("strip") => ControlChars::Strip,
+            Ok(v) if v.eq_ignore_ascii_case("reject") => ControlChars::Reject,
+            Ok(v) => {
+                warn!(control_chars = %v, "Unknown CHAT_CONTROL_CHARS, falling back to strip");
+                ControlChars::Strip
+            }
+            Err(_) => ControlChars::Strip,
+        };
+        let max_message_length = std::env::var("MAX_MESSAGE_LENGTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&max| max > 0)
+            .unwrap_or(DEFAULT_MAX_MESSAGE_LENGTH);
+        info!(
+            ?name_chars,
+            ?control_chars,
+            max_message_length,
+            "Input validation"
+        );
+        Self {
+            name_chars,
+            control_chars,
+            max_message_length,
+        }
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            name_chars: NameChars::Printable,
+            control_chars: ControlChars::Strip,
+            max_message_length: DEFAULT_MAX_MESSAGE_LENGTH,
+        }
+    }
+}
+
+static CONFIG: OnceLock<ValidationConfig> = OnceLock::new();
+
+/// Set the validation policy. Only the first call has effect.
+pub fn init(config: ValidationConfig) {
+    let _ = CONFIG.set(config);
+}
+
+pub fn config() -> &'static ValidationConfig {
+    CONFIG.get_or_init(ValidationConfig::default)
+}
+
+/// Zero-width and bidi formatting characters. They don't show up in a terminal, so they
+/// make look-alike names possible.
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{2069}'
+            | '\u{FEFF}'
+    )
+}
+
+/// Check a requested name and return it in the form it will be shown: trimmed, with runs
+/// of whitespace collapsed to a single space.
+pub fn validate_name(name: &str, policy: NameChars) -> Result<String, String> {
+    let mut prev: Option<char> = None;
+    for c in name.chars() {
+        // A zero-width joiner glues emoji together (👨‍👩‍👧); between letters it only hides
+        let emoji_joiner = c == '\u{200D}'
+            && prev.is_some_and(|p| !p.is_ascii() && !p.is_alphanumeric() && !p.is_whitespace());
+        if c.is_control() || (is_invisible(c) && !emoji_joiner) {
+            return Err(messages::text(Msg::NameInvisible, &[]));
+        }
+        prev = Some(c);
+    }
+
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let len = name.chars().count();
+    if !(2..=32).contains(&len) {
+        return Err(messages::text(Msg::NameLength, &[]));
+    }
+    if policy == NameChars::Strict
+        && !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+    {
+        return Err(messages::text(Msg::NameChars, &[]));
+    }
+    Ok(name)
+}
+
+/// Newlines (multi-line messages) and tabs are harmless in a terminal; every other
+/// control character can move the cursor, clear the screen or ring the bell
+fn is_unsafe_control(c: char) -> bool {
+    c.is_control() && c != '\n' && c != '\t'
+}
+
+/// Remove escape sequences as a whole, so no `[2J` remnants are left, plus any other
+/// unsafe control characters
+fn strip_control(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            match chars.next() {
+                // CSI: parameters up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC and friends: a string up to BEL or ESC \
+                Some(']' | 'P' | '_' | '^') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' {
+                            chars.next_if_eq(&'\\');
+                            break;
+                        }
+                    }
+                }
+                // Two-character sequences like ESC c (reset)
+                _ => {}
+            }
+        } else if !is_unsafe_control(c) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Apply the control character policy, trim, and check the message length limits
+pub fn validate_chat_text(text: &str, config: &ValidationConfig) -> Result<String, String> {
+    let text = match config.control_chars {
+        ControlChars::Strip => strip_control(text),
+        ControlChars::Reject if text.chars().any(is_unsafe_control) => {
+            return Err(messages::text(Msg::MessageControlChars, &[]));
+        }
+        ControlChars::Reject => text.to_string(),
+    };
+
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return Err(messages::text(Msg::MessageEmpty, &[]));
+    }
+    if trimmed.chars().count() > config.max_message_length {
+        return Err(messages::text(
+            Msg::MessageTooLong,
+            &[("max", &config.max_message_length)],
+        ));
+    }
+    Ok(trimmed.to_string())
+}
+
+/// Check the metadata of a file offer: a plain file name without a path, a size within
+/// [`MAX_FILE_OFFER_GB`], and an http(s) URL. Returns the trimmed name and URL.
+pub fn validate_file_offer(name: &str, size: u64, url: &str) -> Result<(String, String), String> {
+    let name = name.trim();
+    let len = name.chars().count();
+    if len == 0
+        || len > MAX_FILE_NAME_LENGTH
+        || name == "."
+        || name == ".."
+        || name
+            .chars()
+            .any(|c| c.is_control() || is_invisible(c) || c == '/' || c == '\\')
+    {
+        return Err(messages::text(
+            Msg::FileNameInvalid,
+            &[("max", &MAX_FILE_NAME_LENGTH)],
+        ));
+    }
+
+    if size == 0 || size > MAX_FILE_OFFER_GB * 1024 * 1024 * 1024 {
+        return Err(messages::text(
+            Msg::FileSizeInvalid,
+            &[("max", &MAX_FILE_OFFER_GB)],
+        ));
+    }
+
+    let url = url.trim();
+    let has_host = url.split_once("://").is_some_and(|(scheme, rest)| {
+        (scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+            && !rest.is_empty()
+            && !rest.starts_with('/')
+    });
+    if !has_host
+        || url.len() > MAX_FILE_URL_LENGTH
+        || url.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(messages::text(Msg::FileUrlInvalid, &[]));
+    }
+
+    Ok((name.to_string(), url.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected_as_invisible(name: &str) -> bool {
+        validate_name(name, NameChars::Printable) == Err(messages::text(Msg::NameInvisible, &[]))
+    }
+
+    #[test]
+    fn control_characters_are_rejected() {
+        assert!(rejected_as_invisible("jan\nde vries"));
+        assert!(rejected_as_invisible("jan\tdv"));
+        assert!(rejected_as_invisible("\x1b[31mrood"));
+        assert!(rejected_as_invisible("bel\x07"));
+    }
+
+    #[test]
+    fn invisible_characters_are_rejected() {
+        assert!(rejected_as_invisible("ad\u{200B}min"));
+        assert!(rejected_as_invisible("ad\u{200D}min"));
+        assert!(rejected_as_invisible("\u{202E}nimda"));
+    }
+
+    #[test]
+    fn whitespace_is_collapsed() {
+        assert_eq!(
+            validate_name("  Jan   de\u{00A0}Vries ", NameChars::Printable),
+            Ok("Jan de Vries".to_string())
+        );
+    }
+
+    #[test]
+    fn emoji_are_allowed_unless_strict() {
+        assert_eq!(
+            validate_name("Bas 🚀", NameChars::Printable),
+            Ok("Bas 🚀".to_string())
+        );
+        assert_eq!(
+            validate_name("👨\u{200D}👩\u{200D}👧 fam", NameChars::Printable),
+            Ok("👨\u{200D}👩\u{200D}👧 fam".to_string())
+        );
+        assert_eq!(
+            validate_name("Bas 🚀", NameChars::Strict),
+            Err(messages::text(Msg::NameChars, &[]))
+        );
+        assert_eq!(
+            validate_name("Bas_123", NameChars::Strict),
+            Ok("Bas_123".to_string())
+        );
+    }
+
+    fn chat_config(control_chars: ControlChars) -> ValidationConfig {
+        ValidationConfig {
+            control_chars,
+            ..ValidationConfig::default()
+        }
+    }
+
+    #[test]
+    fn escape_sequences_are_stripped_from_chat() {
+        let config = chat_config(ControlChars::Strip);
+
+        assert_eq!(
+            validate_chat_text("hoi\x1b[2J\x1b[H allemaal", &config),
+            Ok("hoi allemaal".to_string())
+        );
+        assert_eq!(
+            validate_chat_text("\x1b[1;31mrood\x1b[0m", &config),
+            Ok("rood".to_string())
+        );
+        assert_eq!(
+            validate_chat_text("\x1b]0;titel\x07ping\x07\x07", &config),
+            Ok("ping".to_string())
+        );
+        // Newlines and tabs stay, carriage returns don't
+        assert_eq!(
+            validate_chat_text("regel 1\r\nregel\t2", &config),
+            Ok("regel 1\nregel\t2".to_string())
+        );
+    }
+
+    #[test]
+    fn chat_with_only_control_characters_is_empty() {
+        let config = chat_config(ControlChars::Strip);
+
+        assert_eq!(
+            validate_chat_text("\x07\x1b[2J", &config),
+            Err(messages::text(Msg::MessageEmpty, &[]))
+        );
+    }
+
+    #[test]
+    fn escape_sequences_can_be_rejected() {
+        let config = chat_config(ControlChars::Reject);
+        let rejected = Err(messages::text(Msg::MessageControlChars, &[]));
+
+        assert_eq!(validate_chat_text("hoi\x1b[2J", &config), rejected);
+        assert_eq!(validate_chat_text("ding\x07dong", &config), rejected);
+        assert_eq!(
+            validate_chat_text("regel 1\nregel 2 🚀", &config),
+            Ok("regel 1\nregel 2 🚀".to_string())
+        );
+    }
+
+    #[test]
+    fn message_length_counts_characters_not_bytes() {
+        let config = ValidationConfig {
+            max_message_length: 10,
+            ..ValidationConfig::default()
+        };
+        // 10 characters, 20 bytes
+        let ten = "é".repeat(10);
+        assert_eq!(validate_chat_text(&ten, &config), Ok(ten.clone()));
+
+        let eleven = format!("{ten}é");
+        assert_eq!(
+            validate_chat_text(&eleven, &config),
+            Err(messages::text(Msg::MessageTooLong, &[("max", &10)]))
+        );
+    }
+
+    #[test]
+    fn name_length_counts_characters_not_bytes() {
+        // 32 characters, 96 bytes
+        let cjk = "漢字".repeat(16);
+        assert_eq!(validate_name(&cjk, NameChars::Printable), Ok(cjk.clone()));
+        assert_eq!(validate_name(&cjk, NameChars::Strict), Ok(cjk.clone()));
+        assert_eq!(
+            validate_name(&format!("{cjk}字"), NameChars::Printable),
+            Err(messages::text(Msg::NameLength, &[]))
+        );
+        // A single 3-byte character is still too short
+        assert_eq!(
+            validate_name("漢", NameChars::Printable),
+            Err(messages::text(Msg::NameLength, &[]))
+        );
+    }
+
+    #[test]
+    fn message_of_500_emoji_is_accepted() {
+        let config = ValidationConfig::default();
+        let emoji = "🚀".repeat(500);
+
+        assert_eq!(validate_chat_text(&emoji, &config), Ok(emoji.clone()));
+        assert!(validate_chat_text(&format!("{emoji}🚀"), &config).is_err());
+    }
+
+    #[test]
+    fn file_offers_need_a_plain_name_sane_size_and_web_url() {
+        let url = "https://files.example.com/notulen.pdf";
+        assert_eq!(
+            validate_file_offer(" notulen.pdf ", 1024, url),
+            Ok(("notulen.pdf".to_string(), url.to_string()))
+        );
+
+        let bad_name = Err(messages::text(Msg::FileNameInvalid, &[("max", &255)]));
+        assert_eq!(validate_file_offer("../etc/passwd", 1, url), bad_name);
+        assert_eq!(validate_file_offer("c:\\boot.ini", 1, url), bad_name);
+        assert_eq!(validate_file_offer("  ", 1, url), bad_name);
+        assert_eq!(validate_file_offer("a\x1b[2J.txt", 1, url), bad_name);
+        assert_eq!(validate_file_offer(&"a".repeat(256), 1, url), bad_name);
+
+        let bad_size = Err(messages::text(Msg::FileSizeInvalid, &[("max", &10)]));
+        assert_eq!(validate_file_offer("a.txt", 0, url), bad_size);
+        assert_eq!(validate_file_offer("a.txt", u64::MAX, url), bad_size);
+
+        let bad_url = Err(messages::text(Msg::FileUrlInvalid, &[]));
+        assert_eq!(
+            validate_file_offer("a.txt", 1, "file:///etc/passwd"),
+            bad_url
+        );
+        assert_eq!(
+            validate_file_offer("a.txt", 1, "javascript:alert(1)"),
+            bad_url
+        );
+        assert_eq!(validate_file_offer("a.txt", 1, "https://"), bad_url);
+        assert_eq!(validate_file_offer("a.txt", 1, "https://x.nl/a b"), bad_url);
+    }
+}
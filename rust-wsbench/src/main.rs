@@ -1,18 +1,18 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
     time::{Duration, Instant},
 };
 
-use clap::Parser;
+use chat_protocol::{ClientMessage as Outgoing, ServerMessage as Incoming, SUBPROTOCOL_MSGPACK};
+use clap::{Parser, ValueEnum};
 use futures_util::{SinkExt, StreamExt};
 use rand::Rng;
-use serde::{Deserialize, Serialize};
 use tokio::sync::{Mutex, RwLock};
-use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http::HeaderValue, Message};
 
 #[derive(Parser, Debug)]
 #[command(name = "wsbench")]
@@ -30,6 +30,18 @@ struct Args {
     #[arg(long, default_value = "60")]
     rate: u32,
 
+    /// Ramp the rate: messages per minute per client at the start (use with --rate-end)
+    #[arg(long, requires = "rate_end", conflicts_with = "flood")]
+    rate_start: Option<u32>,
+
+    /// Ramp the rate: messages per minute per client at the end of the run
+    #[arg(long, requires = "rate_start")]
+    rate_end: Option<u32>,
+
+    /// P95 latency (ms) that counts as overloaded when ramping
+    #[arg(long, default_value = "100")]
+    latency_threshold: u64,
+
     /// Test duration in seconds
     #[arg(long, default_value = "30")]
     duration: u64,
@@ -41,28 +53,110 @@ struct Args {
     /// Flood mode: send as fast as possible (ignores --rate)
     #[arg(long, default_value = "false")]
     flood: bool,
+
+    /// Pad or truncate each chat message to this many bytes
+    #[arg(long)]
+    msg_size: Option<usize>,
+
+    /// Message content strategy
+    #[arg(long, value_enum, default_value = "phrases")]
+    payload: Payload,
+
+    /// What to measure: chat echo latency or ping/pong round-trip
+    #[arg(long, value_enum, default_value = "chat")]
+    mode: Mode,
+
+    /// Reconnect clients whose connection drops and keep sending until the end
+    #[arg(long, default_value = "false")]
+    reconnect: bool,
+
+    /// Warn when less than this share of the broadcasts reached the clients in the last second
+    #[arg(long, default_value = "0.95")]
+    min_fanout: f64,
+
+    /// Print only the results, as JSON
+    #[arg(long, default_value = "false")]
+    json: bool,
+
+    /// Ask the server for its status every this many seconds, on a separate connection,
+    /// to record its memory and CPU usage during the run
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    status_poll: Option<u64>,
+
+    /// Negotiate the chat.msgpack subprotocol and exchange MessagePack binary frames
+    /// instead of JSON
+    #[arg(long, default_value = "false")]
+    msgpack: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Payload {
+    /// Short Dutch/English phrases with a random suffix
+    Phrases,
+    /// Lorem ipsum text
+    Lorem,
+    /// Random alphanumeric characters
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// Send chat messages and time the broadcast echo (includes fan-out)
+    Chat,
+    /// Send pings and time the matching pong (request/response only)
+    Ping,
+}
+
+/// Per-client settings derived from the command line
+#[derive(Clone)]
+struct ClientConfig {
+    url: String,
+    rate: u32,
+    quiet: bool,
+    flood: bool,
+    payload: Payload,
+    msg_size: Option<usize>,
+    mode: Mode,
+    reconnect: bool,
+    /// Replaces `rate` when ramping
+    ramp: Option<Ramp>,
+    msgpack: bool,
+}
+
+/// `--rate-start` to `--rate-end`, linear over the run
+#[derive(Clone, Copy)]
+struct Ramp {
+    start: u32,
+    end: u32,
+    duration: Duration,
+}
+
+impl Ramp {
+    /// Messages per minute per client with `remaining` of the run to go
+    fn rate(&self, remaining: Duration) -> f64 {
+        let progress = 1.0 - remaining.as_secs_f64() / self.duration.as_secs_f64().max(0.001);
+        let progress = progress.clamp(0.0, 1.0);
+        self.start as f64 + (self.end as f64 - self.start as f64) * progress
+    }
+}
+
+/// Pause between messages at `rate` messages per minute, in microseconds
+fn interval_us(rate: f64) -> u64 {
+    (60_000_000.0 / rate.max(1.0)) as u64
 }
 
-#[derive(Debug, Serialize)]
-#[serde(tag = "type", rename_all = "camelCase")]
-enum Outgoing {
-    #[serde(rename = "chat")]
-    Chat { text: String },
-    #[serde(rename = "setName")]
-    SetName { name: String },
+/// When something first went wrong during a ramp, and the load at that moment
+#[derive(Clone, Copy)]
+struct Crossing {
+    elapsed_secs: u64,
+    /// Messages sent per second over all clients, in that second
+    rate: u64,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(tag = "type", rename_all = "camelCase")]
-enum Incoming {
-    #[serde(rename = "chat")]
-    Chat { from: String, text: String },
-    #[serde(rename = "ackName")]
-    AckName { name: String },
-    #[serde(rename = "error")]
-    Error { message: String },
-    #[serde(other)]
-    Other,
+#[derive(Default)]
+struct Crossings {
+    errors: Option<Crossing>,
+    latency: Option<Crossing>,
 }
 
 const PHRASES: &[&str] = &[
@@ -92,6 +186,133 @@ fn random_phrase() -> String {
     format!("{}{}", phrase, suffix)
 }
 
+const LOREM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \
+tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud \
+exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. ";
+
+fn generate_payload(kind: Payload) -> String {
+    match kind {
+        Payload::Phrases => random_phrase(),
+        Payload::Lorem => {
+            let start = rand::rng().random_range(0..LOREM.len() / 2);
+            LOREM[start..].trim_start().to_string()
+        }
+        Payload::Random => {
+            let mut rng = rand::rng();
+            let len = rng.random_range(16..=64);
+            (0..len)
+                .map(|_| rng.sample(rand::distr::Alphanumeric) as char)
+                .collect()
+        }
+    }
+}
+
+/// Build the chat text `<msg_id>|<checksum>|<payload>`, padded or truncated to `msg_size` bytes.
+///
+/// Padding repeats the payload itself, so the content stays deterministic for a given payload.
+/// The message id and checksum are never truncated since they're needed for latency tracking
+/// and the integrity check; the checksum covers the payload as sent, after padding.
+/// The server trims chat text, so trailing whitespace is swapped for dots to keep the echo
+/// identical to what was sent.
+fn build_message(msg_id: &str, payload: Payload, msg_size: Option<usize>) -> String {
+    let mut text = pad_message(msg_id, payload, msg_size);
+    let trailing = text.len() - text.trim_end().len();
+    text.truncate(text.len() - trailing);
+    text.push_str(&".".repeat(trailing));
+    let header_len = msg_id.len() + 1;
+    let sum = format!("{:08x}", checksum(&text[header_len + CHECKSUM_LEN + 1..]));
+    text.replace_range(header_len..header_len + CHECKSUM_LEN, &sum);
+    text
+}
+
+/// Hex digits of the checksum field
+const CHECKSUM_LEN: usize = 8;
+
+/// FNV-1a: tiny, and any flipped, dropped or swapped byte changes it
+fn checksum(payload: &str) -> u32 {
+    payload.bytes().fold(0x811c_9dc5, |hash: u32, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// What an echo of our own chat message says about the wire
+enum Integrity {
+    /// Checksum matches; the sequence number of the message
+    Intact(u64),
+    /// Header missing or mangled, or the payload doesn't match its checksum
+    Corrupt,
+}
+
+/// Check the `<client>-<seq>|<checksum>|<payload>` text written by [`build_message`]
+fn verify_message(text: &str) -> Integrity {
+    let mut parts = text.splitn(3, '|');
+    let (Some(msg_id), Some(sum), Some(payload)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Integrity::Corrupt;
+    };
+    let seq = msg_id
+        .rsplit_once('-')
+        .and_then(|(_, seq)| seq.parse().ok());
+    match (seq, u32::from_str_radix(sum, 16)) {
+        (Some(seq), Ok(sum)) if sum == checksum(payload) => Integrity::Intact(seq),
+        _ => Integrity::Corrupt,
+    }
+}
+
+/// `<msg_id>|<checksum placeholder>|<payload>`, padded or truncated to `msg_size` bytes
+fn pad_message(msg_id: &str, payload: Payload, msg_size: Option<usize>) -> String {
+    let content = generate_payload(payload);
+    let mut text = format!("{}|{}|{}", msg_id, "0".repeat(CHECKSUM_LEN), content);
+    let Some(size) = msg_size else {
+        return text;
+    };
+
+    let min_len = msg_id.len() + CHECKSUM_LEN + 2;
+    if text.len() > size {
+        let mut cut = size.max(min_len);
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+        return text;
+    }
+
+    let filler = if content.is_empty() {
+        "."
+    } else {
+        content.as_str()
+    };
+    for c in std::iter::once(' ').chain(filler.chars().cycle()) {
+        if text.len() + c.len_utf8() > size {
+            break;
+        }
+        text.push(c);
+    }
+    // Fill any remainder left by a multi-byte char
+    while text.len() < size {
+        text.push('.');
+    }
+    text
+}
+
+/// Pause before a dropped client tries to reconnect (with --reconnect)
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// How long a stopped client keeps reading so in-flight echoes still count
+const STOP_SETTLE: Duration = Duration::from_millis(500);
+
+/// Sleep for `duration`, waking early once `stop` is set.
+async fn sleep_or_stop(duration: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        tokio::time::sleep(remaining.min(Duration::from_millis(100))).await;
+    }
+}
+
 fn random_interval(base_us: u64) -> Duration {
     if base_us == 0 {
         return Duration::ZERO;
@@ -102,13 +323,36 @@ fn random_interval(base_us: u64) -> Duration {
     Duration::from_micros((base_us as i64 + offset).max(100) as u64)
 }
 
+/// Size of the rolling latency window shown in the progress line
+const RECENT_SAMPLES: usize = 1000;
+
 struct Stats {
     connected: AtomicU64,
     messages_sent: AtomicU64,
     messages_received: AtomicU64,
-    errors: AtomicU64,
+    /// Chat messages received by all clients together, own echoes included
+    chat_received: AtomicU64,
+    /// Connection attempts that failed
+    connect_errors: AtomicU64,
+    /// Frames that couldn't be written
+    send_errors: AtomicU64,
+    /// Connections the server closed or dropped mid-test, and `error` replies
+    read_errors: AtomicU64,
+    /// Own echoes that arrived with a lower sequence number than one already seen
+    out_of_order: AtomicU64,
+    /// Own echoes whose header or checksum didn't survive the round-trip
+    corrupt: AtomicU64,
     rate_limited: AtomicU64,
+    reconnects: AtomicU64,
+    bytes_sent: AtomicU64,
     latencies: Mutex<Vec<u64>>,
+    /// Most recent latency samples for the live progress readout.
+    /// A std mutex: critical sections are a push or a copy, never held across awaits.
+    recent_latencies: std::sync::Mutex<VecDeque<u64>>,
+    /// WebSocket handshake duration per successful connection, in microseconds
+    connect_latencies: Mutex<Vec<u64>>,
+    /// With `--status-poll`; a std mutex like `recent_latencies`
+    server_samples: std::sync::Mutex<Vec<ServerSample>>,
 }
 
 impl Stats {
@@ -117,39 +361,234 @@ impl Stats {
             connected: AtomicU64::new(0),
             messages_sent: AtomicU64::new(0),
             messages_received: AtomicU64::new(0),
-            errors: AtomicU64::new(0),
+            chat_received: AtomicU64::new(0),
+            connect_errors: AtomicU64::new(0),
+            send_errors: AtomicU64::new(0),
+            read_errors: AtomicU64::new(0),
+            out_of_order: AtomicU64::new(0),
+            corrupt: AtomicU64::new(0),
             rate_limited: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
             latencies: Mutex::new(Vec::new()),
+            recent_latencies: std::sync::Mutex::new(VecDeque::with_capacity(RECENT_SAMPLES)),
+            connect_latencies: Mutex::new(Vec::new()),
+            server_samples: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Connect, send and read errors together
+    fn errors(&self) -> u64 {
+        self.connect_errors.load(Ordering::Relaxed)
+            + self.send_errors.load(Ordering::Relaxed)
+            + self.read_errors.load(Ordering::Relaxed)
+    }
+
+    fn record_recent_latency(&self, latency: u64) {
+        if let Ok(mut recent) = self.recent_latencies.lock() {
+            if recent.len() == RECENT_SAMPLES {
+                recent.pop_front();
+            }
+            recent.push_back(latency);
         }
     }
+
+    /// Sorted copy of the recent sample window
+    fn recent_latencies_sorted(&self) -> Vec<u64> {
+        let mut samples: Vec<u64> = self
+            .recent_latencies
+            .lock()
+            .map(|r| r.iter().copied().collect())
+            .unwrap_or_default();
+        samples.sort_unstable();
+        samples
+    }
+}
+
+/// Server resource usage from one `status` reply (`--status-poll`)
+struct ServerSample {
+    elapsed_secs: f64,
+    memory_mb: f64,
+    /// Only sent by servers that sample CPU usage
+    cpu_percent: Option<f64>,
+}
+
+/// Min, average and max, or `None` without values
+fn min_avg_max(values: impl Iterator<Item = f64>) -> Option<(f64, f64, f64)> {
+    let (mut min, mut max, mut sum, mut count) = (f64::MAX, f64::MIN, 0.0, 0);
+    for value in values {
+        min = min.min(value);
+        max = max.max(value);
+        sum += value;
+        count += 1;
+    }
+    (count > 0).then(|| (min, sum / count as f64, max))
+}
+
+/// Request `status` every `every` on a connection of its own and record the server's
+/// memory and CPU usage. Not counted as a benchmark client.
+async fn poll_status(
+    url: String,
+    every: Duration,
+    start_time: Instant,
+    end_time: Instant,
+    stats: Arc<Stats>,
+    stop: Arc<AtomicBool>,
+) {
+    let (mut write, mut read) = match tokio_tungstenite::connect_async(&url).await {
+        Ok((stream, _)) => stream.split(),
+        Err(e) => {
+            eprintln!("[Status poll] Connection failed: {}", e);
+            return;
+        }
+    };
+    let request = serde_json::to_string(&Outgoing::Status).unwrap();
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if Instant::now() >= end_time || stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                if write.send(Message::Text(request.clone().into())).await.is_err() {
+                    break;
+                }
+            }
+            msg = read.next() => match msg {
+                // This connection gets every broadcast too; only parse what can be a status
+                Some(Ok(Message::Text(text))) if text.contains("\"status\"") => {
+                    if let Ok(Incoming::Status {
+                        memory_mb: Some(memory_mb),
+                        cpu_percent,
+                        ..
+                    }) = serde_json::from_str::<Incoming>(&text)
+                    {
+                        if let Ok(mut samples) = stats.server_samples.lock() {
+                            samples.push(ServerSample {
+                                elapsed_secs: start_time.elapsed().as_secs_f64(),
+                                memory_mb,
+                                cpu_percent,
+                            });
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = write.send(Message::Close(None)).await;
+}
+
+type WsStream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Connect, asking for the MessagePack subprotocol with `msgpack`
+async fn connect(url: &str, msgpack: bool) -> Result<WsStream, tungstenite::Error> {
+    let mut request = url.into_client_request()?;
+    if msgpack {
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(SUBPROTOCOL_MSGPACK),
+        );
+    }
+    let (stream, _) = tokio_tungstenite::connect_async(request).await?;
+    Ok(stream)
+}
+
+fn encode(outgoing: &Outgoing, msgpack: bool) -> Message {
+    if msgpack {
+        Message::Binary(rmp_serde::to_vec_named(outgoing).unwrap().into())
+    } else {
+        Message::Text(serde_json::to_string(outgoing).unwrap().into())
+    }
+}
+
+/// JSON text frames and MessagePack binary frames; `None` for anything unparseable
+fn decode(msg: &Message) -> Option<Incoming> {
+    match msg {
+        Message::Text(text) => serde_json::from_str(text).ok(),
+        Message::Binary(bytes) => rmp_serde::from_slice(bytes).ok(),
+        _ => None,
+    }
+}
+
+/// How a single connection of a benchmark client ended
+enum SessionEnd {
+    /// Test duration reached or interrupted
+    Finished,
+    /// Connection failed or was dropped mid-test
+    Dropped,
 }
 
 async fn run_client(
     client_id: usize,
-    url: String,
-    rate: u32,
+    config: ClientConfig,
     end_time: Instant,
     stats: Arc<Stats>,
-    quiet: bool,
-    flood: bool,
+    stop: Arc<AtomicBool>,
 ) {
+    // Message ids keep counting across reconnects so latencies stay unambiguous
+    let mut msg_count = 0u64;
+    let mut sessions = 0u64;
+
+    loop {
+        let (end, connected) =
+            run_session(client_id, &config, end_time, &stats, &stop, &mut msg_count).await;
+        if connected {
+            if sessions > 0 {
+                stats.reconnects.fetch_add(1, Ordering::Relaxed);
+            }
+            sessions += 1;
+        }
+
+        let out_of_time = Instant::now() >= end_time || stop.load(Ordering::Relaxed);
+        match end {
+            SessionEnd::Dropped if config.reconnect && !out_of_time => {
+                if !config.quiet {
+                    println!("[Client {}] Reconnecting...", client_id);
+                }
+                sleep_or_stop(RECONNECT_DELAY, &stop).await;
+            }
+            _ => break,
+        }
+    }
+}
+
+/// Run one connection until the test ends or the connection drops.
+/// Returns how it ended and whether the connection was established at all.
+async fn run_session(
+    client_id: usize,
+    config: &ClientConfig,
+    end_time: Instant,
+    stats: &Arc<Stats>,
+    stop: &AtomicBool,
+    msg_count: &mut u64,
+) -> (SessionEnd, bool) {
     let name = format!("bench-{}", client_id);
     // Calculate interval in microseconds: 60 seconds = 60_000_000 microseconds
-    let base_interval_us = if flood {
+    let fixed_interval_us = if config.flood {
         0
     } else {
-        60_000_000 / rate.max(1) as u64
+        interval_us(config.rate as f64)
     };
+    let quiet = config.quiet;
 
     // Connect
-    let ws_stream = match tokio_tungstenite::connect_async(&url).await {
-        Ok((stream, _)) => stream,
+    let connect_start = Instant::now();
+    let ws_stream = match connect(&config.url, config.msgpack).await {
+        Ok(stream) => {
+            let connect_us = connect_start.elapsed().as_micros() as u64;
+            stats.connect_latencies.lock().await.push(connect_us);
+            stream
+        }
         Err(e) => {
             if !quiet {
                 eprintln!("[Client {}] Connection failed: {}", client_id, e);
             }
-            stats.errors.fetch_add(1, Ordering::Relaxed);
-            return;
+            stats.connect_errors.fetch_add(1, Ordering::Relaxed);
+            return (SessionEnd::Dropped, false);
         }
     };
 
@@ -161,11 +600,11 @@ async fn run_client(
     let (mut write, mut read) = ws_stream.split();
 
     // Send initial name
-    let set_name = serde_json::to_string(&Outgoing::SetName { name: name.clone() }).unwrap();
-    if write.send(Message::Text(set_name.into())).await.is_err() {
-        stats.errors.fetch_add(1, Ordering::Relaxed);
+    let set_name = encode(&Outgoing::SetName { name: name.clone() }, config.msgpack);
+    if write.send(set_name).await.is_err() {
+        stats.send_errors.fetch_add(1, Ordering::Relaxed);
         stats.connected.fetch_sub(1, Ordering::Relaxed);
-        return;
+        return (SessionEnd::Dropped, true);
     }
 
     // Track pending messages for latency
@@ -177,36 +616,73 @@ async fn run_client(
 
     // Reader task
     let reader = tokio::spawn(async move {
+        // Highest sequence number echoed back so far on this connection
+        let mut last_seq = None;
         while let Some(msg) = read.next().await {
             match msg {
-                Ok(Message::Text(text)) => {
-                    stats_read.messages_received.fetch_add(1, Ordering::Relaxed);
+                Ok(msg @ (Message::Text(_) | Message::Binary(_))) => {
+                    let Some(incoming) = decode(&msg) else {
+                        stats_read.messages_received.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    };
+                    // A batch counts as the messages in it, so results compare with and without batching
+                    let messages = match incoming {
+                        Incoming::ChatBatch { messages } => messages,
+                        incoming => vec![incoming],
+                    };
+                    stats_read
+                        .messages_received
+                        .fetch_add(messages.len() as u64, Ordering::Relaxed);
 
-                    if let Ok(incoming) = serde_json::from_str::<Incoming>(&text) {
+                    for incoming in messages {
                         match incoming {
-                            Incoming::AckName { name } => {
+                            Incoming::AckName { name, .. } => {
                                 *client_name_read.write().await = name;
                             }
-                            Incoming::Chat { from, text } => {
+                            Incoming::Chat { from, text, .. } => {
+                                stats_read.chat_received.fetch_add(1, Ordering::Relaxed);
                                 let current_name = client_name_read.read().await.clone();
                                 if from == current_name {
+                                    match verify_message(&text) {
+                                        Integrity::Intact(seq) if last_seq >= Some(seq) => {
+                                            stats_read.out_of_order.fetch_add(1, Ordering::Relaxed);
+                                        }
+                                        Integrity::Intact(seq) => last_seq = Some(seq),
+                                        Integrity::Corrupt => {
+                                            stats_read.corrupt.fetch_add(1, Ordering::Relaxed);
+                                        }
+                                    }
                                     if let Some(msg_id) = text.split('|').next() {
                                         let mut pending = pending_read.write().await;
                                         if let Some(sent_at) = pending.remove(msg_id) {
                                             let latency = sent_at.elapsed().as_millis() as u64;
                                             stats_read.latencies.lock().await.push(latency);
+                                            stats_read.record_recent_latency(latency);
                                         }
                                     }
                                 }
                             }
-                            Incoming::Error { message } => {
+                            Incoming::Pong {
+                                token: Some(token), ..
+                            } => {
+                                if let Some(sent_at) = pending_read.write().await.remove(&token) {
+                                    let latency = sent_at.elapsed().as_millis() as u64;
+                                    stats_read.latencies.lock().await.push(latency);
+                                    stats_read.record_recent_latency(latency);
+                                }
+                            }
+                            Incoming::RateLimited { .. } => {
+                                stats_read.rate_limited.fetch_add(1, Ordering::Relaxed);
+                            }
+                            // Servers with RATE_LIMIT_PLAIN_ERROR=true report it as a plain error
+                            Incoming::Error { message, .. } => {
                                 if message.contains("Rate limit") {
                                     stats_read.rate_limited.fetch_add(1, Ordering::Relaxed);
                                 } else {
-                                    stats_read.errors.fetch_add(1, Ordering::Relaxed);
+                                    stats_read.read_errors.fetch_add(1, Ordering::Relaxed);
                                 }
                             }
-                            Incoming::Other => {}
+                            _ => {}
                         }
                     }
                 }
@@ -217,34 +693,72 @@ async fn run_client(
     });
 
     // Writer loop
-    let mut msg_count = 0u64;
-    while Instant::now() < end_time {
+    let mut end = SessionEnd::Finished;
+    while Instant::now() < end_time && !stop.load(Ordering::Relaxed) {
+        // Reader stopped: the server closed or dropped the connection
+        if reader.is_finished() {
+            stats.read_errors.fetch_add(1, Ordering::Relaxed);
+            end = SessionEnd::Dropped;
+            break;
+        }
+
         let msg_id = format!("{}-{}", client_id, msg_count);
-        let text = format!("{}|{}", msg_id, random_phrase());
+        let (outgoing, text_len) = match config.mode {
+            Mode::Chat => {
+                let text = build_message(&msg_id, config.payload, config.msg_size);
+                let len = text.len() as u64;
+                (
+                    Outgoing::Chat {
+                        text,
+                        reply_to: None,
+                    },
+                    len,
+                )
+            }
+            Mode::Ping => (
+                Outgoing::Ping {
+                    token: Some(msg_id.clone()),
+                },
+                0,
+            ),
+        };
 
         pending.write().await.insert(msg_id, Instant::now());
 
-        let chat = serde_json::to_string(&Outgoing::Chat { text }).unwrap();
-        if write.send(Message::Text(chat.into())).await.is_err() {
-            stats.errors.fetch_add(1, Ordering::Relaxed);
+        if write.send(encode(&outgoing, config.msgpack)).await.is_err() {
+            stats.send_errors.fetch_add(1, Ordering::Relaxed);
+            end = SessionEnd::Dropped;
             break;
         }
 
         stats.messages_sent.fetch_add(1, Ordering::Relaxed);
-        msg_count += 1;
+        stats.bytes_sent.fetch_add(text_len, Ordering::Relaxed);
+        *msg_count += 1;
 
+        let base_interval_us = match config.ramp {
+            Some(ramp) => {
+                interval_us(ramp.rate(end_time.saturating_duration_since(Instant::now())))
+            }
+            None => fixed_interval_us,
+        };
         let interval = random_interval(base_interval_us);
         if interval.is_zero() {
             tokio::task::yield_now().await;
         } else {
-            tokio::time::sleep(interval).await;
+            sleep_or_stop(interval, stop).await;
         }
     }
 
+    // Interrupted: give echoes of the last messages a moment to arrive
+    if stop.load(Ordering::Relaxed) {
+        tokio::time::sleep(STOP_SETTLE).await;
+    }
+
     // Close connection
     let _ = write.send(Message::Close(None)).await;
     reader.abort();
     stats.connected.fetch_sub(1, Ordering::Relaxed);
+    (end, true)
 }
 
 fn percentile(sorted: &[u64], p: f64) -> u64 {
@@ -255,51 +769,122 @@ fn percentile(sorted: &[u64], p: f64) -> u64 {
     sorted[idx.saturating_sub(1).min(sorted.len() - 1)]
 }
 
+/// Share of the expected chat deliveries that arrived. Every accepted message is
+/// broadcast to all clients, the sender included, so `clients` copies are expected of it.
+fn fanout_ratio(chat_received: u64, sent: u64, rate_limited: u64, clients: u64) -> Option<f64> {
+    let expected = sent.saturating_sub(rate_limited) * clients;
+    (expected > 0).then(|| chat_received as f64 / expected as f64)
+}
+
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
 
+    let ramp = args.rate_start.zip(args.rate_end).map(|(start, end)| Ramp {
+        start,
+        end,
+        duration: Duration::from_secs(args.duration),
+    });
     let rate_display = if args.flood {
         "FLOOD (max speed)".to_string()
+    } else if let Some(ramp) = ramp {
+        format!("{} → {} msg/min/client", ramp.start, ramp.end)
     } else {
         format!("{} msg/min/client", args.rate)
     };
 
-    println!(
-        r#"
+    let payload_display = match args.msg_size {
+        _ if args.mode == Mode::Ping => "n/a (ping mode)".to_string(),
+        Some(size) => format!("{:?}, {} bytes", args.payload, size).to_lowercase(),
+        None => format!("{:?}", args.payload).to_lowercase(),
+    };
+
+    // With --json, stdout carries only the results
+    let json = args.json;
+    let quiet = args.quiet || json;
+    if !json {
+        println!(
+            r#"
 WebSocket Benchmark (Rust)
 ═══════════════════════════════════════
 URL:        {}
 Clients:    {}
 Rate:       {}
 Duration:   {}s
+Mode:       {}
+Payload:    {}
+Encoding:   {}
 ═══════════════════════════════════════
 "#,
-        args.url, args.clients, rate_display, args.duration
-    );
+            args.url,
+            args.clients,
+            rate_display,
+            args.duration,
+            format!("{:?}", args.mode).to_lowercase(),
+            payload_display,
+            if args.msgpack { "msgpack" } else { "json" }
+        );
+    }
+
+    let client_config = ClientConfig {
+        url: args.url.clone(),
+        rate: args.rate,
+        quiet,
+        flood: args.flood,
+        payload: args.payload,
+        msg_size: args.msg_size,
+        mode: args.mode,
+        reconnect: args.reconnect,
+        ramp,
+        msgpack: args.msgpack,
+    };
 
     let stats = Arc::new(Stats::new());
     let start_time = Instant::now();
     let end_time = start_time + Duration::from_secs(args.duration);
 
+    // Ctrl+C stops all clients early; the summary still covers what was collected
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_signal = stop.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("\nInterrupted, stopping clients and collecting partial results...");
+            stop_signal.store(true, Ordering::Relaxed);
+        }
+    });
+
+    let status_poll = args.status_poll.map(|secs| {
+        tokio::spawn(poll_status(
+            args.url.clone(),
+            Duration::from_secs(secs),
+            start_time,
+            end_time,
+            stats.clone(),
+            stop.clone(),
+        ))
+    });
+
     // Connect clients in batches
-    println!("Connecting clients...");
+    if !json {
+        println!("Connecting clients...");
+    }
     let mut handles = Vec::new();
     let batch_size = 50;
     let batch_delay = Duration::from_millis(100);
 
     for batch_start in (0..args.clients).step_by(batch_size) {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
         let batch_end = (batch_start + batch_size).min(args.clients);
 
         for client_id in batch_start..batch_end {
-            let url = args.url.clone();
+            let config = client_config.clone();
             let stats = stats.clone();
-            let quiet = args.quiet;
-            let flood = args.flood;
-            let rate = args.rate;
+            let stop = stop.clone();
 
             handles.push(tokio::spawn(async move {
-                run_client(client_id, url, rate, end_time, stats, quiet, flood).await;
+                run_client(client_id, config, end_time, stats, stop).await;
             }));
         }
 
@@ -312,20 +897,31 @@ Duration:   {}s
     tokio::time::sleep(Duration::from_millis(500)).await;
 
     let connected = stats.connected.load(Ordering::Relaxed);
-    println!("Connected: {}/{}\n", connected, args.clients);
+    if !json {
+        println!("Connected: {}/{}\n", connected, args.clients);
+    }
 
     // Progress indicator
     let stats_progress = stats.clone();
+    let stop_progress = stop.clone();
     let duration = args.duration;
     let total_clients = args.clients;
+    let min_fanout = args.min_fanout;
+    let chat_mode = args.mode == Mode::Chat;
+    let latency_threshold = args.latency_threshold;
+    let crossings = Arc::new(std::sync::Mutex::new(Crossings::default()));
+    let crossings_progress = crossings.clone();
     let progress_handle = tokio::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_secs(1));
         let start = Instant::now();
+        // Counters at the previous tick, for the fan-out of the last second
+        let mut last = (0, 0, 0);
+        let mut last_errors = 0;
 
         loop {
             interval.tick().await;
             let elapsed = start.elapsed().as_secs();
-            if elapsed >= duration {
+            if elapsed >= duration || stop_progress.load(Ordering::Relaxed) {
                 break;
             }
 
@@ -333,18 +929,91 @@ Duration:   {}s
             let sent = stats_progress.messages_sent.load(Ordering::Relaxed);
             let recv = stats_progress.messages_received.load(Ordering::Relaxed);
             let rate_limited = stats_progress.rate_limited.load(Ordering::Relaxed);
+            let chat_received = stats_progress.chat_received.load(Ordering::Relaxed);
+            let errors = stats_progress.errors();
+            let (last_sent, last_rate_limited, last_chat_received) = last;
+            last = (sent, rate_limited, chat_received);
+            let recent = stats_progress.recent_latencies_sorted();
+
+            // While ramping, remember when the server first started to struggle. The
+            // first tick fires straight away, before there is a second to compare.
+            let mut crossed = Vec::new();
+            if ramp.is_some() && elapsed > 0 {
+                let now = Crossing {
+                    elapsed_secs: start_time.elapsed().as_secs(),
+                    rate: sent - last_sent,
+                };
+                let mut crossings = crossings_progress.lock().unwrap();
+                if crossings.errors.is_none() && errors > last_errors {
+                    crossings.errors = Some(now);
+                    crossed.push(format!("errors started ({} new)", errors - last_errors));
+                }
+                let p95 = percentile(&recent, 95.0);
+                if crossings.latency.is_none() && p95 > latency_threshold {
+                    crossings.latency = Some(now);
+                    crossed.push(format!("p95 {}ms is over {}ms", p95, latency_threshold));
+                }
+                for what in &mut crossed {
+                    *what = format!(
+                        "  ⚠ At {}s ({} msg/s): {}",
+                        now.elapsed_secs, now.rate, what
+                    );
+                }
+            }
+            last_errors = errors;
+            if json {
+                continue;
+            }
+
+            let mut latency = if recent.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    " | p50: {}ms p95: {}ms",
+                    percentile(&recent, 50.0),
+                    percentile(&recent, 95.0)
+                )
+            };
+            // Latest server sample, next to the throughput it belongs with
+            if let Some(sample) = stats_progress
+                .server_samples
+                .lock()
+                .ok()
+                .and_then(|samples| samples.last().map(|s| (s.memory_mb, s.cpu_percent)))
+            {
+                latency.push_str(&format!(" | server: {:.1}MB", sample.0));
+                if let Some(cpu) = sample.1 {
+                    latency.push_str(&format!(" {:.0}% CPU", cpu));
+                }
+            }
 
             if rate_limited > 0 {
                 println!(
-                    "[{}s/{}s] Connected: {}/{} | Sent: {} | Recv: {} | Rate limited: {}",
-                    elapsed, duration, connected, total_clients, sent, recv, rate_limited
+                    "[{}s/{}s] Connected: {}/{} | Sent: {} | Recv: {} | Rate limited: {}{}",
+                    elapsed, duration, connected, total_clients, sent, recv, rate_limited, latency
                 );
             } else {
                 println!(
-                    "[{}s/{}s] Connected: {}/{} | Sent: {} | Recv: {}",
-                    elapsed, duration, connected, total_clients, sent, recv
+                    "[{}s/{}s] Connected: {}/{} | Sent: {} | Recv: {}{}",
+                    elapsed, duration, connected, total_clients, sent, recv, latency
+                );
+            }
+
+            let fanout = fanout_ratio(
+                chat_received - last_chat_received,
+                sent - last_sent,
+                rate_limited - last_rate_limited,
+                connected,
+            );
+            if let Some(fanout) = fanout.filter(|&f| chat_mode && f < min_fanout) {
+                println!(
+                    "  ⚠ Fan-out {:.1}% in the last second: clients are missing broadcasts",
+                    fanout * 100.0
                 );
             }
+            for line in crossed {
+                println!("{}", line);
+            }
         }
     });
 
@@ -354,12 +1023,28 @@ Duration:   {}s
     }
 
     progress_handle.abort();
+    if let Some(status_poll) = status_poll {
+        let _ = status_poll.await;
+    }
+
+    // Actual run time: shorter than --duration when interrupted
+    let run_secs = start_time
+        .elapsed()
+        .as_secs_f64()
+        .min(args.duration as f64)
+        .max(0.001);
+    let interrupted = stop.load(Ordering::Relaxed);
 
     // Calculate final stats
     let total_sent = stats.messages_sent.load(Ordering::Relaxed);
     let total_recv = stats.messages_received.load(Ordering::Relaxed);
-    let total_errors = stats.errors.load(Ordering::Relaxed);
+    let connect_errors = stats.connect_errors.load(Ordering::Relaxed);
+    let send_errors = stats.send_errors.load(Ordering::Relaxed);
+    let read_errors = stats.read_errors.load(Ordering::Relaxed);
+    let total_errors = connect_errors + send_errors + read_errors;
     let total_rate_limited = stats.rate_limited.load(Ordering::Relaxed);
+    let out_of_order = stats.out_of_order.load(Ordering::Relaxed);
+    let corrupt = stats.corrupt.load(Ordering::Relaxed);
 
     let mut latencies = stats.latencies.lock().await;
     latencies.sort_unstable();
@@ -374,37 +1059,257 @@ Duration:   {}s
     let p95 = percentile(&latencies, 95.0);
     let p99 = percentile(&latencies, 99.0);
 
-    let throughput = total_sent as f64 / args.duration as f64;
+    let throughput = total_sent as f64 / run_secs;
+    let avg_msg_size = if total_sent > 0 {
+        stats.bytes_sent.load(Ordering::Relaxed) as f64 / total_sent as f64
+    } else {
+        0.0
+    };
+
+    let mut connect_latencies = stats.connect_latencies.lock().await;
+    connect_latencies.sort_unstable();
+
+    let avg_connect = if connect_latencies.is_empty() {
+        0.0
+    } else {
+        connect_latencies.iter().sum::<u64>() as f64 / connect_latencies.len() as f64 / 1000.0
+    };
+    let connect_ms = |p: f64| percentile(&connect_latencies, p) as f64 / 1000.0;
+
+    let fanout = match args.mode {
+        Mode::Chat => fanout_ratio(
+            stats.chat_received.load(Ordering::Relaxed),
+            total_sent,
+            total_rate_limited,
+            connected,
+        ),
+        Mode::Ping => None,
+    };
+
+    let server_samples = std::mem::take(&mut *stats.server_samples.lock().unwrap());
+    let crossings = std::mem::take(&mut *crossings.lock().unwrap());
+    let server_memory = min_avg_max(server_samples.iter().map(|s| s.memory_mb));
+    let server_cpu = min_avg_max(server_samples.iter().filter_map(|s| s.cpu_percent));
+
+    if json {
+        let min_avg_max_json = |values: Option<(f64, f64, f64)>| {
+            values.map(|(min, avg, max)| serde_json::json!({ "min": min, "avg": avg, "max": max }))
+        };
+        let server = args.status_poll.map(|_| {
+            serde_json::json!({
+                "memoryMb": min_avg_max_json(server_memory),
+                "cpuPercent": min_avg_max_json(server_cpu),
+                "samples": server_samples
+                    .iter()
+                    .map(|s| serde_json::json!({
+                        "elapsedSecs": s.elapsed_secs,
+                        "memoryMb": s.memory_mb,
+                        "cpuPercent": s.cpu_percent,
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        });
+        let crossing_json = |crossing: Option<Crossing>| {
+            crossing.map(|c| serde_json::json!({ "elapsedSecs": c.elapsed_secs, "rate": c.rate }))
+        };
+        let ramp = ramp.map(|ramp| {
+            serde_json::json!({
+                "rateStart": ramp.start,
+                "rateEnd": ramp.end,
+                "latencyThresholdMs": args.latency_threshold,
+                "errorsAt": crossing_json(crossings.errors),
+                "latencyAt": crossing_json(crossings.latency),
+            })
+        });
+        let results = serde_json::json!({
+            "url": args.url,
+            "clients": args.clients,
+            "connected": connected,
+            "durationSecs": run_secs,
+            "interrupted": interrupted,
+            "mode": format!("{:?}", args.mode).to_lowercase(),
+            "encoding": if args.msgpack { "msgpack" } else { "json" },
+            "messagesSent": total_sent,
+            "messagesReceived": total_recv,
+            "chatReceived": stats.chat_received.load(Ordering::Relaxed),
+            "fanoutRatio": fanout,
+            "errors": total_errors,
+            "connectErrors": connect_errors,
+            "sendErrors": send_errors,
+            "readErrors": read_errors,
+            "outOfOrder": out_of_order,
+            "corrupt": corrupt,
+            "rateLimited": total_rate_limited,
+            "reconnects": stats.reconnects.load(Ordering::Relaxed),
+            "throughput": throughput,
+            "avgMessageSize": avg_msg_size,
+            "latencyMs": { "avg": avg_latency, "p50": p50, "p95": p95, "p99": p99 },
+            "connectMs": {
+                "avg": avg_connect,
+                "p50": connect_ms(50.0),
+                "p95": connect_ms(95.0),
+                "p99": connect_ms(99.0),
+            },
+            "server": server,
+            "ramp": ramp,
+        });
+        println!("{}", serde_json::to_string_pretty(&results).unwrap());
+        return;
+    }
 
     println!(
         r#"
 ═══════════════════════════════════════
-Results
+Results{}
 ═══════════════════════════════════════
 Clients connected:  {}/{}
 Messages sent:      {}
 Messages received:  {}
-Errors:             {}
-Rate limited:       {}
+{}Errors:             {}
+  Connect:          {}
+  Send:             {}
+  Read:             {}
+{}Rate limited:       {}
+Reconnects:         {}
 Throughput:         {:.1} msg/s
+Avg message size:   {:.0} bytes
 
-Latency (ms):
+Latency ({}, ms):
   Average:  {:.2}
   P50:      {}
   P95:      {}
   P99:      {}
-═══════════════════════════════════════
+
+Connect time (ms):
+  Average:  {:.2}
+  P50:      {:.2}
+  P95:      {:.2}
+  P99:      {:.2}
+{}{}═══════════════════════════════════════
 "#,
+        if interrupted {
+            format!(" (interrupted after {:.1}s)", run_secs)
+        } else {
+            String::new()
+        },
         connected,
         args.clients,
         total_sent,
         total_recv,
+        fanout
+            .map(|f| format!("Fan-out ratio:      {:.1}%\n", f * 100.0))
+            .unwrap_or_default(),
         total_errors,
+        connect_errors,
+        send_errors,
+        read_errors,
+        match args.mode {
+            Mode::Chat => format!(
+                "Out of order:       {}\nCorrupt:            {}\n",
+                out_of_order, corrupt
+            ),
+            Mode::Ping => String::new(),
+        },
         total_rate_limited,
+        stats.reconnects.load(Ordering::Relaxed),
         throughput,
+        avg_msg_size,
+        match args.mode {
+            Mode::Chat => "chat echo",
+            Mode::Ping => "ping round-trip",
+        },
         avg_latency,
         p50,
         p95,
-        p99
+        p99,
+        avg_connect,
+        connect_ms(50.0),
+        connect_ms(95.0),
+        connect_ms(99.0),
+        match args.status_poll {
+            Some(secs) =>
+                format_server_usage(secs, server_samples.len(), server_memory, server_cpu),
+            None => String::new(),
+        },
+        ramp.map(|ramp| format_ramp(ramp, args.latency_threshold, &crossings))
+            .unwrap_or_default()
     );
 }
+
+/// Summary section for `--rate-start`/`--rate-end`
+fn format_ramp(ramp: Ramp, latency_threshold: u64, crossings: &Crossings) -> String {
+    let at = |crossing: Option<Crossing>| match crossing {
+        Some(c) => format!("{}s at {} msg/s", c.elapsed_secs, c.rate),
+        None => "n/a".to_string(),
+    };
+    format!(
+        "\nRamp ({} → {} msg/min/client):\n  {:<18}{}\n  {:<18}{}\n",
+        ramp.start,
+        ramp.end,
+        "Errors from:",
+        at(crossings.errors),
+        format!("P95 > {}ms from:", latency_threshold),
+        at(crossings.latency)
+    )
+}
+
+/// Summary section for `--status-poll`
+fn format_server_usage(
+    every_secs: u64,
+    samples: usize,
+    memory: Option<(f64, f64, f64)>,
+    cpu: Option<(f64, f64, f64)>,
+) -> String {
+    let line = |label: &str, values: Option<(f64, f64, f64)>, decimals: usize| match values {
+        Some((min, avg, max)) => {
+            format!("  {label} min {min:.decimals$}  avg {avg:.decimals$}  max {max:.decimals$}\n")
+        }
+        None => format!("  {label} n/a\n"),
+    };
+    format!(
+        "\nServer (status every {}s, {} samples):\n{}{}",
+        every_secs,
+        samples,
+        line("Memory (MB):", memory, 1),
+        line("CPU (%):    ", cpu, 0)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echoed_messages_pass_the_integrity_check() {
+        let payloads = [Payload::Phrases, Payload::Lorem, Payload::Random];
+        let sizes = std::iter::once(None).chain((0..=300).map(Some));
+        for msg_size in sizes {
+            for payload in payloads {
+                // Random payloads, so each combination a few times
+                for seq in 0..5 {
+                    let text = build_message(&format!("3-{seq}"), payload, msg_size);
+                    if let Some(size) = msg_size {
+                        assert_eq!(text.len(), size.max(13), "{payload:?} at {size}");
+                    }
+                    // What the server echoes back
+                    let echo = text.trim();
+                    assert!(
+                        matches!(verify_message(echo), Integrity::Intact(s) if s == seq),
+                        "{payload:?} at {msg_size:?}: {text:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn damaged_messages_are_corrupt() {
+        let text = build_message("3-7", Payload::Lorem, Some(120));
+        assert!(matches!(verify_message(&text[..100]), Integrity::Corrupt));
+        let mut flipped = text.clone().into_bytes();
+        flipped[60] ^= 0x01;
+        let flipped = String::from_utf8(flipped).unwrap();
+        assert!(matches!(verify_message(&flipped), Integrity::Corrupt));
+        assert!(matches!(verify_message("3-7"), Integrity::Corrupt));
+    }
+}
@@ -1,29 +1,153 @@
-use std::time::Instant;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    str::FromStr,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
+use chat_protocol::{ClientMessage as Outgoing, ServerMessage as Incoming};
 use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    client_async_tls_with_config,
+    tungstenite::{client::IntoClientRequest, Message},
+    Connector, MaybeTlsStream, WebSocketStream,
+};
 
 const DEFAULT_URL: &str = "ws://127.0.0.1:3001";
 
-#[derive(Serialize)]
-struct PingRequest {
-    #[serde(rename = "type")]
-    msg_type: &'static str,
-    token: String,
-}
-
-#[derive(Deserialize)]
-struct PongResponse {
-    #[serde(rename = "type")]
-    msg_type: String,
-    token: Option<String>,
-}
-
 struct Args {
     url: String,
     verbose: bool,
     count: u32,
+    csv: Option<String>,
+    status: bool,
+    max_users: Option<usize>,
+    max_memory_mb: Option<f64>,
+    cafile: Option<String>,
+    insecure: bool,
+}
+
+/// Duration of each connection phase, in milliseconds
+struct ConnectTiming {
+    dns_ms: f64,
+    tcp_ms: f64,
+    /// WebSocket upgrade, including the TLS handshake for wss:// URLs
+    upgrade_ms: f64,
+}
+
+/// TLS connector for `--cafile` / `--insecure`; `None` keeps the default system roots.
+fn tls_connector(cafile: Option<&str>, insecure: bool) -> Result<Option<Connector>, String> {
+    if cafile.is_none() && !insecure {
+        return Ok(None);
+    }
+
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(path) = cafile {
+        let pem = std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        let cert = native_tls::Certificate::from_pem(&pem)
+            .map_err(|e| format!("Invalid certificate in {}: {}", path, e))?;
+        builder.add_root_certificate(cert);
+    }
+    if insecure {
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+
+    let connector = builder
+        .build()
+        .map_err(|e| format!("Cannot set up TLS: {}", e))?;
+    Ok(Some(Connector::NativeTls(connector)))
+}
+
+/// Connect in separate DNS, TCP and upgrade steps so each phase can be timed.
+async fn connect(
+    url: &str,
+    connector: Option<Connector>,
+) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, ConnectTiming), String> {
+    let request = url
+        .into_client_request()
+        .map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+    let uri = request.uri();
+    let host = uri
+        .host()
+        .ok_or_else(|| format!("Invalid URL {}: missing host", url))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("wss") => 443,
+        _ => 80,
+    });
+
+    let start = Instant::now();
+    let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|e| format!("DNS lookup for {} failed: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("DNS lookup for {} returned no addresses", host));
+    }
+    let dns_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    // Try each resolved address in turn, like connect_async does
+    let start = Instant::now();
+    let stream = TcpStream::connect(&addrs[..])
+        .await
+        .map_err(|e| format!("TCP connect to {}:{} failed: {}", host, port, e))?;
+    let tcp_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let start = Instant::now();
+    let (ws_stream, _) = client_async_tls_with_config(request, stream, None, connector)
+        .await
+        .map_err(|e| format!("WebSocket upgrade failed: {}", e))?;
+    let upgrade_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    Ok((
+        ws_stream,
+        ConnectTiming {
+            dns_ms,
+            tcp_ms,
+            upgrade_ms,
+        },
+    ))
+}
+
+/// Appends one `timestamp,rtt_ms,success` row per ping to a CSV file.
+/// The timestamp is Unix epoch milliseconds; failed pings leave `rtt_ms` empty.
+struct CsvLog {
+    file: File,
+}
+
+impl CsvLog {
+    fn open(path: &str) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Cannot open CSV file {}: {}", path, e))?;
+
+        let is_empty = file.metadata().map(|m| m.len() == 0).unwrap_or(false);
+        if is_empty {
+            writeln!(file, "timestamp,rtt_ms,success")
+                .map_err(|e| format!("Cannot write CSV file {}: {}", path, e))?;
+        }
+        Ok(Self { file })
+    }
+
+    fn record(&mut self, rtt_ms: Option<f64>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let row = match rtt_ms {
+            Some(ms) => format!("{},{:.3},true", timestamp, ms),
+            None => format!("{},,false", timestamp),
+        };
+        // A full disk shouldn't abort the health check itself
+        let _ = writeln!(self.file, "{}", row).and_then(|_| self.file.flush());
+    }
 }
 
 fn parse_args() -> Args {
@@ -31,26 +155,49 @@ fn parse_args() -> Args {
         url: DEFAULT_URL.to_string(),
         verbose: false,
         count: 1,
+        csv: None,
+        status: false,
+        max_users: None,
+        max_memory_mb: None,
+        cafile: None,
+        insecure: false,
     };
 
-    let iter = std::env::args().skip(1);
-    for arg in iter {
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
         match arg.as_str() {
             "-v" | "--print" | "--verbose" => args.verbose = true,
             "-h" | "--help" => {
                 print_help();
                 std::process::exit(0);
             }
-            s if s.starts_with("--count=") => {
-                if let Ok(n) = s.trim_start_matches("--count=").parse() {
-                    args.count = n;
-                }
+            "--csv" => args.csv = iter.next(),
+            "--status" => args.status = true,
+            "--cafile" => args.cafile = iter.next(),
+            "--insecure" => args.insecure = true,
+            s if s.starts_with("--cafile=") => {
+                args.cafile = Some(s.trim_start_matches("--cafile=").to_string());
             }
-            s if s.starts_with("-c") => {
-                if let Ok(n) = s.trim_start_matches("-c").parse() {
-                    args.count = n;
-                }
+            "--max-users" => args.max_users = Some(number("--max-users", iter.next().as_deref())),
+            "--max-memory-mb" => {
+                args.max_memory_mb = Some(number("--max-memory-mb", iter.next().as_deref()));
+            }
+            s if s.starts_with("--max-users=") => {
+                args.max_users = Some(number("--max-users", s.strip_prefix("--max-users=")));
             }
+            s if s.starts_with("--max-memory-mb=") => {
+                args.max_memory_mb = Some(number(
+                    "--max-memory-mb",
+                    s.strip_prefix("--max-memory-mb="),
+                ));
+            }
+            s if s.starts_with("--csv=") => {
+                args.csv = Some(s.trim_start_matches("--csv=").to_string());
+            }
+            s if s.starts_with("--count=") => {
+                args.count = number("--count", s.strip_prefix("--count="))
+            }
+            s if s.starts_with("-c") => args.count = number("-c", s.strip_prefix("-c")),
             s if !s.starts_with('-') => {
                 args.url = s.to_string();
             }
@@ -58,9 +205,30 @@ fn parse_args() -> Args {
         }
     }
 
+    // Thresholds only make sense against a status response
+    if args.max_users.is_some() || args.max_memory_mb.is_some() {
+        args.status = true;
+    }
+
     args
 }
 
+/// The number given to `option`, or exit with a usage error
+fn number<T: FromStr>(option: &str, value: Option<&str>) -> T {
+    match value.and_then(|v| v.parse().ok()) {
+        Some(n) => n,
+        None => {
+            eprintln!(
+                "Invalid value for {}: {}",
+                option,
+                value.filter(|v| !v.is_empty()).unwrap_or("(missing)")
+            );
+            eprintln!("Run 'wsmonitor --help' for usage");
+            std::process::exit(2);
+        }
+    }
+}
+
 fn print_help() {
     eprintln!("Usage: wsmonitor [OPTIONS] [URL]");
     eprintln!();
@@ -68,30 +236,211 @@ fn print_help() {
     eprintln!("  [URL]  WebSocket server URL (default: {})", DEFAULT_URL);
     eprintln!();
     eprintln!("Options:");
-    eprintln!("  -v, --verbose      Print response times");
-    eprintln!("  -c, --count=<N>    Number of pings to send (default: 1)");
-    eprintln!("  -h, --help         Show this help");
+    eprintln!("  -v, --verbose            Print response and connection phase times");
+    eprintln!("  -c, --count=<N>          Number of pings to send (default: 1)");
+    eprintln!("      --csv <PATH>         Append each ping result to a CSV file");
+    eprintln!("      --status             Query and print server status instead of pinging");
+    eprintln!("      --max-users <N>      Fail if more than N users are online (implies --status)");
+    eprintln!("      --max-memory-mb <M>  Fail if server memory exceeds M MB (implies --status)");
+    eprintln!("      --cafile <PATH>      Trust an extra root certificate (PEM) for wss:// URLs");
+    eprintln!("      --insecure           Skip TLS certificate verification (testing only)");
+    eprintln!("  -h, --help               Show this help");
     eprintln!();
     eprintln!("Exit codes:");
     eprintln!("  0  All pings successful");
     eprintln!("  1  Connection or ping failed");
+    eprintln!("  2  Status threshold exceeded, or an invalid option value");
+}
+
+/// Whether a frame that didn't parse as a server message claims to be a status reply
+fn is_status(text: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(text)
+        .is_ok_and(|value| value.get("type").and_then(|t| t.as_str()) == Some("status"))
+}
+
+/// Request the server status, print it and check the thresholds.
+/// Returns the process exit code.
+async fn check_status(mut ws: WebSocketStream<MaybeTlsStream<TcpStream>>, args: &Args) -> i32 {
+    let json = serde_json::to_string(&Outgoing::Status).unwrap();
+    if ws.send(Message::Text(json.into())).await.is_err() {
+        eprintln!("Failed to send status request");
+        return 1;
+    }
+
+    let response = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while let Some(msg) = ws.next().await {
+            if let Ok(Message::Text(text)) = msg {
+                match serde_json::from_str(&text) {
+                    Ok(status @ Incoming::Status { .. }) => return Some(Ok(status)),
+                    Ok(_) => {}
+                    // A status reply we can't read won't be followed by another one
+                    Err(e) if is_status(&text) => return Some(Err(e)),
+                    Err(_) => {}
+                }
+            }
+        }
+        None
+    })
+    .await;
+    let _ = ws.close(None).await;
+
+    let status = match response {
+        Ok(Some(Ok(status))) => status,
+        Ok(Some(Err(e))) => {
+            eprintln!("Cannot parse status response: {}", e);
+            return 1;
+        }
+        Ok(None) => {
+            eprintln!("Connection closed before status response");
+            return 1;
+        }
+        Err(_) => {
+            eprintln!("Timeout waiting for status response");
+            return 1;
+        }
+    };
+    let Incoming::Status {
+        version,
+        rust_version,
+        protocol_version,
+        os,
+        cpu_cores,
+        uptime_seconds,
+        user_count,
+        peak_users,
+        connections_total,
+        messages_sent,
+        messages_per_second,
+        memory_mb,
+        cpu_percent,
+        total_session_seconds,
+        ai_enabled,
+        ai_model,
+        ai_cost_today,
+        ai_tokens_today,
+        ..
+    } = status
+    else {
+        unreachable!("only status messages are returned");
+    };
+
+    // Fields the Bun backend and older servers leave out are skipped
+    if let Some(version) = version {
+        println!("version:           {}", version);
+    }
+    if let Some(rust_version) = rust_version {
+        println!("rustVersion:       {}", rust_version);
+    }
+    if let Some(protocol) = protocol_version {
+        println!("protocolVersion:   {}", protocol);
+    }
+    if let Some(os) = os {
+        println!("os:                {}", os);
+    }
+    if let Some(cores) = cpu_cores {
+        println!("cpuCores:          {}", cores);
+    }
+    println!("uptimeSeconds:     {}", uptime_seconds);
+    println!("userCount:         {}", user_count);
+    if let Some(peak) = peak_users {
+        println!("peakUsers:         {}", peak);
+    }
+    if let Some(total) = connections_total {
+        println!("connectionsTotal:  {}", total);
+    }
+    println!("messagesSent:      {}", messages_sent);
+    if let Some(rate) = messages_per_second {
+        println!("messagesPerSecond: {:.2}", rate);
+    }
+    if let Some(mb) = memory_mb {
+        println!("memoryMb:          {:.1}", mb);
+    }
+    if let Some(cpu) = cpu_percent {
+        println!("cpuPercent:        {:.1}", cpu);
+    }
+    if let Some(secs) = total_session_seconds {
+        println!("totalSessionSeconds: {}", secs);
+    }
+    if let Some(enabled) = ai_enabled {
+        println!("aiEnabled:         {}", enabled);
+    }
+    if let Some(model) = &ai_model {
+        println!("aiModel:           {}", model);
+    }
+    if let Some(cost) = ai_cost_today {
+        println!("aiCostToday:       {:.4}", cost);
+    }
+    if let Some(tokens) = ai_tokens_today {
+        println!("aiTokensToday:     {}", tokens);
+    }
+
+    let mut code = 0;
+    if let Some(max) = args.max_users {
+        if user_count > max {
+            eprintln!("FAIL: userCount {} exceeds --max-users {}", user_count, max);
+            code = 2;
+        }
+    }
+    if let Some(max) = args.max_memory_mb {
+        match memory_mb {
+            Some(mb) if mb > max => {
+                eprintln!("FAIL: memoryMb {:.1} exceeds --max-memory-mb {}", mb, max);
+                code = 2;
+            }
+            Some(_) => {}
+            None => {
+                eprintln!("FAIL: server does not report memoryMb for --max-memory-mb");
+                code = 2;
+            }
+        }
+    }
+    code
 }
 
 #[tokio::main]
 async fn main() {
     let args = parse_args();
 
+    let mut csv = match args.csv.as_deref().map(CsvLog::open).transpose() {
+        Ok(csv) => csv,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let connector = match tls_connector(args.cafile.as_deref(), args.insecure) {
+        Ok(connector) => connector,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Connect
-    let (ws_stream, _) = match connect_async(&args.url).await {
+    let (ws_stream, timing) = match connect(&args.url, connector).await {
         Ok(conn) => conn,
         Err(e) => {
             if args.verbose {
                 eprintln!("Failed to connect to {}: {}", args.url, e);
             }
+            if let Some(csv) = csv.as_mut() {
+                csv.record(None);
+            }
             std::process::exit(1);
         }
     };
 
+    if args.verbose {
+        println!("dns:     {:.2}ms", timing.dns_ms);
+        println!("tcp:     {:.2}ms", timing.tcp_ms);
+        println!("upgrade: {:.2}ms", timing.upgrade_ms);
+    }
+
+    if args.status {
+        std::process::exit(check_status(ws_stream, &args).await);
+    }
+
     let (mut write, mut read) = ws_stream.split();
 
     if args.verbose {
@@ -105,9 +454,8 @@ async fn main() {
 
     for seq in 1..=args.count {
         let token = uuid::Uuid::new_v4().to_string();
-        let ping = PingRequest {
-            msg_type: "ping",
-            token: token.clone(),
+        let ping = Outgoing::Ping {
+            token: Some(token.clone()),
         };
 
         let start = Instant::now();
@@ -118,6 +466,9 @@ async fn main() {
             if args.verbose {
                 eprintln!("seq={}: send failed", seq);
             }
+            if let Some(csv) = csv.as_mut() {
+                csv.record(None);
+            }
             continue;
         }
 
@@ -125,8 +476,8 @@ async fn main() {
         let timeout = tokio::time::timeout(std::time::Duration::from_secs(5), async {
             while let Some(msg) = read.next().await {
                 if let Ok(Message::Text(text)) = msg {
-                    if let Ok(pong) = serde_json::from_str::<PongResponse>(&text) {
-                        if pong.msg_type == "pong" && pong.token.as_ref() == Some(&token) {
+                    if let Ok(Incoming::Pong { token: Some(t), .. }) = serde_json::from_str(&text) {
+                        if t == token {
                             return Some(start.elapsed());
                         }
                     }
@@ -136,6 +487,14 @@ async fn main() {
         })
         .await;
 
+        if let Some(csv) = csv.as_mut() {
+            let rtt_ms = match &timeout {
+                Ok(Some(elapsed)) => Some(elapsed.as_secs_f64() * 1000.0),
+                _ => None,
+            };
+            csv.record(rtt_ms);
+        }
+
         match timeout {
             Ok(Some(elapsed)) => {
                 let ms = elapsed.as_secs_f64() * 1000.0;
@@ -183,6 +542,10 @@ async fn main() {
                 min_time, avg, max_time
             );
         }
+        eprintln!(
+            "connect dns/tcp/upgrade = {:.2}/{:.2}/{:.2} ms",
+            timing.dns_ms, timing.tcp_ms, timing.upgrade_ms
+        );
     }
 
     // Exit code